@@ -1,51 +1,515 @@
-use crate::helpers::file::create_new_my_git;
+use crate::helpers::clean::CleanOptions;
+use crate::helpers::clone::{clone_dir_name, local_git_dir, CloneHead};
+use crate::helpers::config;
+use crate::helpers::config::{ConfigEntry, ConfigScope};
+use crate::helpers::describe::DescribeOptions;
+use crate::helpers::file::{create_new_my_git, path_from_prefix, path_to_prefix, quote_path};
 use crate::helpers::git::GitRepo;
 use crate::helpers::git_objects::commit::GitCommit;
-use crate::helpers::git_objects::git_ignore::GitIgnore;
-use crate::helpers::git_objects::git_object::{AsAny, ObjectType};
+use crate::helpers::git_objects::git_object::ObjectType;
+use crate::helpers::git_objects::tag::GitTag;
 use crate::helpers::git_objects::tree::GitTree;
-use crate::helpers::git_objects::tree_leaf::GitTreeLeaf;
-use crate::helpers::kvlm::kvlm_parse;
+use crate::helpers::http::is_http_url;
+use crate::helpers::lfs::{format_size, LfsPointer};
 use crate::helpers::pager::display_with_pager;
+use crate::helpers::pkt_line::pkt_write_line;
+use crate::helpers::refspec::{ref_candidates, ref_shorten, Refspec};
+use crate::helpers::remote::{remote_name_valid, Lease, RefStatus, RefUpdate};
+use crate::helpers::show::{message_parts, CommitFormat};
+use crate::helpers::signing::{split_tag_signature, SignatureCheck};
+use crate::helpers::sparse::{cone_dirs, SparsePatterns};
+use crate::helpers::submodule::{Submodule, SubmoduleState};
 use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::{Stdin, Write};
+use std::io::Write;
 use std::os::unix::fs::MetadataExt;
-use std::path::PathBuf;
-use std::process::Stdio;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, UNIX_EPOCH};
 
-pub fn init(path: String) {
-    let r = create_new_my_git(path.into());
+/// Finds the repository of the current directory, or fails the way git does.
+fn find_repo() -> GitRepo {
+    GitRepo::repo_find(".".into()).unwrap_or_else(|| {
+        eprintln!("fatal: not a git repository (or any of the parent directories): .git");
+        std::process::exit(128);
+    })
+}
+
+/// Like `find_repo`, for the commands that need a working tree.
+fn work_tree_repo() -> GitRepo {
+    let repo = find_repo();
+    if repo.bare {
+        eprintln!("fatal: this operation must be run in a work tree");
+        std::process::exit(128);
+    }
+    repo
+}
+
+pub fn init(path: String, bare: bool) {
+    let r = create_new_my_git(path.into(), bare);
     match r {
         Ok(_) => {
             println!("git dri was successfully created")
         }
         Err(e) => {
-            eprintln!("{}", e.to_string());
+            eprintln!("{}", e);
             std::process::exit(1)
         }
     }
 }
 
-pub fn add(path: String) {
-    let git_repo = GitRepo::repo_find(path.into());
-    match git_repo {
-        None => {}
-        Some(v) => {
-            v.index_read();
+pub fn clone(url: &str, dir: Option<&str>, bare: bool, branch: Option<&str>, depth: Option<usize>) {
+    let fail = |message: String| -> ! {
+        eprintln!("fatal: {}", message);
+        std::process::exit(128);
+    };
+    if !is_http_url(url) && local_git_dir(url).is_none() {
+        fail(format!("repository '{}' does not exist", url));
+    }
+    let dir = match dir {
+        Some(v) => v.to_string(),
+        None if bare => format!("{}.git", clone_dir_name(url)),
+        None => clone_dir_name(url),
+    };
+    let target = PathBuf::from(&dir);
+    if target.exists() && !target.read_dir().is_ok_and(|mut v| v.next().is_none()) {
+        fail(format!("destination path '{}' already exists and is not an empty directory.", dir));
+    }
+    if bare {
+        eprintln!("Cloning into bare repository '{}'...", dir);
+    } else {
+        eprintln!("Cloning into '{}'...", dir);
+    }
+    let local = !url.starts_with("file://") && !is_http_url(url);
+    if depth.is_some() && local {
+        eprintln!("warning: --depth is ignored in local clones; use file:// instead.");
+    }
+
+    let created = !target.exists();
+    let open = || match bare {
+        true => GitRepo::from_dirs(None, target.clone(), false),
+        false => GitRepo::init(target.clone(), false),
+    };
+    create_new_my_git(target.clone(), bare).unwrap_or_else(|e| fail(e));
+    let repo = open();
+    let head = repo.transport_open(url).and_then(|source| repo.clone_from(url, &source, branch, depth)).unwrap_or_else(|e| {
+        if created {
+            let _ = fs::remove_dir_all(&target);
+        }
+        fail(e)
+    });
+
+    // The configuration and objects have changed since the repository was opened.
+    let repo = open();
+    match &head {
+        CloneHead::Unborn(_) => eprintln!("warning: You appear to have cloned an empty repository."),
+        CloneHead::Branch(_, sha) | CloneHead::Detached(sha) if !bare => {
+            let tree = repo.obj_find(sha.clone(), Some("tree".to_string()), None).unwrap_or_else(|e| fail(e));
+            repo.tree_checkout(repo.object_read(tree.clone()).unwrap_or_else(|e| fail(e)), repo.work_dir.clone());
+            repo.index_from_tree(&tree).unwrap_or_else(|e| fail(e));
+        }
+        _ => {}
+    }
+    if local {
+        eprintln!("done.");
+    }
+    if let (CloneHead::Detached(sha), false) = (&head, bare) {
+        if repo.config_bool("advice.detachedHead") != Some(false) {
+            eprintln!("Note: switching to '{}'.", sha);
+            eprintln!();
+            eprintln!("You are in 'detached HEAD' state. You can look around, make experimental");
+            eprintln!("changes and commit them, and you can discard any commits you make in this");
+            eprintln!("state without impacting any branches by switching back to a branch.");
+            eprintln!();
+            eprintln!("If you want to create a new branch to retain commits you create, you may");
+            eprintln!("do so (now or later) by using -c with the switch command. Example:");
+            eprintln!();
+            eprintln!("  git switch -c <new-branch-name>");
+            eprintln!();
+            eprintln!("Or undo this operation with:");
+            eprintln!();
+            eprintln!("  git switch -");
+            eprintln!();
+            eprintln!("Turn off this advice by setting config variable advice.detachedHead to false");
+            eprintln!();
+        }
+    }
+}
+
+/// Fails the way git does when a remote can't be reached.
+fn remote_unreachable(name: &str) -> ! {
+    eprintln!("fatal: '{}' does not appear to be a git repository", name);
+    eprintln!("fatal: Could not read from remote repository.");
+    eprintln!();
+    eprintln!("Please make sure you have the correct access rights");
+    eprintln!("and the repository exists.");
+    std::process::exit(128);
+}
+
+fn parse_refspecs(refspecs: &[String]) -> Vec<Refspec> {
+    refspecs
+        .iter()
+        .map(|v| {
+            Refspec::parse(v).unwrap_or_else(|e| {
+                eprintln!("fatal: {}", e);
+                std::process::exit(128);
+            })
+        })
+        .collect()
+}
+
+/// Prints what a fetch or a push did to a ref, the way git lines them up.
+fn print_ref_update(update: &RefUpdate, push: bool, width: usize) {
+    let (flag, summary, note) = update.summary(push);
+    let dst = update.dst.as_deref().map(ref_shorten).unwrap_or("FETCH_HEAD");
+    if push && update.new.is_none() {
+        let note = note.map(|v| format!(" ({})", v)).unwrap_or_default();
+        eprintln!(" {} {:<17} {}{}", flag, summary, dst, note);
+    } else if push {
+        let note = note.map(|v| format!(" ({})", v)).unwrap_or_default();
+        eprintln!(" {} {:<17} {} -> {}{}", flag, summary, ref_shorten(&update.src), dst, note);
+    } else {
+        let note = note.map(|v| format!("  ({})", v)).unwrap_or_default();
+        eprintln!(" {} {:<17} {:<width$} -> {}{}", flag, summary, ref_shorten(&update.src), dst, note, width = width);
+    }
+}
+
+pub fn fetch(remote: Option<&str>, refspecs: &[String]) {
+    let repo = find_repo();
+    let name = match remote {
+        Some(v) => v.to_string(),
+        None => repo
+            .get_active_branch()
+            .and_then(|v| repo.config_get(&format!("branch.{}.remote", v)))
+            .unwrap_or("origin".to_string()),
+    };
+    let remote = match (repo.remote_get(&name), remote) {
+        (Some(v), _) => v,
+        // Without a remote to fetch from there is nothing to do.
+        (None, None) => return,
+        (None, Some(_)) => remote_unreachable(&name),
+    };
+    let source = repo.transport_open(&remote.url).unwrap_or_else(|_| remote_unreachable(&remote.url));
+    let updates = repo.fetch(&remote, &source, &parse_refspecs(refspecs)).unwrap_or_else(|e| {
+        eprintln!("fatal: {}", e);
+        std::process::exit(128);
+    });
+
+    let shown: Vec<&RefUpdate> = updates.iter().filter(|v| v.status != RefStatus::UpToDate).collect();
+    if !shown.is_empty() {
+        eprintln!("From {}", remote.url_fetched());
+    }
+    let width = shown.iter().map(|v| ref_shorten(&v.src).len()).max().unwrap_or(0).max(10);
+    for update in &shown {
+        print_ref_update(update, false, width);
+    }
+    if shown.iter().any(|v| !v.ok()) {
+        std::process::exit(1);
+    }
+}
+
+pub fn push(remote: Option<&str>, refspecs: &[String], force: bool, leases: &[String], delete: bool) {
+    let repo = find_repo();
+    let branch = repo.get_active_branch();
+    let branch_config = |key: &str| branch.as_ref().and_then(|v| repo.config_get(&format!("branch.{}.{}", v, key)));
+    let name = match remote {
+        Some(v) => v.to_string(),
+        None => branch_config("pushRemote")
+            .or_else(|| repo.config_get("remote.pushDefault"))
+            .or_else(|| branch_config("remote"))
+            .unwrap_or("origin".to_string()),
+    };
+    let remote = match (repo.remote_get(&name), remote) {
+        (Some(v), _) => v,
+        (None, None) => {
+            eprintln!("fatal: No configured push destination.");
+            eprintln!("Either specify the URL from the command-line or configure a remote repository using");
+            eprintln!();
+            eprintln!("    git remote add <name> <url>");
+            eprintln!();
+            eprintln!("and then push using the remote name");
+            eprintln!();
+            eprintln!("    git push <name>");
+            eprintln!();
+            std::process::exit(128);
+        }
+        (None, Some(_)) => remote_unreachable(&name),
+    };
+    let target = repo.transport_open(&remote.url).unwrap_or_else(|_| remote_unreachable(&remote.url));
+
+    let refspecs = match delete {
+        true if refspecs.is_empty() => {
+            eprintln!("fatal: --delete doesn't make sense without any refs");
+            std::process::exit(128);
+        }
+        true => parse_refspecs(&refspecs.iter().map(|v| format!(":{}", v)).collect::<Vec<_>>()),
+        false => parse_refspecs(refspecs),
+    };
+    let refspecs = match (refspecs.is_empty(), remote.push.is_empty()) {
+        (false, _) => refspecs,
+        (true, false) => remote.push.clone(),
+        (true, true) => vec![repo.push_default_refspec(&remote).unwrap_or_else(|e| {
+            eprint!("fatal: {}", e);
+            std::process::exit(128);
+        })],
+    };
+    let leases: Vec<Lease> = leases
+        .iter()
+        .map(|v| match v.split_once(':') {
+            _ if v.is_empty() => Lease::Tracking,
+            Some((name, expect)) => Lease::Ref(name.to_string(), Some(expect.to_string())),
+            None => Lease::Ref(v.to_string(), None),
+        })
+        .collect();
+
+    let failed = || {
+        eprintln!("error: failed to push some refs to '{}'", remote.url_display());
+        std::process::exit(1);
+    };
+    let (updates, messages) = repo.push(&remote, &target, &refspecs, force, &leases).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        failed()
+    });
+    for message in messages {
+        match message.is_empty() {
+            true => eprintln!("remote: "),
+            false => eprintln!("remote: {}        ", message),
+        }
+    }
+    let shown: Vec<&RefUpdate> = updates.iter().filter(|v| v.status != RefStatus::UpToDate).collect();
+    if shown.is_empty() {
+        eprintln!("Everything up-to-date");
+        return;
+    }
+    eprintln!("To {}", remote.url_display());
+    for update in &shown {
+        print_ref_update(update, true, 0);
+    }
+    if shown.iter().all(|v| v.ok()) {
+        return;
+    }
+
+    let rejected = |reason: &str, current: Option<bool>| {
+        shown.iter().any(|v| {
+            let is_current = branch.as_ref().is_some_and(|b| v.src == format!("refs/heads/{}", b) || v.src == "HEAD");
+            v.status == RefStatus::Rejected(reason.to_string()) && current.is_none_or(|c| c == is_current)
+        })
+    };
+    eprintln!("error: failed to push some refs to '{}'", remote.url_display());
+    let hint = if rejected("non-fast-forward", Some(true)) {
+        "Updates were rejected because the tip of your current branch is behind\n\
+         its remote counterpart. Integrate the remote changes (e.g.\n\
+         'git pull ...') before pushing again.\n\
+         See the 'Note about fast-forwards' in 'git push --help' for details."
+    } else if rejected("non-fast-forward", Some(false)) {
+        "Updates were rejected because a pushed branch tip is behind its remote\n\
+         counterpart. Check out this branch and integrate the remote changes\n\
+         (e.g. 'git pull ...') before pushing again.\n\
+         See the 'Note about fast-forwards' in 'git push --help' for details."
+    } else if rejected("already exists", None) {
+        "Updates were rejected because the tag already exists in the remote."
+    } else if rejected("fetch first", None) {
+        "Updates were rejected because the remote contains work that you do\n\
+         not have locally. This is usually caused by another repository pushing\n\
+         to the same ref. You may want to first integrate the remote changes\n\
+         (e.g., 'git pull ...') before pushing again.\n\
+         See the 'Note about fast-forwards' in 'git push --help' for details."
+    } else {
+        ""
+    };
+    for line in hint.lines() {
+        eprintln!("hint: {}", line);
+    }
+    std::process::exit(1);
+}
+
+pub fn remote_list(verbose: bool) {
+    let repo = find_repo();
+    for name in repo.remote_names() {
+        if !verbose {
+            println!("{}", name);
+            continue;
+        }
+        let url = repo.config_get(&format!("remote.{}.url", name)).unwrap_or_default();
+        let push_url = repo.config_get(&format!("remote.{}.pushurl", name)).unwrap_or(url.clone());
+        println!("{}\t{} (fetch)", name, url);
+        println!("{}\t{} (push)", name, push_url);
+    }
+}
+
+/// Checks that `name` is a remote, with the messages and statuses of `git remote`.
+fn remote_check(repo: &GitRepo, name: &str, exists: bool, missing: &str) {
+    if !remote_name_valid(name) {
+        eprintln!("fatal: '{}' is not a valid remote name", name);
+        std::process::exit(128);
+    }
+    match (repo.remote_names().iter().any(|v| v == name), exists) {
+        (true, false) => {
+            eprintln!("error: remote {} already exists.", name);
+            std::process::exit(3);
+        }
+        (false, true) => {
+            eprintln!("error: {}", missing.replace("{}", name));
+            std::process::exit(2);
+        }
+        _ => {}
+    }
+}
+
+fn remote_done(result: Result<(), String>) {
+    if let Err(e) = result {
+        eprintln!("fatal: {}", e);
+        std::process::exit(128);
+    }
+}
+
+pub fn remote_add(name: &str, url: &str) {
+    let repo = find_repo();
+    remote_check(&repo, name, false, "");
+    remote_done(repo.remote_add(name, url));
+}
+
+pub fn remote_remove(name: &str) {
+    let repo = find_repo();
+    remote_check(&repo, name, true, "No such remote: '{}'");
+    remote_done(repo.remote_remove(name));
+}
+
+pub fn remote_rename(old: &str, new: &str) {
+    let repo = find_repo();
+    remote_check(&repo, old, true, "No such remote: '{}'");
+    remote_check(&repo, new, false, "");
+    remote_done(repo.remote_rename(old, new));
+}
+
+pub fn remote_set_url(name: &str, url: &str) {
+    let repo = find_repo();
+    remote_check(&repo, name, true, "No such remote '{}'");
+    remote_done(repo.remote_set_url(name, url));
+}
+
+/// Lists the branches, or makes `upstream` the upstream of `name`, the current branch by default.
+pub fn branch(set_upstream_to: Option<&str>, name: Option<&str>) {
+    let repo = find_repo();
+    let current = repo.get_active_branch();
+    let fail = |message: String| -> ! {
+        eprintln!("fatal: {}", message);
+        std::process::exit(128);
+    };
+    let upstream = match set_upstream_to {
+        Some(v) => v,
+        None => {
+            if current.is_none() {
+                let sha = repo.obj_find("HEAD".to_string(), None, None).unwrap_or_default();
+                println!("* (HEAD detached at {})", &sha[..7.min(sha.len())]);
+            }
+            for branch in repo.branch_names() {
+                let mark = if current.as_ref() == Some(&branch) { '*' } else { ' ' };
+                println!("{} {}", mark, branch);
+            }
+            return;
+        }
+    };
+
+    let branch = match (name, &current) {
+        (Some(v), _) => v.to_string(),
+        (None, Some(v)) => v.clone(),
+        (None, None) => fail(format!("could not set upstream of HEAD to {} when it does not point to any branch.", upstream)),
+    };
+    if repo.ref_resolve(format!("refs/heads/{}", branch).into()).is_none() {
+        fail(format!("branch '{}' does not exist", branch));
+    }
+    let full = ref_candidates(upstream).into_iter().find(|v| repo.ref_resolve(v.into()).is_some()).unwrap_or_else(|| {
+        eprintln!("fatal: the requested upstream branch '{}' does not exist", upstream);
+        for line in SET_UPSTREAM_FAILURE.lines() {
+            eprintln!("hint: {}", line);
         }
+        std::process::exit(128);
+    });
+    if full == format!("refs/heads/{}", branch) {
+        eprintln!("warning: not setting branch '{}' as its own upstream", branch);
+        return;
     }
+    if !full.starts_with("refs/heads/") && !full.starts_with("refs/remotes/") {
+        fail(format!("cannot set up tracking information; starting point '{}' is not a branch", upstream));
+    }
+    repo.branch_set_upstream(&branch, &full).unwrap_or_else(|e| fail(e));
+    println!("branch '{}' set up to track '{}'.", branch, ref_shorten(&full));
 }
 
-pub fn cat_file(object_type: &ObjectType, object: &String) {
+/// Serves the repository at `dir` to a fetching client on stdin and stdout.
+pub fn upload_pack(dir: &str) {
+    let git_dir = local_git_dir(dir).unwrap_or_else(|| {
+        eprintln!("fatal: '{}' does not appear to be a git repository", dir);
+        std::process::exit(128);
+    });
+    let repo = GitRepo::from_dirs(None, git_dir, false);
+    let mut output = std::io::BufWriter::new(std::io::stdout().lock());
+    if let Err(e) = repo.upload_pack(&mut std::io::stdin().lock(), &mut output) {
+        // The client shows errors sent as a packet; stderr only reaches a local one.
+        let _ = pkt_write_line(&mut output, &format!("ERR {}", e));
+        let _ = output.flush();
+        eprintln!("fatal: {}", e);
+        std::process::exit(128);
+    }
+}
+
+pub fn receive_pack(dir: &str) {
+    let git_dir = local_git_dir(dir).unwrap_or_else(|| {
+        eprintln!("fatal: '{}' does not appear to be a git repository", dir);
+        std::process::exit(128);
+    });
+    let repo = GitRepo::from_dirs(None, git_dir, false);
+    let mut output = std::io::BufWriter::new(std::io::stdout().lock());
+    if let Err(e) = repo.receive_pack(&mut std::io::stdin().lock(), &mut output) {
+        let _ = output.flush();
+        eprintln!("fatal: {}", e);
+        std::process::exit(128);
+    }
+}
+
+const SET_UPSTREAM_FAILURE: &str = "
+If you are planning on basing your work on an upstream
+branch that already exists at the remote, you may need to
+run \"git fetch\" to retrieve it.
+
+If you are planning to push out a new local branch that
+will track its remote counterpart, you may want to use
+\"git push -u\" to set the upstream config as you push.
+Disable this message with \"git config advice.setUpstreamFailure false\"";
+
+pub fn add(paths: &[PathBuf], force: bool) {
+    let repo = work_tree_repo();
+    let ignored = match repo.add(paths, force) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            std::process::exit(128);
+        }
+    };
+    if !ignored.is_empty() {
+        eprintln!("The following paths are ignored by one of your .gitignore files:");
+        for path in ignored {
+            eprintln!("{}", path);
+        }
+        if repo.config_bool("advice.addIgnoredFile") != Some(false) {
+            eprintln!("hint: Use -f if you really want to add them.");
+            eprintln!("hint: Turn this message off by running");
+            eprintln!("hint: \"git config advice.addIgnoredFile false\"");
+        }
+        std::process::exit(1);
+    }
+}
+
+pub fn cat_file(object_type: &ObjectType, object: &str) {
     let repo = GitRepo::repo_find(".".into());
     match repo {
         None => {
             eprintln!("No git repo find")
         }
         Some(v) => {
-            std::io::stdout().write(&*v.cat_file(object.clone(), object_type.clone()).unwrap());
+            std::io::stdout()
+                .write_all(&v.cat_file(object.to_string(), object_type.clone()).unwrap())
+                .unwrap();
         }
     }
 }
@@ -62,7 +526,7 @@ pub fn hash_obj(object_type: &ObjectType, path: &String, write: &bool) {
     };
 }
 
-pub fn log(commit: String) {
+pub fn log(commit: String, show_signature: bool) {
     let repo = GitRepo::repo_find(".".into());
     match repo {
         None => {
@@ -74,28 +538,14 @@ pub fn log(commit: String) {
                 v.obj_find(commit, None, None).unwrap(),
                 &mut HashSet::new(),
                 &mut data,
+                show_signature,
             );
-            let pager = std::env::var("PAGER").unwrap_or_else(|_| "less".to_string());
-
-            let mut child = std::process::Command::new(&pager)
-                .stdin(Stdio::piped())
-                .spawn()
-                .unwrap_or_else(|_| panic!("Failed to start pager: {}", pager));
-
-            // Write the output to the pager
-            if let Some(mut stdin) = child.stdin.take() {
-                stdin
-                    .write_all(data.as_bytes())
-                    .expect("Failed to write to pager");
-            }
-
-            // Wait for the pager process to exit
-            child.wait().expect("Failed to wait on pager");
+            display_with_pager(Some(&v), &data);
         }
     }
 }
 
-pub fn ls_tree(recursive: &bool, tree: &String) {
+pub fn ls_tree(recursive: &bool, tree: &str) {
     let repo = match GitRepo::repo_find(".".into()) {
         None => {
             println!("No gi repo find");
@@ -151,8 +601,8 @@ pub fn checkout(sha: String, path: PathBuf) {
             );
             return;
         }
-        if path.read_dir().iter().len() != 0 {
-            //eprintln!("Not a directory: {:?}",path.into_os_string().into_string());
+        if path.read_dir().map(|mut v| v.next().is_some()).unwrap_or(true) {
+            eprintln!("Not empty: {}", path.display());
             return;
         }
     } else {
@@ -169,37 +619,412 @@ pub fn show_ref() {
     }
 }
 
-pub fn tag(name: &Option<String>, create: &bool, object: &String, message: &Option<String>) {
-    let repo = GitRepo::repo_find(".".into()).unwrap();
-    match name {
-        None => {
-            let ref_list = repo
-                .ref_list(
-                    Some(repo.repo_dir("refs/tags".to_string(), true).unwrap()),
-                    "refs".to_string(),
-                )
-                .unwrap();
-            let mut tags = String::new();
-            for (key, _value) in ref_list {
-                let key: Vec<_> = key.rsplit('/').collect();
-                if let Some(key) = key.first() {
-                    tags.push_str(&format!("{}\n", key));
+pub struct TagOptions {
+    pub annotate: bool,
+    pub message: Option<String>,
+    pub delete: bool,
+    pub list: bool,
+    pub force: bool,
+    pub lines: Option<usize>,
+    pub sign: bool,
+}
+
+/// Returns the first `lines` lines of the message of an annotated tag, or of the commit
+/// a lightweight tag points at.
+fn tag_annotation(repo: &GitRepo, sha: &str, lines: usize) -> Vec<String> {
+    let obj = match repo.object_read(sha.to_string()) {
+        Ok(v) => v,
+        Err(_) => return Vec::new(),
+    };
+    let kvlm = if let Some(tag) = obj.as_ref().as_any().downcast_ref::<GitTag>() {
+        tag.kvlm.clone()
+    } else if let Some(commit) = obj.as_ref().as_any().downcast_ref::<GitCommit>() {
+        commit.kvlm.clone()
+    } else {
+        return Vec::new();
+    };
+    let message = kvlm
+        .get(b"None".as_ref())
+        .and_then(|v| v.first())
+        .map(|v| String::from_utf8_lossy(split_tag_signature(v).0).into_owned())
+        .unwrap_or_default();
+    message
+        .trim()
+        .lines()
+        .take(lines)
+        .map(|v| v.to_string())
+        .collect()
+}
+
+pub fn tag(args: &[String], options: TagOptions) {
+    let repo = find_repo();
+
+    if options.delete {
+        let mut failed = false;
+        for name in args {
+            match repo.delete_tag(name) {
+                Ok(sha) => println!("Deleted tag '{}' (was {})", name, &sha[..7]),
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    failed = true;
+                }
+            }
+        }
+        if failed {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if options.list || options.lines.is_some() || args.is_empty() {
+        let ref_list = repo
+            .ref_list(
+                Some(repo.repo_dir("refs/tags".to_string(), true).unwrap()),
+                "refs/tags".to_string(),
+            )
+            .unwrap();
+        let patterns: Vec<glob::Pattern> = args
+            .iter()
+            .filter_map(|v| glob::Pattern::new(v).ok())
+            .collect();
+        let mut tags = String::new();
+        for (key, value) in ref_list {
+            let key = key.strip_prefix("refs/tags/").unwrap_or(&key);
+            if !patterns.is_empty() && !patterns.iter().any(|v| v.matches(key)) {
+                continue;
+            }
+            match options.lines {
+                None => tags.push_str(&format!("{}\n", key)),
+                Some(lines) => {
+                    let annotation = tag_annotation(&repo, &value, lines);
+                    tags.push_str(&format!("{:<15} {}\n", key, annotation.join("\n    ")));
+                }
+            }
+        }
+        display_with_pager(Some(&repo), &tags);
+        return;
+    }
+
+    let name = &args[0];
+    let object = args.get(1).map(|v| v.as_str()).unwrap_or("HEAD");
+    let sign = options.sign || repo.config_bool("tag.gpgSign").unwrap_or(false);
+    let annotate = options.annotate || sign || options.message.is_some();
+    let message = match (&options.message, annotate) {
+        (None, true) => {
+            let template = format!(
+                "\n#\n# Write a message for tag:\n#   {}\n# Lines starting with '#' will be ignored.\n",
+                name
+            );
+            match repo.edit_message("TAG_EDITMSG", &template) {
+                Ok(v) if !v.is_empty() => Some(v),
+                Ok(_) => {
+                    eprintln!("fatal: no tag message?");
+                    std::process::exit(128);
+                }
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    std::process::exit(128);
                 }
             }
-            display_with_pager(&*tags)
         }
-        Some(name) => repo.create_tag(
-            name,
-            object.clone(),
-            create.clone(),
-            message.clone().as_ref(),
-        ),
+        (message, _) => message.clone(),
+    };
+
+    match repo.create_tag(
+        name,
+        object.to_string(),
+        annotate,
+        message.as_ref(),
+        options.force,
+        sign,
+    ) {
+        Ok(Some(previous)) => println!("Updated tag '{}' (was {})", name, &previous[..7]),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            std::process::exit(128);
+        }
     }
 }
 
-pub fn rev_parse(name: &String) {
+pub fn commit(message: &Option<String>, sign: bool, no_sign: bool) {
+    let repo = work_tree_repo();
+    let fail = |e: String| -> ! {
+        eprintln!("fatal: {}", e);
+        std::process::exit(128);
+    };
+
+    let tree = repo.write_tree().unwrap_or_else(|e| fail(e));
+    let parent = repo.ref_resolve("HEAD".into());
+    let parent_tree = parent
+        .as_ref()
+        .and_then(|v| repo.obj_find(v.clone(), Some("tree".to_string()), None).ok());
+    let empty_root = parent.is_none() && repo.index_read().entries.is_empty();
+    if parent_tree.as_deref() == Some(tree.as_str()) || empty_root {
+        println!("nothing to commit, working tree clean");
+        std::process::exit(1);
+    }
+
+    let message = match message {
+        Some(v) => v.trim_end().to_string(),
+        None => repo
+            .edit_message(
+                "COMMIT_EDITMSG",
+                "\n# Please enter the commit message for your changes. Lines starting\n\
+                 # with '#' will be ignored, and an empty message aborts the commit.\n",
+            )
+            .unwrap_or_else(|e| fail(e)),
+    };
+    if message.is_empty() {
+        eprintln!("Aborting commit due to empty commit message.");
+        std::process::exit(1);
+    }
+
+    let sign = !no_sign && (sign || repo.config_bool("commit.gpgSign").unwrap_or(false));
+    let parents: Vec<String> = parent.into_iter().collect();
+    let sha = repo
+        .commit_create(&tree, &parents, &format!("{}\n", message), sign)
+        .unwrap_or_else(|e| fail(e));
+    repo.head_update(&sha).unwrap_or_else(|e| fail(e));
+
+    let branch = repo
+        .get_active_branch()
+        .unwrap_or_else(|| "detached HEAD".to_string());
+    let root = if parents.is_empty() { " (root-commit)" } else { "" };
+    let (subject, _) = message_parts(&message);
+    println!("[{}{} {}] {}", branch, root, &sha[..7], subject);
+}
+
+/// Checks the signatures of `names`, resolved with `resolve`, reporting to stderr.
+fn verify_signatures(
+    names: &[String],
+    resolve: impl Fn(&GitRepo, &str) -> Result<SignatureCheck, String>,
+) {
     let repo = GitRepo::repo_find(".".into()).unwrap();
-    println!("{}", repo.obj_find(name.clone(), None, Some(true)).unwrap())
+    let mut failed = false;
+    for name in names {
+        match resolve(&repo, name) {
+            Ok(check) => {
+                eprintln!("{}", check.message);
+                failed |= !check.good;
+            }
+            Err(e) => {
+                eprintln!("error: {}: {}", name, e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+pub fn verify_commit(commits: &[String]) {
+    verify_signatures(commits, |repo, name| {
+        let sha = repo.obj_find(name.to_string(), Some("commit".to_string()), None)?;
+        repo.verify_commit(&sha)
+    })
+}
+
+pub fn verify_tag(tags: &[String]) {
+    verify_signatures(tags, |repo, name| {
+        let sha = repo.obj_find(name.to_string(), None, None)?;
+        repo.verify_tag(&sha)
+    })
+}
+
+pub struct ConfigOptions {
+    pub scope: Option<ConfigScope>,
+    pub get: bool,
+    pub get_all: bool,
+    pub add: bool,
+    pub unset: bool,
+    pub unset_all: bool,
+    pub list: bool,
+    pub show_origin: bool,
+}
+
+pub fn config(args: &[String], options: ConfigOptions) {
+    let repo = GitRepo::repo_find(".".into());
+    let git_dir = repo.as_ref().map(|v| v.git_dir.as_path());
+    let fail = |code: i32, e: String| -> ! {
+        eprintln!("{}", e);
+        std::process::exit(code);
+    };
+    let layers = config::read_layers(git_dir).unwrap_or_else(|e| fail(128, format!("fatal: {}", e)));
+    let scope = match options.scope {
+        Some(ConfigScope::Worktree) => {
+            let worktrees = repo.as_ref().map(|v| v.worktrees().len()).unwrap_or(0);
+            Some(config::worktree_scope(&layers, worktrees).unwrap_or_else(|e| fail(128, format!("fatal: {}", e))))
+        }
+        v => v,
+    };
+    let visible = match scope {
+        Some(scope) => layers.scope(scope),
+        None => layers,
+    };
+    let origin = |entry: &ConfigEntry| {
+        if !options.show_origin {
+            return String::new();
+        }
+        let work_dir = repo.as_ref().filter(|v| !v.bare).map(|v| v.work_dir.as_path());
+        let path = config::origin_name(&entry.origin, git_dir, work_dir);
+        format!("file:{}\t", path.strip_prefix("./").unwrap_or(&path))
+    };
+    let target = || {
+        if scope.is_none() && git_dir.is_none() {
+            fail(128, "fatal: not in a git directory".to_string());
+        }
+        config::config_file_for(scope.unwrap_or(ConfigScope::Local), git_dir)
+            .unwrap_or_else(|e| fail(128, format!("fatal: {}", e)))
+    };
+    let usage = || -> ! { fail(129, "error: wrong number of arguments".to_string()) };
+
+    if options.list {
+        let mut output = String::new();
+        for entry in &visible.entries {
+            match &entry.value {
+                Some(value) => output.push_str(&format!("{}{}={}\n", origin(entry), entry.key, value)),
+                None => output.push_str(&format!("{}{}\n", origin(entry), entry.key)),
+            }
+        }
+        print!("{}", output);
+        return;
+    }
+
+    let key = args.first().unwrap_or_else(|| usage());
+    if let Err(e) = config::canonical_key(key) {
+        fail(1, format!("error: {}", e));
+    }
+    if options.get || options.get_all || (args.len() == 1 && !options.add && !options.unset && !options.unset_all) {
+        let entries = visible.get_all(key);
+        let entries = if options.get_all {
+            entries
+        } else {
+            entries.into_iter().last().into_iter().collect()
+        };
+        if entries.is_empty() {
+            std::process::exit(1);
+        }
+        for entry in entries {
+            println!("{}{}", origin(entry), entry.value.clone().unwrap_or_default());
+        }
+        return;
+    }
+
+    if options.unset || options.unset_all {
+        match config::config_unset(&target(), key, options.unset_all) {
+            Ok(true) => {}
+            Ok(false) => std::process::exit(5),
+            Err(e) => fail(5, format!("warning: {}", e)),
+        }
+        return;
+    }
+
+    let value = match args {
+        [_, value] => value,
+        _ => usage(),
+    };
+    if let Err(e) = config::config_set(&target(), key, value, options.add) {
+        fail(
+            5,
+            format!(
+                "warning: {}\nerror: cannot overwrite multiple values with a single value\n       \
+                 Use a regexp, --add or --replace-all to change {}.",
+                e, key
+            ),
+        );
+    }
+}
+
+pub fn show(objects: &[String], stat: bool, format: &Option<String>) {
+    let repo = find_repo();
+    let format = match format {
+        None => CommitFormat::Medium,
+        Some(v) => match CommitFormat::parse(v) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("fatal: {}", e);
+                std::process::exit(128);
+            }
+        },
+    };
+    let objects = if objects.is_empty() {
+        vec!["HEAD".to_string()]
+    } else {
+        objects.to_vec()
+    };
+
+    let mut output = Vec::new();
+    for object in objects {
+        match repo.show(&object, &format, stat) {
+            Ok(v) => output.extend_from_slice(&v),
+            Err(e) => {
+                eprintln!("fatal: bad object {}: {}", object, e);
+                std::process::exit(128);
+            }
+        }
+    }
+    display_with_pager(Some(&repo), &output);
+}
+
+pub fn describe(commit: &Option<String>, options: DescribeOptions) {
+    let repo = if options.dirty.is_some() {
+        work_tree_repo()
+    } else {
+        find_repo()
+    };
+    if commit.is_some() && options.dirty.is_some() {
+        eprintln!("fatal: --dirty is incompatible with commit-ishes");
+        std::process::exit(128);
+    }
+    let commit = commit.clone().unwrap_or("HEAD".to_string());
+    match repo.describe(&commit, &options) {
+        Ok(v) => println!("{}", v),
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            std::process::exit(128);
+        }
+    }
+}
+
+pub fn rev_parse(args: &[String]) {
+    let repo = find_repo();
+    for arg in args {
+        match arg.as_str() {
+            "--git-dir" => println!("{}", git_dir_display(&repo)),
+            "--show-toplevel" => {
+                let repo = work_tree_repo();
+                println!("{}", fs::canonicalize(&repo.work_dir).unwrap_or(repo.work_dir).display());
+            }
+            // Inside a `.git` there is no working tree either, but the repository isn't bare.
+            "--is-bare-repository" => println!("{}", repo.bare && repo.config_bool("core.bare") != Some(false)),
+            "--is-inside-work-tree" => {
+                let inside = !repo.bare
+                    && std::env::current_dir()
+                        .and_then(fs::canonicalize)
+                        .is_ok_and(|v| fs::canonicalize(&repo.work_dir).is_ok_and(|top| v.starts_with(top)));
+                println!("{}", inside)
+            }
+            name => println!("{}", repo.obj_find(name.to_string(), None, Some(true)).unwrap()),
+        }
+    }
+}
+
+/// Shows the git directory the way `rev-parse --git-dir` does: `GIT_DIR` as given, `.git` or `.`
+/// when it is right here, an absolute path otherwise.
+fn git_dir_display(repo: &GitRepo) -> String {
+    if let Ok(v) = std::env::var("GIT_DIR") {
+        if repo.bare || repo.cwd_prefix().is_empty() {
+            return v;
+        }
+    }
+    let git_dir = fs::canonicalize(&repo.git_dir).unwrap_or(repo.git_dir.clone());
+    match std::env::current_dir().and_then(fs::canonicalize) {
+        Ok(cwd) if cwd == git_dir => ".".to_string(),
+        Ok(cwd) if cwd.join(".git") == git_dir => ".git".to_string(),
+        _ => git_dir.to_string_lossy().into_owned(),
+    }
 }
 
 pub fn ls_files(verbose: bool) {
@@ -256,27 +1081,178 @@ pub fn ls_files(verbose: bool) {
             }
 
             println!(
-                "  flags: stage={} assume_valid={}",
-                e.flag_stage, e.flag_assume_valid
+                "  flags: stage={} assume_valid={} skip_worktree={}",
+                e.flag_stage, e.flag_assume_valid, e.flag_skip_worktree
             );
         }
     }
 }
 
-pub fn check_git_ignore(paths: Vec<PathBuf>) {
-    let repo = GitRepo::repo_find(".".into()).unwrap();
+pub struct CheckIgnoreOptions {
+    pub verbose: bool,
+    pub stdin: bool,
+    pub nul: bool,
+    pub non_matching: bool,
+    pub no_index: bool,
+}
+
+pub fn check_git_ignore(paths: &[String], options: CheckIgnoreOptions) {
+    let fail = |message: &str| -> ! {
+        eprintln!("fatal: {}", message);
+        std::process::exit(128);
+    };
+    if options.stdin && !paths.is_empty() {
+        fail("cannot specify pathnames with --stdin");
+    }
+    if options.nul && !options.stdin {
+        fail("-z only makes sense with --stdin");
+    }
+    if !options.stdin && paths.is_empty() {
+        fail("no path specified");
+    }
+    if options.non_matching && !options.verbose {
+        fail("--non-matching is only valid with --verbose");
+    }
+
+    let repo = work_tree_repo();
     let git_ignore = repo.gitignore_read();
+    let tracked: Vec<String> = if options.no_index {
+        Vec::new()
+    } else {
+        repo.index_read().entries.into_iter().map(|e| e.name).collect()
+    };
+    let mut ignored = 0;
+    let mut stdout = std::io::stdout();
+
+    let prefix = repo.cwd_prefix();
+    let mut check = |path: &str| {
+        let relative = match path_from_prefix(&prefix, path) {
+            Some(v) => v,
+            None => fail(&format!("{}: '{}' is outside repository at '{}'", path, path, repo.work_dir.display())),
+        };
+        let relative = relative.as_str();
+        let relative_dir = relative.trim_end_matches('/');
+        // Tracked paths are never reported as ignored, whatever the rules say.
+        let is_tracked = tracked.iter().any(|v| {
+            v == relative_dir || v.strip_prefix(relative_dir).is_some_and(|v| v.starts_with('/'))
+        });
+        let rule = if is_tracked {
+            None
+        } else {
+            let is_dir = relative.ends_with('/') || repo.work_dir.join(relative_dir).is_dir();
+            git_ignore.check(relative, is_dir)
+        };
+        // Outside of --verbose a negated rule is the same as no rule.
+        let rule = rule.filter(|v| options.verbose || !v.negated);
+        if rule.is_some() {
+            ignored += 1;
+        }
+        if rule.is_none() && !options.non_matching {
+            return;
+        }
+
+        let shown = path_to_prefix(&prefix, relative);
+        let relative = shown.as_str();
+        let line = match (options.verbose, options.nul, &rule) {
+            (false, false, _) => format!("{}\n", quote_path(relative)),
+            (false, true, _) => format!("{}\0", relative),
+            (true, false, Some(rule)) => format!(
+                "{}:{}:{}\t{}\n",
+                quote_path(&rule.source),
+                rule.line,
+                rule.pattern,
+                quote_path(relative)
+            ),
+            (true, false, None) => format!("::\t{}\n", quote_path(relative)),
+            (true, true, Some(rule)) => {
+                format!("{}\0{}\0{}\0{}\0", rule.source, rule.line, rule.pattern, relative)
+            }
+            (true, true, None) => format!("\0\0\0{}\0", relative),
+        };
+        let _ = stdout.write_all(line.as_bytes());
+        // Tools driving --stdin wait for each answer before sending the next path.
+        let _ = stdout.flush();
+    };
+
+    if options.stdin {
+        let separator = if options.nul { b'\0' } else { b'\n' };
+        let mut input = std::io::stdin().lock();
+        let mut record = Vec::new();
+        loop {
+            record.clear();
+            match std::io::BufRead::read_until(&mut input, separator, &mut record) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) => fail(&e.to_string()),
+            }
+            if record.last() == Some(&separator) {
+                record.pop();
+            }
+            if !record.is_empty() {
+                check(&String::from_utf8_lossy(&record));
+            }
+        }
+    } else {
+        for path in paths {
+            check(path);
+        }
+    }
+    std::process::exit(if ignored > 0 { 0 } else { 1 });
+}
+
+pub fn check_attr(all: bool, args: &[String], paths: &[String]) {
+    let repo = GitRepo::repo_find(".".into()).unwrap();
+    // Without `--` the first argument is the attribute and the rest are paths.
+    let (attrs, paths) = match (all, paths.is_empty()) {
+        (true, true) => (&[][..], args),
+        (_, false) => (args, paths),
+        (false, true) if !args.is_empty() => (&args[..1], &args[1..]),
+        _ => {
+            eprintln!("error: at least one attribute must be specified");
+            std::process::exit(129);
+        }
+    };
+    if all && !attrs.is_empty() {
+        eprintln!("error: cannot specify both --all and attributes");
+        std::process::exit(129);
+    }
+
+    let attributes = repo.gitattributes_read(false);
     for path in paths {
-        if git_ignore.check_ignore(path.clone()).unwrap() {
-            println!("{}", path.to_str().unwrap())
+        let path = path.trim_start_matches("./");
+        let states = if all {
+            attributes.check_all(path)
+        } else {
+            attributes.check(path, attrs)
+        };
+        for (name, state) in states {
+            println!("{}: {}: {}", path, name, state);
         }
     }
 }
+
 pub fn status() {
-    let repo = GitRepo::repo_find(".".into()).unwrap();
+    let repo = work_tree_repo();
     let branch = repo.get_active_branch();
     if let Some(branch) = branch {
         println!("On branch {}.", branch);
+        match repo.branch_tracking(&branch) {
+            None => {}
+            Some((upstream, None)) => println!("Your branch is based on '{}', but the upstream is gone.", ref_shorten(&upstream)),
+            Some((upstream, Some(counts))) => {
+                let upstream = ref_shorten(&upstream);
+                let commits = |n: usize| if n == 1 { "1 commit".to_string() } else { format!("{} commits", n) };
+                match counts {
+                    (0, 0) => println!("Your branch is up to date with '{}'.", upstream),
+                    (ahead, 0) => println!("Your branch is ahead of '{}' by {}.", upstream, commits(ahead)),
+                    (0, behind) => println!("Your branch is behind '{}' by {}, and can be fast-forwarded.", upstream, commits(behind)),
+                    (ahead, behind) => {
+                        println!("Your branch and '{}' have diverged,", upstream);
+                        println!("and have {} and {} different commits each, respectively.", ahead, behind);
+                    }
+                }
+            }
+        }
     } else {
         println!(
             "HEAD detached at {}",
@@ -284,10 +1260,18 @@ pub fn status() {
         )
     }
     
+    let index = repo.index_read();
+    if repo.sparse_checkout_read().is_some() && !index.entries.is_empty() {
+        let skipped = index.entries.iter().filter(|e| e.flag_skip_worktree).count();
+        println!(
+            "You are in a sparse checkout with {}% of tracked files present.",
+            100 - 100 * skipped / index.entries.len()
+        );
+    }
+
     println!("Changes to be committed:");
 
     let mut head = repo.to_hash_map("HEAD".to_string(),None);
-    let index = repo.index_read();
 
     for entry in &index.entries {
         if let Some(head_sha) = head.get(&entry.name) {
@@ -304,22 +1288,48 @@ pub fn status() {
     for entry in head.keys() {
         println!("  deleted:  {}", entry);
     }
+
+    // Submodules outside of the sparse checkout aren't there to compare.
+    let skipped: HashSet<&String> = index.entries.iter().filter(|e| e.flag_skip_worktree).map(|e| &e.name).collect();
+    let submodules: Vec<(String, SubmoduleState)> = repo
+        .gitlinks()
+        .into_iter()
+        .filter(|(path, _)| !skipped.contains(path))
+        .map(|(path, sha)| {
+            let state = repo.submodule_state(&path, &sha);
+            (path, state)
+        })
+        .collect();
+    let changed: Vec<_> = submodules
+        .iter()
+        .filter_map(|(path, state)| match state {
+            SubmoduleState::NewCommits => Some((path, "new commits")),
+            SubmoduleState::ModifiedContent => Some((path, "modified content")),
+            _ => None,
+        })
+        .collect();
+    if !changed.is_empty() {
+        println!("Changes not staged for commit:");
+        for (path, what) in changed {
+            println!("  modified: {} ({})", path, what);
+        }
+    }
 }
 
 pub fn remove(paths: &Vec<PathBuf>) {
-    let repo = GitRepo::repo_find(".".into()).unwrap();
+    let repo = work_tree_repo();
     repo.rm(paths,true,false).unwrap()
 }
 
-fn get_user_by_uid(uid: u32) -> Result<User, &'static str> {
-    let user = fs::metadata(format!("/proc/self/fd/0")).map_err(|_| "Failed to get user")?;
+fn get_user_by_uid(_uid: u32) -> Result<User, &'static str> {
+    let user = fs::metadata("/proc/self/fd/0").map_err(|_| "Failed to get user")?;
     Ok(User {
         name: user.uid().to_string(),
     })
 }
 
-fn get_group_by_gid(gid: u32) -> Result<Group, &'static str> {
-    let group = fs::metadata(format!("/proc/self/fd/0")).map_err(|_| "Failed to get group")?;
+fn get_group_by_gid(_gid: u32) -> Result<Group, &'static str> {
+    let group = fs::metadata("/proc/self/fd/0").map_err(|_| "Failed to get group")?;
     Ok(Group {
         name: group.gid().to_string(),
     })
@@ -332,3 +1342,526 @@ struct User {
 struct Group {
     name: String,
 }
+
+pub fn lfs_ls_files(reference: Option<&str>, long: bool, size: bool) {
+    let repo = find_repo();
+    let pointers = match reference {
+        Some(v) => repo.lfs_pointers_in_tree(v).unwrap_or_else(|e| {
+            eprintln!("fatal: {}", e);
+            std::process::exit(128);
+        }),
+        // Before the first commit there is only the index to look at.
+        None => repo
+            .lfs_pointers_in_tree("HEAD")
+            .unwrap_or_else(|_| repo.lfs_pointers_in_index()),
+    };
+    for (path, pointer) in pointers {
+        // `*` when the working tree has the content, `-` when it only has the pointer.
+        let checked_out = fs::read(repo.work_dir.join(&path))
+            .map(|v| LfsPointer::parse(&v).is_none())
+            .unwrap_or(false);
+        let oid = if long { &pointer.oid[..] } else { &pointer.oid[..10] };
+        let marker = if checked_out { '*' } else { '-' };
+        if size {
+            println!("{} {} {} ({})", oid, marker, path, format_size(pointer.size));
+        } else {
+            println!("{} {} {}", oid, marker, path);
+        }
+    }
+}
+
+pub fn lfs_fsck(dry_run: bool) {
+    let repo = find_repo();
+    let mut pointers = repo.lfs_pointers_in_tree("HEAD").unwrap_or_default();
+    pointers.extend(repo.lfs_pointers_in_index());
+
+    let mut ok = true;
+    let mut checked = HashSet::new();
+    for (path, pointer) in pointers {
+        if !checked.insert(pointer.oid.clone()) {
+            continue;
+        }
+        match repo.lfs_object_check(&pointer.oid) {
+            Ok(true) => {}
+            Ok(false) => {
+                ok = false;
+                println!("objects: corruptObject: {} ({}) is corrupt", path, pointer.oid);
+                if !dry_run {
+                    let bad = repo.common_dir.join("lfs/bad");
+                    let moved = fs::create_dir_all(&bad)
+                        .and_then(|_| fs::rename(repo.lfs_object_path(&pointer.oid), bad.join(&pointer.oid)));
+                    if let Err(e) = moved {
+                        eprintln!("error: could not move {} to {}: {}", pointer.oid, bad.display(), e);
+                    }
+                }
+            }
+            Err(e) => {
+                ok = false;
+                println!("objects: openError: {} ({}) could not be checked: {}", path, pointer.oid, e);
+            }
+        }
+    }
+    if !ok {
+        if !dry_run {
+            println!("moving invalid objects to {}", repo.common_dir.join("lfs/bad").display());
+        }
+        std::process::exit(1);
+    }
+    println!("Git LFS fsck OK");
+}
+
+pub fn lfs_prune(dry_run: bool, verbose: bool) {
+    let repo = find_repo();
+    let local = repo.lfs_local_objects();
+    let retained = repo.lfs_retained_objects();
+    let prunable: Vec<&String> = local.iter().filter(|v| !retained.contains(*v)).collect();
+    let size_of = |oid: &str| fs::metadata(repo.lfs_object_path(oid)).map(|v| v.len()).unwrap_or(0);
+
+    println!(
+        "prune: {} local object(s), {} retained, done.",
+        local.len(),
+        local.len() - prunable.len()
+    );
+    if prunable.is_empty() {
+        return;
+    }
+    let total: u64 = prunable.iter().map(|v| size_of(v)).sum();
+    if verbose {
+        for oid in &prunable {
+            println!(" * {} ({})", oid, format_size(size_of(oid)));
+        }
+    }
+    if dry_run {
+        println!("prune: {} file(s) would be pruned ({})", prunable.len(), format_size(total));
+        return;
+    }
+    let mut deleted = 0;
+    for oid in &prunable {
+        match fs::remove_file(repo.lfs_object_path(oid)) {
+            Ok(_) => deleted += 1,
+            Err(e) => eprintln!("error: could not delete {}: {}", oid, e),
+        }
+    }
+    println!("prune: Deleting objects: 100% ({}/{}), done.", deleted, prunable.len());
+}
+
+pub fn clean(dry_run: bool, options: CleanOptions) {
+    let repo = work_tree_repo();
+    let fail = |message: &str| -> ! {
+        eprintln!("fatal: {}", message);
+        std::process::exit(128);
+    };
+    if options.no_ignore && options.only_ignored {
+        fail("-x and -X cannot be used together");
+    }
+    if options.force == 0 && !dry_run {
+        match repo.config_bool("clean.requireForce") {
+            Some(false) => {}
+            Some(true) => fail("clean.requireForce set to true and neither -i, -n, nor -f given; refusing to clean"),
+            None => fail("clean.requireForce defaults to true and neither -i, -n, nor -f given; refusing to clean"),
+        }
+    }
+
+    // Paths are relative to the current directory, which also bounds what gets cleaned.
+    let prefix = repo.cwd_prefix();
+    let mut options = options;
+    options.paths = if options.paths.is_empty() && !prefix.is_empty() {
+        vec![prefix.clone()]
+    } else {
+        options
+            .paths
+            .iter()
+            .map(|v| {
+                path_from_prefix(&prefix, v)
+                    .unwrap_or_else(|| fail(&format!("{}: '{}' is outside repository at '{}'", v, v, repo.work_dir.display())))
+            })
+            .collect()
+    };
+
+    for path in repo.clean_list(&options) {
+        let shown = quote_path(&path_to_prefix(&prefix, &path));
+        if dry_run {
+            println!("Would remove {}", shown);
+            continue;
+        }
+        let full = repo.work_dir.join(&path);
+        let result = if path.ends_with('/') {
+            fs::remove_dir_all(&full)
+        } else {
+            fs::remove_file(&full)
+        };
+        match result {
+            Ok(_) => println!("Removing {}", shown),
+            Err(e) => eprintln!("warning: failed to remove {}: {}", shown, e),
+        }
+    }
+}
+
+/// The submodules `paths` select, all of them when it is empty, in the order of the index.
+fn selected_submodules(repo: &GitRepo, paths: &[String]) -> Vec<(Submodule, String)> {
+    let submodules = repo.submodules().unwrap_or_else(|e| {
+        eprintln!("fatal: {}", e);
+        std::process::exit(128);
+    });
+    let selected = |path: &str| {
+        paths.is_empty()
+            || paths.iter().any(|p| {
+                let p = p.trim_end_matches('/');
+                path == p || path.strip_prefix(p).is_some_and(|v| v.starts_with('/'))
+            })
+    };
+    let mut ret = Vec::new();
+    for (path, sha) in repo.gitlinks() {
+        if !selected(&path) {
+            continue;
+        }
+        match submodules.iter().find(|v| v.path == path) {
+            Some(v) => ret.push((v.clone(), sha)),
+            None => {
+                eprintln!("fatal: no submodule mapping found in .gitmodules for path '{}'", path);
+                std::process::exit(128);
+            }
+        }
+    }
+    ret
+}
+
+pub fn submodule_init(paths: &[String]) {
+    let repo = work_tree_repo();
+    for (submodule, _) in selected_submodules(&repo, paths) {
+        match repo.submodule_init(&submodule) {
+            Ok(Some(url)) => eprintln!(
+                "Submodule '{}' ({}) registered for path '{}'",
+                submodule.name, url, submodule.path
+            ),
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("fatal: {}", e);
+                std::process::exit(128);
+            }
+        }
+    }
+}
+
+pub fn submodule_update(init: bool, paths: &[String]) {
+    if init {
+        submodule_init(paths);
+    }
+    // Initializing rewrote the configuration, so read it afresh.
+    let repo = work_tree_repo();
+    for (submodule, sha) in selected_submodules(&repo, paths) {
+        let url = match repo.config_get(&format!("submodule.{}.url", submodule.name)) {
+            Some(v) => v,
+            // Submodules that were never initialized are left alone.
+            None => continue,
+        };
+        match repo.submodule_update(&submodule, &url, &sha) {
+            Ok(true) => println!("Submodule path '{}': checked out '{}'", submodule.path, sha),
+            Ok(false) => {}
+            Err(e) => {
+                eprintln!("error: {}", e);
+                eprintln!("fatal: Unable to checkout '{}' in submodule path '{}'", sha, submodule.path);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+pub fn submodule_status(paths: &[String]) {
+    let repo = work_tree_repo();
+    for (submodule, sha) in selected_submodules(&repo, paths) {
+        let head = match repo.submodule_head(&submodule.path) {
+            Some(v) => v,
+            None => {
+                println!("-{} {}", sha, submodule.path);
+                continue;
+            }
+        };
+        let flag = if head == sha { ' ' } else { '+' };
+        match repo.submodule_describe(&submodule.path) {
+            Some(name) => println!("{}{} {} ({})", flag, head, submodule.path, name),
+            None => println!("{}{} {}", flag, head, submodule.path),
+        }
+    }
+}
+
+pub fn submodule_foreach(command: &[String]) {
+    let repo = work_tree_repo();
+    let command = command.join(" ");
+    let toplevel = fs::canonicalize(&repo.work_dir).unwrap_or(repo.work_dir.clone());
+    for (submodule, sha) in selected_submodules(&repo, &[]) {
+        if repo.submodule_head(&submodule.path).is_none() {
+            continue;
+        }
+        println!("Entering '{}'", submodule.path);
+        let status = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .current_dir(repo.work_dir.join(&submodule.path))
+            .env("name", &submodule.name)
+            .env("sm_path", &submodule.path)
+            .env("displaypath", &submodule.path)
+            .env("sha1", &sha)
+            .env("toplevel", &toplevel)
+            .status();
+        if !status.is_ok_and(|v| v.success()) {
+            eprintln!("fatal: run_command returned non-zero status for {}\n.", submodule.path);
+            std::process::exit(128);
+        }
+    }
+}
+
+pub fn worktree_add(path: &str, commit: Option<&str>, new_branch: Option<&str>, detach: bool, force: bool) {
+    let repo = GitRepo::repo_find(".".into()).expect("Not a git repository");
+    let fail = |message: String| -> ! {
+        eprintln!("fatal: {}", message);
+        std::process::exit(128);
+    };
+    let branch_exists = |name: &str| repo.ref_resolve(format!("refs/heads/{}", name).into()).is_some();
+    let resolve = |name: &str| {
+        repo.obj_find(name.to_string(), Some("commit".to_string()), None)
+            .unwrap_or_else(|_| fail(format!("invalid reference: {}", name)))
+    };
+    let basename = Path::new(path)
+        .file_name()
+        .map(|v| v.to_string_lossy().into_owned())
+        .unwrap_or_else(|| fail(format!("'{}' is not a valid path", path)));
+
+    // Without a commit the worktree gets the branch named after its directory, made from HEAD.
+    let (branch, create, sha) = match (new_branch, commit) {
+        (Some(name), start) => (Some(name.to_string()), true, resolve(start.unwrap_or("HEAD"))),
+        (None, Some(name)) if !detach && branch_exists(name) => (Some(name.to_string()), false, resolve(name)),
+        (None, Some(name)) => (None, false, resolve(name)),
+        (None, None) if detach => (None, false, resolve("HEAD")),
+        (None, None) if branch_exists(&basename) => (Some(basename.clone()), false, resolve(&basename)),
+        (None, None) => (Some(basename), true, resolve("HEAD")),
+    };
+    match (&branch, create) {
+        (Some(name), true) => eprintln!("Preparing worktree (new branch '{}')", name),
+        (Some(name), false) => eprintln!("Preparing worktree (checking out '{}')", name),
+        (None, _) => eprintln!("Preparing worktree (detached HEAD {})", &sha[..7]),
+    }
+
+    if let (Some(name), true) = (&branch, create) {
+        if !GitRepo::ref_name_valid(name) {
+            fail(format!("'{}' is not a valid branch name", name));
+        }
+        if branch_exists(name) {
+            // Git reports this from the `git branch` it runs, whose failure shows as 255.
+            eprintln!("fatal: a branch named '{}' already exists", name);
+            std::process::exit(255);
+        }
+    }
+    let target = Path::new(path);
+    if target.exists() && !target.read_dir().is_ok_and(|mut v| v.next().is_none()) {
+        fail(format!("'{}' already exists", path));
+    }
+    if let (Some(name), false, false) = (&branch, create, force) {
+        if let Some(other) = repo.worktree_with_branch(&format!("refs/heads/{}", name)) {
+            fail(format!("'{}' is already checked out at '{}'", name, other.path.display()));
+        }
+    }
+
+    let head = match &branch {
+        Some(name) => {
+            if create {
+                repo.ref_create(&format!("heads/{}", name), &sha);
+            }
+            format!("ref: refs/heads/{}", name)
+        }
+        None => sha.clone(),
+    };
+    repo.worktree_add(target, &head, &sha).unwrap_or_else(|e| fail(e));
+    let subject = repo.commit_subject(&sha).unwrap_or_default();
+    println!("HEAD is now at {} {}", &sha[..7], subject);
+}
+
+pub fn worktree_list(porcelain: bool) {
+    let repo = GitRepo::repo_find(".".into()).expect("Not a git repository");
+    let worktrees = repo.worktrees();
+    let width = worktrees.iter().map(|v| v.path.to_string_lossy().len()).max().unwrap_or(0);
+    let zero = "0".repeat(40);
+    for worktree in worktrees {
+        let head = worktree.head.as_deref().unwrap_or(&zero);
+        if porcelain {
+            println!("worktree {}", worktree.path.display());
+            if worktree.bare {
+                println!("bare");
+                println!();
+                continue;
+            }
+            println!("HEAD {}", head);
+            match &worktree.branch {
+                Some(branch) => println!("branch {}", branch),
+                None => println!("detached"),
+            }
+            if let Some(reason) = &worktree.prunable {
+                println!("prunable {}", reason);
+            }
+            println!();
+            continue;
+        }
+        if worktree.bare {
+            println!("{:<width$} (bare)", worktree.path.display(), width = width + 1);
+            continue;
+        }
+        let mut line = format!("{:<width$} {} ", worktree.path.display(), &head[..7], width = width + 1);
+        match &worktree.branch {
+            Some(branch) => line.push_str(&format!("[{}]", branch.strip_prefix("refs/heads/").unwrap_or(branch))),
+            None => line.push_str("(detached HEAD)"),
+        }
+        if worktree.prunable.is_some() {
+            line.push_str(" prunable");
+        }
+        println!("{}", line);
+    }
+}
+
+pub fn worktree_remove(path: &str, force: bool) {
+    let repo = GitRepo::repo_find(".".into()).expect("Not a git repository");
+    let fail = |message: String| -> ! {
+        eprintln!("fatal: {}", message);
+        std::process::exit(128);
+    };
+    let absolute = fs::canonicalize(path).unwrap_or_else(|_| fail(format!("'{}' is not a working tree", path)));
+    let worktree = repo
+        .worktrees()
+        .into_iter()
+        .find(|v| fs::canonicalize(&v.path).is_ok_and(|v| v == absolute))
+        .unwrap_or_else(|| fail(format!("'{}' is not a working tree", path)));
+    if worktree.id.is_none() {
+        fail(format!("'{}' is a main working tree", path));
+    }
+    if !force {
+        if let Some(linked) = GitRepo::open(&worktree.path) {
+            let options = CleanOptions {
+                dirs: true,
+                ..CleanOptions::default()
+            };
+            if linked.worktree_is_dirty() || !linked.clean_list(&options).is_empty() {
+                fail(format!("'{}' contains modified or untracked files, use --force to delete it", path));
+            }
+        }
+    }
+    repo.worktree_remove(&worktree).unwrap_or_else(|e| fail(e));
+}
+
+pub fn worktree_prune(dry_run: bool, verbose: bool) {
+    let repo = GitRepo::repo_find(".".into()).expect("Not a git repository");
+    for worktree in repo.worktrees() {
+        let (id, reason) = match (&worktree.id, &worktree.prunable) {
+            (Some(id), Some(reason)) => (id, reason),
+            _ => continue,
+        };
+        if dry_run || verbose {
+            println!("Removing worktrees/{}: {}", id, reason);
+        }
+        if !dry_run {
+            if let Err(e) = repo.worktree_prune_admin(id) {
+                eprintln!("error: failed to remove worktrees/{}: {}", id, e);
+            }
+        }
+    }
+}
+
+/// Writes the patterns of the sparse checkout, turns it on and updates the worktree to match.
+fn sparse_checkout_update(repo: &GitRepo, patterns: SparsePatterns) {
+    let cone = matches!(patterns, SparsePatterns::Cone(_));
+    let result = repo
+        .sparse_checkout_write(&patterns)
+        .and_then(|_| repo.sparse_checkout_configure(true, cone))
+        .and_then(|_| repo.sparse_checkout_apply(Some(&patterns)));
+    sparse_checkout_report(result);
+}
+
+fn sparse_checkout_report(result: Result<Vec<String>, String>) {
+    let left = result.unwrap_or_else(|e| {
+        eprintln!("fatal: {}", e);
+        std::process::exit(128);
+    });
+    if !left.is_empty() {
+        eprintln!("warning: The following paths are not up to date and were left despite sparse patterns:");
+        for path in left {
+            eprintln!("\t{}", path);
+        }
+        eprintln!("\nAfter fixing the above paths, you may want to run `git sparse-checkout reapply`.");
+    }
+}
+
+fn sparse_checkout_current(repo: &GitRepo, message: &str) -> SparsePatterns {
+    repo.sparse_checkout_read().unwrap_or_else(|| {
+        eprintln!("fatal: {}", message);
+        std::process::exit(128);
+    })
+}
+
+pub fn sparse_checkout_init(cone: Option<bool>) {
+    let repo = work_tree_repo();
+    let cone = cone.unwrap_or(true);
+    // Patterns already written are kept, only the worktree starts following them.
+    let patterns = repo
+        .sparse_checkout_file_read(cone)
+        .unwrap_or_else(|| SparsePatterns::parse("/*\n!/*/\n", cone));
+    sparse_checkout_update(&repo, patterns);
+}
+
+pub fn sparse_checkout_set(cone: Option<bool>, args: &[String]) {
+    let repo = work_tree_repo();
+    let current = repo.sparse_checkout_read();
+    if current.is_none() {
+        if let Err(e) = repo.sparse_index_disable() {
+            eprintln!("fatal: {}", e);
+            std::process::exit(128);
+        }
+    }
+    let cone = cone.unwrap_or(!matches!(current, Some(SparsePatterns::Patterns(_))));
+    let patterns = if cone {
+        SparsePatterns::Cone(cone_dirs(args).unwrap_or_else(|e| {
+            eprintln!("fatal: {}", e);
+            std::process::exit(128);
+        }))
+    } else {
+        SparsePatterns::Patterns(args.to_vec())
+    };
+    sparse_checkout_update(&repo, patterns);
+}
+
+pub fn sparse_checkout_add(args: &[String]) {
+    let repo = work_tree_repo();
+    let mut patterns = sparse_checkout_current(&repo, "no sparse-checkout to add to");
+    let more = match patterns {
+        SparsePatterns::Cone(_) => SparsePatterns::Cone(cone_dirs(args).unwrap_or_else(|e| {
+            eprintln!("fatal: {}", e);
+            std::process::exit(128);
+        })),
+        SparsePatterns::Patterns(_) => SparsePatterns::Patterns(args.to_vec()),
+    };
+    patterns.extend(more);
+    sparse_checkout_update(&repo, patterns);
+}
+
+pub fn sparse_checkout_list() {
+    let repo = work_tree_repo();
+    match sparse_checkout_current(&repo, "this worktree is not sparse") {
+        SparsePatterns::Cone(dirs) => dirs.iter().for_each(|v| println!("{}", v)),
+        SparsePatterns::Patterns(patterns) => patterns.iter().for_each(|v| println!("{}", v)),
+    }
+}
+
+pub fn sparse_checkout_reapply() {
+    let repo = work_tree_repo();
+    let patterns = sparse_checkout_current(&repo, "must be in a sparse-checkout to reapply sparsity patterns");
+    sparse_checkout_report(repo.sparse_checkout_apply(Some(&patterns)));
+}
+
+pub fn sparse_checkout_disable() {
+    let repo = work_tree_repo();
+    // Everything comes back first; the patterns file stays for a later `init`.
+    let result = repo.sparse_checkout_apply(None);
+    let result = result.and_then(|left| {
+        repo.sparse_checkout_configure(false, false)?;
+        repo.sparse_index_disable()?;
+        Ok(left)
+    });
+    sparse_checkout_report(result);
+}
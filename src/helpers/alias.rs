@@ -0,0 +1,156 @@
+use crate::helpers::config;
+use crate::helpers::git::GitRepo;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Splits an alias definition into words, honouring quotes and backslashes like git.
+pub fn split_cmdline(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (Some(q), c) if c == q => quote = None,
+            (None | Some('"'), '\\') => {
+                word.push(chars.next().ok_or("unclosed quote")?);
+                in_word = true;
+            }
+            (_, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err("unclosed quote".to_string());
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Finds `mygit-<name>` in the directories of `PATH`.
+fn find_external(name: &str) -> Option<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|v| v.join(format!("mygit-{}", name)))
+        .find(|v| {
+            v.metadata()
+                .map(|v| v.is_file() && v.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false)
+        })
+}
+
+fn run(command: &mut Command) -> ! {
+    match command.status() {
+        Ok(status) => std::process::exit(status.code().unwrap_or(128)),
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            std::process::exit(128);
+        }
+    }
+}
+
+/// Rewrites the command line until it names a builtin, running `mygit-<name>`
+/// executables and `!shell` aliases on the way, which never return.
+///
+/// Like git, builtins win over external commands, which win over aliases.
+pub fn resolve_command(mut args: Vec<String>, is_builtin: impl Fn(&str) -> bool) -> Vec<String> {
+    if !args
+        .get(1)
+        .is_some_and(|v| !v.starts_with('-') && !is_builtin(v))
+    {
+        return args;
+    }
+    let repo = GitRepo::repo_find(".".into());
+    let git_dir = repo.as_ref().map(|v| v.git_dir.as_path());
+    let config = config::read_layers(git_dir).unwrap_or_default();
+    let mut expanded: Vec<String> = Vec::new();
+
+    loop {
+        let name = match args.get(1) {
+            Some(v) if !v.starts_with('-') && !is_builtin(v) => v.clone(),
+            _ => return args,
+        };
+
+        if let Some(external) = find_external(&name) {
+            let mut command = Command::new(external);
+            command.args(&args[2..]);
+            if let Some(git_dir) = git_dir {
+                command.env(
+                    "GIT_DIR",
+                    std::fs::canonicalize(git_dir).unwrap_or(git_dir.to_path_buf()),
+                );
+            }
+            run(&mut command);
+        }
+
+        let alias = match config.get(&format!("alias.{}", name)) {
+            Some(v) => v,
+            None => return args,
+        };
+        if expanded.contains(&name) {
+            eprintln!(
+                "fatal: alias loop detected: expansion of '{}' does not terminate:\n  {}",
+                expanded[0],
+                expanded.join("\n  ")
+            );
+            std::process::exit(128);
+        }
+        expanded.push(name.clone());
+
+        if let Some(shell) = alias.strip_prefix('!') {
+            // Shell aliases run from the top of the working tree and get the arguments as "$@".
+            let mut command = Command::new("sh");
+            command.arg("-c");
+            if args.len() > 2 {
+                command.arg(format!("{} \"$@\"", shell));
+            } else {
+                command.arg(shell);
+            }
+            command.arg(shell).args(&args[2..]);
+            if let Some(repo) = &repo {
+                let cwd = std::env::current_dir().unwrap_or_default();
+                let top = std::fs::canonicalize(&repo.work_dir).unwrap_or(repo.work_dir.clone());
+                let prefix = cwd
+                    .strip_prefix(&top)
+                    .map(|v| v.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                command
+                    .current_dir(&top)
+                    .env("GIT_PREFIX", if prefix.is_empty() { prefix } else { prefix + "/" });
+            }
+            run(&mut command);
+        }
+
+        let words = match split_cmdline(&alias) {
+            Ok(v) if !v.is_empty() => v,
+            Ok(_) => {
+                eprintln!("fatal: empty alias for {}", name);
+                std::process::exit(128);
+            }
+            Err(e) => {
+                eprintln!("error: {} while expanding alias '{}': '{}'", e, name, alias);
+                std::process::exit(128);
+            }
+        };
+        let rest = args.split_off(2);
+        args.truncate(1);
+        args.extend(words);
+        args.extend(rest);
+    }
+}
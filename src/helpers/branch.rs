@@ -0,0 +1,56 @@
+use crate::helpers::clone::list_refs;
+use crate::helpers::config;
+use crate::helpers::git::GitRepo;
+use crate::helpers::refspec::ref_shorten;
+
+impl GitRepo {
+    /// The local branches, sorted.
+    pub fn branch_names(&self) -> Vec<String> {
+        let refs = list_refs(&self.common_dir);
+        refs.keys().filter_map(|v| v.strip_prefix("refs/heads/")).map(|v| v.to_string()).collect()
+    }
+
+    /// The ref the upstream of `branch` is known as here, whether it exists or not: a local
+    /// branch, or the remote-tracking ref its remote fetches it into.
+    pub fn branch_upstream(&self, branch: &str) -> Option<String> {
+        let remote = self.config_get(&format!("branch.{}.remote", branch))?;
+        let merge = self.config_get(&format!("branch.{}.merge", branch))?;
+        if remote == "." {
+            return Some(merge);
+        }
+        Self::remote_tracking_ref(&self.remote_get(&remote)?, &merge)
+    }
+
+    /// Makes `upstream`, a local branch or a remote-tracking ref, the upstream of `branch`.
+    pub fn branch_set_upstream(&self, branch: &str, upstream: &str) -> Result<(), String> {
+        let (remote, merge) = match upstream.starts_with("refs/heads/") {
+            true => (".".to_string(), upstream.to_string()),
+            false => self
+                .remote_names()
+                .iter()
+                .filter_map(|name| self.remote_get(name))
+                .find_map(|remote| Some((remote.name.clone(), remote.fetch.iter().find_map(|v| v.unmap(upstream))?)))
+                .ok_or_else(|| format!("cannot set up tracking information; starting point '{}' is not a branch", ref_shorten(upstream)))?,
+        };
+        let config_file = self.common_dir.join("config");
+        config::config_set(&config_file, &format!("branch.{}.remote", branch), &remote, false)?;
+        config::config_set(&config_file, &format!("branch.{}.merge", branch), &merge, false)
+    }
+
+    /// Counts the commits only `sha` leads to, and those only `other` does, which are the ones
+    /// on each side of their merge base.
+    pub fn ahead_behind(&self, sha: &str, other: &str) -> (usize, usize) {
+        let ours = self.commit_ancestors(sha);
+        let theirs = self.commit_ancestors(other);
+        (ours.difference(&theirs).count(), theirs.difference(&ours).count())
+    }
+
+    /// The upstream of `branch` with how far ahead of it and behind it the branch is, or no
+    /// counts when the upstream ref is gone.
+    pub fn branch_tracking(&self, branch: &str) -> Option<(String, Option<(usize, usize)>)> {
+        let upstream = self.branch_upstream(branch)?;
+        let sha = self.ref_resolve(format!("refs/heads/{}", branch).into())?;
+        let counts = self.ref_resolve(upstream.clone().into()).map(|v| self.ahead_behind(&sha, &v));
+        Some((upstream, counts))
+    }
+}
@@ -0,0 +1,191 @@
+use crate::helpers::git::GitRepo;
+use crate::helpers::git_objects::git_ignore::GitIgnore;
+use std::collections::HashSet;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default)]
+pub struct CleanOptions {
+    /// Given twice, also removes untracked nested repositories.
+    pub force: u8,
+    /// Also remove untracked directories.
+    pub dirs: bool,
+    /// Don't use the ignore files (`-x`).
+    pub no_ignore: bool,
+    /// Remove only ignored files (`-X`).
+    pub only_ignored: bool,
+    /// Extra ignore patterns (`-e`).
+    pub excludes: Vec<String>,
+    /// Limit the cleaning to these paths.
+    pub paths: Vec<String>,
+}
+
+struct CleanWalk<'a> {
+    repo: &'a GitRepo,
+    options: &'a CleanOptions,
+    ignore: GitIgnore,
+    tracked: HashSet<String>,
+    /// Directories holding tracked files, which are never removed as a whole.
+    tracked_dirs: HashSet<String>,
+    paths: Vec<String>,
+}
+
+impl CleanWalk<'_> {
+    fn in_pathspec(&self, path: &str) -> bool {
+        self.paths.is_empty()
+            || self
+                .paths
+                .iter()
+                .any(|p| path == p || path.strip_prefix(p.as_str()).is_some_and(|v| v.starts_with('/')))
+    }
+
+    fn leads_to_pathspec(&self, dir: &str) -> bool {
+        self.paths
+            .iter()
+            .any(|p| p.strip_prefix(dir).is_some_and(|v| v.starts_with('/')))
+    }
+
+    /// Collects what to remove below `dir`; the flag tells if everything in it goes, or with
+    /// `-X` is ignored, in which case the caller can remove the directory as a whole.
+    fn walk(&self, dir: &str) -> (Vec<String>, bool) {
+        let mut entries: Vec<_> = match std::fs::read_dir(self.repo.work_dir.join(dir)) {
+            Ok(v) => v.flatten().collect(),
+            Err(_) => return (Vec::new(), false),
+        };
+        entries.sort_by_key(|v| v.file_name());
+
+        let mut removed = Vec::new();
+        let mut kept = false;
+        for entry in entries {
+            if entry.file_name() == ".git" {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = if dir.is_empty() { name } else { format!("{}/{}", dir, name) };
+            let is_dir = entry.file_type().map(|v| v.is_dir()).unwrap_or(false);
+
+            if self.tracked.contains(&path) {
+                kept = true;
+                continue;
+            }
+            let partial = !self.in_pathspec(&path) && self.leads_to_pathspec(&path);
+            if is_dir && (self.tracked_dirs.contains(&path) || partial) {
+                // Only parts of this directory may go.
+                removed.extend(self.walk(&path).0);
+                kept = true;
+                continue;
+            }
+            if !self.in_pathspec(&path) {
+                kept = true;
+                continue;
+            }
+
+            let ignored = self.ignore.is_ignored(&path, is_dir);
+            if !is_dir {
+                if ignored == self.options.only_ignored {
+                    removed.push(path);
+                } else {
+                    kept = true;
+                }
+                continue;
+            }
+
+            if entry.path().join(".git").exists() && self.options.force < 2 {
+                // Another repository; only `-ff` touches it.
+                kept = true;
+                continue;
+            }
+            // With `-X` a directory counts as gone once all of it is ignored, though only `-d`
+            // removes it.
+            let removed_whole = if ignored {
+                if self.options.only_ignored && self.options.dirs {
+                    removed.push(format!("{}/", path));
+                }
+                self.options.only_ignored
+            } else if self.options.only_ignored {
+                // Ignored files in untracked directories go too, unless the directory
+                // holds nothing but ignored files, which makes it ignored as a whole.
+                // An empty directory is not ignored.
+                let (inner, all) = self.walk(&path);
+                let whole = all && std::fs::read_dir(entry.path()).is_ok_and(|mut v| v.next().is_some());
+                if whole && self.options.dirs {
+                    removed.push(format!("{}/", path));
+                } else if !all {
+                    removed.extend(inner);
+                }
+                whole
+            } else if self.options.dirs {
+                let (inner, all) = self.walk(&path);
+                if all {
+                    removed.push(format!("{}/", path));
+                } else {
+                    removed.extend(inner);
+                }
+                all
+            } else {
+                false
+            };
+            kept = kept || !removed_whole;
+        }
+        (removed, !kept)
+    }
+}
+
+impl GitRepo {
+    /// Lists the untracked paths `clean` would remove, directories ending with `/`.
+    pub fn clean_list(&self, options: &CleanOptions) -> Vec<String> {
+        let tracked: HashSet<String> = self.index_read().entries.into_iter().map(|e| e.name).collect();
+        let tracked_dirs = tracked
+            .iter()
+            .flat_map(|v| Path::new(v).ancestors().skip(1).map(|v| v.to_string_lossy().into_owned()))
+            .collect();
+
+        let mut ignore = if options.no_ignore {
+            GitIgnore::command_line_only(self.work_dir.clone())
+        } else {
+            self.gitignore_read()
+        };
+        ignore.add_excludes(&options.excludes);
+
+        let walk = CleanWalk {
+            repo: self,
+            options,
+            ignore,
+            tracked,
+            tracked_dirs,
+            paths: options
+                .paths
+                .iter()
+                .map(|v| v.trim_start_matches("./").trim_end_matches('/').to_string())
+                .filter(|v| !v.is_empty() && v != ".")
+                .collect(),
+        };
+        walk.walk("").0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::file::create_new_my_git;
+
+    #[test]
+    fn only_ignored_keeps_directories_that_are_not_ignored() {
+        let dir = std::env::temp_dir().join(format!("mygit-clean-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        create_new_my_git(dir.clone(), false).unwrap();
+        std::fs::write(dir.join(".gitignore"), "*.o\n").unwrap();
+        for path in ["emptydir", "mixed", "objects/deep", "withempty/empty"] {
+            std::fs::create_dir_all(dir.join(path)).unwrap();
+        }
+        for path in ["mixed/a.o", "mixed/a.c", "objects/b.o", "objects/deep/c.o", "withempty/d.o"] {
+            std::fs::write(dir.join(path), "").unwrap();
+        }
+        let repo = GitRepo::from_dirs(Some(dir.clone()), dir.join(".git"), false);
+
+        let options = CleanOptions { dirs: true, only_ignored: true, ..Default::default() };
+        assert_eq!(repo.clean_list(&options), ["mixed/a.o", "objects/", "withempty/d.o"]);
+        let options = CleanOptions { only_ignored: true, ..Default::default() };
+        assert_eq!(repo.clean_list(&options), ["mixed/a.o", "withempty/d.o"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,314 @@
+use crate::helpers::config;
+use crate::helpers::git::GitRepo;
+use crate::helpers::http::is_http_url;
+use crate::helpers::remote::Transport;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The git directory of the repository a local URL points at, bare or not.
+pub(crate) fn local_git_dir(url: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(url.strip_prefix("file://").unwrap_or(url));
+    let git_dir = crate::helpers::file::git_dir_of(&path);
+    if git_dir.join("objects").is_dir() {
+        Some(git_dir)
+    } else if path.join("objects").is_dir() && path.join("HEAD").is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Copies every file below `from` that `to` doesn't have yet, as hard links when `link` is
+/// set and both are on the same filesystem.
+pub(crate) fn copy_missing(from: &Path, to: &Path, link: bool) -> std::io::Result<()> {
+    if !from.is_dir() {
+        return Ok(());
+    }
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let dest = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_missing(&entry.path(), &dest, link)?;
+        } else if !dest.exists() && (!link || fs::hard_link(entry.path(), &dest).is_err()) {
+            fs::copy(entry.path(), &dest)?;
+        }
+    }
+    Ok(())
+}
+
+/// Lists the refs of a git directory, loose ones winning over `packed-refs`.
+fn read_refs(dir: &Path, prefix: &str, refs: &mut BTreeMap<String, String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+        if entry.path().is_dir() {
+            read_refs(&entry.path(), &name, refs);
+        } else if let Ok(content) = fs::read_to_string(entry.path()) {
+            let sha = content.trim();
+            if sha.len() == 40 {
+                refs.insert(name, sha.to_string());
+            }
+        }
+    }
+}
+
+pub(crate) fn list_refs(git_dir: &Path) -> BTreeMap<String, String> {
+    let mut refs = BTreeMap::new();
+    if let Ok(packed) = fs::read_to_string(git_dir.join("packed-refs")) {
+        for line in packed.lines() {
+            if let Some((sha, name)) = line.split_once(' ') {
+                if !line.starts_with('#') && !line.starts_with('^') {
+                    refs.insert(name.to_string(), sha.to_string());
+                }
+            }
+        }
+    }
+    read_refs(&git_dir.join("refs"), "refs", &mut refs);
+    refs
+}
+
+/// The directory `clone` makes when it isn't given one: the last part of the URL without
+/// `.git`, or with it for a bare repository.
+pub fn clone_dir_name(url: &str) -> String {
+    let path = url.strip_prefix("file://").unwrap_or(url).trim_end_matches('/');
+    let path = path.strip_suffix("/.git").unwrap_or(path).trim_end_matches('/');
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.strip_suffix(".git").unwrap_or(name).to_string()
+}
+
+/// What a new clone has checked out.
+pub enum CloneHead {
+    Branch(String, String),
+    /// A tag was asked for; HEAD is its commit.
+    Detached(String),
+    /// The source has no commits yet on this branch.
+    Unborn(String),
+}
+
+impl GitRepo {
+    /// Fills a freshly made repository from `source`, which `url` names: its objects, its
+    /// branches as `refs/remotes/origin/*` (as they are when bare), its tags, and the `origin`
+    /// remote.
+    ///
+    /// A `depth` limits the clone to `branch`, or the branch of the source HEAD, and through a
+    /// URL to the commits that many generations down it, recording where history was cut in
+    /// `shallow`.
+    pub fn clone_from(&self, url: &str, source: &Transport, branch: Option<&str>, depth: Option<usize>) -> Result<CloneHead, String> {
+        let listed = source.refs()?;
+        let mut refs = listed.refs.clone();
+        let source_head = refs.remove("HEAD");
+        let source_branch = listed.head.as_deref().and_then(|v| v.strip_prefix("refs/heads/")).map(|v| v.to_string());
+        let local = !url.starts_with("file://") && !is_http_url(url);
+        let shallow_depth = depth.filter(|_| !local);
+
+        let head = match branch {
+            Some(name) => match refs.get(&format!("refs/heads/{}", name)) {
+                Some(sha) => CloneHead::Branch(name.to_string(), sha.clone()),
+                None => {
+                    let tag = refs
+                        .get(&format!("refs/tags/{}", name))
+                        .ok_or_else(|| format!("Remote branch {} not found in upstream origin", name))?;
+                    CloneHead::Detached(listed.peel(&format!("refs/tags/{}", name), tag).to_string())
+                }
+            },
+            None => match &source_branch {
+                Some(name) => match refs.get(&format!("refs/heads/{}", name)) {
+                    Some(sha) => CloneHead::Branch(name.clone(), sha.clone()),
+                    None => CloneHead::Unborn(name.clone()),
+                },
+                None => CloneHead::Detached(source_head.unwrap_or_default()),
+            },
+        };
+
+        // A shallow clone only has the branch, or tag, it checks out.
+        let single = match (depth, &head, branch) {
+            (None, _, _) => None,
+            (Some(_), CloneHead::Detached(_), Some(name)) => Some(format!("refs/tags/{}", name)),
+            (Some(_), CloneHead::Branch(name, _) | CloneHead::Unborn(name), _) => Some(format!("refs/heads/{}", name)),
+            (Some(_), CloneHead::Detached(_), None) => Some("HEAD".to_string()),
+        };
+        let refs: BTreeMap<String, String> = match &single {
+            Some(name) => refs.into_iter().filter(|(k, _)| k == name || k.starts_with("refs/tags/")).collect(),
+            None => refs,
+        };
+
+        // With a single branch, tags only come along with the history they point at.
+        let tips: Vec<String> = match &head {
+            CloneHead::Branch(_, sha) | CloneHead::Detached(sha) => vec![sha.clone()],
+            CloneHead::Unborn(_) => vec![],
+        };
+        let mut history = HashSet::new();
+        let mut copied = HashSet::new();
+        match source {
+            Transport::Local(source_repo) => {
+                let shallow;
+                (history, shallow) = match single {
+                    Some(_) => Self::clone_history(source_repo, &tips, shallow_depth),
+                    None => Default::default(),
+                };
+                match shallow_depth {
+                    Some(_) => {
+                        for sha in &history {
+                            self.objects_copy(source_repo, sha, false, &mut copied)?;
+                        }
+                        if !shallow.is_empty() {
+                            let content: String = shallow.iter().map(|v| format!("{}\n", v)).collect();
+                            fs::write(self.common_dir.join("shallow"), content).map_err(|e| e.to_string())?;
+                        }
+                    }
+                    // Objects are hard linked from a plain path, copied through a URL.
+                    None => copy_missing(&source_repo.common_dir.join("objects"), &self.common_dir.join("objects"), local).map_err(|e| e.to_string())?,
+                }
+                self.lfs_objects_copy(source_repo, local)?;
+            }
+            // The server sends the tags pointing into what it sends along.
+            Transport::Http(remote) => {
+                let mut wants: Vec<String> = match single {
+                    Some(_) => tips.clone(),
+                    None => refs.iter().filter(|(k, _)| k.starts_with("refs/heads/") || k.starts_with("refs/tags/")).map(|(_, v)| v.clone()).collect(),
+                };
+                wants.sort();
+                wants.dedup();
+                self.http_fetch(remote, &wants, shallow_depth)?;
+            }
+        }
+
+        for (name, sha) in &refs {
+            let dest = if let Some(branch) = name.strip_prefix("refs/heads/") {
+                if self.bare {
+                    name.clone()
+                } else {
+                    format!("refs/remotes/origin/{}", branch)
+                }
+            } else if name.starts_with("refs/tags/") {
+                if single.is_some() && single.as_ref() != Some(name) {
+                    let target = listed.peel(name, sha);
+                    let kept = match source {
+                        Transport::Local(_) => history.contains(target),
+                        Transport::Http(_) => self.object_exists(target),
+                    };
+                    if !kept {
+                        continue;
+                    }
+                }
+                if let (Some(_), Transport::Local(source_repo)) = (shallow_depth, source) {
+                    self.objects_copy(source_repo, sha, false, &mut copied)?;
+                }
+                name.clone()
+            } else {
+                continue;
+            };
+            self.ref_create(dest.trim_start_matches("refs/"), sha);
+        }
+
+        let config_file = self.common_dir.join("config");
+        let url = match local {
+            true => std::env::current_dir().map_err(|e| e.to_string())?.join(url).to_string_lossy().into_owned(),
+            false => url.to_string(),
+        };
+        config::config_set(&config_file, "remote.origin.url", &url, false)?;
+        let write = |file: &str, content: String| fs::write(self.git_dir.join(file), content).map_err(|e| e.to_string());
+        if self.bare {
+            match &head {
+                CloneHead::Branch(name, _) | CloneHead::Unborn(name) => write("HEAD", format!("ref: refs/heads/{}\n", name))?,
+                CloneHead::Detached(sha) => write("HEAD", format!("{}\n", sha))?,
+            }
+            return Ok(head);
+        }
+
+        let fetch = match &single {
+            Some(name) if name.starts_with("refs/heads/") => {
+                format!("+{}:refs/remotes/origin/{}", name, name.trim_start_matches("refs/heads/"))
+            }
+            Some(name) => format!("+{}:{}", name, name),
+            None => "+refs/heads/*:refs/remotes/origin/*".to_string(),
+        };
+        config::config_set(&config_file, "remote.origin.fetch", &fetch, false)?;
+        if let Some(name) = &source_branch {
+            if self.ref_resolve(format!("refs/remotes/origin/{}", name).into()).is_some() {
+                write("refs/remotes/origin/HEAD", format!("ref: refs/remotes/origin/{}\n", name))?;
+            }
+        }
+        match &head {
+            CloneHead::Branch(name, _) | CloneHead::Unborn(name) => {
+                if let CloneHead::Branch(_, sha) = &head {
+                    self.ref_create(&format!("heads/{}", name), sha);
+                }
+                config::config_set(&config_file, &format!("branch.{}.remote", name), "origin", false)?;
+                config::config_set(&config_file, &format!("branch.{}.merge", name), &format!("refs/heads/{}", name), false)?;
+                write("HEAD", format!("ref: refs/heads/{}\n", name))?;
+            }
+            CloneHead::Detached(sha) => write("HEAD", format!("{}\n", sha))?,
+        }
+        Ok(head)
+    }
+
+    /// Lists the commits up to `depth` generations from `tips`, or all of their history, along
+    /// with those at the depth, whose parents are left out.
+    fn clone_history(source: &GitRepo, tips: &[String], depth: Option<usize>) -> (HashSet<String>, Vec<String>) {
+        let mut history = HashSet::new();
+        let mut shallow = Vec::new();
+        let mut generation = tips.to_vec();
+        let mut level = 1;
+        while !generation.is_empty() {
+            let mut next = Vec::new();
+            for sha in generation {
+                if !history.insert(sha.clone()) {
+                    continue;
+                }
+                // Like git, a commit at the depth is shallow even when it has no parents.
+                if depth.is_none_or(|v| level < v) {
+                    next.extend(source.commit_parents(&sha));
+                } else {
+                    shallow.push(sha);
+                }
+            }
+            generation = next;
+            level += 1;
+        }
+        (history, shallow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::helpers::file::create_new_my_git;
+    use crate::helpers::git::GitRepo;
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn local_clone_brings_the_lfs_store() {
+        let root = std::env::temp_dir().join(format!("mygit-clone-lfs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        create_new_my_git(root.join("src.git"), true).unwrap();
+        let source = GitRepo::from_dirs(None, root.join("src.git"), false);
+        let pointer = source.lfs_clean(b"large content\n".to_vec()).unwrap();
+        let blob = source.object_store(b"blob", &pointer).unwrap();
+        let tree = source.object_store(b"tree", &[&b"100644 big.bin\0"[..], &hex::decode(&blob).unwrap()].concat()).unwrap();
+        let commit = format!(
+            "tree {}\nauthor A U Thor <a@example.com> 0 +0000\ncommitter A U Thor <a@example.com> 0 +0000\n\nfirst\n",
+            tree
+        );
+        let commit = source.object_store(b"commit", commit.as_bytes()).unwrap();
+        std::fs::write(root.join("src.git/refs/heads/master"), format!("{}\n", commit)).unwrap();
+        let oid = crate::helpers::lfs::LfsPointer::parse(&pointer).unwrap().oid;
+
+        // Hard linked from a plain path, copied through a URL.
+        let url = root.join("src.git").to_string_lossy().into_owned();
+        for (name, url, links) in [("plain.git", url.clone(), 2), ("url.git", format!("file://{}", url), 1)] {
+            create_new_my_git(root.join(name), true).unwrap();
+            let clone = GitRepo::from_dirs(None, root.join(name), false);
+            let transport = clone.transport_open(&url).unwrap();
+            clone.clone_from(&url, &transport, None, None).unwrap();
+            let object = clone.lfs_object_path(&oid);
+            assert_eq!(std::fs::read(&object).unwrap(), b"large content\n");
+            assert_eq!(std::fs::metadata(&object).unwrap().nlink(), links);
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,36 +1,818 @@
-use configparser::ini::{Ini, WriteOptions};
-use std::path::PathBuf;
+use crate::helpers::file::common_dir_of;
+use std::path::{Path, PathBuf};
 
-pub fn get_default_conf() -> Ini {
-    let mut config = Ini::new();
+/// Where a configuration value comes from, from the weakest to the strongest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConfigScope {
+    System,
+    Global,
+    Local,
+    Worktree,
+}
+
+impl std::fmt::Display for ConfigScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ConfigScope::System => "system",
+            ConfigScope::Global => "global",
+            ConfigScope::Local => "local",
+            ConfigScope::Worktree => "worktree",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigEntry {
+    /// `section.name` or `section.subsection.name`, with the section and the name lowercased.
+    pub key: String,
+    /// `None` for a bare `name` line, which git reads as a true boolean.
+    pub value: Option<String>,
+    pub origin: PathBuf,
+    pub scope: ConfigScope,
+    /// First and last line of the entry in its file, continuations included.
+    lines: (usize, usize),
+    /// Character the entry starts at on its first line, after a section header sharing it.
+    column: usize,
+}
+
+/// Every entry of the configuration files that were read, in reading order.
+#[derive(Debug, Clone, Default)]
+pub struct GitConfig {
+    pub entries: Vec<ConfigEntry>,
+    /// `section` or `section.subsection` of every header, with its line.
+    sections: Vec<(String, usize)>,
+}
+
+impl GitConfig {
+    /// The last value of `key`, which is the one that wins.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let key = canonical_key(key).ok()?;
+        self.entries
+            .iter()
+            .rev()
+            .find(|v| v.key == key)
+            .map(|v| v.value.clone().unwrap_or_default())
+    }
+
+    pub fn get_all(&self, key: &str) -> Vec<&ConfigEntry> {
+        match canonical_key(key) {
+            Ok(key) => self.entries.iter().filter(|v| v.key == key).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        let key = canonical_key(key).ok()?;
+        let entry = self.entries.iter().rev().find(|v| v.key == key)?;
+        match &entry.value {
+            None => Some(true),
+            Some(v) => parse_bool(v),
+        }
+    }
+
+    pub fn scope(&self, scope: ConfigScope) -> GitConfig {
+        GitConfig {
+            entries: self
+                .entries
+                .iter()
+                .filter(|v| v.scope == scope)
+                .cloned()
+                .collect(),
+            sections: Vec::new(),
+        }
+    }
+}
 
-    config.set("core", "repositoryformatversion", Some("0".to_string()));
-    config.set("core", "filemode", Some("true".to_string()));
-    config.set("core", "bare", Some("false".to_string()));
-    config.set("core", "logallrefupdates", Some("true".to_string()));
+/// Reads a boolean the way git does (`true`/`yes`/`on`/`1` and their opposites).
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" | "" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
 
-    config
+/// Splits `section[.subsection].name` into its parts, checking the names git accepts.
+pub fn split_key(key: &str) -> Result<(&str, Option<&str>, &str), String> {
+    let (section, name) = key
+        .rsplit_once('.')
+        .ok_or_else(|| format!("key does not contain a section: {}", key))?;
+    let (section, subsection) = match section.split_once('.') {
+        Some((section, subsection)) => (section, Some(subsection)),
+        None => (section, None),
+    };
+    let section_valid = !section.is_empty()
+        && section
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-');
+    let name_valid = name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if !section_valid || !name_valid {
+        return Err(format!("invalid key: {}", key));
+    }
+    Ok((section, subsection, name))
 }
 
-pub fn write_conf(path: PathBuf, config: Ini) -> std::io::Result<()> {
-    config.pretty_write(path, &get_default_write_options())
+/// Lowercases the section and the name of a key, leaving the subsection alone.
+pub fn canonical_key(key: &str) -> Result<String, String> {
+    let (section, subsection, name) = split_key(key)?;
+    Ok(match subsection {
+        Some(subsection) => format!(
+            "{}.{}.{}",
+            section.to_lowercase(),
+            subsection,
+            name.to_lowercase()
+        ),
+        None => format!("{}.{}", section.to_lowercase(), name.to_lowercase()),
+    })
 }
 
-fn get_default_write_options() -> WriteOptions {
-    WriteOptions::new_with_params(true, 1, 4)
+pub fn get_default_conf() -> String {
+    "[core]\n\
+     \trepositoryformatversion = 0\n\
+     \tfilemode = true\n\
+     \tbare = false\n\
+     \tlogallrefupdates = true\n"
+        .to_string()
 }
 
-pub fn read_conf(path: PathBuf) -> Result<Ini, String> {
+pub fn write_conf(path: PathBuf, config: String) -> std::io::Result<()> {
+    std::fs::write(path, config)
+}
+
+/// Git refuses to follow includes nested deeper than this.
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// Reads a configuration file along with the files it includes. `git_dir` is the
+/// repository `includeIf` conditions are evaluated against.
+pub fn read_conf(path: PathBuf, scope: ConfigScope, git_dir: Option<&Path>) -> Result<GitConfig, String> {
     if !path.is_file() {
         return Err("config is not a file".to_string());
     }
+    read_conf_nested(&path, scope, git_dir, &mut Vec::new())
+}
+
+fn read_conf_nested(
+    path: &Path,
+    scope: ConfigScope,
+    git_dir: Option<&Path>,
+    stack: &mut Vec<PathBuf>,
+) -> Result<GitConfig, String> {
+    let real_path = std::fs::canonicalize(path).unwrap_or(path.to_path_buf());
+    if stack.contains(&real_path) {
+        let chain: Vec<String> = stack
+            .iter()
+            .chain([&real_path])
+            .map(|v| v.display().to_string())
+            .collect();
+        return Err(format!("include cycle detected: {}", chain.join(" -> ")));
+    }
+    if stack.len() > MAX_INCLUDE_DEPTH {
+        return Err(format!(
+            "exceeded maximum include depth ({}) while including {}",
+            MAX_INCLUDE_DEPTH,
+            path.display()
+        ));
+    }
+
     let content = match std::fs::read_to_string(path) {
         Ok(content) => content,
         Err(e) => return Err(e.to_string()),
     };
+    let parsed = parse_conf(&content, path, scope)?;
+
+    stack.push(real_path);
+    let mut config = GitConfig {
+        entries: Vec::new(),
+        sections: parsed.sections,
+    };
+    for entry in parsed.entries {
+        let include = match &entry.value {
+            Some(value) if include_applies(&entry.key, path, git_dir) => Some(value.clone()),
+            _ => None,
+        };
+        config.entries.push(entry);
+        // Included values take effect right where the include is, like in git.
+        if let Some(value) = include {
+            let included = resolve_include(&value, path);
+            if included.is_file() {
+                config
+                    .entries
+                    .extend(read_conf_nested(&included, scope, git_dir, stack)?.entries);
+            }
+        }
+    }
+    stack.pop();
+    Ok(config)
+}
 
-    let mut config = Ini::new();
-    config.read(content)?;
+/// Expands `~/` and makes a relative path relative to the directory of `base`.
+fn resolve_include(value: &str, base: &Path) -> PathBuf {
+    let path = match (value.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(value),
+    };
+    if path.is_absolute() {
+        path
+    } else {
+        base.parent().unwrap_or(Path::new(".")).join(path)
+    }
+}
+
+/// Tells if `key` is an `include.path`, or an `includeIf.<condition>.path` whose
+/// condition holds for the repository.
+fn include_applies(key: &str, origin: &Path, git_dir: Option<&Path>) -> bool {
+    if key == "include.path" {
+        return true;
+    }
+    let condition = match key
+        .strip_prefix("includeif.")
+        .and_then(|v| v.strip_suffix(".path"))
+    {
+        Some(v) => v,
+        None => return false,
+    };
+    let git_dir = match git_dir {
+        Some(v) => v,
+        None => return false,
+    };
+
+    let (pattern, case_sensitive, subject) = if let Some(v) = condition.strip_prefix("gitdir:") {
+        (gitdir_pattern(v, origin), true, gitdir_subject(git_dir))
+    } else if let Some(v) = condition.strip_prefix("gitdir/i:") {
+        (gitdir_pattern(v, origin), false, gitdir_subject(git_dir))
+    } else if let Some(v) = condition.strip_prefix("onbranch:") {
+        let branch = match current_branch(git_dir) {
+            Some(v) => v,
+            None => return false,
+        };
+        let pattern = if v.ends_with('/') {
+            format!("{}**", v)
+        } else {
+            v.to_string()
+        };
+        (pattern, true, branch)
+    } else {
+        return false;
+    };
+
+    let options = glob::MatchOptions {
+        case_sensitive,
+        require_literal_separator: true,
+        require_literal_leading_dot: false,
+    };
+    glob::Pattern::new(&pattern)
+        .map(|v| v.matches_with(&subject, options))
+        .unwrap_or(false)
+}
+
+/// Turns a `gitdir:` condition into a glob the way git does: `~/` and `./` are
+/// expanded, relative patterns match anywhere and a trailing `/` matches everything below.
+fn gitdir_pattern(value: &str, origin: &Path) -> String {
+    let mut pattern = if let (Some(rest), Ok(home)) = (value.strip_prefix("~/"), std::env::var("HOME")) {
+        format!("{}/{}", home.trim_end_matches('/'), rest)
+    } else if let Some(rest) = value.strip_prefix("./") {
+        let dir = origin.parent().unwrap_or(Path::new("."));
+        let dir = std::fs::canonicalize(dir).unwrap_or(dir.to_path_buf());
+        format!("{}/{}", dir.display(), rest)
+    } else if !value.starts_with('/') {
+        format!("**/{}", value)
+    } else {
+        value.to_string()
+    };
+    if pattern.ends_with('/') {
+        pattern.push_str("**");
+    }
+    pattern
+}
+
+fn gitdir_subject(git_dir: &Path) -> String {
+    std::fs::canonicalize(git_dir)
+        .unwrap_or(git_dir.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn current_branch(git_dir: &Path) -> Option<String> {
+    let head = std::fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    head.trim_end()
+        .strip_prefix("ref: refs/heads/")
+        .map(|v| v.to_string())
+}
+
+/// The files of each scope, in the order they are read.
+pub fn config_files(git_dir: Option<&Path>) -> Vec<(ConfigScope, PathBuf)> {
+    let mut files = Vec::new();
+    let env_true = |name: &str| {
+        std::env::var(name)
+            .ok()
+            .and_then(|v| parse_bool(&v))
+            .unwrap_or(false)
+    };
+
+    if !env_true("GIT_CONFIG_NOSYSTEM") {
+        let system = std::env::var("GIT_CONFIG_SYSTEM").unwrap_or("/etc/gitconfig".to_string());
+        files.push((ConfigScope::System, PathBuf::from(system)));
+    }
+    match std::env::var("GIT_CONFIG_GLOBAL") {
+        Ok(global) => files.push((ConfigScope::Global, PathBuf::from(global))),
+        Err(_) => {
+            if let Some(xdg) = xdg_config_file() {
+                files.push((ConfigScope::Global, xdg));
+            }
+            if let Ok(home) = std::env::var("HOME") {
+                files.push((ConfigScope::Global, PathBuf::from(home).join(".gitconfig")));
+            }
+        }
+    }
+    if let Some(git_dir) = git_dir {
+        files.push((ConfigScope::Local, common_dir_of(git_dir).join("config")));
+        files.push((ConfigScope::Worktree, git_dir.join("config.worktree")));
+    }
+    files
+}
+
+fn xdg_config_file() -> Option<PathBuf> {
+    match std::env::var("XDG_CONFIG_HOME") {
+        Ok(v) if !v.is_empty() => Some(PathBuf::from(v).join("git/config")),
+        _ => std::env::var("HOME")
+            .ok()
+            .map(|v| PathBuf::from(v).join(".config/git/config")),
+    }
+}
+
+/// What `--worktree` stands for: the per-worktree file once the repository opted into it,
+/// else the repository file, which git only allows while there is a single worktree.
+pub fn worktree_scope(config: &GitConfig, worktrees: usize) -> Result<ConfigScope, String> {
+    if config.get_bool("extensions.worktreeConfig").unwrap_or(false) {
+        Ok(ConfigScope::Worktree)
+    } else if worktrees > 1 {
+        Err("--worktree cannot be used with multiple working trees unless the config\n\
+             extension worktreeConfig is enabled. Please read \"CONFIGURATION FILE\"\n\
+             section in \"git help worktree\" for details"
+            .to_string())
+    } else {
+        Ok(ConfigScope::Local)
+    }
+}
 
+/// Names a configuration file like `--show-origin`: files of the common directory go through
+/// the git directory as git found it, `.git` from the working tree and nothing from inside it;
+/// anything else keeps its full path.
+pub fn origin_name(origin: &Path, git_dir: Option<&Path>, work_dir: Option<&Path>) -> String {
+    let full = origin.to_string_lossy().to_string();
+    let git_dir = match git_dir {
+        Some(v) => v,
+        None => return full,
+    };
+    let read_as = common_dir_of(git_dir);
+    let rest = match origin.strip_prefix(&read_as) {
+        Ok(v) => v,
+        Err(_) => return full,
+    };
+    let common_dir = std::fs::canonicalize(&read_as).unwrap_or(read_as.clone());
+    let cwd = std::env::current_dir().and_then(std::fs::canonicalize).ok();
+    let shown = match std::env::var_os("GIT_DIR") {
+        Some(v) if read_as == git_dir => PathBuf::from(v),
+        _ if cwd.as_ref() == Some(&common_dir) => PathBuf::new(),
+        _ if work_dir.is_some_and(|v| v.join(".git") == common_dir) => PathBuf::from(".git"),
+        _ => common_dir,
+    };
+    shown.join(rest).to_string_lossy().to_string()
+}
+
+/// The file `--system`, `--global`, `--local` or `--worktree` writes to.
+pub fn config_file_for(scope: ConfigScope, git_dir: Option<&Path>) -> Result<PathBuf, String> {
+    match scope {
+        ConfigScope::System => Ok(PathBuf::from(
+            std::env::var("GIT_CONFIG_SYSTEM").unwrap_or("/etc/gitconfig".to_string()),
+        )),
+        ConfigScope::Global => {
+            if let Ok(global) = std::env::var("GIT_CONFIG_GLOBAL") {
+                return Ok(PathBuf::from(global));
+            }
+            let home = std::env::var("HOME")
+                .map(|v| PathBuf::from(v).join(".gitconfig"))
+                .map_err(|_| "$HOME not set".to_string())?;
+            // Like git, only fall back to the XDG file when it is the one in use.
+            match xdg_config_file() {
+                Some(xdg) if !home.exists() && xdg.is_file() => Ok(xdg),
+                _ => Ok(home),
+            }
+        }
+        ConfigScope::Local | ConfigScope::Worktree => {
+            let git_dir = git_dir.ok_or("--local can only be used inside a git repository")?;
+            Ok(match scope {
+                ConfigScope::Local => common_dir_of(git_dir).join("config"),
+                _ => git_dir.join("config.worktree"),
+            })
+        }
+    }
+}
+
+/// Reads the system, global, repository and worktree configuration, the later
+/// files overriding the earlier ones.
+pub fn read_layers(git_dir: Option<&Path>) -> Result<GitConfig, String> {
+    let mut config = GitConfig::default();
+    for (scope, path) in config_files(git_dir) {
+        if !path.is_file() {
+            continue;
+        }
+        // The per-worktree file only counts when the repository opted into it.
+        if scope == ConfigScope::Worktree
+            && !config.get_bool("extensions.worktreeConfig").unwrap_or(false)
+        {
+            continue;
+        }
+        config
+            .entries
+            .extend(read_conf(path, scope, git_dir)?.entries);
+    }
     Ok(config)
 }
+
+/// Parses a configuration file with git's rules for sections, quoting and escapes.
+pub fn parse_conf(content: &str, origin: &Path, scope: ConfigScope) -> Result<GitConfig, String> {
+    let chars: Vec<char> = content.chars().collect();
+    let mut config = GitConfig::default();
+    let mut pos = 0;
+    let mut line = 0;
+    let mut section: Option<String> = None;
+    let bad_line = |line: usize| format!("bad config line {} in file {}", line + 1, origin.display());
+
+    while pos < chars.len() {
+        let c = chars[pos];
+        if c == '\n' {
+            line += 1;
+            pos += 1;
+        } else if c.is_whitespace() {
+            pos += 1;
+        } else if c == '#' || c == ';' {
+            while pos < chars.len() && chars[pos] != '\n' {
+                pos += 1;
+            }
+        } else if c == '[' {
+            pos += 1;
+            let start = pos;
+            while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || "-.".contains(chars[pos])) {
+                pos += 1;
+            }
+            let name: String = chars[start..pos].iter().collect::<String>().to_lowercase();
+            if name.is_empty() || pos >= chars.len() {
+                return Err(bad_line(line));
+            }
+            let header = if chars[pos] == ']' {
+                // Deprecated `[section.subsection]` form, whose subsection is case-insensitive.
+                name
+            } else {
+                while pos < chars.len() && (chars[pos] == ' ' || chars[pos] == '\t') {
+                    pos += 1;
+                }
+                if pos >= chars.len() || chars[pos] != '"' || name.contains('.') {
+                    return Err(bad_line(line));
+                }
+                pos += 1;
+                let mut subsection = String::new();
+                loop {
+                    match chars.get(pos) {
+                        None | Some('\n') => return Err(bad_line(line)),
+                        Some('"') => break,
+                        Some('\\') => {
+                            pos += 1;
+                            match chars.get(pos) {
+                                None | Some('\n') => return Err(bad_line(line)),
+                                Some(v) => subsection.push(*v),
+                            }
+                        }
+                        Some(v) => subsection.push(*v),
+                    }
+                    pos += 1;
+                }
+                pos += 1;
+                format!("{}.{}", name, subsection)
+            };
+            if chars.get(pos) != Some(&']') {
+                return Err(bad_line(line));
+            }
+            pos += 1;
+            config.sections.push((header.clone(), line));
+            section = Some(header);
+        } else if c.is_ascii_alphabetic() {
+            let first_line = line;
+            let start = pos;
+            let column = start - chars[..start].iter().rposition(|v| *v == '\n').map_or(0, |v| v + 1);
+            while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '-') {
+                pos += 1;
+            }
+            let name: String = chars[start..pos].iter().collect::<String>().to_lowercase();
+            while pos < chars.len() && (chars[pos] == ' ' || chars[pos] == '\t') {
+                pos += 1;
+            }
+            let value = match chars.get(pos) {
+                Some('=') => {
+                    pos += 1;
+                    Some(parse_value(&chars, &mut pos, &mut line).ok_or_else(|| bad_line(line))?)
+                }
+                None | Some('\n') | Some('\r') | Some('#') | Some(';') => None,
+                _ => return Err(bad_line(line)),
+            };
+            let section = section.as_ref().ok_or_else(|| bad_line(line))?;
+            config.entries.push(ConfigEntry {
+                key: format!("{}.{}", section, name),
+                value,
+                origin: origin.to_path_buf(),
+                scope,
+                lines: (first_line, line),
+                column,
+            });
+        } else {
+            return Err(bad_line(line));
+        }
+    }
+    Ok(config)
+}
+
+/// Reads a value up to the end of its line, leaving `pos` on the newline.
+fn parse_value(chars: &[char], pos: &mut usize, line: &mut usize) -> Option<String> {
+    let mut value = String::new();
+    let mut quote = false;
+    let mut spaces = 0;
+    while let Some(&c) = chars.get(*pos) {
+        if c == '\n' {
+            break;
+        }
+        *pos += 1;
+        if !quote {
+            if c == ';' || c == '#' {
+                while *pos < chars.len() && chars[*pos] != '\n' {
+                    *pos += 1;
+                }
+                break;
+            }
+            if c.is_whitespace() {
+                // Whitespace is kept inside the value but dropped around it.
+                if !value.is_empty() {
+                    spaces += 1;
+                }
+                continue;
+            }
+        }
+        value.push_str(&" ".repeat(spaces));
+        spaces = 0;
+        match c {
+            '\\' => {
+                let escaped = *chars.get(*pos)?;
+                *pos += 1;
+                match escaped {
+                    '\n' => *line += 1,
+                    '\r' if chars.get(*pos) == Some(&'\n') => {
+                        *pos += 1;
+                        *line += 1;
+                    }
+                    'n' => value.push('\n'),
+                    't' => value.push('\t'),
+                    'b' => value.push('\u{8}'),
+                    '\\' | '"' => value.push(escaped),
+                    _ => return None,
+                }
+            }
+            '"' => quote = !quote,
+            _ => value.push(c),
+        }
+    }
+    if quote {
+        return None;
+    }
+    Some(value)
+}
+
+/// Formats a value so that `parse_value` reads it back unchanged.
+fn quote_value(value: &str) -> String {
+    let needs_quotes = value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains(['#', ';']);
+    let mut output = String::new();
+    for c in value.chars() {
+        match c {
+            '\\' => output.push_str("\\\\"),
+            '"' => output.push_str("\\\""),
+            '\n' => output.push_str("\\n"),
+            '\t' => output.push_str("\\t"),
+            '\u{8}' => output.push_str("\\b"),
+            _ => output.push(c),
+        }
+    }
+    if needs_quotes {
+        format!("\"{}\"", output)
+    } else {
+        output
+    }
+}
+
+fn section_header(section: &str, subsection: Option<&str>) -> String {
+    match subsection {
+        Some(subsection) => format!(
+            "[{} \"{}\"]\n",
+            section,
+            subsection.replace('\\', "\\\\").replace('"', "\\\"")
+        ),
+        None => format!("[{}]\n", section),
+    }
+}
+
+fn read_for_update(path: &Path) -> Result<(Vec<String>, GitConfig), String> {
+    let content = if path.exists() {
+        std::fs::read_to_string(path).map_err(|e| e.to_string())?
+    } else {
+        String::new()
+    };
+    let config = parse_conf(&content, path, ConfigScope::Local)?;
+    let lines = content.split_inclusive('\n').map(|v| v.to_string()).collect();
+    Ok((lines, config))
+}
+
+/// Replaces the lines of `entry` with `line`, or drops them. Whatever comes before the
+/// entry on its first line is kept on a line of its own.
+fn entry_replace(lines: &mut Vec<String>, entry: &ConfigEntry, line: Option<String>) {
+    let last = entry.lines.1.min(lines.len() - 1);
+    let before: String = lines[entry.lines.0].chars().take(entry.column).collect();
+    let before = before.trim_end();
+    let mut replacement = Vec::new();
+    if !before.is_empty() {
+        replacement.push(format!("{}\n", before));
+    }
+    replacement.extend(line);
+    lines.splice(entry.lines.0..=last, replacement);
+}
+
+fn write_lines(path: &Path, lines: &[String]) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    std::fs::write(path, lines.concat()).map_err(|e| e.to_string())
+}
+
+/// Sets `key` in the file at `path`. With `add` a new value is appended even when
+/// the key already has some, otherwise the single existing value is replaced.
+pub fn config_set(path: &Path, key: &str, value: &str, add: bool) -> Result<(), String> {
+    let (section, subsection, name) = split_key(key)?;
+    let canonical = canonical_key(key)?;
+    let (mut lines, config) = read_for_update(path)?;
+    let line = format!("\t{} = {}\n", name, quote_value(value));
+
+    let existing: Vec<&ConfigEntry> = config.get_all(&canonical);
+    if !add && existing.len() > 1 {
+        return Err(format!("{} has multiple values", key));
+    }
+    if let (false, Some(entry)) = (add, existing.first()) {
+        entry_replace(&mut lines, entry, Some(line));
+        return write_lines(path, &lines);
+    }
+
+    let prefix = canonical.rsplit_once('.').unwrap().0;
+    let header = config.sections.iter().rev().find(|(v, _)| v == prefix);
+    match header {
+        Some((_, header_line)) => {
+            // Append after the last entry of the section, or right after its header.
+            let next_header = config
+                .sections
+                .iter()
+                .map(|(_, v)| *v)
+                .find(|v| v > header_line)
+                .unwrap_or(usize::MAX);
+            let at = config
+                .entries
+                .iter()
+                .filter(|v| v.lines.0 >= *header_line && v.lines.0 < next_header)
+                .map(|v| v.lines.1 + 1)
+                .max()
+                .unwrap_or(header_line + 1)
+                .min(lines.len());
+            if at > 0 && !lines[at - 1].ends_with('\n') {
+                lines[at - 1].push('\n');
+            }
+            lines.insert(at, line);
+        }
+        None => {
+            if lines.last().is_some_and(|v| !v.ends_with('\n')) {
+                lines.last_mut().unwrap().push('\n');
+            }
+            lines.push(section_header(section, subsection));
+            lines.push(line);
+        }
+    }
+    write_lines(path, &lines)
+}
+
+/// Removes `key` from the file at `path`, every value of it when `all` is set.
+///
+/// Returns false when the key was not there.
+pub fn config_unset(path: &Path, key: &str, all: bool) -> Result<bool, String> {
+    let canonical = canonical_key(key)?;
+    let (mut lines, config) = read_for_update(path)?;
+    let existing = config.get_all(&canonical);
+    if existing.is_empty() {
+        return Ok(false);
+    }
+    if existing.len() > 1 && !all {
+        return Err(format!("{} has multiple values", key));
+    }
+    for entry in existing.iter().rev() {
+        entry_replace(&mut lines, entry, None);
+    }
+    write_lines(path, &lines)?;
+    Ok(true)
+}
+
+/// The lines of every section of `config` named `name`, `section` or `section.subsection`,
+/// header included, from the last to the first.
+fn section_ranges(config: &GitConfig, name: &str, len: usize) -> Vec<(usize, usize)> {
+    let name = match name.split_once('.') {
+        Some((section, subsection)) => format!("{}.{}", section.to_lowercase(), subsection),
+        None => name.to_lowercase(),
+    };
+    let mut ranges = Vec::new();
+    for (i, (header, line)) in config.sections.iter().enumerate() {
+        if *header == name {
+            let end = config.sections.get(i + 1).map(|(_, v)| *v).unwrap_or(len);
+            ranges.push((*line, end));
+        }
+    }
+    ranges.reverse();
+    ranges
+}
+
+/// Removes the sections named `name` from the file at `path`, with everything in them.
+///
+/// Returns false when there was none.
+pub fn config_section_remove(path: &Path, name: &str) -> Result<bool, String> {
+    let (mut lines, config) = read_for_update(path)?;
+    let ranges = section_ranges(&config, name, lines.len());
+    for (start, end) in &ranges {
+        lines.drain(*start..*end);
+    }
+    if ranges.is_empty() {
+        return Ok(false);
+    }
+    write_lines(path, &lines)?;
+    Ok(true)
+}
+
+/// Renames the sections named `old` to `new`, keeping what is in them.
+///
+/// Returns false when there was none.
+pub fn config_section_rename(path: &Path, old: &str, new: &str) -> Result<bool, String> {
+    let (mut lines, config) = read_for_update(path)?;
+    let ranges = section_ranges(&config, old, lines.len());
+    let header = match new.split_once('.') {
+        Some((section, subsection)) => section_header(section, Some(subsection)),
+        None => section_header(new, None),
+    };
+    for (start, _) in &ranges {
+        lines[*start] = header.clone();
+    }
+    if ranges.is_empty() {
+        return Ok(false);
+    }
+    write_lines(path, &lines)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::file::create_new_my_git;
+
+    #[test]
+    fn origin_names_files_through_the_git_directory() {
+        let dir = std::env::temp_dir().join(format!("mygit-config-origin-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = std::fs::canonicalize(&dir).unwrap();
+        create_new_my_git(dir.clone(), false).unwrap();
+        let git_dir = dir.join(".git");
+        let linked = git_dir.join("worktrees/wt");
+        std::fs::create_dir_all(&linked).unwrap();
+        std::fs::write(linked.join("commondir"), "../..\n").unwrap();
+
+        let name = |origin: &Path| origin_name(origin, Some(&git_dir), Some(&dir));
+        assert_eq!(name(&git_dir.join("config")), ".git/config");
+        assert_eq!(name(&git_dir.join("../inc")), ".git/../inc");
+        assert_eq!(name(Path::new("/etc/gitconfig")), "/etc/gitconfig");
+        assert_eq!(
+            origin_name(&common_dir_of(&linked).join("config"), Some(&linked), Some(&dir.join("wt"))),
+            git_dir.join("config").to_string_lossy()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn worktree_scope_needs_the_extension_with_several_worktrees() {
+        let plain = parse_conf("[core]\n\tbare = false\n", Path::new("config"), ConfigScope::Local).unwrap();
+        let opted = parse_conf("[extensions]\n\tworktreeConfig = true\n", Path::new("config"), ConfigScope::Local).unwrap();
+        assert_eq!(worktree_scope(&plain, 1), Ok(ConfigScope::Local));
+        assert!(worktree_scope(&plain, 2).is_err());
+        assert_eq!(worktree_scope(&opted, 2), Ok(ConfigScope::Worktree));
+    }
+}
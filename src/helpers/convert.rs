@@ -0,0 +1,254 @@
+use crate::helpers::config::parse_bool;
+use crate::helpers::filter::FilterDirection;
+use crate::helpers::git::GitRepo;
+use crate::helpers::git_objects::git_attributes::{AttrState, GitAttributes};
+
+/// What to do with line endings of a path, decided from its `text`, `crlf` and
+/// `eol` attributes and from `core.autocrlf`/`core.eol`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CrlfAction {
+    /// Leave the content alone.
+    Binary,
+    /// Normalize to LF in the repository and keep LF in the working tree.
+    TextInput,
+    /// Normalize to LF in the repository and write CRLF to the working tree.
+    TextCrlf,
+    /// Like `TextInput`, for content that does not look binary.
+    AutoInput,
+    /// Like `TextCrlf`, for content that does not look binary.
+    AutoCrlf,
+}
+
+#[derive(Debug, Default)]
+struct TextStats {
+    nul: usize,
+    lonecr: usize,
+    lonelf: usize,
+    crlf: usize,
+    printable: usize,
+    nonprintable: usize,
+}
+
+impl TextStats {
+    fn gather(data: &[u8]) -> Self {
+        let mut stats = TextStats::default();
+        let mut i = 0;
+        while i < data.len() {
+            match data[i] {
+                b'\r' if data.get(i + 1) == Some(&b'\n') => {
+                    stats.crlf += 1;
+                    i += 1;
+                }
+                b'\r' => stats.lonecr += 1,
+                b'\n' => stats.lonelf += 1,
+                127 => stats.nonprintable += 1,
+                0 => {
+                    stats.nul += 1;
+                    stats.nonprintable += 1;
+                }
+                // Backspace, tab, escape and form feed are common in text files.
+                b'\x08' | b'\t' | b'\x1b' | b'\x0c' => stats.printable += 1,
+                c if c < 32 => stats.nonprintable += 1,
+                _ => stats.printable += 1,
+            }
+            i += 1;
+        }
+        // A trailing ^Z is a DOS end-of-file marker.
+        if data.last() == Some(&b'\x1a') {
+            stats.nonprintable = stats.nonprintable.saturating_sub(1);
+        }
+        stats
+    }
+
+    /// Git's heuristic for content that should never be converted.
+    fn is_binary(&self) -> bool {
+        self.lonecr > 0 || self.nul > 0 || (self.printable >> 7) < self.nonprintable
+    }
+}
+
+/// The line ending setting of a path as the attributes state it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CrlfAttr {
+    Undefined,
+    Text,
+    Binary,
+    Input,
+    Auto,
+}
+
+fn crlf_attr(state: &AttrState) -> CrlfAttr {
+    match state {
+        AttrState::Set => CrlfAttr::Text,
+        AttrState::Unset => CrlfAttr::Binary,
+        AttrState::Value(v) if v == "input" => CrlfAttr::Input,
+        AttrState::Value(v) if v == "auto" => CrlfAttr::Auto,
+        _ => CrlfAttr::Undefined,
+    }
+}
+
+impl GitRepo {
+    /// Tells if text files get CRLF in the working tree when nothing more specific says so.
+    fn text_eol_is_crlf(&self) -> bool {
+        match self.config_get("core.autocrlf").as_deref() {
+            Some("input") => return false,
+            Some(v) if parse_bool(v) == Some(true) => return true,
+            _ => {}
+        }
+        self.config_get("core.eol").as_deref() == Some("crlf")
+    }
+
+    /// Works out the line ending conversion of `path`.
+    pub fn crlf_action(&self, attributes: &GitAttributes, path: &str) -> CrlfAction {
+        // `crlf` is the deprecated name of `text`.
+        let mut attr = crlf_attr(&attributes.get(path, "text"));
+        if attr == CrlfAttr::Undefined {
+            attr = crlf_attr(&attributes.get(path, "crlf"));
+        }
+        if attr != CrlfAttr::Binary {
+            // An `eol` attribute implies `text` and picks the line ending.
+            match (attributes.get(path, "eol"), attr) {
+                (AttrState::Value(v), CrlfAttr::Auto) if v == "lf" => return CrlfAction::AutoInput,
+                (AttrState::Value(v), CrlfAttr::Auto) if v == "crlf" => return CrlfAction::AutoCrlf,
+                (AttrState::Value(v), _) if v == "lf" => return CrlfAction::TextInput,
+                (AttrState::Value(v), _) if v == "crlf" => return CrlfAction::TextCrlf,
+                _ => {}
+            }
+        }
+
+        let crlf = self.text_eol_is_crlf();
+        match attr {
+            CrlfAttr::Binary => CrlfAction::Binary,
+            CrlfAttr::Input => CrlfAction::TextInput,
+            CrlfAttr::Text if crlf => CrlfAction::TextCrlf,
+            CrlfAttr::Text => CrlfAction::TextInput,
+            CrlfAttr::Auto if crlf => CrlfAction::AutoCrlf,
+            CrlfAttr::Auto => CrlfAction::AutoInput,
+            CrlfAttr::Undefined => match self.config_get("core.autocrlf").as_deref() {
+                Some("input") => CrlfAction::AutoInput,
+                Some(v) if parse_bool(v) == Some(true) => CrlfAction::AutoCrlf,
+                _ => CrlfAction::Binary,
+            },
+        }
+    }
+
+    /// Complains about conversions that would not survive a round trip, per `core.safecrlf`.
+    fn check_safe_crlf(
+        &self,
+        path: &str,
+        action: CrlfAction,
+        stats: &TextStats,
+    ) -> Result<(), String> {
+        let mode = match self.config_get("core.safecrlf") {
+            None => Some(false),
+            Some(v) if v == "warn" => Some(false),
+            Some(v) => match parse_bool(&v) {
+                Some(true) => Some(true),
+                _ => None,
+            },
+        };
+        let die = match mode {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
+        // Checking the blob back out either keeps LF everywhere or writes CRLF everywhere.
+        let (from, to) = match action {
+            CrlfAction::TextInput | CrlfAction::AutoInput if stats.crlf > 0 => ("CRLF", "LF"),
+            CrlfAction::TextCrlf | CrlfAction::AutoCrlf if stats.lonelf > 0 => ("LF", "CRLF"),
+            _ => return Ok(()),
+        };
+        if die {
+            return Err(format!("{} would be replaced by {} in {}", from, to, path));
+        }
+        eprintln!(
+            "warning: in the working copy of '{}', {} will be replaced by {} the next time Git touches it",
+            path, from, to
+        );
+        Ok(())
+    }
+
+    /// Converts worktree content of `path` to what gets stored in a blob.
+    ///
+    /// `index_sha` is the blob the index has for the path, if any: text that was
+    /// committed with CRLF is not normalized behind the user's back by `text=auto`.
+    pub fn convert_to_git(
+        &self,
+        attributes: &GitAttributes,
+        path: &str,
+        data: Vec<u8>,
+        index_sha: Option<&str>,
+    ) -> Result<Vec<u8>, String> {
+        let data = self.apply_filter(attributes, path, data, FilterDirection::Clean)?;
+        let action = self.crlf_action(attributes, path);
+        if action == CrlfAction::Binary {
+            return Ok(data);
+        }
+        let stats = TextStats::gather(&data);
+        if matches!(action, CrlfAction::AutoInput | CrlfAction::AutoCrlf) {
+            if stats.is_binary() {
+                return Ok(data);
+            }
+            let has_crlf_in_index = index_sha
+                .and_then(|v| self.object_read(v.to_string()).ok())
+                .map(|v| {
+                    let data = v.data();
+                    !TextStats::gather(&data).is_binary() && data.contains(&b'\r')
+                })
+                .unwrap_or(false);
+            if has_crlf_in_index {
+                return Ok(data);
+            }
+        }
+
+        let converted = if stats.crlf == 0 {
+            data
+        } else {
+            let mut output = Vec::with_capacity(data.len());
+            for (i, c) in data.iter().enumerate() {
+                if !(*c == b'\r' && data.get(i + 1) == Some(&b'\n')) {
+                    output.push(*c);
+                }
+            }
+            output
+        };
+        self.check_safe_crlf(path, action, &stats)?;
+        Ok(converted)
+    }
+
+    /// Converts blob content of `path` to what gets written to the working tree.
+    pub fn convert_to_worktree(
+        &self,
+        attributes: &GitAttributes,
+        path: &str,
+        data: Vec<u8>,
+    ) -> Result<Vec<u8>, String> {
+        let data = self.crlf_to_worktree(attributes, path, data);
+        self.apply_filter(attributes, path, data, FilterDirection::Smudge)
+    }
+
+    fn crlf_to_worktree(&self, attributes: &GitAttributes, path: &str, data: Vec<u8>) -> Vec<u8> {
+        let action = self.crlf_action(attributes, path);
+        if !matches!(action, CrlfAction::TextCrlf | CrlfAction::AutoCrlf) {
+            return data;
+        }
+        let stats = TextStats::gather(&data);
+        if stats.lonelf == 0 {
+            return data;
+        }
+        // Content that already has CRLF or looks binary is left as it is.
+        if action == CrlfAction::AutoCrlf
+            && (stats.lonecr > 0 || stats.crlf > 0 || stats.is_binary())
+        {
+            return data;
+        }
+
+        let mut output = Vec::with_capacity(data.len() + stats.lonelf);
+        for (i, c) in data.iter().enumerate() {
+            if *c == b'\n' && (i == 0 || data[i - 1] != b'\r') {
+                output.push(b'\r');
+            }
+            output.push(*c);
+        }
+        output
+    }
+}
@@ -0,0 +1,224 @@
+use crate::helpers::git::GitRepo;
+use crate::helpers::git_objects::tag::GitTag;
+use crate::helpers::ident::GitIdent;
+use glob::Pattern;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Git stops looking for better names after this many candidates.
+const MAX_CANDIDATES: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct DescribeOptions {
+    /// Also use lightweight tags.
+    pub tags: bool,
+    /// Use any ref, not only tags.
+    pub all: bool,
+    /// Always print the `-<count>-g<abbrev>` suffix.
+    pub long: bool,
+    pub abbrev: usize,
+    /// Suffix appended when the working tree differs from HEAD.
+    pub dirty: Option<String>,
+    pub match_patterns: Vec<String>,
+    pub exclude_patterns: Vec<String>,
+}
+
+struct Candidate {
+    name: String,
+    commit: String,
+}
+
+/// A ref naming a commit. Annotated tags rank above lightweight ones, which rank above other refs.
+struct Name {
+    prio: u8,
+    date: i64,
+    display: String,
+}
+
+impl GitRepo {
+    /// Picks the name `describe` uses for each commit a ref points at.
+    fn describe_names(&self, options: &DescribeOptions) -> Result<HashMap<String, Name>, String> {
+        let matches = |patterns: &Vec<String>, name: &str| {
+            patterns
+                .iter()
+                .filter_map(|v| Pattern::new(v).ok())
+                .any(|v| v.matches(name))
+        };
+
+        let (path, prefix) = if options.all {
+            ("refs", "refs")
+        } else {
+            ("refs/tags", "refs/tags")
+        };
+        let refs = self
+            .ref_list(
+                Some(self.repo_dir(path.to_string(), true)?),
+                prefix.to_string(),
+            )
+            .map_err(|e| e.to_string())?;
+
+        let mut names: HashMap<String, Name> = HashMap::new();
+        for (ref_name, sha) in refs {
+            let tag_name = ref_name.strip_prefix("refs/tags/");
+            let obj = match self.object_read(sha.clone()) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            let tag = obj.as_ref().as_any().downcast_ref::<GitTag>();
+            let annotated = tag.is_some();
+            if !options.all && (tag_name.is_none() || (!annotated && !options.tags)) {
+                continue;
+            }
+            // Patterns apply to the tag name, refs outside of refs/tags never match them.
+            let pattern_name = tag_name.unwrap_or("");
+            if !options.match_patterns.is_empty()
+                && (tag_name.is_none() || !matches(&options.match_patterns, pattern_name))
+            {
+                continue;
+            }
+            if tag_name.is_some() && matches(&options.exclude_patterns, pattern_name) {
+                continue;
+            }
+
+            let display = if options.all {
+                ref_name
+                    .strip_prefix("refs/")
+                    .unwrap_or(&ref_name)
+                    .to_string()
+            } else {
+                pattern_name.to_string()
+            };
+            let commit = match self.obj_find(sha, Some("commit".to_string()), None) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            let name = Name {
+                prio: match tag_name {
+                    Some(_) if annotated => 2,
+                    Some(_) => 1,
+                    None => 0,
+                },
+                date: tag
+                    .and_then(|v| v.kvlm.get(b"tagger".as_ref()))
+                    .and_then(|v| v.first())
+                    .and_then(|v| GitIdent::parse(v))
+                    .map_or(0, |v| v.timestamp),
+                display,
+            };
+            // Like git, the first ref seen keeps the commit unless it ranks lower, or both are
+            // annotated tags and the new one is more recent.
+            let replace = match names.get(&commit) {
+                None => true,
+                Some(e) => e.prio < name.prio || (e.prio == 2 && name.prio == 2 && e.date < name.date),
+            };
+            if replace {
+                names.insert(commit, name);
+            }
+        }
+        Ok(names)
+    }
+
+    /// Tells if the index or the working tree differ from HEAD.
+    pub fn worktree_is_dirty(&self) -> bool {
+        let head = self.to_hash_map("HEAD".to_string(), None);
+        let index = self.index_read();
+        let attributes = self.gitattributes_read(false);
+        if head.len() != index.entries.len() {
+            return true;
+        }
+        for entry in &index.entries {
+            if head.get(&entry.name) != Some(&entry.sha) {
+                return true;
+            }
+            if entry.flag_skip_worktree {
+                continue;
+            }
+            let path = self.work_dir.join(&entry.name);
+            let metadata = match std::fs::symlink_metadata(&path) {
+                Ok(v) => v,
+                Err(_) => return true,
+            };
+            if metadata.len() as u32 != entry.fsize {
+                return true;
+            }
+            // The index holds what `add` would store, so the file goes through the same conversion.
+            let content = if metadata.file_type().is_symlink() {
+                std::fs::read_link(&path).map(|v| v.into_os_string().into_encoded_bytes())
+            } else {
+                std::fs::read(&path)
+            };
+            let content = match content {
+                Ok(v) if entry.mode_type == 0b1010 => v,
+                Ok(v) => match self.convert_to_git(&attributes, &entry.name, v, Some(&entry.sha)) {
+                    Ok(v) => v,
+                    Err(_) => return true,
+                },
+                Err(_) => return true,
+            };
+            if GitRepo::object_hash(b"blob", &content) != entry.sha {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Names a commit after the nearest tag reachable from it.
+    pub fn describe(&self, commit: &str, options: &DescribeOptions) -> Result<String, String> {
+        let sha = self.obj_find(commit.to_string(), Some("commit".to_string()), None)?;
+        let names = self.describe_names(options)?;
+        if names.is_empty() {
+            return Err("No names found, cannot describe anything.".to_string());
+        }
+
+        // Walk the history breadth-first, remembering the first few named commits we meet.
+        let mut candidates: Vec<Candidate> = Vec::new();
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::from([sha.clone()]);
+        while let Some(current) = queue.pop_front() {
+            if !seen.insert(current.clone()) {
+                continue;
+            }
+            if let Some(name) = names.get(&current) {
+                candidates.push(Candidate {
+                    name: name.display.clone(),
+                    commit: current.clone(),
+                });
+                if candidates.len() >= MAX_CANDIDATES {
+                    break;
+                }
+            }
+            queue.extend(self.commit_parents(&current));
+        }
+
+        if candidates.is_empty() {
+            return Err(format!(
+                "No tags can describe '{}'.\nTry creating some tags.",
+                sha
+            ));
+        }
+
+        let history = self.commit_ancestors(&sha);
+        let (count, best) = candidates
+            .iter()
+            .map(|v| {
+                let tagged = self.commit_ancestors(&v.commit);
+                (history.difference(&tagged).count(), v)
+            })
+            .min_by_key(|(count, _)| *count)
+            .unwrap();
+
+        let mut output = best.name.clone();
+        if options.abbrev > 0 && (count > 0 || options.long) {
+            output.push_str(&format!(
+                "-{}-g{}",
+                count,
+                &sha[..options.abbrev.min(sha.len())]
+            ));
+        }
+        if let Some(dirty) = &options.dirty {
+            if self.worktree_is_dirty() {
+                output.push_str(dirty);
+            }
+        }
+        Ok(output)
+    }
+}
@@ -0,0 +1,481 @@
+use crate::helpers::git::GitRepo;
+use crate::helpers::git_objects::tree::GitTree;
+use crate::helpers::git_objects::tree_leaf::GitTreeLeaf;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+const CONTEXT_LINES: usize = 3;
+const NULL_SHA: &str = "0000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffOp {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// One changed path between two trees.
+#[derive(Debug, Clone)]
+pub struct FileChange {
+    pub path: String,
+    pub old: Option<GitTreeLeaf>,
+    pub new: Option<GitTreeLeaf>,
+}
+
+/// Line counts of a single file patch, used by `--stat`.
+#[derive(Debug, Clone)]
+pub struct FileStat {
+    pub path: String,
+    pub insertions: usize,
+    pub deletions: usize,
+    pub binary: Option<(usize, usize)>,
+}
+
+/// Splits content into lines, keeping the trailing `\n` on each of them.
+pub fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (i, byte) in data.iter().enumerate() {
+        if *byte == b'\n' {
+            lines.push(&data[start..=i]);
+            start = i + 1;
+        }
+    }
+    if start < data.len() {
+        lines.push(&data[start..]);
+    }
+    lines
+}
+
+/// Git treats content as binary when a NUL byte shows up in the first 8000 bytes.
+pub fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(8000).any(|&b| b == 0)
+}
+
+/// Computes the shortest edit script between two sequences with Myers' algorithm.
+pub fn diff_lines<T: PartialEq>(old: &[T], new: &[T]) -> Vec<DiffOp> {
+    let n = old.len() as isize;
+    let m = new.len() as isize;
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    let mut v = vec![0isize; 2 * max + 3];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'outer: for d in 0..=max as isize {
+        trace.push(v.clone());
+        let mut k = -d;
+        while k <= d {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                break 'outer;
+            }
+            k += 2;
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let idx = (k + offset) as usize;
+        let prev_k = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[(prev_k + offset) as usize];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            ops.push(DiffOp::Equal(x as usize, y as usize));
+        }
+        if d > 0 {
+            if x == prev_x {
+                ops.push(DiffOp::Insert(prev_y as usize));
+            } else {
+                ops.push(DiffOp::Delete(prev_x as usize));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    ops.reverse();
+    ops
+}
+
+fn hunk_range(start: usize, count: usize) -> String {
+    let start = if count == 0 { start } else { start + 1 };
+    if count == 1 {
+        format!("{}", start)
+    } else {
+        format!("{},{}", start, count)
+    }
+}
+
+/// Finds the hunk header context: the last line before `pos` that starts like a definition.
+fn function_context(lines: &[&[u8]], pos: usize) -> Option<String> {
+    lines[..pos.min(lines.len())]
+        .iter()
+        .rev()
+        .find(|v| {
+            v.first()
+                .map(|c| c.is_ascii_alphabetic() || *c == b'_' || *c == b'$')
+                .unwrap_or(false)
+        })
+        .map(|v| {
+            let line: String = String::from_utf8_lossy(v).chars().take(80).collect();
+            line.trim_end().to_string()
+        })
+}
+
+fn push_line(output: &mut Vec<u8>, sign: u8, line: &[u8]) {
+    output.push(sign);
+    output.extend_from_slice(line);
+    if !line.ends_with(b"\n") {
+        output.extend_from_slice(b"\n\\ No newline at end of file\n");
+    }
+}
+
+/// Renders the hunks of a unified diff between two texts.
+///
+/// Returns the hunk text together with the number of inserted and deleted lines.
+pub fn unified_diff(old: &[u8], new: &[u8]) -> (Vec<u8>, usize, usize) {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let ops = diff_lines(&old_lines, &new_lines);
+
+    let insertions = ops
+        .iter()
+        .filter(|v| matches!(v, DiffOp::Insert(_)))
+        .count();
+    let deletions = ops
+        .iter()
+        .filter(|v| matches!(v, DiffOp::Delete(_)))
+        .count();
+
+    // Group the changed operations into hunks that keep CONTEXT_LINES around them.
+    let changes: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, DiffOp::Equal(_, _)))
+        .map(|(i, _)| i)
+        .collect();
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for i in changes {
+        let start = i.saturating_sub(CONTEXT_LINES);
+        let end = (i + CONTEXT_LINES + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut output = Vec::new();
+    for (start, end) in hunks {
+        let (mut old_start, mut new_start) = (None, None);
+        let (mut old_count, mut new_count) = (0, 0);
+        // Position in each file right before the hunk, for empty ranges.
+        let (mut old_pos, mut new_pos) = (0, 0);
+        for op in &ops[..start] {
+            match op {
+                DiffOp::Equal(_, _) => {
+                    old_pos += 1;
+                    new_pos += 1;
+                }
+                DiffOp::Delete(_) => old_pos += 1,
+                DiffOp::Insert(_) => new_pos += 1,
+            }
+        }
+        let mut body = Vec::new();
+        for op in &ops[start..end] {
+            match *op {
+                DiffOp::Equal(o, n) => {
+                    old_start.get_or_insert(o);
+                    new_start.get_or_insert(n);
+                    old_count += 1;
+                    new_count += 1;
+                    push_line(&mut body, b' ', old_lines[o]);
+                }
+                DiffOp::Delete(o) => {
+                    old_start.get_or_insert(o);
+                    old_count += 1;
+                    push_line(&mut body, b'-', old_lines[o]);
+                }
+                DiffOp::Insert(n) => {
+                    new_start.get_or_insert(n);
+                    new_count += 1;
+                    push_line(&mut body, b'+', new_lines[n]);
+                }
+            }
+        }
+        let old_start = old_start.unwrap_or(old_pos);
+        let context = function_context(&old_lines, old_start)
+            .map(|v| format!(" {}", v))
+            .unwrap_or_default();
+        output.extend_from_slice(
+            format!(
+                "@@ -{} +{} @@{}\n",
+                hunk_range(old_start, old_count),
+                hunk_range(new_start.unwrap_or(new_pos), new_count),
+                context
+            )
+            .as_bytes(),
+        );
+        output.extend_from_slice(&body);
+    }
+    (output, insertions, deletions)
+}
+
+/// Renders a `--stat` block for the given file stats.
+pub fn format_stat(stats: &[FileStat]) -> String {
+    let mut output = String::new();
+    if stats.is_empty() {
+        return output;
+    }
+    let width = stats.iter().map(|v| v.path.len()).max().unwrap_or(0);
+    let max_changes = stats
+        .iter()
+        .map(|v| v.insertions + v.deletions)
+        .max()
+        .unwrap_or(0);
+    let count_width = max_changes.to_string().len();
+    let graph_width = 50usize;
+
+    let (mut insertions, mut deletions) = (0, 0);
+    for stat in stats {
+        insertions += stat.insertions;
+        deletions += stat.deletions;
+        if let Some((old, new)) = stat.binary {
+            output.push_str(&format!(
+                " {:<width$} | Bin {} -> {} bytes\n",
+                stat.path,
+                old,
+                new,
+                width = width
+            ));
+            continue;
+        }
+        let total = stat.insertions + stat.deletions;
+        let (plus, minus) = if max_changes > graph_width {
+            let scale = |v: usize| {
+                if v == 0 {
+                    0
+                } else {
+                    (v * graph_width / max_changes).max(1)
+                }
+            };
+            (scale(stat.insertions), scale(stat.deletions))
+        } else {
+            (stat.insertions, stat.deletions)
+        };
+        output.push_str(&format!(
+            " {:<width$} | {:>count_width$} {}{}\n",
+            stat.path,
+            total,
+            "+".repeat(plus),
+            "-".repeat(minus),
+            width = width,
+            count_width = count_width
+        ));
+    }
+
+    let plural = |v: usize, one: &str, many: &str| {
+        if v == 1 {
+            one.to_string()
+        } else {
+            many.to_string()
+        }
+    };
+    output.push_str(&format!(
+        " {} {}",
+        stats.len(),
+        plural(stats.len(), "file changed", "files changed")
+    ));
+    if insertions > 0 || deletions == 0 {
+        output.push_str(&format!(
+            ", {} {}",
+            insertions,
+            plural(insertions, "insertion(+)", "insertions(+)")
+        ));
+    }
+    if deletions > 0 || insertions == 0 {
+        output.push_str(&format!(
+            ", {} {}",
+            deletions,
+            plural(deletions, "deletion(-)", "deletions(-)")
+        ));
+    }
+    output.push('\n');
+    output
+}
+
+impl GitRepo {
+    /// Flattens a tree into a map from full path to its (non-tree) leaf.
+    pub fn tree_leafs_flat(&self, tree_sha: &str, prefix: &str) -> BTreeMap<String, GitTreeLeaf> {
+        let mut ret = BTreeMap::new();
+        let obj = match self.object_read(tree_sha.to_string()) {
+            Ok(v) => v,
+            Err(_) => return ret,
+        };
+        let tree = match obj.as_ref().as_any().downcast_ref::<GitTree>() {
+            None => return ret,
+            Some(v) => v,
+        };
+        for leaf in &tree.leafs {
+            let full_path = Path::new(prefix)
+                .join(&leaf.path)
+                .to_string_lossy()
+                .into_owned();
+            if leaf.mode.starts_with("04") {
+                ret.extend(self.tree_leafs_flat(&leaf.sha, &full_path));
+            } else {
+                let mut leaf = leaf.clone();
+                leaf.path = full_path.clone().into();
+                ret.insert(full_path, leaf);
+            }
+        }
+        ret
+    }
+
+    /// Lists the paths that differ between two trees, `None` standing for the empty tree.
+    pub fn diff_trees(&self, old: Option<&str>, new: Option<&str>) -> Vec<FileChange> {
+        let old = old.map(|v| self.tree_leafs_flat(v, "")).unwrap_or_default();
+        let new = new.map(|v| self.tree_leafs_flat(v, "")).unwrap_or_default();
+        let paths: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+
+        let mut changes = Vec::new();
+        for path in paths {
+            let (o, n) = (old.get(path), new.get(path));
+            if let (Some(o), Some(n)) = (o, n) {
+                if o.sha == n.sha && o.mode == n.mode {
+                    continue;
+                }
+            }
+            changes.push(FileChange {
+                path: path.clone(),
+                old: o.cloned(),
+                new: n.cloned(),
+            });
+        }
+        changes
+    }
+
+    fn blob_content(&self, leaf: Option<&GitTreeLeaf>) -> Vec<u8> {
+        match leaf {
+            // Gitlinks point at commits of another repository, there is no content to show.
+            Some(leaf) if !leaf.mode.starts_with("16") => self
+                .object_read(leaf.sha.clone())
+                .map(|v| v.data())
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Produces the `diff --git` patch and the stat line for a single change.
+    pub fn diff_file(&self, change: &FileChange) -> (Vec<u8>, FileStat) {
+        let path = &change.path;
+        let old_data = self.blob_content(change.old.as_ref());
+        let new_data = self.blob_content(change.new.as_ref());
+        let old_sha = change
+            .old
+            .as_ref()
+            .map(|v| v.sha.as_str())
+            .unwrap_or(NULL_SHA);
+        let new_sha = change
+            .new
+            .as_ref()
+            .map(|v| v.sha.as_str())
+            .unwrap_or(NULL_SHA);
+
+        let mut header = format!("diff --git a/{} b/{}\n", path, path);
+        match (&change.old, &change.new) {
+            (None, Some(new)) => {
+                header.push_str(&format!("new file mode {}\n", new.mode));
+                header.push_str(&format!("index {}..{}\n", &old_sha[..7], &new_sha[..7]));
+            }
+            (Some(old), None) => {
+                header.push_str(&format!("deleted file mode {}\n", old.mode));
+                header.push_str(&format!("index {}..{}\n", &old_sha[..7], &new_sha[..7]));
+            }
+            (Some(old), Some(new)) => {
+                if old.mode != new.mode {
+                    header.push_str(&format!("old mode {}\nnew mode {}\n", old.mode, new.mode));
+                }
+                if old.sha != new.sha {
+                    header.push_str(&format!("index {}..{}", &old_sha[..7], &new_sha[..7]));
+                    if old.mode == new.mode {
+                        header.push_str(&format!(" {}", new.mode));
+                    }
+                    header.push('\n');
+                }
+            }
+            (None, None) => {}
+        }
+
+        let mut stat = FileStat {
+            path: path.clone(),
+            insertions: 0,
+            deletions: 0,
+            binary: None,
+        };
+        let mut output = header.into_bytes();
+        if old_sha == new_sha {
+            return (output, stat);
+        }
+        if is_binary(&old_data) || is_binary(&new_data) {
+            let old_name = match change.old {
+                Some(_) => format!("a/{}", path),
+                None => "/dev/null".to_string(),
+            };
+            let new_name = match change.new {
+                Some(_) => format!("b/{}", path),
+                None => "/dev/null".to_string(),
+            };
+            output.extend_from_slice(
+                format!("Binary files {} and {} differ\n", old_name, new_name).as_bytes(),
+            );
+            stat.binary = Some((old_data.len(), new_data.len()));
+            return (output, stat);
+        }
+
+        let old_name = match change.old {
+            Some(_) => format!("a/{}", path),
+            None => "/dev/null".to_string(),
+        };
+        let new_name = match change.new {
+            Some(_) => format!("b/{}", path),
+            None => "/dev/null".to_string(),
+        };
+        output.extend_from_slice(format!("--- {}\n+++ {}\n", old_name, new_name).as_bytes());
+        let (hunks, insertions, deletions) = unified_diff(&old_data, &new_data);
+        output.extend_from_slice(&hunks);
+        stat.insertions = insertions;
+        stat.deletions = deletions;
+        (output, stat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::function_context;
+
+    #[test]
+    fn function_context_trims_after_truncating() {
+        let long = format!("fn {} (x: u8) {{", "a".repeat(76));
+        let lines: Vec<&[u8]> = vec![long.as_bytes(), b"    body"];
+        assert_eq!(function_context(&lines, 2), Some(format!("fn {}", "a".repeat(76))));
+    }
+}
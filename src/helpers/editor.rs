@@ -0,0 +1,39 @@
+use crate::helpers::git::GitRepo;
+use std::process::Command;
+
+impl GitRepo {
+    /// Picks the editor the same way git does: `GIT_EDITOR`, `core.editor`, `VISUAL`, `EDITOR`, then `vi`.
+    fn editor_command(&self) -> String {
+        std::env::var("GIT_EDITOR")
+            .ok()
+            .or_else(|| self.config_get("core.editor"))
+            .or_else(|| std::env::var("VISUAL").ok())
+            .or_else(|| std::env::var("EDITOR").ok())
+            .unwrap_or_else(|| "vi".to_string())
+    }
+
+    /// Lets the user write a message in `.git/<file>` and returns it without `#` comment lines.
+    pub fn edit_message(&self, file: &str, template: &str) -> Result<String, String> {
+        let path = self.repo_file(file.to_string(), true)?;
+        std::fs::write(&path, template).map_err(|e| e.to_string())?;
+
+        let editor = self.editor_command();
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$@\"", editor))
+            .arg(&editor)
+            .arg(&path)
+            .status()
+            .map_err(|e| format!("unable to start editor '{}': {}", editor, e))?;
+        if !status.success() {
+            return Err(format!("there was a problem with the editor '{}'", editor));
+        }
+
+        let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        let message: Vec<&str> = content
+            .lines()
+            .filter(|v| !v.starts_with('#'))
+            .collect();
+        Ok(message.join("\n").trim().to_string())
+    }
+}
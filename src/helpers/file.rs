@@ -1,8 +1,7 @@
 use crate::helpers::{config, git};
-use std::collections::HashSet;
 use std::io;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub fn dir_exists(path: &str) -> bool {
     std::path::Path::new(path).is_dir()
@@ -20,37 +19,52 @@ pub fn get_exe_dir() -> String {
         .to_string()
 }
 
-pub fn is_my_git_dir(mut path: PathBuf) -> bool {
-    if !path.is_dir() {
-        return false;
-    }
-    path.push(".git");
-    if !path.is_dir() {
-        return false;
+/// Finds the git directory of a worktree: `.git` itself, or where the `gitdir:` line of a
+/// `.git` file points, as submodules and linked worktrees have.
+pub fn git_dir_of(work_dir: &Path) -> PathBuf {
+    let dot_git = work_dir.join(".git");
+    if let Ok(content) = std::fs::read_to_string(&dot_git) {
+        if let Some(target) = content.trim_end().strip_prefix("gitdir: ") {
+            return work_dir.join(target);
+        }
     }
+    dot_git
+}
 
-    let mut mandatory_files = HashSet::from(["config", "description", "HEAD"]);
-    for entry in std::fs::read_dir(path).unwrap() {
-        let entry = entry.unwrap();
-        let dir_path = entry.path();
-        if dir_path.is_file() {
-            if let file_name = dir_path.file_name().unwrap().to_str().unwrap() {
-                if mandatory_files.contains(&file_name) {
-                    mandatory_files.remove(file_name);
-                }
-            }
-        }
+/// The directory a linked worktree shares objects, refs and config through, named by its
+/// `commondir` file; for any other git directory it is that directory itself.
+pub fn common_dir_of(git_dir: &Path) -> PathBuf {
+    match std::fs::read_to_string(git_dir.join("commondir")) {
+        Ok(content) => git_dir.join(content.trim_end()),
+        Err(_) => git_dir.to_path_buf(),
     }
-    mandatory_files.len() == 0
 }
 
-pub fn create_new_my_git(path: PathBuf) -> Result<bool, String> {
-    let get_repo = git::GitRepo::init(path.clone(), true);
+/// Tells if `git_dir` is a git directory. A linked worktree only has its own HEAD there;
+/// the rest lives in the common directory.
+pub fn is_git_dir(git_dir: &Path) -> bool {
+    let common_dir = common_dir_of(git_dir);
+    git_dir.join("HEAD").is_file() && common_dir.join("config").is_file() && common_dir.join("description").is_file()
+}
 
-    if get_repo.work_dir.exists() {
-        if !get_repo.work_dir.is_dir() {
-            return Err(format!("Path: {:?} is dir", get_repo.work_dir));
-        } else if get_repo.git_dir.read_dir().iter().len() != 0 {
+pub fn is_my_git_dir(path: PathBuf) -> bool {
+    path.is_dir() && is_git_dir(&git_dir_of(&path))
+}
+
+/// Creates a repository at `path`; a bare one is the git directory itself, without a working tree.
+pub fn create_new_my_git(path: PathBuf, bare: bool) -> Result<bool, String> {
+    let get_repo = if bare {
+        git::GitRepo::from_dirs(None, path.clone(), true)
+    } else {
+        git::GitRepo::init(path.clone(), true)
+    };
+
+    if path.exists() {
+        if !path.is_dir() {
+            return Err(format!("Path: {:?} is dir", path));
+        } else if (bare && path.read_dir().is_ok_and(|mut v| v.next().is_some()))
+            || (!bare && get_repo.git_dir.read_dir().iter().len() != 0)
+        {
             return Err(format!("Dir: {:?} is not empty", get_repo.git_dir));
         }
     } else {
@@ -70,14 +84,17 @@ pub fn create_new_my_git(path: PathBuf) -> Result<bool, String> {
     let conf = config::get_default_conf();
     let conf_file = get_repo.repo_file("config".to_string(), true)?;
 
-    match conf.write(conf_file) {
+    match config::write_conf(conf_file.clone(), conf) {
         Ok(_) => {}
         Err(e) => return Err(e.to_string()),
     }
+    if bare {
+        config::config_set(&conf_file, "core.bare", "true", false)?;
+    }
 
     match std::fs::File::create(get_repo.repo_file("description".to_string(), false)?) {
         Ok(mut f) => {
-            f.write(
+            f.write_all(
                 "Unnamed repository; edit this file 'description' to name the repository.\n"
                     .as_ref(),
             )
@@ -88,10 +105,71 @@ pub fn create_new_my_git(path: PathBuf) -> Result<bool, String> {
 
     match std::fs::File::create(get_repo.repo_file("HEAD".to_string(), false)?) {
         Ok(mut f) => {
-            f.write("ref: refs/heads/master\n".as_ref()).unwrap();
+            f.write_all("ref: refs/heads/master\n".as_ref()).unwrap();
         }
         Err(e) => return Err(e.to_string()),
     }
 
     Ok(true)
 }
+
+/// Resolves `path`, given relative to the `prefix` directory of the worktree, into a path from
+/// its top. `None` means it leads outside of the worktree.
+pub fn path_from_prefix(prefix: &str, path: &str) -> Option<String> {
+    let mut parts: Vec<&str> = prefix.split('/').filter(|v| !v.is_empty()).collect();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            v => parts.push(v),
+        }
+    }
+    let mut ret = parts.join("/");
+    // A trailing slash says the path is a directory, so it stays.
+    if path.ends_with('/') && !ret.is_empty() {
+        ret.push('/');
+    }
+    Some(ret)
+}
+
+/// Shows a path from the top of the worktree relative to the `prefix` directory, as git prints it.
+pub fn path_to_prefix(prefix: &str, path: &str) -> String {
+    let prefix: Vec<&str> = prefix.split('/').filter(|v| !v.is_empty()).collect();
+    let parts: Vec<&str> = path.split('/').collect();
+    let common = prefix.iter().zip(&parts).take_while(|(a, b)| a == b).count();
+    let ret = format!("{}{}", "../".repeat(prefix.len() - common), parts[common..].join("/"));
+    match ret.as_str() {
+        "" if path.ends_with('/') => "./".to_string(),
+        "" => ".".to_string(),
+        _ => ret,
+    }
+}
+
+/// Quotes a path like git does when it has control characters, quotes, backslashes
+/// or non-ASCII bytes in it, and returns it untouched otherwise.
+pub fn quote_path(path: &str) -> String {
+    let needs_quoting = path.bytes().any(|c| !(0x20..0x7f).contains(&c) || c == b'"' || c == b'\\');
+    if !needs_quoting {
+        return path.to_string();
+    }
+    let mut ret = String::from("\"");
+    for c in path.bytes() {
+        match c {
+            b'\x07' => ret.push_str("\\a"),
+            b'\x08' => ret.push_str("\\b"),
+            b'\t' => ret.push_str("\\t"),
+            b'\n' => ret.push_str("\\n"),
+            b'\x0b' => ret.push_str("\\v"),
+            b'\x0c' => ret.push_str("\\f"),
+            b'\r' => ret.push_str("\\r"),
+            b'"' => ret.push_str("\\\""),
+            b'\\' => ret.push_str("\\\\"),
+            c if !(0x20..0x7f).contains(&c) => ret.push_str(&format!("\\{:03o}", c)),
+            c => ret.push(c as char),
+        }
+    }
+    ret.push('"');
+    ret
+}
@@ -0,0 +1,265 @@
+use crate::helpers::git::GitRepo;
+use crate::helpers::git_objects::git_attributes::{AttrState, GitAttributes};
+use crate::helpers::pkt_line::{pkt_flush, pkt_read_data, pkt_read_lines, pkt_write_data, pkt_write_line};
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+/// Which way content goes through a filter driver.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterDirection {
+    /// From the working tree into a blob.
+    Clean,
+    /// From a blob into the working tree.
+    Smudge,
+}
+
+impl FilterDirection {
+    fn name(&self) -> &'static str {
+        match self {
+            FilterDirection::Clean => "clean",
+            FilterDirection::Smudge => "smudge",
+        }
+    }
+}
+
+/// A `filter.<driver>.process` command that stays up to serve every path of a command.
+#[derive(Debug)]
+pub struct FilterProcess {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: ChildStdout,
+    capabilities: HashSet<String>,
+}
+
+impl FilterProcess {
+    /// Starts `command` and runs the version 2 handshake.
+    fn start(command: &str, work_dir: &std::path::Path) -> Result<Self, String> {
+        let failed = || format!("initialization for subprocess '{}' failed", command);
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .current_dir(work_dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("cannot fork to run subprocess '{}': {}", command, e))?;
+        let stdin = child.stdin.take().ok_or_else(failed)?;
+        let stdout = child.stdout.take().ok_or_else(failed)?;
+        let mut process = FilterProcess {
+            child,
+            stdin: Some(stdin),
+            stdout,
+            capabilities: HashSet::new(),
+        };
+        process.handshake().map_err(|_| failed())?;
+        Ok(process)
+    }
+
+    fn handshake(&mut self) -> io::Result<()> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let stdin = self.stdin.as_mut().ok_or_else(|| invalid("closed"))?;
+        pkt_write_line(stdin, "git-filter-client")?;
+        pkt_write_line(stdin, "version=2")?;
+        pkt_flush(stdin)?;
+        stdin.flush()?;
+
+        let welcome = pkt_read_lines(&mut self.stdout)?;
+        if welcome.first().map(|v| v.as_str()) != Some("git-filter-server") {
+            return Err(invalid("unexpected line, expected git-filter-server"));
+        }
+        if !welcome.iter().any(|v| v == "version=2") {
+            return Err(invalid("unexpected version"));
+        }
+
+        pkt_write_line(stdin, "capability=clean")?;
+        pkt_write_line(stdin, "capability=smudge")?;
+        pkt_flush(stdin)?;
+        stdin.flush()?;
+        self.capabilities = pkt_read_lines(&mut self.stdout)?
+            .iter()
+            .filter_map(|v| v.strip_prefix("capability=").map(|v| v.to_string()))
+            .collect();
+        Ok(())
+    }
+
+    /// Sends one path through the process. `Ok(None)` means the filter declined it.
+    fn run(&mut self, direction: FilterDirection, path: &str, data: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        if !self.capabilities.contains(direction.name()) {
+            return Ok(None);
+        }
+        let stdin = self
+            .stdin
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "closed"))?;
+        pkt_write_line(stdin, &format!("command={}", direction.name()))?;
+        pkt_write_line(stdin, &format!("pathname={}", path))?;
+        pkt_flush(stdin)?;
+        pkt_write_data(stdin, data)?;
+        pkt_flush(stdin)?;
+        stdin.flush()?;
+
+        let status = |lines: Vec<String>| {
+            lines
+                .iter()
+                .rev()
+                .find_map(|v| v.strip_prefix("status=").map(|v| v.to_string()))
+        };
+        match status(pkt_read_lines(&mut self.stdout)?).as_deref() {
+            Some("success") => {}
+            Some("abort") => {
+                // The filter gives up on this command for the rest of the session.
+                self.capabilities.remove(direction.name());
+                return Ok(None);
+            }
+            _ => return Ok(None),
+        }
+        let output = pkt_read_data(&mut self.stdout)?;
+        // An empty list keeps the earlier status; the filter can still fail after sending content.
+        match status(pkt_read_lines(&mut self.stdout)?).as_deref() {
+            None | Some("success") => Ok(Some(output)),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Drop for FilterProcess {
+    fn drop(&mut self) {
+        // Closing its input tells the filter we are done.
+        self.stdin.take();
+        let _ = self.child.wait();
+    }
+}
+
+/// Quotes `value` for the shell, as git does for `%f`.
+fn sq_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+impl GitRepo {
+    /// Pipes `data` through a one-shot `filter.<driver>.clean`/`smudge` command.
+    fn run_filter_command(&self, command: &str, path: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+        let failed = || format!("external filter '{}' failed", command);
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command.replace("%f", &sq_quote(path)))
+            .current_dir(&self.work_dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("cannot fork to run external filter '{}': {}", command, e))?;
+
+        // Feed the input from another thread so a filter that writes early can't deadlock us.
+        let mut stdin = child.stdin.take().ok_or_else(failed)?;
+        let input = data.to_vec();
+        let writer = std::thread::spawn(move || stdin.write_all(&input));
+        let mut output = Vec::new();
+        child
+            .stdout
+            .take()
+            .ok_or_else(failed)?
+            .read_to_end(&mut output)
+            .map_err(|_| failed())?;
+        let written = writer.join().map_err(|_| failed())?;
+        let status = child.wait().map_err(|_| failed())?;
+        if !status.success() {
+            eprintln!("error: external filter '{}' failed {}", command, status.code().unwrap_or(-1));
+            return Err(failed());
+        }
+        // Like git, a filter may exit without reading all of its input.
+        match written {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => {
+                Err(format!("cannot feed the input to external filter '{}'", command))
+            }
+            _ => Ok(output),
+        }
+    }
+
+    /// Sends `data` to the long-running filter `command`, starting it on first use.
+    fn run_filter_process(
+        &self,
+        command: &str,
+        direction: FilterDirection,
+        path: &str,
+        data: &[u8],
+    ) -> Result<Option<Vec<u8>>, String> {
+        let mut processes = self.filter_processes.borrow_mut();
+        if !processes.contains_key(command) {
+            let process = FilterProcess::start(command, &self.work_dir)?;
+            processes.insert(command.to_string(), process);
+        }
+        let process = processes.get_mut(command).unwrap();
+        match process.run(direction, path, data) {
+            Ok(v) => Ok(v),
+            Err(_) => {
+                // The process is out of step with us now, so the next path gets a fresh one.
+                processes.remove(command);
+                Err(format!("external filter '{}' failed", command))
+            }
+        }
+    }
+
+    /// Runs the `filter` attribute driver of `path` over `data`, if it has one.
+    ///
+    /// Failures keep the content as it is unless `filter.<driver>.required` is set.
+    pub fn apply_filter(
+        &self,
+        attributes: &GitAttributes,
+        path: &str,
+        data: Vec<u8>,
+        direction: FilterDirection,
+    ) -> Result<Vec<u8>, String> {
+        let driver = match attributes.get(path, "filter") {
+            AttrState::Value(v) => v,
+            _ => return Ok(data),
+        };
+        let key = |name: &str| format!("filter.{}.{}", driver, name);
+        let process = self.config_get(&key("process")).filter(|v| !v.is_empty());
+        let command = self.config_get(&key(direction.name())).filter(|v| !v.is_empty());
+        // `lfs` is handled in-process unless a driver of that name is configured.
+        let builtin_lfs = driver == "lfs" && process.is_none() && command.is_none();
+        let required = self.config_bool(&key("required")).unwrap_or(builtin_lfs);
+
+        let result = match (process, command) {
+            (Some(process), _) => self.run_filter_process(&process, direction, path, &data),
+            (None, Some(command)) => self.run_filter_command(&command, path, &data).map(Some),
+            (None, None) if builtin_lfs => match direction {
+                FilterDirection::Clean => self.lfs_clean(data.clone()).map(Some),
+                FilterDirection::Smudge => self.lfs_smudge(path, data.clone()).map(Some),
+            },
+            (None, None) => Ok(None),
+        };
+        match result {
+            Ok(Some(v)) => Ok(v),
+            Ok(None) if !required => Ok(data),
+            Err(e) if !required => {
+                eprintln!("error: {}", e);
+                Ok(data)
+            }
+            result => {
+                if let Err(e) = result {
+                    eprintln!("error: {}", e);
+                }
+                Err(format!("{}: {} filter '{}' failed", path, direction.name(), driver))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::helpers::file::create_new_my_git;
+    use crate::helpers::git::GitRepo;
+
+    #[test]
+    fn filter_may_exit_without_reading_its_input() {
+        let dir = std::env::temp_dir().join(format!("mygit-filter-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        create_new_my_git(dir.clone(), false).unwrap();
+        let repo = GitRepo::from_dirs(Some(dir.clone()), dir.join(".git"), false);
+        let data = vec![b'a'; 1 << 20];
+        assert_eq!(repo.run_filter_command("echo done", "a.txt", &data), Ok(b"done\n".to_vec()));
+        assert!(repo.run_filter_command("exit 1", "a.txt", &data).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
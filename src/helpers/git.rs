@@ -1,33 +1,50 @@
 use crate::helpers::config;
-use crate::helpers::file::is_my_git_dir;
+use crate::helpers::config::{ConfigScope, GitConfig};
+use crate::helpers::file::{common_dir_of, git_dir_of, is_git_dir, is_my_git_dir};
 use crate::helpers::git_objects::commit::GitCommit;
-use crate::helpers::git_objects::git_object::GitObject;
-use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone, Utc};
-use configparser::ini::Ini;
-use std::collections::HashSet;
-use std::path::PathBuf;
+use chrono::{DateTime, FixedOffset, TimeZone};
+use crate::helpers::filter::FilterProcess;
+use crate::helpers::pack::Pack;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::path::{Path, PathBuf};
+use std::env;
 
 #[derive(Debug, Clone)]
 pub struct GitRepo {
+    /// The top of the working tree; the git directory itself in a bare repository.
     pub work_dir: PathBuf,
+    /// Whether there is no working tree at all.
+    pub bare: bool,
     pub git_dir: PathBuf,
-    pub config: Option<Ini>,
+    /// Where objects, refs and config are; the git directory itself unless this is a linked worktree.
+    pub common_dir: PathBuf,
+    pub config: GitConfig,
+    /// Long-running filter processes, keyed by command, shared by clones of the handle.
+    pub(crate) filter_processes: Rc<RefCell<HashMap<String, FilterProcess>>>,
+    pub(crate) packs: Rc<RefCell<Option<Rc<Vec<Pack>>>>>,
 }
 
 impl GitRepo {
     pub fn init(path: PathBuf, force: bool) -> Self {
-        let work_dir = path.clone();
-        let git_dir = path.clone().join(".git");
+        let git_dir = git_dir_of(&path);
+        Self::from_dirs(Some(path), git_dir, force)
+    }
+
+    /// Opens the repository at `git_dir`, bare when there is no `work_dir`.
+    pub fn from_dirs(work_dir: Option<PathBuf>, git_dir: PathBuf, force: bool) -> Self {
+        let common_dir = common_dir_of(&git_dir);
 
-        if !is_my_git_dir(path.clone().into()) && !force {
+        if !is_git_dir(&git_dir) && !force {
             eprintln!(
                 "Dir: {:?} is not a git repository need to run mygit init",
-                path.to_str()
+                work_dir.as_ref().unwrap_or(&git_dir).to_str()
             );
             std::process::exit(1);
         }
 
-        let cf = match config::read_conf(git_dir.clone().join("config")) {
+        let cf = match config::read_conf(common_dir.join("config"), ConfigScope::Local, Some(&git_dir)) {
             Ok(o) => Some(o),
             Err(e) => {
                 if !force {
@@ -38,9 +55,8 @@ impl GitRepo {
             }
         };
 
-        if !force && cf.is_some() {
-            let version = cf.clone().unwrap().get("core", "repositoryformatversion");
-            match version {
+        if let (false, Some(cf)) = (force, &cf) {
+            match cf.get("core.repositoryformatversion") {
                 None => {
                     eprintln!("missing mandatory attribute section: core attribute: repositoryformatversion");
                     std::process::exit(1);
@@ -54,38 +70,125 @@ impl GitRepo {
             }
         }
 
-        let git_repo = Self {
-            work_dir,
-            git_dir,
-            config: cf,
+        let config = match config::read_layers(Some(&git_dir)) {
+            Ok(v) => v,
+            Err(e) => {
+                if !force {
+                    eprintln!("fatal: {}", e);
+                    std::process::exit(128);
+                }
+                GitConfig::default()
+            }
         };
 
-        git_repo
+        Self {
+            bare: work_dir.is_none(),
+            work_dir: work_dir.unwrap_or(git_dir.clone()),
+            git_dir,
+            common_dir,
+            config,
+            filter_processes: Rc::default(),
+            packs: Rc::default(),
+        }
     }
 
+    /// Finds the repository `path` belongs to, the way git does: `GIT_DIR` if set, else the
+    /// first directory up from `path` that has a `.git` or is a bare repository itself,
+    /// without going up into the directories of `GIT_CEILING_DIRECTORIES`.
+    ///
+    /// `GIT_WORK_TREE`, then `core.worktree`, say where the working tree is.
     pub fn repo_find(path: PathBuf) -> Option<GitRepo> {
-        let git_path = path.clone().join(".git");
-        if git_path.exists() {
-            return Some(GitRepo::init(path, false));
+        let cwd = env::current_dir().ok()?;
+        let configured_work_tree = |git_dir: &Path| {
+            if let Some(v) = env::var_os("GIT_WORK_TREE") {
+                return Some(Some(cwd.join(v)));
+            }
+            let config = config::read_conf(common_dir_of(git_dir).join("config"), ConfigScope::Local, Some(git_dir)).ok()?;
+            if let Some(v) = config.get("core.worktree") {
+                return Some(Some(git_dir.join(v)));
+            }
+            config.get_bool("core.bare").filter(|v| *v).map(|_| None)
+        };
+
+        if let Some(git_dir) = env::var_os("GIT_DIR") {
+            let git_dir = cwd.join(git_dir);
+            if !is_git_dir(&git_dir) {
+                eprintln!("fatal: not a git repository: '{}'", env::var("GIT_DIR").unwrap_or_default());
+                std::process::exit(128);
+            }
+            // Without a configured working tree, the current directory is its top.
+            let work_dir = configured_work_tree(&git_dir).unwrap_or(Some(cwd.clone()));
+            return Some(GitRepo::from_dirs(work_dir, git_dir, false));
         }
 
-        let parent = match path.parent() {
-            None => {
+        let ceilings: Vec<PathBuf> = env::var("GIT_CEILING_DIRECTORIES")
+            .unwrap_or_default()
+            .split(':')
+            .filter(|v| Path::new(v).is_absolute())
+            .map(|v| std::fs::canonicalize(v).unwrap_or(PathBuf::from(v)))
+            .collect();
+        let mut dir = std::fs::canonicalize(cwd.join(path)).ok()?;
+        loop {
+            if dir.join(".git").exists() {
+                let git_dir = git_dir_of(&dir);
+                return Some(match env::var_os("GIT_WORK_TREE") {
+                    Some(v) => GitRepo::from_dirs(Some(cwd.join(v)), git_dir, false),
+                    None => GitRepo::init(dir, false),
+                });
+            }
+            if is_git_dir(&dir) {
+                // A bare repository, or the inside of a `.git`: no working tree unless one is configured.
+                let work_dir = configured_work_tree(&dir).flatten();
+                return Some(GitRepo::from_dirs(work_dir, dir, false));
+            }
+            let parent = dir.parent()?.to_path_buf();
+            if ceilings.contains(&parent) {
                 return None;
             }
-            Some(v) => v,
-        };
-        if path.eq(parent) {
-            return None;
+            dir = parent;
+        }
+    }
+
+    /// Where the current directory is inside the working tree, as `a/b`, empty at its top.
+    pub fn cwd_prefix(&self) -> String {
+        let cwd = env::current_dir().and_then(std::fs::canonicalize);
+        let top = std::fs::canonicalize(&self.work_dir);
+        match (cwd, top) {
+            (Ok(cwd), Ok(top)) => cwd
+                .strip_prefix(&top)
+                .map(|v| v.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            _ => String::new(),
+        }
+    }
+
+    /// Opens the repository whose worktree is exactly `path`, if there is one.
+    pub fn open(path: &Path) -> Option<GitRepo> {
+        if is_my_git_dir(path.to_path_buf()) {
+            Some(GitRepo::init(path.to_path_buf(), false))
+        } else {
+            None
         }
+    }
+
+    /// Looks a `section.key` or `section.subsection.key` value up in the configuration.
+    pub fn config_get(&self, key: &str) -> Option<String> {
+        self.config.get(key)
+    }
 
-        GitRepo::repo_find(parent.to_path_buf())
+    /// Reads a boolean the way git does (`true`/`yes`/`on`/`1` and their opposites).
+    pub fn config_bool(&self, key: &str) -> Option<bool> {
+        self.config.get_bool(key)
     }
 
     /// Returns path from git directory and file in it
     ///
     pub fn repo_path(&self, file_path: String) -> PathBuf {
-        let mut git_dir = self.git_dir.to_owned();
+        let mut git_dir = if is_per_worktree(&file_path) {
+            self.git_dir.to_owned()
+        } else {
+            self.common_dir.to_owned()
+        };
         git_dir.push(file_path);
         git_dir
     }
@@ -124,7 +227,13 @@ impl GitRepo {
         Err("Try that again late".to_string())
     }
 
-    pub fn log(&self, sha: String, mut seen: &mut HashSet<String>, mut output: &mut String) {
+    pub fn log(
+        &self,
+        sha: String,
+        seen: &mut HashSet<String>,
+        output: &mut String,
+        show_signature: bool,
+    ) {
         if seen.contains(&sha) {
             return;
         }
@@ -144,7 +253,6 @@ impl GitRepo {
                 return;
             }
         };
-        let short_sha = &sha[0..8];
         let mut message = commit
             .kvlm
             .get(b"None".as_ref())
@@ -163,6 +271,12 @@ impl GitRepo {
         }
 
         output.push_str(&format!("commit: {}\n", sha));
+        if show_signature && commit.kvlm.contains_key(b"gpgsig".as_ref()) {
+            match self.verify_commit(&sha) {
+                Ok(check) => output.push_str(&format!("{}\n", check.message)),
+                Err(e) => output.push_str(&format!("error: {}\n", e)),
+            }
+        }
         if let Some(author_info) = commit.kvlm.get(b"author".as_ref()) {
             if let Some(author_line) = author_info.first() {
                 let author_str = String::from_utf8_lossy(author_line);
@@ -176,13 +290,13 @@ impl GitRepo {
 
                     if let Ok(timestamp) = timestamp_str.parse::<i64>() {
                         if let Some(naive_datetime) =
-                            NaiveDateTime::from_timestamp_opt(timestamp, 0)
+                            DateTime::from_timestamp(timestamp, 0).map(|v| v.naive_utc())
                         {
                             if let Ok(timezone_offset) = timezone_str.parse::<i32>() {
                                 let hours = timezone_offset / 100;
                                 let minutes = timezone_offset % 100;
                                 let offset = FixedOffset::east_opt(hours * 3600 + minutes * 60)
-                                    .unwrap_or(FixedOffset::east(0));
+                                    .unwrap_or(FixedOffset::east_opt(0).unwrap());
 
                                 let datetime = offset.from_utc_datetime(&naive_datetime);
 
@@ -204,8 +318,21 @@ impl GitRepo {
         if let Some(parents) = commit.kvlm.get(&b"parent".to_vec()) {
             for parent in parents {
                 let p = String::from_utf8_lossy(parent).to_string();
-                self.log(p, seen, output);
+                self.log(p, seen, output, show_signature);
             }
         }
     }
 }
+
+/// Tells if a path of the git directory belongs to one worktree rather than being shared:
+/// the index, `HEAD` and the other all-caps files at the top, and a few ref namespaces.
+fn is_per_worktree(path: &str) -> bool {
+    let path = path.trim_start_matches('/');
+    let top_level_file = !path.contains('/') && path.chars().all(|c| c.is_ascii_uppercase() || c == '_');
+    path.is_empty()
+        || top_level_file
+        || matches!(path, "index" | "logs/HEAD" | "info/sparse-checkout" | "config.worktree")
+        || ["refs/bisect/", "refs/worktree/", "refs/rewritten/"]
+            .iter()
+            .any(|v| path.starts_with(v))
+}
@@ -1,5 +1,4 @@
 use crate::helpers::git_objects::git_object::GitObject;
-use std::any::Any;
 
 #[derive(Debug, Clone)]
 pub struct GitBlob {
@@ -17,14 +16,8 @@ impl GitBlob {
 }
 
 impl GitObject for GitBlob {
-    fn serialize(&self) -> String {
-        self.data
-            .iter()
-            .filter(|&byte| {
-                byte.is_ascii() && (byte.is_ascii_graphic() || byte.is_ascii_whitespace())
-            })
-            .map(|&byte| byte as char)
-            .collect::<String>()
+    fn serialize(&self) -> Vec<u8> {
+        self.data.clone()
     }
     fn deserialize(&self) -> Vec<u8> {
         self.data.clone()
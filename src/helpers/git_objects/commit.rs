@@ -1,7 +1,8 @@
+use crate::helpers::git::GitRepo;
 use crate::helpers::git_objects::git_object::GitObject;
 use crate::helpers::kvlm::{kvlm_parse, kvlm_serialize};
 use ordermap::OrderMap;
-use std::any::Any;
+use std::collections::{HashSet, VecDeque};
 use std::hash::RandomState;
 
 #[derive(Debug, Clone)]
@@ -13,13 +14,17 @@ pub struct GitCommit {
 
 impl GitCommit {
     pub fn new(data: Vec<u8>) -> Self {
-        let borrowed_kvlm = kvlm_parse(&data, None, None);
+        let kvlm = if !data.is_empty() {
+            let borrowed_kvlm = kvlm_parse(&data, None, None);
 
-        // Convert borrowed OrderMap to owned OrderMap
-        let kvlm: OrderMap<Vec<u8>, Vec<Vec<u8>>, RandomState> = borrowed_kvlm
-            .into_iter()
-            .map(|(k, v)| (k.to_vec(), v))
-            .collect();
+            // Convert borrowed OrderMap to owned OrderMap
+            borrowed_kvlm
+                .into_iter()
+                .map(|(k, v)| (k.to_vec(), v))
+                .collect()
+        } else {
+            OrderMap::new()
+        };
         GitCommit {
             fmt: b"commit".to_vec(),
             data,
@@ -29,7 +34,7 @@ impl GitCommit {
 }
 
 impl GitObject for GitCommit {
-    fn serialize(&self) -> String {
+    fn serialize(&self) -> Vec<u8> {
         kvlm_serialize(&self.kvlm)
     }
 
@@ -45,3 +50,84 @@ impl GitObject for GitCommit {
         self.fmt.clone()
     }
 }
+
+impl GitRepo {
+    /// Returns the parents of a commit, or nothing when it can't be read as a commit.
+    pub fn commit_parents(&self, sha: &str) -> Vec<String> {
+        let obj = match self.object_read(sha.to_string()) {
+            Ok(v) => v,
+            Err(_) => return Vec::new(),
+        };
+        match obj.as_ref().as_any().downcast_ref::<GitCommit>() {
+            None => Vec::new(),
+            Some(commit) => commit
+                .kvlm
+                .get(b"parent".as_ref())
+                .map(|v| {
+                    v.iter()
+                        .map(|v| String::from_utf8_lossy(v).into_owned())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Writes a commit of `tree` on top of `parents`, authored and committed by the
+    /// configured identity, and signs it when `sign` is set.
+    pub fn commit_create(
+        &self,
+        tree: &str,
+        parents: &[String],
+        message: &str,
+        sign: bool,
+    ) -> Result<String, String> {
+        let author = self.ident("AUTHOR")?;
+        let committer = self.ident("COMMITTER")?;
+
+        let mut commit = GitCommit::new(vec![]);
+        commit
+            .kvlm
+            .insert(b"tree".to_vec(), vec![tree.as_bytes().to_vec()]);
+        if !parents.is_empty() {
+            commit.kvlm.insert(
+                b"parent".to_vec(),
+                parents.iter().map(|v| v.as_bytes().to_vec()).collect(),
+            );
+        }
+        commit
+            .kvlm
+            .insert(b"author".to_vec(), vec![author.to_string().into_bytes()]);
+        commit
+            .kvlm
+            .insert(b"committer".to_vec(), vec![committer.to_string().into_bytes()]);
+
+        if sign {
+            // The signature covers the commit exactly as it looks without the gpgsig header.
+            let mut payload = commit.kvlm.clone();
+            payload.insert(b"None".to_vec(), vec![message.as_bytes().to_vec()]);
+            let signature = self.sign_payload(&kvlm_serialize(&payload))?;
+            commit.kvlm.insert(
+                b"gpgsig".to_vec(),
+                vec![signature.trim_end_matches('\n').as_bytes().to_vec()],
+            );
+        }
+        commit
+            .kvlm
+            .insert(b"None".to_vec(), vec![message.as_bytes().to_vec()]);
+
+        GitRepo::object_write(Some(self), Box::new(commit))
+    }
+
+    /// Collects every commit reachable from `sha`, including `sha` itself.
+    pub fn commit_ancestors(&self, sha: &str) -> HashSet<String> {
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::from([sha.to_string()]);
+        while let Some(sha) = queue.pop_front() {
+            if !seen.insert(sha.clone()) {
+                continue;
+            }
+            queue.extend(self.commit_parents(&sha));
+        }
+        seen
+    }
+}
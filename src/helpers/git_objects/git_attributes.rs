@@ -0,0 +1,394 @@
+use crate::helpers::git::GitRepo;
+use crate::helpers::wildmatch::wildmatch;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// The macro git defines for every repository.
+const BUILTIN_MACROS: &str = "[attr]binary -diff -merge -text";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttrState {
+    /// `attr`
+    Set,
+    /// `-attr`
+    Unset,
+    /// `attr=value`
+    Value(String),
+    /// `!attr`, or no rule mentions the attribute at all.
+    Unspecified,
+}
+
+impl std::fmt::Display for AttrState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttrState::Set => write!(f, "set"),
+            AttrState::Unset => write!(f, "unset"),
+            AttrState::Value(v) => write!(f, "{}", v),
+            AttrState::Unspecified => write!(f, "unspecified"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct AttrRule {
+    pattern: String,
+    attrs: Vec<(String, AttrState)>,
+}
+
+#[derive(Debug)]
+pub struct GitAttributes {
+    /// `core.attributesFile`, weaker than any `.gitattributes`.
+    global: Vec<AttrRule>,
+    /// `.gitattributes` rules keyed by the directory holding the file, "" for the top.
+    scoped: HashMap<String, Vec<AttrRule>>,
+    /// `info/attributes`, stronger than any `.gitattributes`.
+    info: Vec<AttrRule>,
+    macros: HashMap<String, Vec<(String, AttrState)>>,
+    /// Attribute names in the order they were first seen, which `check-attr -a` follows.
+    names: Vec<String>,
+}
+
+impl GitAttributes {
+    fn new() -> Self {
+        let mut ret = Self {
+            global: Vec::new(),
+            scoped: HashMap::new(),
+            info: Vec::new(),
+            macros: HashMap::new(),
+            names: Vec::new(),
+        };
+        ret.parse(BUILTIN_MACROS, "[builtin]", true);
+        ret
+    }
+
+    fn register(&mut self, name: &str) {
+        if !self.names.iter().any(|v| v == name) {
+            self.names.push(name.to_string());
+        }
+    }
+
+    /// Parses an attributes file, defining its macros on the way when `macros` is
+    /// set, since only top-level files may define them.
+    fn parse(&mut self, content: &str, source: &str, macros: bool) -> Vec<AttrRule> {
+        let mut rules = Vec::new();
+        for (number, raw) in content.lines().enumerate() {
+            let line = raw.trim_start();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (pattern, rest) = match parse_pattern(line) {
+                Some(v) => v,
+                None => continue,
+            };
+            let macro_name = pattern.strip_prefix("[attr]");
+            if let Some(name) = macro_name {
+                if !macros {
+                    eprintln!("{} not allowed: {}:{}", raw.trim(), source, number + 1);
+                    continue;
+                }
+                if !attr_name_valid(name) {
+                    continue;
+                }
+                self.register(name);
+            }
+            let attrs: Vec<(String, AttrState)> = rest
+                .split_whitespace()
+                .filter_map(parse_attr)
+                .collect();
+            for (name, _) in &attrs {
+                self.register(name);
+            }
+
+            if let Some(name) = macro_name {
+                self.macros.insert(name.to_string(), attrs);
+                continue;
+            }
+            // Negative patterns are not allowed in attribute files.
+            if pattern.starts_with('!') {
+                continue;
+            }
+            rules.push(AttrRule { pattern, attrs });
+        }
+        rules
+    }
+
+    /// Fills the attributes of the rules matching `path` that are still unassigned,
+    /// going from the last rule to the first as later lines win.
+    fn fill(
+        &self,
+        rules: &[AttrRule],
+        path: &str,
+        dir: &str,
+        assigned: &mut HashMap<String, AttrState>,
+    ) {
+        for rule in rules.iter().rev() {
+            if !attr_matches(&rule.pattern, path, dir) {
+                continue;
+            }
+            self.fill_attrs(&rule.attrs, assigned);
+        }
+    }
+
+    fn fill_attrs(&self, attrs: &[(String, AttrState)], assigned: &mut HashMap<String, AttrState>) {
+        for (name, state) in attrs.iter().rev() {
+            if assigned.contains_key(name) {
+                continue;
+            }
+            assigned.insert(name.clone(), state.clone());
+            // Setting a macro sets everything it stands for.
+            if *state == AttrState::Set {
+                if let Some(expansion) = self.macros.get(name) {
+                    self.fill_attrs(expansion, assigned);
+                }
+            }
+        }
+    }
+
+    fn collect(&self, path: &str) -> HashMap<String, AttrState> {
+        let mut assigned = HashMap::new();
+        self.fill(&self.info, path, "", &mut assigned);
+
+        // The closest `.gitattributes` wins over the ones of the parent directories.
+        for dir in Path::new(path).ancestors().skip(1) {
+            let dir = dir.to_string_lossy();
+            if let Some(rules) = self.scoped.get(dir.as_ref()) {
+                self.fill(rules, path, &dir, &mut assigned);
+            }
+        }
+
+        self.fill(&self.global, path, "", &mut assigned);
+        assigned
+    }
+
+    /// The state of each attribute of `names` for `path`, relative to the top of the repository.
+    pub fn check(&self, path: &str, names: &[String]) -> Vec<(String, AttrState)> {
+        let assigned = self.collect(path);
+        names
+            .iter()
+            .map(|v| {
+                let state = assigned.get(v).cloned().unwrap_or(AttrState::Unspecified);
+                (v.clone(), state)
+            })
+            .collect()
+    }
+
+    /// Every attribute that is set, unset or has a value for `path`.
+    pub fn check_all(&self, path: &str) -> Vec<(String, AttrState)> {
+        let assigned = self.collect(path);
+        self.names
+            .iter()
+            .filter_map(|v| match assigned.get(v) {
+                None | Some(AttrState::Unspecified) => None,
+                Some(state) => Some((v.clone(), state.clone())),
+            })
+            .collect()
+    }
+
+    /// Shortcut for the state of a single attribute.
+    pub fn get(&self, path: &str, name: &str) -> AttrState {
+        self.collect(path)
+            .remove(name)
+            .unwrap_or(AttrState::Unspecified)
+    }
+}
+
+fn attr_name_valid(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-._".contains(c))
+}
+
+fn parse_attr(raw: &str) -> Option<(String, AttrState)> {
+    let (name, state) = if let Some(name) = raw.strip_prefix('-') {
+        (name, AttrState::Unset)
+    } else if let Some(name) = raw.strip_prefix('!') {
+        (name, AttrState::Unspecified)
+    } else if let Some((name, value)) = raw.split_once('=') {
+        (name, AttrState::Value(value.to_string()))
+    } else {
+        (raw, AttrState::Set)
+    };
+    if !attr_name_valid(name) {
+        return None;
+    }
+    Some((name.to_string(), state))
+}
+
+/// Reads the pattern at the start of a line, which may be C-quoted.
+fn parse_pattern(line: &str) -> Option<(String, &str)> {
+    if let Some(rest) = line.strip_prefix('"') {
+        let mut pattern = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Some((pattern, &rest[i + 1..])),
+                '\\' => match chars.next()?.1 {
+                    'n' => pattern.push('\n'),
+                    't' => pattern.push('\t'),
+                    c => pattern.push(c),
+                },
+                c => pattern.push(c),
+            }
+        }
+        return None;
+    }
+    let end = line.find(char::is_whitespace).unwrap_or(line.len());
+    Some((line[..end].to_string(), &line[end..]))
+}
+
+/// Matches a pattern from the attributes file of `dir` against `path`.
+///
+/// Patterns without a slash match the file name at any depth below `dir`,
+/// the others are anchored to `dir`.
+fn attr_matches(pattern: &str, path: &str, dir: &str) -> bool {
+    // Directory patterns never match in attribute files.
+    if pattern.ends_with('/') {
+        return false;
+    }
+    let relative = if dir.is_empty() {
+        path
+    } else {
+        match path.strip_prefix(dir).and_then(|v| v.strip_prefix('/')) {
+            Some(v) => v,
+            None => return false,
+        }
+    };
+    // The same rules as `.gitignore`: a leading slash only anchors the pattern.
+    if pattern.contains('/') {
+        wildmatch(pattern.strip_prefix('/').unwrap_or(pattern), relative, true)
+    } else {
+        wildmatch(pattern, relative.rsplit('/').next().unwrap_or(relative), false)
+    }
+}
+
+impl GitRepo {
+    /// Builds the attribute rules out of the `.gitattributes` contents of each directory,
+    /// `core.attributesFile` and `info/attributes`.
+    fn gitattributes_load(&self, files: BTreeMap<String, Vec<u8>>) -> GitAttributes {
+        let mut ret = GitAttributes::new();
+
+        let global = self
+            .config_get("core.attributesFile")
+            .map(|v| match (v.strip_prefix("~/"), env::var("HOME")) {
+                (Some(rest), Ok(home)) => PathBuf::from(home).join(rest),
+                _ => PathBuf::from(v),
+            })
+            .or_else(|| match env::var("XDG_CONFIG_HOME") {
+                Ok(v) if !v.is_empty() => Some(PathBuf::from(v).join("git/attributes")),
+                _ => env::var("HOME")
+                    .ok()
+                    .map(|v| PathBuf::from(v).join(".config/git/attributes")),
+            });
+        if let Some(content) = global.and_then(|v| std::fs::read_to_string(v).ok()) {
+            ret.global = ret.parse(&content, "core.attributesFile", true);
+        }
+
+        for (dir, content) in files {
+            let source = Path::new(&dir).join(".gitattributes");
+            let rules = ret.parse(
+                &String::from_utf8_lossy(&content),
+                &source.to_string_lossy(),
+                dir.is_empty(),
+            );
+            ret.scoped.insert(dir, rules);
+        }
+
+        if let Ok(content) = std::fs::read_to_string(self.repo_path("info/attributes".into())) {
+            ret.info = ret.parse(&content, "info/attributes", true);
+        }
+        ret
+    }
+
+    /// Reads the attribute rules of the repository. `.gitattributes` files are taken
+    /// from the working tree, falling back to the index, or the other way round with
+    /// `index_first` as git does when checking files out.
+    pub fn gitattributes_read(&self, index_first: bool) -> GitAttributes {
+        let index = self.index_read();
+        let mut index_files = HashMap::new();
+        let mut dirs = BTreeSet::from([String::new()]);
+        for entry in &index.entries {
+            let mut dir = Path::new(&entry.name).parent();
+            while let Some(d) = dir {
+                dirs.insert(d.to_string_lossy().into_owned());
+                dir = d.parent();
+            }
+            if entry.name == ".gitattributes" || entry.name.ends_with("/.gitattributes") {
+                let dir_name = Path::new(&entry.name)
+                    .parent()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned();
+                index_files.insert(dir_name, entry.sha.clone());
+            }
+        }
+
+        // Untracked directories can have attributes files of their own too, unless they are ignored.
+        let ignore = self.gitignore_read();
+        let mut untracked = Vec::new();
+        let mut pending = vec![String::new()];
+        while let Some(current) = pending.pop() {
+            let entries = match std::fs::read_dir(self.work_dir.join(&current)) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if entry.file_name() == ".git" || !path.is_dir() || path.is_symlink() {
+                    continue;
+                }
+                let name = match current.is_empty() {
+                    true => entry.file_name().to_string_lossy().into_owned(),
+                    false => format!("{}/{}", current, entry.file_name().to_string_lossy()),
+                };
+                if dirs.contains(&name) {
+                    pending.push(name);
+                } else if !ignore.is_ignored(&name, true) {
+                    if path.join(".gitattributes").is_file() {
+                        untracked.push(name.clone());
+                    }
+                    pending.push(name);
+                }
+            }
+        }
+        dirs.extend(untracked);
+
+        let mut files = BTreeMap::new();
+        for dir in dirs {
+            let from_worktree = || std::fs::read(self.work_dir.join(&dir).join(".gitattributes")).ok();
+            let from_index = || {
+                index_files
+                    .get(&dir)
+                    .and_then(|sha| self.object_read(sha.clone()).ok())
+                    .map(|v| v.data())
+            };
+            let content = if index_first {
+                from_index().or_else(from_worktree)
+            } else {
+                from_worktree().or_else(from_index)
+            };
+            if let Some(content) = content {
+                files.insert(dir, content);
+            }
+        }
+        self.gitattributes_load(files)
+    }
+
+    /// Reads the attribute rules from the `.gitattributes` files of a tree, which is
+    /// what applies while that tree is being checked out.
+    pub fn gitattributes_read_tree(&self, tree_sha: &str) -> GitAttributes {
+        let mut files = BTreeMap::new();
+        for (path, leaf) in self.tree_leafs_flat(tree_sha, "") {
+            let dir = match path.strip_suffix(".gitattributes") {
+                Some(v) if v.is_empty() || v.ends_with('/') => v.trim_end_matches('/').to_string(),
+                _ => continue,
+            };
+            if let Ok(obj) = self.object_read(leaf.sha) {
+                files.insert(dir, obj.data());
+            }
+        }
+        self.gitattributes_load(files)
+    }
+}
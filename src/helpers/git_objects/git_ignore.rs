@@ -1,136 +1,227 @@
 use crate::helpers::git::GitRepo;
-use glob::Pattern;
+use crate::helpers::wildmatch::wildmatch;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::env;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Cursor, Lines};
 use std::path::{Path, PathBuf};
 
-#[derive(Debug)]
-pub struct GitIgnore {
-    absolute: Vec<(String, bool)>,
-    scoped: HashMap<String, Vec<(String, bool)>>,
+/// One pattern line of an ignore file.
+#[derive(Debug, Clone)]
+pub struct IgnoreRule {
+    /// The pattern as written, for `check-ignore -v`.
+    pub pattern: String,
+    pub negated: bool,
+    /// The file the rule comes from and its line number.
+    pub source: String,
+    pub line: usize,
+    glob: String,
+    dir_only: bool,
+    /// Patterns without a slash match the file name at any depth.
+    basename: bool,
+    /// The directory of the `.gitignore`, relative to the top of the worktree.
+    base: String,
 }
 
-impl GitIgnore {
-    fn new() -> Self {
-        Self {
-            absolute: Vec::new(),
-            scoped: HashMap::new(),
+impl IgnoreRule {
+    fn parse(raw: &str, source: &str, line: usize, base: &str) -> Option<Self> {
+        if raw.is_empty() || raw.starts_with('#') {
+            return None;
+        }
+        let pattern = trim_trailing_spaces(raw);
+        let (negated, glob) = match pattern.strip_prefix('!') {
+            Some(v) => (true, v),
+            None => (false, pattern),
+        };
+        let (dir_only, glob) = match glob.strip_suffix('/') {
+            Some(v) => (true, v),
+            None => (false, glob),
+        };
+        if glob.is_empty() {
+            return None;
         }
+        Some(IgnoreRule {
+            pattern: pattern.to_string(),
+            negated,
+            source: source.to_string(),
+            line,
+            glob: glob.strip_prefix('/').unwrap_or(glob).to_string(),
+            dir_only,
+            basename: !glob.contains('/'),
+            base: base.to_string(),
+        })
     }
-    fn check_ignore_file(rules: &Vec<(String, bool)>, path: &PathBuf) -> Option<bool> {
-        let mut result = None;
-        for (pattern, value) in rules {
-            if let Ok(pattern) = Pattern::new(&*pattern) {
-                if pattern.matches(path.to_str().unwrap()) {}
-                result = Some(value.clone());
+
+    /// Tells if the rule applies to `path`, relative to the top of the worktree.
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let relative = if self.base.is_empty() {
+            path
+        } else {
+            match path.strip_prefix(&self.base).and_then(|v| v.strip_prefix('/')) {
+                Some(v) => v,
+                None => return false,
             }
+        };
+        if self.basename {
+            wildmatch(&self.glob, relative.rsplit('/').next().unwrap_or(relative), false)
+        } else {
+            wildmatch(&self.glob, relative, true)
         }
-        result
     }
-    fn check_ignore_scoped(
-        rules: HashMap<String, Vec<(String, bool)>>,
-        path: PathBuf,
-    ) -> Option<bool> {
-        let mut parent = path.parent();
+}
 
-        while let Some(p) = parent {
-            if let Some(rule) = rules.get(p.to_str().unwrap_or("")) {
-                if let Some(result) = Self::check_ignore_file(rule, &path) {
-                    return Some(result);
-                }
-            }
-            parent = p.parent();
+/// Drops trailing spaces that aren't escaped with a backslash.
+fn trim_trailing_spaces(raw: &str) -> &str {
+    let bytes = raw.as_bytes();
+    let mut end = bytes.len();
+    while end > 0 && bytes[end - 1] == b' ' {
+        let backslashes = bytes[..end - 1].iter().rev().take_while(|c| **c == b'\\').count();
+        if backslashes % 2 == 1 {
+            break;
         }
+        end -= 1;
+    }
+    &raw[..end]
+}
 
-        None
+fn parse_rules(content: &str, source: &str, base: &str) -> Vec<IgnoreRule> {
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(i, v)| IgnoreRule::parse(v, source, i + 1, base))
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct GitIgnore {
+    work_dir: PathBuf,
+    /// Patterns given with `-e`, which win over every file.
+    command_line: Vec<IgnoreRule>,
+    /// Whether `.gitignore` files are read at all.
+    per_directory: bool,
+    /// `core.excludesFile`, the weakest rules.
+    global: Vec<IgnoreRule>,
+    /// `.git/info/exclude`.
+    info: Vec<IgnoreRule>,
+    /// The `.gitignore` of each directory, read the first time a path below it is checked.
+    scoped: RefCell<HashMap<String, Vec<IgnoreRule>>>,
+}
+
+impl GitIgnore {
+    /// Rules that come only from the command line, as `clean -x -e <pattern>` uses.
+    pub fn command_line_only(work_dir: PathBuf) -> Self {
+        GitIgnore {
+            work_dir,
+            command_line: Vec::new(),
+            per_directory: false,
+            global: Vec::new(),
+            info: Vec::new(),
+            scoped: RefCell::new(HashMap::new()),
+        }
     }
-    fn check_ignore_absolute(rules: Vec<(String, bool)>, path: PathBuf) -> bool {
-        for ruleset in rules {
-            if let Some(result) = Self::check_ignore_file(&vec![ruleset], &path) {
-                return result;
-            }
+
+    /// Adds patterns that take precedence over every ignore file.
+    pub fn add_excludes(&mut self, patterns: &[String]) {
+        let start = self.command_line.len();
+        self.command_line.extend(
+            patterns
+                .iter()
+                .enumerate()
+                .filter_map(|(i, v)| IgnoreRule::parse(v, "", start + i + 1, "")),
+        );
+    }
+
+    fn scoped_rules(&self, dir: &str) -> Vec<IgnoreRule> {
+        if !self.per_directory {
+            return Vec::new();
         }
-        false
+        let mut scoped = self.scoped.borrow_mut();
+        scoped
+            .entry(dir.to_string())
+            .or_insert_with(|| {
+                let source = Path::new(dir).join(".gitignore");
+                std::fs::read(self.work_dir.join(&source))
+                    .map(|v| parse_rules(&String::from_utf8_lossy(&v), &source.to_string_lossy(), dir))
+                    .unwrap_or_default()
+            })
+            .clone()
     }
-    pub fn check_ignore(&self, path: PathBuf) -> Result<bool, String> {
-        if path.is_absolute() {
-            return Err(
-                "This function requires path to be relative to the repository's root".to_string(),
-            );
+
+    /// The last rule of the closest file that has one matching `path`.
+    fn last_match(&self, path: &str, is_dir: bool) -> Option<IgnoreRule> {
+        let find = |rules: &[IgnoreRule]| rules.iter().rev().find(|v| v.matches(path, is_dir)).cloned();
+        if let Some(rule) = find(&self.command_line) {
+            return Some(rule);
         }
+        for dir in Path::new(path).ancestors().skip(1) {
+            if let Some(rule) = find(&self.scoped_rules(&dir.to_string_lossy())) {
+                return Some(rule);
+            }
+        }
+        find(&self.info).or_else(|| find(&self.global))
+    }
 
-        if let Some(result) = GitIgnore::check_ignore_scoped(self.scoped.clone(), path.clone()) {
-            return Ok(result);
+    /// Finds the rule that decides about `path`, relative to the top of the worktree.
+    ///
+    /// A negated rule means the path is explicitly not ignored. Once a directory is
+    /// excluded nothing below it can be re-included, as git never looks inside.
+    pub fn check(&self, path: &str, is_dir: bool) -> Option<IgnoreRule> {
+        let path = path.trim_end_matches('/');
+        let mut parents: Vec<&Path> = Path::new(path).ancestors().skip(1).collect();
+        parents.pop();
+        for dir in parents.iter().rev() {
+            if let Some(rule) = self.last_match(&dir.to_string_lossy(), true) {
+                if !rule.negated {
+                    return Some(rule);
+                }
+            }
         }
+        self.last_match(path, is_dir)
+    }
 
-        Ok(GitIgnore::check_ignore_absolute(
-            self.absolute.clone(),
-            path,
-        ))
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        self.check(path, is_dir).is_some_and(|v| !v.negated)
     }
 }
 
 impl GitRepo {
-    pub fn gitignore_read(&self) -> GitIgnore {
-        let mut ret = GitIgnore::new();
-        let repo_file = self.repo_file("info/exclude".into(), false).unwrap();
-        if repo_file.exists() {
-            let file = File::open(repo_file).unwrap();
-            let reader = BufReader::new(file);
-            ret.absolute.extend(Self::gitignore_parse(reader.lines()));
-        }
-
-        let config_home = env::var("XDG_CONFIG_HOME")
-            .map(PathBuf::from)
-            .unwrap_or_else(|_| {
-                let home = env::var("HOME").expect("Could not find home directory");
-                PathBuf::from(home).join(".config/git/ignore")
+    /// Where the user-wide ignore file is: `core.excludesFile`, else `$XDG_CONFIG_HOME/git/ignore`
+    /// or `~/.config/git/ignore`.
+    fn excludes_file(&self) -> Option<PathBuf> {
+        if let Some(v) = self.config_get("core.excludesFile") {
+            return Some(match (v.strip_prefix("~/"), env::var("HOME")) {
+                (Some(rest), Ok(home)) => PathBuf::from(home).join(rest),
+                _ => PathBuf::from(v),
             });
-
-        if config_home.exists() {
-            let file = File::open(config_home).unwrap();
-            let reader = BufReader::new(file);
-            ret.absolute.extend(Self::gitignore_parse(reader.lines()));
         }
-
-        let index = self.index_read();
-        for entry in index.entries {
-            if entry.name == ".gitignore" || entry.name.ends_with(".gitignore") {
-                let dir_name = Path::new(&entry.name)
-                    .parent()
-                    .unwrap()
-                    .to_string_lossy()
-                    .into_owned();
-                let contents = self.object_read(entry.sha);
-                let blobdata_str =
-                    String::from_utf8(contents.unwrap().data()).expect("Invalid UTF-8");
-                let cursor = Cursor::new(blobdata_str);
-                let reader = BufReader::new(cursor);
-                let lines = reader.lines();
-                ret.scoped.insert(dir_name, GitRepo::gitignore_parse(lines));
-            }
+        match env::var("XDG_CONFIG_HOME") {
+            Ok(v) if !v.is_empty() => Some(PathBuf::from(v).join("git/ignore")),
+            _ => env::var("HOME")
+                .ok()
+                .map(|v| PathBuf::from(v).join(".config/git/ignore")),
         }
-        ret
     }
-    fn gitignore_parse_line(raw: &String) -> Option<(String, bool)> {
-        let raw = raw.trim();
-        if raw.is_empty() || raw.starts_with("#") {
-            return None;
-        } else if raw.starts_with("!") {
-            return Some((raw[1..].to_string(), false));
-        } else if raw.starts_with("\\") {
-            return Some((raw[1..].to_string(), true));
-        } else {
-            return Some((raw.to_string(), true));
+
+    pub fn gitignore_read(&self) -> GitIgnore {
+        let read = |path: &Path, source: &str| {
+            std::fs::read(path)
+                .map(|v| parse_rules(&String::from_utf8_lossy(&v), source, ""))
+                .unwrap_or_default()
+        };
+        let global = self
+            .excludes_file()
+            .map(|v| read(&v, &v.to_string_lossy()))
+            .unwrap_or_default();
+        let info = read(&self.common_dir.join("info/exclude"), ".git/info/exclude");
+        GitIgnore {
+            work_dir: self.work_dir.clone(),
+            command_line: Vec::new(),
+            per_directory: true,
+            global,
+            info,
+            scoped: RefCell::new(HashMap::new()),
         }
     }
-    fn gitignore_parse<R: BufRead>(lines: Lines<R>) -> Vec<(String, bool)> {
-        let lines: Vec<String> = lines.map(|v| v.unwrap()).collect();
-        lines
-            .iter()
-            .filter_map(|v| Self::gitignore_parse_line(v))
-            .collect()
-    }
 }
@@ -1,10 +1,12 @@
 use std::{fs, io};
 use std::fs::File;
 use crate::helpers::git::GitRepo;
-use sha1::digest::generic_array::arr;
-use std::io::{BufWriter, Read, Write};
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
-use hex::decode;
+use crate::helpers::git_objects::blob::GitBlob;
+use crate::helpers::git_objects::git_ignore::GitIgnore;
+use sha1::Digest;
 
 #[derive(Debug,Clone)]
 pub struct GitIndexEntry {
@@ -16,47 +18,15 @@ pub struct GitIndexEntry {
     pub(crate) mode_perms: u16,
     pub(crate) uid: u32,
     pub(crate) gid: u32,
-    fsize: u32,
+    pub(crate) fsize: u32,
     pub(crate) sha: String,
     pub(crate) flag_assume_valid: bool,
     pub(crate) flag_stage: u16,
+    /// Left out of the working tree by a sparse checkout; needs the extended flags of version 3.
+    pub(crate) flag_skip_worktree: bool,
     pub(crate) name: String,
 }
 
-impl GitIndexEntry {
-    fn new(
-        ctime: (u32, u32),
-        mtime: (u32, u32),
-        dev: u32,
-        ino: u32,
-        mode_type: u16,
-        mode_perms: u16,
-        uid: u32,
-        gid: u32,
-        fsize: u32,
-        sha: String,
-        flag_assume_valid: bool,
-        flag_stage: u16,
-        name: String,
-    ) -> Self {
-        Self {
-            ctime,
-            mtime,
-            dev,
-            ino,
-            mode_type,
-            mode_perms,
-            uid,
-            gid,
-            fsize,
-            sha,
-            flag_assume_valid,
-            flag_stage,
-            name,
-        }
-    }
-}
-
 #[derive(Debug)]
 pub struct GitIndex {
     pub(crate) version: Option<u32>,
@@ -77,25 +47,21 @@ impl GitRepo {
         }
         let raw = std::fs::read(index_file_path).unwrap();
         let header = &raw[..12];
-        let signature = &header[..4];
         let version = u32::from_be_bytes(header[4..8].try_into().unwrap());
-        if version != 2 {
-            panic!("mygit supports only index file version 2");
+        if version != 2 && version != 3 {
+            panic!("mygit supports only index file versions 2 and 3");
         }
         let count = u32::from_be_bytes(header[8..12].try_into().unwrap());
         let content = &raw[12..];
         let mut entries: Vec<GitIndexEntry> = Vec::new();
         let mut idx = 0;
-        for i in 0..count {
+        for _ in 0..count {
             let ctime_s = u32::from_be_bytes(content[idx..idx + 4].try_into().unwrap());
-            let time_ns = u32::from_be_bytes(content[idx + 4..idx + 8].try_into().unwrap());
             let mtime_s = u32::from_be_bytes(content[idx + 8..idx + 12].try_into().unwrap());
             let ctime_ns = u32::from_be_bytes(content[idx + 4..idx + 8].try_into().unwrap());
             let mtime_ns = u32::from_be_bytes(content[idx + 12..idx + 16].try_into().unwrap());
             let dev = u32::from_be_bytes(content[idx + 16..idx + 20].try_into().unwrap());
             let ino = u32::from_be_bytes(content[idx + 20..idx + 24].try_into().unwrap());
-            let unused = u16::from_be_bytes(content[idx + 24..idx + 26].try_into().unwrap());
-            //assert_eq!(unused, 0);
             let mode = u16::from_be_bytes(content[idx + 26..idx + 28].try_into().unwrap());
             let mode_type = mode >> 12;
             //assert!([0b1000, 0b1010, 0b1110].contains(&mode_type));
@@ -107,12 +73,16 @@ impl GitRepo {
             let flags = u16::from_be_bytes(content[idx + 60..idx + 62].try_into().unwrap());
             let flag_assume_valid = (flags & 0b1000000000000000) != 0;
             let flag_extended = (flags & 0b0100000000000000) != 0;
-            //assert!(!flag_extended);
             let flag_stage = flags & 0b0011000000000000;
-            let name_length = flags & 0b0000111111111111;
 
             // We've read 62 bytes so far.
             idx += 62;
+            let mut flag_skip_worktree = false;
+            if version >= 3 && flag_extended {
+                let extended = u16::from_be_bytes(content[idx..idx + 2].try_into().unwrap());
+                flag_skip_worktree = (extended & 0b0100000000000000) != 0;
+                idx += 2;
+            }
             let name_length = flags & 0b0000111111111111;
             let raw_name: &[u8];
             if name_length < 0xFFF {
@@ -133,7 +103,7 @@ impl GitRepo {
 
             let name = std::str::from_utf8(raw_name).expect("Invalid UTF-8 sequence");
 
-            idx = 8 * ((idx + 7) / 8);
+            idx = 8 * idx.div_ceil(8);
 
             entries.push(GitIndexEntry {
                 ctime: (ctime_s, ctime_ns),
@@ -148,19 +118,24 @@ impl GitRepo {
                 sha,
                 flag_assume_valid,
                 flag_stage,
+                flag_skip_worktree,
                 name: name.to_string(),
             });
         }
 
         GitIndex::new(Some(version), entries)
     }
-    fn index_write(&self, index: &GitIndex) -> io::Result<()> {
+    pub(crate) fn index_write(&self, index: &GitIndex) -> io::Result<()> {
         let index_file = self.repo_file("index".into(),false).unwrap();
-        let mut file = File::create(index_file)?;
+        // Git checks the trailing SHA-1 of the whole file, so build it in memory first.
+        let mut file: Vec<u8> = Vec::new();
 
         // HEADER
         file.write_all(b"DIRC")?;
-        file.write_all(&index.version.unwrap_or(2_u32).to_be_bytes())?;
+        // Version 2 can't hold the extended flags, so only use version 3 when an entry has some.
+        let extended = index.entries.iter().any(|e| e.flag_skip_worktree);
+        let version = if extended { 3 } else { 2 };
+        file.write_all(&u32::to_be_bytes(version))?;
         file.write_all(&(index.entries.len() as u32).to_be_bytes())?;
 
         // ENTRIES
@@ -174,7 +149,7 @@ impl GitRepo {
             file.write_all(&e.ino.to_be_bytes())?;
 
             // Mode
-            let mode = (e.mode_type << 12) | e.mode_perms;
+            let mode = ((e.mode_type as u32) << 12) | e.mode_perms as u32;
             file.write_all(&mode.to_be_bytes())?;
 
             file.write_all(&e.uid.to_be_bytes())?;
@@ -191,8 +166,13 @@ impl GitRepo {
             let name_length = if bytes_len >= 0xFFF { 0xFFF } else { bytes_len as u16 };
 
             // Flags and name length
-            let flags = flag_assume_valid | e.flag_stage | name_length;
+            let flag_extended = if e.flag_skip_worktree { 0x1 << 14 } else { 0 };
+            let flags = flag_assume_valid | flag_extended | e.flag_stage | name_length;
             file.write_all(&flags.to_be_bytes())?;
+            if e.flag_skip_worktree {
+                file.write_all(&u16::to_be_bytes(0x1 << 14))?;
+                idx += 2;
+            }
 
             // Name and padding
             file.write_all(name_bytes)?;
@@ -206,7 +186,264 @@ impl GitRepo {
             }
         }
 
-        Ok(())
+        let checksum = sha1::Sha1::digest(&file);
+        file.write_all(&checksum)?;
+        File::create(index_file)?.write_all(&file)
+    }
+
+    /// Builds the index entry of a worktree file whose content is stored as `sha`.
+    pub(crate) fn index_entry_from_file(
+        name: String,
+        metadata: &fs::Metadata,
+        sha: String,
+        mode_type: u16,
+        mode_perms: u16,
+    ) -> GitIndexEntry {
+        GitIndexEntry {
+            ctime: (metadata.ctime() as u32, metadata.ctime_nsec() as u32),
+            mtime: (metadata.mtime() as u32, metadata.mtime_nsec() as u32),
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            mode_type,
+            mode_perms,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            fsize: metadata.size() as u32,
+            sha,
+            flag_assume_valid: false,
+            flag_stage: 0,
+            flag_skip_worktree: false,
+            name,
+        }
+    }
+
+    /// Builds the index entry of a blob that has no file in the worktree to take stat data from.
+    pub(crate) fn index_entry_unstat(name: String, sha: String, mode_type: u16, mode_perms: u16) -> GitIndexEntry {
+        GitIndexEntry {
+            ctime: (0, 0),
+            mtime: (0, 0),
+            dev: 0,
+            ino: 0,
+            mode_type,
+            mode_perms,
+            uid: 0,
+            gid: 0,
+            fsize: 0,
+            sha,
+            flag_assume_valid: false,
+            flag_stage: 0,
+            flag_skip_worktree: false,
+            name,
+        }
+    }
+
+    /// Makes the index match `tree`, taking stat data from the files checked out from it.
+    ///
+    /// Entries already recording the same blob are kept as they are, since their files weren't touched.
+    /// The ones a sparse checkout leaves out are marked skip-worktree.
+    pub fn index_from_tree(&self, tree_sha: &str) -> Result<(), String> {
+        let previous: std::collections::HashMap<String, GitIndexEntry> =
+            self.index_read().entries.into_iter().map(|e| (e.name.clone(), e)).collect();
+        let sparse = self.sparse_checkout_read();
+        let entries = self
+            .tree_leafs_flat(tree_sha, "")
+            .into_iter()
+            .map(|(name, leaf)| {
+                let mode = u32::from_str_radix(&leaf.mode, 8).unwrap_or(0o100644);
+                let (mode_type, mode_perms) = ((mode >> 12) as u16, (mode & 0o777) as u16);
+                let skip_worktree = sparse.as_ref().is_some_and(|v| !v.includes(&name));
+                if let Some(entry) = previous.get(&name) {
+                    if entry.sha == leaf.sha && (entry.mode_type, entry.mode_perms) == (mode_type, mode_perms) {
+                        let mut entry = entry.clone();
+                        entry.flag_skip_worktree = skip_worktree;
+                        return entry;
+                    }
+                }
+                match fs::symlink_metadata(self.work_dir.join(&name)) {
+                    Ok(metadata) if mode_type != 0b1110 && !skip_worktree => {
+                        Self::index_entry_from_file(name, &metadata, leaf.sha, mode_type, mode_perms)
+                    }
+                    _ => GitIndexEntry {
+                        flag_skip_worktree: skip_worktree,
+                        ..Self::index_entry_unstat(name, leaf.sha, mode_type, mode_perms)
+                    },
+                }
+            })
+            .collect();
+        self.index_write(&GitIndex::new(Some(2), entries)).map_err(|e| e.to_string())
+    }
+
+    /// Stages the files under `paths`, normalizing their content on the way in, and
+    /// drops index entries whose files are gone.
+    ///
+    /// Untracked ignored files are skipped unless `force` is set; the ones named
+    /// explicitly are returned so the caller can complain about them.
+    pub fn add<P: AsRef<Path>>(&self, paths: &[P], force: bool) -> Result<Vec<String>, String> {
+        let mut index = self.index_read();
+        let worktree = fs::canonicalize(&self.work_dir).map_err(|e| e.to_string())?;
+        let attributes = self.gitattributes_read(false);
+        let ignore = if force { None } else { Some(self.gitignore_read()) };
+        let tracked: Vec<String> = index.entries.iter().map(|e| e.name.clone()).collect();
+        let mut ignored = Vec::new();
+
+        let mut prefixes: Vec<String> = Vec::new();
+        let mut files: Vec<String> = Vec::new();
+        for path in paths {
+            let path = path.as_ref();
+            let absolute = if path.is_absolute() {
+                path.to_path_buf()
+            } else {
+                std::env::current_dir().map_err(|e| e.to_string())?.join(path)
+            };
+            // Missing paths can't be canonicalized, so only resolve their parent.
+            let absolute = match (absolute.parent(), absolute.file_name()) {
+                (Some(parent), Some(file_name)) => fs::canonicalize(parent)
+                    .map(|v| v.join(file_name))
+                    .unwrap_or(absolute.clone()),
+                _ => fs::canonicalize(&absolute).unwrap_or(absolute.clone()),
+            };
+            let relative = absolute
+                .strip_prefix(&worktree)
+                .map_err(|_| format!("{}: '{}' is outside repository", path.display(), absolute.display()))?
+                .to_string_lossy()
+                .trim_end_matches('/')
+                .to_string();
+            let relative = if relative == "." { String::new() } else { relative };
+
+            let has_tracked = tracked.iter().any(|v| path_is_under(v, &relative));
+            if absolute.symlink_metadata().is_err() && !has_tracked {
+                return Err(format!("pathspec '{}' did not match any files", path.display()));
+            }
+            if let Some(ignore) = &ignore {
+                if !has_tracked && ignore.is_ignored(&relative, absolute.is_dir()) {
+                    ignored.push(relative);
+                    continue;
+                }
+            }
+            files.extend(self.worktree_files(&absolute, &relative, ignore.as_ref(), &tracked));
+            prefixes.push(relative);
+        }
+
+        let in_pathspec = |name: &str| prefixes.iter().any(|p| path_is_under(name, p));
+        let previous: std::collections::HashMap<String, GitIndexEntry> = index
+            .entries
+            .iter()
+            .map(|e| (e.name.clone(), e.clone()))
+            .collect();
+        index.entries.retain(|e| {
+            !in_pathspec(&e.name)
+                || e.flag_skip_worktree
+                || fs::symlink_metadata(self.work_dir.join(&e.name)).is_ok_and(|v| !v.is_dir() || e.mode_type == 0b1110)
+        });
+
+        let filemode = self.config_bool("core.filemode").unwrap_or(true);
+        let symlinks = self.config_bool("core.symlinks").unwrap_or(true);
+        for name in files {
+            let file = self.work_dir.join(&name);
+            let metadata = fs::symlink_metadata(&file).map_err(|e| e.to_string())?;
+            let previous = previous.get(&name);
+            // Outside of the sparse checkout, whatever is left in the worktree isn't staged.
+            if previous.is_some_and(|e| e.flag_skip_worktree) {
+                continue;
+            }
+            if metadata.is_dir() {
+                // A nested repository is recorded as a gitlink to the commit it has checked out.
+                let sha = GitRepo::open(&file)
+                    .and_then(|v| v.ref_resolve("HEAD".into()))
+                    .ok_or_else(|| format!("'{}' does not have a commit checked out", name))?;
+                let embedded = previous.is_none_or(|e| e.mode_type != 0b1110)
+                    && !self.submodules()?.iter().any(|v| v.path == name);
+                if embedded {
+                    eprintln!("warning: adding embedded git repository: {}", name);
+                }
+                let entry = Self::index_entry_from_file(name.clone(), &metadata, sha, 0b1110, 0);
+                match index.entries.iter_mut().find(|e| e.name == name) {
+                    Some(existing) => *existing = entry,
+                    None => index.entries.push(entry),
+                }
+                continue;
+            }
+            let (mode_type, mode_perms, data) = if metadata.file_type().is_symlink() {
+                // A symlink is stored as a blob holding its target, unconverted.
+                let target = fs::read_link(&file).map_err(|e| e.to_string())?;
+                (0b1010, 0, target.into_os_string().into_encoded_bytes())
+            } else {
+                let data = fs::read(&file).map_err(|e| e.to_string())?;
+                let (mode_type, mode_perms) = match previous.map(|e| (e.mode_type, e.mode_perms)) {
+                    // Without symlink support, checkout wrote the link as a file holding the target.
+                    Some((0b1010, perms)) if !symlinks => (0b1010, perms),
+                    // The filesystem's exec bit can't be trusted, so the index keeps its own.
+                    Some((0b1000, perms)) if !filemode => (0b1000, perms),
+                    _ if filemode && metadata.mode() & 0o100 != 0 => (0b1000, 0o755),
+                    _ => (0b1000, 0o644),
+                };
+                let data = if mode_type == 0b1010 {
+                    data
+                } else {
+                    self.convert_to_git(&attributes, &name, data, previous.map(|e| e.sha.as_str()))?
+                };
+                (mode_type, mode_perms, data)
+            };
+            let sha = GitRepo::object_write(Some(self), Box::new(GitBlob::new(data)))?;
+
+            let entry = Self::index_entry_from_file(name.clone(), &metadata, sha, mode_type, mode_perms);
+            match index.entries.iter_mut().find(|e| e.name == name) {
+                Some(existing) => *existing = entry,
+                None => index.entries.push(entry),
+            }
+        }
+
+        index.entries.sort_by(|a, b| a.name.as_bytes().cmp(b.name.as_bytes()));
+        self.index_write(&index).map_err(|e| e.to_string())?;
+        Ok(ignored)
+    }
+
+    /// Lists the files below `path`, as paths relative to the top of the worktree,
+    /// leaving out the ignored ones that aren't tracked.
+    fn worktree_files(
+        &self,
+        path: &Path,
+        relative: &str,
+        ignore: Option<&GitIgnore>,
+        tracked: &[String],
+    ) -> Vec<String> {
+        let join = |name: &str| {
+            if relative.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}/{}", relative, name)
+            }
+        };
+        // Symlinks are recorded as they are, even when they point at a directory.
+        if fs::symlink_metadata(path).is_ok_and(|v| !v.is_dir()) {
+            return vec![relative.to_string()];
+        }
+        // So is a nested repository, which becomes a gitlink.
+        if !relative.is_empty() && path.join(".git").exists() {
+            return vec![relative.to_string()];
+        }
+        let mut entries: Vec<_> = match fs::read_dir(path) {
+            Ok(v) => v.flatten().collect(),
+            Err(_) => return Vec::new(),
+        };
+        entries.sort_by_key(|v| v.file_name());
+        let mut files = Vec::new();
+        for entry in entries {
+            if entry.file_name() == ".git" {
+                continue;
+            }
+            let name = join(&entry.file_name().to_string_lossy());
+            let path = entry.path();
+            if let Some(ignore) = ignore {
+                // Ignored directories are only entered for the tracked files they hold.
+                let is_dir = entry.file_type().is_ok_and(|v| v.is_dir());
+                if !tracked.iter().any(|v| path_is_under(v, &name)) && ignore.is_ignored(&name, is_dir) {
+                    continue;
+                }
+            }
+            files.extend(self.worktree_files(&path, &name, ignore, tracked));
+        }
+        files
     }
     
     /// Removes files from the Git index and optionally deletes them from the filesystem.
@@ -230,7 +467,7 @@ impl GitRepo {
         let mut index = self.index_read();
 
         // Step 2: Define the worktree path as a PathBuf
-        let worktree = fs::canonicalize(Path::new(&self.work_dir).to_path_buf()).map_err(|e| {
+        let worktree = fs::canonicalize(Path::new(&self.work_dir)).map_err(|e| {
                 format!(
                     "Error: Cannot canonicalize worktree path {:?}: {}",
                     self.work_dir, e
@@ -343,3 +580,8 @@ impl GitRepo {
         }
     }
 }
+
+/// Tells if `path` is `dir` or inside it; the empty `dir` is the top of the worktree.
+fn path_is_under(path: &str, dir: &str) -> bool {
+    dir.is_empty() || path == dir || path.strip_prefix(dir).is_some_and(|v| v.starts_with('/'))
+}
@@ -3,13 +3,12 @@ use crate::helpers::git_objects::blob::GitBlob;
 use crate::helpers::git_objects::commit::GitCommit;
 use crate::helpers::git_objects::tag::GitTag;
 use crate::helpers::git_objects::tree::GitTree;
+use crate::helpers::pack::RawObject;
 use clap::ValueEnum;
 use regex::Regex;
-use sha1::digest::consts::True;
 use sha1::Digest;
 use std::any::Any;
 use std::fmt::Debug;
-use std::io::Read;
 use std::ops::Add;
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -34,13 +33,13 @@ impl ObjectType {
     }
 }
 
-impl ToString for ObjectType {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for ObjectType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ObjectType::Blob => "blob".to_string(),
-            ObjectType::Tree => "tree".to_string(),
-            ObjectType::Commit => "commit".to_string(),
-            ObjectType::Tag => "tag".to_string(),
+            ObjectType::Blob => write!(f, "blob"),
+            ObjectType::Tree => write!(f, "tree"),
+            ObjectType::Commit => write!(f, "commit"),
+            ObjectType::Tag => write!(f, "tag"),
         }
     }
 }
@@ -61,10 +60,11 @@ impl FromStr for ObjectType {
 pub struct GitObjectFactory;
 
 impl GitObjectFactory {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(object_type: ObjectType, data: Vec<u8>) -> Box<dyn GitObject> {
         match object_type {
             ObjectType::Blob => Box::new(GitBlob::new(data)),
-            ObjectType::Tree => Box::new(GitTree::new(data, vec![])),
+            ObjectType::Tree => Box::new(GitTree::from_raw(&data)),
             ObjectType::Commit => Box::new(GitCommit::new(data)),
             ObjectType::Tag => Box::new(GitTag::new(data)),
         }
@@ -72,7 +72,7 @@ impl GitObjectFactory {
 }
 
 pub trait GitObject: Any + Debug + AsAny {
-    fn serialize(&self) -> String;
+    fn serialize(&self) -> Vec<u8>;
     fn deserialize(&self) -> Vec<u8>;
     fn format(&self) -> Vec<u8>;
     fn data(&self) -> Vec<u8>;
@@ -92,11 +92,35 @@ impl GitRepo {
     /// Read object sha from Git repository repo.  Return a
     /// GitObject whose exact type depends on the object.
     pub fn object_read(&self, sha: String) -> Result<Box<dyn GitObject>, String> {
+        let (fmt, data) = self.object_read_raw(&sha)?;
+        let object: Box<dyn GitObject> = match fmt.as_slice() {
+            b"commit" => Box::new(GitCommit::new(data)),
+            b"tree" => Box::new(GitTree::from_raw(&data)),
+            b"tag" => Box::new(GitTag::new(data)),
+            b"blob" => Box::new(GitBlob::new(data)),
+            _ => {
+                return Err(format!(
+                    "Unknown type {} for object {}",
+                    std::str::from_utf8(&fmt).unwrap_or("<invalid UTF-8>"),
+                    sha
+                ));
+            }
+        };
+        Ok(object)
+    }
+
+    /// Reads the type and content of object sha, loose or packed.
+    pub fn object_read_raw(&self, sha: &str) -> Result<RawObject, String> {
+        if sha.len() < 3 {
+            return Err(format!("Invalid object name {}", sha));
+        }
         let sha_split = sha.split_at(2);
         let path = self.repo_file(format!("objects/{}/{}", sha_split.0, sha_split.1), false)?;
 
         if !path.is_file() {
-            return Err(format!("Path: {:?} is not a file", path));
+            return self
+                .pack_object_read(sha)
+                .unwrap_or_else(|| Err(format!("Path: {:?} is not a file", path)));
         }
 
         let data = match std::fs::read(path) {
@@ -105,7 +129,7 @@ impl GitRepo {
                 return Err(e.to_string());
             }
         };
-        let raw = match zune_inflate::DeflateDecoder::new(&*data).decode_zlib() {
+        let raw = match zune_inflate::DeflateDecoder::new(&data).decode_zlib() {
             Ok(v) => v,
             Err(e) => {
                 return Err(e.to_string());
@@ -125,22 +149,7 @@ impl GitRepo {
                     return Err(format!("Malformed object {}: bad length", sha));
                 }
 
-                let data = &raw[y + 1..];
-
-                let object: Box<dyn GitObject> = match fmt {
-                    b"commit" => Box::new(GitCommit::new(Vec::from(data))),
-                    b"tree" => Box::new(GitTree::from_raw(data)),
-                    b"tag" => Box::new(GitTag::new(Vec::from(data))),
-                    b"blob" => Box::new(GitBlob::new(Vec::from(data))),
-                    _ => {
-                        return Err(format!(
-                            "Unknown type {} for object {}",
-                            std::str::from_utf8(fmt).unwrap_or("<invalid UTF-8>"),
-                            sha
-                        ));
-                    }
-                };
-                Ok(object)
+                Ok((fmt.to_vec(), raw[y + 1..].to_vec()))
             } else {
                 Err("Null byte not found".into())
             }
@@ -149,40 +158,72 @@ impl GitRepo {
         }
     }
 
-    pub fn object_write(
-        repo: Option<&GitRepo>,
-        object: Box<dyn GitObject>,
-    ) -> Result<String, String> {
-        let data = object.serialize();
-        let size_str = data.len().to_string();
-        let size_bytes = size_str.as_bytes();
+    /// Tells if the repository has object sha, loose or packed.
+    pub fn object_exists(&self, sha: &str) -> bool {
+        sha.len() > 2 && self.repo_path(format!("objects/{}/{}", &sha[..2], &sha[2..])).is_file() || self.pack_contains(sha)
+    }
+
+    /// Follows tags down to the object they end up pointing at.
+    pub fn object_peel(&self, sha: &str) -> Result<String, String> {
+        let mut sha = sha.to_string();
+        loop {
+            let (fmt, data) = self.object_read_raw(&sha)?;
+            if fmt != b"tag" {
+                return Ok(sha);
+            }
+            let tag = GitTag::new(data);
+            sha = tag
+                .kvlm
+                .get(b"object".as_ref())
+                .and_then(|v| v.first())
+                .map(|v| String::from_utf8_lossy(v).into_owned())
+                .ok_or("Tag does not contain an object reference.")?;
+        }
+    }
 
+    /// Builds the loose object representation (`<fmt> <size>\0<data>`) of some content.
+    fn object_raw(fmt: &[u8], data: &[u8]) -> Vec<u8> {
         let mut result = Vec::new();
-        result.extend_from_slice(&object.format());
+        result.extend_from_slice(fmt);
         result.push(b' ');
-        result.extend_from_slice(size_bytes);
+        result.extend_from_slice(data.len().to_string().as_bytes());
         result.push(b'\x00');
-        result.extend_from_slice(data.as_ref());
+        result.extend_from_slice(data);
+        result
+    }
 
+    /// Computes the object id of some content without writing it anywhere.
+    pub fn object_hash(fmt: &[u8], data: &[u8]) -> String {
         let mut hasher = sha1::Sha1::new();
-        let data: &[u8] = &*result;
-        Digest::update(&mut hasher, data);
+        Digest::update(&mut hasher, Self::object_raw(fmt, data));
         let hash = hasher.finalize();
-        let sha: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
-        if let Some(repo) = repo {
-            // write data to file
-            let split_sha = sha.split_at(2);
-            let path = repo.repo_file(format!("objects/{}/{}", split_sha.0, split_sha.1), true)?;
-
-            if !path.exists() {
-                match std::fs::write(path, zune_inflate::DeflateEncoder::new(data).encode_zlib()) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        return Err(format!("{}", e.to_string()));
-                    }
+        hash.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn object_write(
+        repo: Option<&GitRepo>,
+        object: Box<dyn GitObject>,
+    ) -> Result<String, String> {
+        let content = object.serialize();
+        match repo {
+            Some(repo) => repo.object_store(&object.format(), &content),
+            None => Ok(Self::object_hash(&object.format(), &content)),
+        }
+    }
+
+    /// Writes some content as a loose object, unless the repository has it already.
+    pub fn object_store(&self, fmt: &[u8], content: &[u8]) -> Result<String, String> {
+        let sha = Self::object_hash(fmt, content);
+        let split_sha = sha.split_at(2);
+        let path = self.repo_file(format!("objects/{}/{}", split_sha.0, split_sha.1), true)?;
+
+        if !path.exists() && !self.pack_contains(&sha) {
+            let data = Self::object_raw(fmt, content);
+            match std::fs::write(path, zune_inflate::DeflateEncoder::new(&data).encode_zlib()) {
+                Ok(_) => {}
+                Err(e) => {
+                    return Err(format!("{}", e));
                 }
-            } else {
-                println!("existuje");
             }
         }
 
@@ -204,18 +245,23 @@ impl GitRepo {
         fmt: Option<String>,
         follow: Option<bool>,
     ) -> Result<String, String> {
-        let sha = self.object_resolve(object.clone()).unwrap_or(Vec::new());
-        if sha.len() == 0 {
-            return Err("None object find".to_string());
-        }
-        if sha.len() > 1 {
-            let candidates = sha.join("\n - ");
-            return Err(format!(
-                "Ambiguous reference {}: Candidates are:\n - {}.",
-                object, candidates
-            ));
-        }
-        let mut sha = sha.first().unwrap().clone();
+        let mut sha = match object.split_once(':') {
+            Some((rev, path)) => self.tree_entry_find(rev, path)?,
+            None => {
+                let sha = self.object_resolve(object.clone()).unwrap_or_default();
+                if sha.is_empty() {
+                    return Err("None object find".to_string());
+                }
+                if sha.len() > 1 {
+                    let candidates = sha.join("\n - ");
+                    return Err(format!(
+                        "Ambiguous reference {}: Candidates are:\n - {}.",
+                        object, candidates
+                    ));
+                }
+                sha.first().unwrap().clone()
+            }
+        };
         let fmt = match fmt {
             None => {
                 return Ok(sha.to_owned());
@@ -276,28 +322,32 @@ impl GitRepo {
 
     pub fn object_resolve(&self, name: String) -> Option<Vec<String>> {
         if name == "HEAD" {
-            return Some(vec![self.ref_resolve("HEAD".into()).unwrap()]);
+            return self.ref_resolve("HEAD".into()).map(|v| vec![v]);
         }
 
         let mut candidates: Vec<String> = Vec::new();
         let hash_re = Regex::new(r"^[0-9A-Fa-f]{4,40}$").unwrap();
 
-        if hash_re.is_match(&*name) {
+        if hash_re.is_match(&name) {
             let name = name.to_lowercase();
             let prefix = &name[0..2];
-            match self.repo_dir("objects/".to_string().add(prefix).into(), false) {
-                Ok(path) => {
-                    let rem = &name[2..];
-                    for f in std::fs::read_dir(path).unwrap() {
-                        let f = f.unwrap();
-                        let file_name = f.file_name();
-                        let name = file_name.to_string_lossy();
-                        if name.starts_with(rem) {
-                            candidates.push(prefix.to_string().add(&name))
-                        }
+            if let Ok(path) = self.repo_dir("objects/".to_string().add(prefix), false) {
+                let rem = &name[2..];
+                for f in std::fs::read_dir(path).into_iter().flatten() {
+                    let f = f.unwrap();
+                    let file_name = f.file_name();
+                    let name = file_name.to_string_lossy();
+                    if name.starts_with(rem) {
+                        candidates.push(prefix.to_string().add(&name))
+                    }
+                }
+            }
+            for pack in self.packs().iter() {
+                for sha in pack.shas().filter(|v| v.starts_with(&name)) {
+                    if !candidates.contains(&sha) {
+                        candidates.push(sha);
                     }
                 }
-                Err(_) => {}
             }
         }
 
@@ -1,5 +1,6 @@
 pub mod blob;
 pub mod commit;
+pub mod git_attributes;
 pub mod git_ignore;
 pub mod git_index_entry;
 pub mod git_object;
@@ -1,15 +1,10 @@
 use crate::helpers::git::GitRepo;
 use crate::helpers::git_objects::git_object::GitObject;
 use crate::helpers::kvlm::{kvlm_parse, kvlm_serialize};
-use clap::builder::Str;
-use ordermap::{OrderMap, OrderSet};
-use sha1::digest::generic_array::arr;
-use std::any::Any;
-use std::collections::{BTreeMap, HashMap};
-use std::fs::File;
+use ordermap::OrderMap;
+use std::collections::BTreeMap;
 use std::hash::RandomState;
-use std::ops::Add;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
 #[derive(Debug)]
 pub struct GitTag {
@@ -20,18 +15,17 @@ pub struct GitTag {
 
 impl GitTag {
     pub fn new(data: Vec<u8>) -> Self {
-        let mut kvlm;
-        if data.len() > 0 {
+        let kvlm = if !data.is_empty() {
             let borrowed_kvlm = kvlm_parse(&data, None, None);
 
             // Convert borrowed OrderMap to owned OrderMap
-            kvlm = borrowed_kvlm
+            borrowed_kvlm
                 .into_iter()
                 .map(|(k, v)| (k.to_vec(), v))
-                .collect();
+                .collect()
         } else {
-            kvlm = OrderMap::new();
-        }
+            OrderMap::new()
+        };
         GitTag {
             fmt: b"tag".to_vec(),
             data,
@@ -41,7 +35,7 @@ impl GitTag {
 }
 
 impl GitObject for GitTag {
-    fn serialize(&self) -> String {
+    fn serialize(&self) -> Vec<u8> {
         kvlm_serialize(&self.kvlm)
     }
 
@@ -60,20 +54,21 @@ impl GitObject for GitTag {
 
 impl GitRepo {
     pub fn ref_resolve(&self, path: PathBuf) -> Option<String> {
-        let path = match self.repo_file(path.into_os_string().to_str()?.to_string(), false) {
+        let name = path.into_os_string().into_string().ok()?;
+        let path = match self.repo_file(name.clone(), false) {
             Ok(v) => v,
             Err(_) => return None,
         };
         if !path.is_file() {
-            return None;
+            return self.packed_ref(&name);
         }
 
         let data = std::fs::read_to_string(path)
             .unwrap()
             .trim_end()
             .to_string();
-        if data.starts_with("ref: ") {
-            self.ref_resolve(data[5..].to_string().into())
+        if let Some(target) = data.strip_prefix("ref: ") {
+            self.ref_resolve(target.into())
         } else {
             Some(data)
         }
@@ -101,7 +96,11 @@ impl GitRepo {
             if can.is_dir() {
                 let nested_refs = self.ref_list(Some(can), new_prefix)?;
                 ret.extend(nested_refs);
-            } else if let Some(sha) = self.ref_resolve(can) {
+            } else if let Some(sha) = self.ref_resolve(
+                can.strip_prefix(&self.common_dir)
+                    .map(|v| v.to_path_buf())
+                    .unwrap_or(can),
+            ) {
                 ret.insert(new_prefix, sha);
             }
         }
@@ -109,45 +108,148 @@ impl GitRepo {
         Ok(ret)
     }
 
+    /// Checks a tag or branch name against the rules of `git check-ref-format`.
+    pub fn ref_name_valid(name: &str) -> bool {
+        !name.is_empty()
+            && !name.starts_with('-')
+            && !name.starts_with('/')
+            && !name.ends_with('/')
+            && !name.ends_with('.')
+            && !name.ends_with(".lock")
+            && !name.contains("..")
+            && !name.contains("@{")
+            && !name.contains("//")
+            && name != "@"
+            && !name
+                .chars()
+                .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+            && !name.split('/').any(|v| v.starts_with('.'))
+    }
+
+    /// Creates `refs/tags/<name>`, wrapping the target in an annotated tag object
+    /// when `annotate` is set, signed when `sign` is set.
+    ///
+    /// Returns the sha the tag pointed at before when it was replaced with `force`.
     pub fn create_tag(
         &self,
-        name: &String,
+        name: &str,
         reference: String,
-        create_tag_object: bool,
+        annotate: bool,
         message: Option<&String>,
-    ) {
+        force: bool,
+        sign: bool,
+    ) -> Result<Option<String>, String> {
+        if !Self::ref_name_valid(name) {
+            return Err(format!("'{}' is not a valid tag name.", name));
+        }
+        let ref_name = format!("tags/{}", name);
+        let previous = self.ref_resolve(format!("refs/{}", ref_name).into());
+        if previous.is_some() && !force {
+            return Err(format!("tag '{}' already exists", name));
+        }
+
         let sha = self
-            .obj_find(reference, Some("tags".to_string()), None)
-            .unwrap();
+            .obj_find(reference.clone(), None, None)
+            .map_err(|_| format!("Failed to resolve '{}' as a valid ref.", reference))?;
 
-        if !create_tag_object {
-            self.ref_create(&"tags/".to_string().add(name), &sha);
-            return;
+        if !annotate && !sign {
+            self.ref_create(&ref_name, &sha);
+            return Ok(previous.filter(|v| *v != sha));
         }
 
+        let target_type = self.object_read(sha.clone())?.format();
+        let tagger = self.ident("COMMITTER")?;
+
         let mut tag = GitTag::new(vec![]);
         tag.kvlm = OrderMap::new();
         tag.kvlm
             .insert(b"object".to_vec(), vec![sha.as_bytes().to_vec()]);
-        tag.kvlm.insert(b"type".to_vec(), vec![b"commit".to_vec()]);
+        tag.kvlm.insert(b"type".to_vec(), vec![target_type]);
         tag.kvlm
             .insert(b"tag".to_vec(), vec![name.as_bytes().to_vec()]);
-        tag.kvlm.insert(
-            b"tagger".to_vec(),
-            vec![b"Wyag <wyag@example.com>".to_vec()],
-        );
-        if let Some(message) = message {
+        tag.kvlm
+            .insert(b"tagger".to_vec(), vec![tagger.to_string().into_bytes()]);
+        let mut message = message.map(|v| v.trim_end().to_string()).unwrap_or_default();
+        if !message.is_empty() {
+            message.push('\n');
+        }
+        tag.kvlm
+            .insert(b"None".to_vec(), vec![message.clone().into_bytes()]);
+        if sign {
+            // Tag signatures are appended to the message rather than kept in a header.
+            let signature = self.sign_payload(&kvlm_serialize(&tag.kvlm))?;
+            message.push_str(&signature);
             tag.kvlm
-                .insert(b"None".to_vec(), vec![message.as_bytes().to_vec()]);
+                .insert(b"None".to_vec(), vec![message.into_bytes()]);
+        }
+        let tag_sha = GitRepo::object_write(Some(self), Box::new(tag))?;
+        self.ref_create(&ref_name, &tag_sha);
+        Ok(previous.filter(|v| *v != tag_sha))
+    }
+
+    /// Removes `refs/tags/<name>` and returns the sha it pointed at.
+    pub fn delete_tag(&self, name: &str) -> Result<String, String> {
+        let ref_name = format!("refs/tags/{}", name);
+        let sha = self
+            .ref_resolve(ref_name.clone().into())
+            .ok_or_else(|| format!("tag '{}' not found.", name))?;
+        std::fs::remove_file(self.repo_path(ref_name)).map_err(|e| e.to_string())?;
+        Ok(sha)
+    }
+
+    /// Points HEAD at `sha`, through the branch it refers to when it is not detached.
+    pub fn head_update(&self, sha: &str) -> Result<(), String> {
+        let head = self.repo_file("HEAD".to_string(), false)?;
+        let data = std::fs::read_to_string(&head).map_err(|e| e.to_string())?;
+        let path = match data.trim_end().strip_prefix("ref: ") {
+            Some(target) => self.repo_file(target.to_string(), true)?,
+            None => head,
+        };
+        std::fs::write(path, format!("{}\n", sha)).map_err(|e| e.to_string())
+    }
+
+    /// Looks a ref up in `packed-refs`, where `git gc` moves them.
+    fn packed_ref(&self, name: &str) -> Option<String> {
+        let packed = std::fs::read_to_string(self.common_dir.join("packed-refs")).ok()?;
+        packed
+            .lines()
+            .filter(|v| !v.starts_with('#') && !v.starts_with('^'))
+            .find_map(|v| v.split_once(' ').filter(|(_, ref_name)| *ref_name == name).map(|(sha, _)| sha.to_string()))
+    }
+
+    /// Removes the ref `name`, e.g. `refs/heads/main`, whether it is loose or packed.
+    pub fn ref_delete(&self, name: &str) -> Result<(), String> {
+        let path = self.repo_path(name.to_string());
+        if path.is_file() {
+            std::fs::remove_file(path).map_err(|e| e.to_string())?;
+        }
+        let packed_path = self.common_dir.join("packed-refs");
+        if let Ok(packed) = std::fs::read_to_string(&packed_path) {
+            let mut kept = String::new();
+            let mut removed = false;
+            for line in packed.lines() {
+                // The peeled value of a tag follows it.
+                if removed && line.starts_with('^') {
+                    continue;
+                }
+                removed = line.split_once(' ').is_some_and(|(_, v)| v == name);
+                if !removed {
+                    kept.push_str(line);
+                    kept.push('\n');
+                }
+            }
+            if kept != packed {
+                std::fs::write(packed_path, kept).map_err(|e| e.to_string())?;
+            }
         }
-        let tag_sha = GitRepo::object_write(Some(self), Box::new(tag)).unwrap();
-        self.ref_create(&"/tags/".to_string().add(name), &tag_sha);
+        Ok(())
     }
 
-    pub fn ref_create(&self, ref_name: &String, sha: &String) {
+    /// Writes `refs/<ref_name>`, e.g. `ref_create("tags/v1.0", sha)`.
+    pub fn ref_create(&self, ref_name: &str, sha: &str) {
         let path = self
-            .repo_file("refs".to_string().add(ref_name), true)
+            .repo_file(format!("refs/{}", ref_name.trim_start_matches('/')), true)
             .unwrap();
-        std::fs::write(path, sha.clone().add("\n")).unwrap();
+        std::fs::write(path, format!("{}\n", sha)).unwrap();
     }
 }
@@ -1,17 +1,21 @@
 use crate::helpers::git::GitRepo;
-use crate::helpers::git_objects::git_object::{AsAny, GitObject, ObjectType};
+use crate::helpers::git_objects::git_attributes::GitAttributes;
+use crate::helpers::git_objects::git_object::GitObject;
 use crate::helpers::git_objects::tree_leaf::GitTreeLeaf;
-use std::collections::HashMap;
+use crate::helpers::sparse::SparsePatterns;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
 use std::fs::File;
 use std::io::Write;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone)]
 pub struct GitTree {
     fmt: Vec<u8>,
     data: Vec<u8>,
-    leafs: Vec<GitTreeLeaf>,
+    pub leafs: Vec<GitTreeLeaf>,
 }
 
 impl GitTree {
@@ -28,7 +32,7 @@ impl GitTree {
         let mut leafs = Vec::new();
         while pos < max {
             match GitTreeLeaf::new_from_raw(raw, Some(pos)) {
-                None => {}
+                None => break,
                 Some(value) => {
                     pos = value.0;
                     leafs.push(value.1);
@@ -40,17 +44,18 @@ impl GitTree {
 }
 
 impl GitObject for GitTree {
-    fn serialize(&self) -> String {
+    fn serialize(&self) -> Vec<u8> {
         let mut leafs = self.leafs.clone();
-        leafs.sort_by(|a, b| a.clone().sort_keys().cmp(&b.clone().sort_keys()));
-        let mut output = String::new();
+        leafs.sort_by_key(|a| a.clone().sort_keys());
+        let mut output = Vec::new();
         for leaf in leafs {
-            output.push_str(&*leaf.mode);
-            output.push(' ');
-            output.push_str(&leaf.clone().sort_keys());
-            output.push('\x00');
+            // Git stores modes without leading zeros, e.g. `40000` for trees.
+            output.extend_from_slice(leaf.mode.trim_start_matches('0').as_bytes());
+            output.push(b' ');
+            output.extend_from_slice(leaf.path.to_string_lossy().as_bytes());
+            output.push(b'\x00');
             if let Ok(sha_bytes) = hex::decode(&leaf.sha) {
-                output.push_str(&String::from_utf8(sha_bytes).unwrap_or("".to_string()))
+                output.extend_from_slice(&sha_bytes);
             }
         }
         output
@@ -70,9 +75,9 @@ impl GitObject for GitTree {
 }
 
 impl GitRepo {
-    pub fn ls_tree(&self, tree: &String, recursive: &bool, prefix: Option<String>) {
+    pub fn ls_tree(&self, tree: &str, recursive: &bool, prefix: Option<String>) {
         let sha = self
-            .obj_find(tree.clone(), Some("tree".to_string()), None)
+            .obj_find(tree.to_string(), Some("tree".to_string()), None)
             .unwrap();
         let prefix = prefix.unwrap_or("".to_string());
         let obj = match self.object_read(sha) {
@@ -83,7 +88,7 @@ impl GitRepo {
         };
         let obj = match obj.as_ref().as_any().downcast_ref::<GitTree>() {
             None => {
-                println!("{}", "Object is not a tree".to_string());
+                println!("Object is not a tree");
                 return;
             }
             Some(v) => v.clone(),
@@ -119,7 +124,43 @@ impl GitRepo {
             }
         }
     }
+    /// Resolves `<rev>:<path>` to the sha of the entry at `path` in the tree of `rev`.
+    pub fn tree_entry_find(&self, rev: &str, path: &str) -> Result<String, String> {
+        let rev = if rev.is_empty() { "HEAD" } else { rev };
+        let mut sha = self.obj_find(rev.to_string(), Some("tree".to_string()), None)?;
+        for component in path.split('/').filter(|v| !v.is_empty()) {
+            let obj = self.object_read(sha.clone())?;
+            let tree = obj
+                .as_ref()
+                .as_any()
+                .downcast_ref::<GitTree>()
+                .ok_or_else(|| format!("path '{}' does not exist in '{}'", path, rev))?;
+            sha = tree
+                .leafs
+                .iter()
+                .find(|leaf| leaf.path.as_os_str() == component)
+                .map(|leaf| leaf.sha.clone())
+                .ok_or_else(|| format!("path '{}' does not exist in '{}'", path, rev))?;
+        }
+        Ok(sha)
+    }
+
+    /// Writes out the files of `tree` under `path`, leaving out the ones outside of the sparse checkout.
     pub fn tree_checkout(&self, tree: Box<dyn GitObject>, path: PathBuf) {
+        let tree_sha = GitRepo::object_hash(b"tree", &tree.serialize());
+        let attributes = self.gitattributes_read_tree(&tree_sha);
+        let sparse = self.sparse_checkout_read();
+        self.tree_checkout_into(tree, path, Path::new(""), &attributes, sparse.as_ref());
+    }
+
+    fn tree_checkout_into(
+        &self,
+        tree: Box<dyn GitObject>,
+        path: PathBuf,
+        prefix: &Path,
+        attributes: &GitAttributes,
+        sparse: Option<&SparsePatterns>,
+    ) {
         let tree = match tree.as_ref().as_any().downcast_ref::<GitTree>() {
             None => {
                 eprintln!("Tree obj is not a tree");
@@ -128,44 +169,104 @@ impl GitRepo {
             Some(v) => v.clone(),
         };
         for leaf in tree.leafs {
-            let obj = self.object_read(leaf.sha).unwrap();
             let mut dest = path.clone();
-            dest.push(leaf.path);
+            dest.push(&leaf.path);
+            let name = prefix.join(&leaf.path);
+            if !leaf.mode.starts_with("04") && sparse.is_some_and(|v| !v.includes(&name.to_string_lossy())) {
+                continue;
+            }
+            if leaf.mode == "160000" {
+                // The commit of a submodule lives in its own repository; leave it an empty directory.
+                std::fs::create_dir_all(dest).unwrap();
+                continue;
+            }
+            let obj = self.object_read(leaf.sha).unwrap();
 
             if obj.format() == b"tree".to_vec() {
                 std::fs::create_dir_all(dest.clone()).unwrap();
-                self.tree_checkout(obj, dest)
+                self.tree_checkout_into(obj, dest.clone(), &name, attributes, sparse);
+                if sparse.is_some() {
+                    // Nothing of it may be in the sparse checkout.
+                    let _ = std::fs::remove_dir(dest);
+                }
             } else if obj.format() == b"blob".to_vec() {
-                let mut file = File::create(dest).unwrap();
-                file.write_all(&*obj.data()).unwrap();
+                self.blob_checkout(&leaf.mode, obj.data(), &dest, &name.to_string_lossy(), attributes);
+            }
+        }
+    }
+
+    /// Writes the blob of the tree entry `name` at `dest`, as a file or a symlink depending on `mode`.
+    pub(crate) fn blob_checkout(&self, mode: &str, data: Vec<u8>, dest: &Path, name: &str, attributes: &GitAttributes) {
+        if mode == "120000" {
+            // The blob of a symlink is its target.
+            let target = std::ffi::OsString::from_vec(data);
+            if self.config_bool("core.symlinks").unwrap_or(true) {
+                std::os::unix::fs::symlink(target, dest).unwrap();
+            } else {
+                std::fs::write(dest, target.as_bytes()).unwrap();
             }
+            return;
+        }
+        let data = match self.convert_to_worktree(attributes, name, data) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("fatal: {}", e);
+                std::process::exit(128);
+            }
+        };
+        let mut file = File::create(dest).unwrap();
+        file.write_all(&data).unwrap();
+        if mode == "100755" {
+            // Executable for whoever may read it, which keeps the umask in effect.
+            let mut permissions = file.metadata().unwrap().permissions();
+            permissions.set_mode(permissions.mode() | ((permissions.mode() & 0o444) >> 2));
+            file.set_permissions(permissions).unwrap();
         }
     }
     pub fn to_hash_map(&self, reference: String, prefix: Option<String>) -> HashMap<String, String> {
         let prefix = prefix.unwrap_or("".to_string());
-        let mut ret = HashMap::new();
         let tre_sha = match self.obj_find(reference.clone(),Some("tree".to_string()),None) {
             Ok(v) => {v}
-            Err(_) => {return ret}
-        };
-        let git_object = self.object_read(tre_sha.clone()).unwrap();
-        let tree = match git_object.as_ref().as_any().downcast_ref::<GitTree>() {
-            None => {return ret}
-            Some(v) => v,
+            Err(_) => {return HashMap::new()}
         };
 
-        for leaf in tree.leafs.clone() {
-            let full_path = Path::new(&prefix).join(leaf.path.clone());
-            
-            let is_sub_tree = leaf.mode.starts_with("04");
-            if is_sub_tree { 
-                ret.extend(self.to_hash_map(leaf.sha,Some(full_path.to_str().unwrap().to_string())));
-            } else { 
-                ret.insert(full_path.to_str().unwrap().to_string(),GitRepo::hash_obj(None,full_path,ObjectType::Blob).unwrap());
+        self.tree_leafs_flat(&tre_sha, &prefix)
+            .into_iter()
+            .map(|(path, leaf)| (path, leaf.sha))
+            .collect()
+    }
+
+    /// Writes the trees recorded by the index and returns the sha of the root one.
+    pub fn write_tree(&self) -> Result<String, String> {
+        let index = self.index_read();
+        let entries: Vec<(String, String, String)> = index
+            .entries
+            .iter()
+            .map(|e| {
+                let mode = format!("{:06o}", ((e.mode_type as u32) << 12) | e.mode_perms as u32);
+                (e.name.clone(), mode, e.sha.clone())
+            })
+            .collect();
+        self.write_tree_level(&entries)
+    }
+
+    fn write_tree_level(&self, entries: &[(String, String, String)]) -> Result<String, String> {
+        let mut leafs = Vec::new();
+        let mut subtrees: BTreeMap<String, Vec<(String, String, String)>> = BTreeMap::new();
+        for (name, mode, sha) in entries {
+            match name.split_once('/') {
+                Some((dir, rest)) => subtrees.entry(dir.to_string()).or_default().push((
+                    rest.to_string(),
+                    mode.clone(),
+                    sha.clone(),
+                )),
+                None => leafs.push(GitTreeLeaf::new(sha.clone(), name.into(), mode.clone())),
             }
         }
-        
-        
-        ret
+        for (dir, entries) in subtrees {
+            let sha = self.write_tree_level(&entries)?;
+            leafs.push(GitTreeLeaf::new(sha, dir.into(), "040000".to_string()));
+        }
+        GitRepo::object_write(Some(self), Box::new(GitTree::new(vec![], leafs)))
     }
 }
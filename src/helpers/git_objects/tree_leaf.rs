@@ -1,4 +1,3 @@
-use std::fmt::format;
 use std::ops::Add;
 use std::path::PathBuf;
 
@@ -15,7 +14,7 @@ impl GitTreeLeaf {
     }
 
     pub fn sort_keys(self) -> String {
-        if self.mode.starts_with("10") {
+        if !self.mode.starts_with("04") {
             self.path.into_os_string().into_string().unwrap()
         } else {
             self.path.into_os_string().into_string().unwrap().add("/")
@@ -0,0 +1,54 @@
+use crate::helpers::git::GitRepo;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+impl GitRepo {
+    /// Where hooks are looked for: `core.hooksPath`, or `hooks` in the common directory.
+    pub fn hooks_dir(&self) -> PathBuf {
+        match self.config_get("core.hooksPath") {
+            Some(path) => self.git_dir.join(path),
+            None => self.common_dir.join("hooks"),
+        }
+    }
+
+    /// Runs the hook `name` with `args`, feeding it `stdin`, from the git directory the way
+    /// `receive-pack` does.
+    ///
+    /// Returns nothing when there is no such executable hook, otherwise whether it succeeded
+    /// along with what it printed, on stdout and stderr alike.
+    pub fn hook_run(&self, name: &str, args: &[&str], stdin: &str) -> Option<(bool, Vec<u8>)> {
+        // The hook runs from inside the git directory, so a relative path would no longer resolve.
+        let path = std::path::absolute(self.hooks_dir().join(name)).ok()?;
+        let executable = std::fs::metadata(&path).is_ok_and(|v| v.is_file() && v.permissions().mode() & 0o111 != 0);
+        if !executable {
+            return None;
+        }
+        // The shell puts both outputs of the hook in the same pipe, in the order it writes them.
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg("exec \"$0\" \"$@\" 2>&1")
+            .arg(&path)
+            .args(args)
+            .current_dir(&self.git_dir)
+            .env("GIT_DIR", ".")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(v) => v,
+            Err(e) => return Some((false, format!("error: cannot run {}: {}\n", path.display(), e).into_bytes())),
+        };
+
+        // A hook may exit without reading its input, which then doesn't matter.
+        let mut input = child.stdin.take()?;
+        let data = stdin.as_bytes().to_vec();
+        let writer = std::thread::spawn(move || input.write_all(&data));
+        let mut output = Vec::new();
+        let _ = child.stdout.take()?.read_to_end(&mut output);
+        let _ = writer.join();
+        let success = child.wait().is_ok_and(|v| v.success());
+        Some((success, output))
+    }
+}
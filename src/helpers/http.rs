@@ -0,0 +1,776 @@
+use crate::helpers::clone::list_refs;
+use crate::helpers::git::GitRepo;
+use crate::helpers::pack::crc32;
+use crate::helpers::pkt_line::{pkt_delim, pkt_flush, pkt_read, pkt_write_line, Packet};
+use crate::helpers::remote::{RefStatus, RefUpdate, RemoteRefs};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Tells if `url` names a repository served over HTTP.
+pub fn is_http_url(url: &str) -> bool {
+    url.starts_with("http://")
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |acc, (i, &v)| acc | ((v as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[((bits >> (18 - 6 * i)) & 0x3f) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+/// Compresses `data` as gzip. The zlib stream holds the same deflate data between a two byte
+/// header and its checksum.
+fn gzip_encode(data: &[u8]) -> Vec<u8> {
+    let zlib = zune_inflate::DeflateEncoder::new(data).encode_zlib();
+    let mut gzip = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    gzip.extend_from_slice(&zlib[2..zlib.len() - 4]);
+    gzip.extend(crc32(data).to_le_bytes());
+    gzip.extend((data.len() as u32).to_le_bytes());
+    gzip
+}
+
+/// A request or a response: the line it starts with, its headers and its body, uncompressed.
+#[derive(Debug, Default)]
+pub struct HttpMessage {
+    pub start: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpMessage {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    /// Reads a message sized by `Content-Length` or sent in chunks, or, for a response without
+    /// either, one that ends with the connection.
+    fn read(input: &mut impl BufRead, response: bool) -> io::Result<HttpMessage> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        fn read_line(input: &mut impl BufRead) -> io::Result<String> {
+            let mut line = String::new();
+            input.read_line(&mut line)?;
+            Ok(line.trim_end_matches(['\r', '\n']).to_string())
+        }
+        let mut message = HttpMessage { start: read_line(input)?, ..Default::default() };
+        if message.start.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "empty message"));
+        }
+        loop {
+            let line = read_line(input)?;
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').ok_or_else(|| invalid("malformed header"))?;
+            message.headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+
+        let chunked = message.header("Transfer-Encoding").is_some_and(|v| v.eq_ignore_ascii_case("chunked"));
+        if chunked {
+            loop {
+                let size = read_line(input)?;
+                let size = usize::from_str_radix(size.split(';').next().unwrap_or_default().trim(), 16).map_err(|_| invalid("bad chunk size"))?;
+                if size == 0 {
+                    // Trailers end like the headers.
+                    while !read_line(input)?.is_empty() {}
+                    break;
+                }
+                let start = message.body.len();
+                message.body.resize(start + size, 0);
+                input.read_exact(&mut message.body[start..])?;
+                read_line(input)?;
+            }
+        } else if let Some(length) = message.header("Content-Length") {
+            message.body = vec![0; length.parse().map_err(|_| invalid("bad content length"))?];
+            input.read_exact(&mut message.body)?;
+        } else if response {
+            input.read_to_end(&mut message.body)?;
+        }
+
+        if message.header("Content-Encoding").is_some_and(|v| v.eq_ignore_ascii_case("gzip")) {
+            message.body = zune_inflate::DeflateDecoder::new(&message.body).decode_gzip().map_err(|e| invalid(&format!("{:?}", e)))?;
+        }
+        Ok(message)
+    }
+
+    /// Shows a plain text body, which a server explains errors with, as coming from it.
+    fn print_text(&self) {
+        if self.header("Content-Type").is_some_and(|v| v.starts_with("text/plain")) {
+            for line in String::from_utf8_lossy(&self.body).lines() {
+                eprintln!("remote: {}", line);
+            }
+        }
+    }
+
+    /// Sends the message with its length, compressing the body when `gzip` is set.
+    fn write(&self, output: &mut impl Write, gzip: bool) -> io::Result<()> {
+        let body = match gzip {
+            true => gzip_encode(&self.body),
+            false => self.body.clone(),
+        };
+        let mut head = format!("{}\r\n", self.start);
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if gzip {
+            head.push_str("Content-Encoding: gzip\r\n");
+        }
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+        output.write_all(head.as_bytes())?;
+        output.write_all(&body)?;
+        output.flush()
+    }
+}
+
+/// A username and password for a host, and whether it has worked yet.
+#[derive(Debug, Clone)]
+struct Credential {
+    username: String,
+    password: String,
+    approved: bool,
+}
+
+/// A repository served over the smart HTTP protocol.
+#[derive(Debug)]
+pub struct HttpRemote {
+    /// The URL without credentials or a trailing slash.
+    pub url: String,
+    /// Where to connect, `host:port`.
+    address: String,
+    /// The host as the `Host` header and the credential helpers give it.
+    host: String,
+    path: String,
+    username: Option<String>,
+    password: Option<String>,
+    /// The `credential.helper` commands, in order.
+    helpers: Vec<String>,
+    credential: RefCell<Option<Credential>>,
+}
+
+impl HttpRemote {
+    pub fn new(url: &str, helpers: Vec<String>) -> Result<HttpRemote, String> {
+        let rest = url.strip_prefix("http://").ok_or_else(|| format!("'{}' is not an http URL", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+        let (userinfo, host) = match authority.rsplit_once('@') {
+            Some((userinfo, host)) => (Some(userinfo), host),
+            None => (None, authority),
+        };
+        let (username, password) = match userinfo.map(|v| v.split_once(':').unwrap_or((v, ""))) {
+            Some((username, password)) => (Some(username.to_string()), Some(password.to_string()).filter(|v| !v.is_empty())),
+            None => (None, None),
+        };
+        if host.is_empty() {
+            return Err(format!("unable to access '{}': URL using bad/illegal format or missing URL", url));
+        }
+        let address = match host.rsplit_once(':') {
+            Some((_, port)) if port.parse::<u16>().is_ok() => host.to_string(),
+            _ => format!("{}:80", host),
+        };
+        let path = path.trim_end_matches('/').to_string();
+        Ok(HttpRemote {
+            url: format!("http://{}{}", host, path),
+            address,
+            host: host.to_string(),
+            path,
+            username,
+            password,
+            helpers,
+            credential: RefCell::new(None),
+        })
+    }
+
+    /// Sends one request for `target` below the repository URL, over a connection of its own.
+    fn send(&self, method: &str, target: &str, headers: &[(&str, &str)], body: Option<&[u8]>) -> Result<HttpMessage, String> {
+        let unable = |e: io::Error| format!("unable to access '{}/': {}", self.url, e);
+        let mut request = HttpMessage { start: format!("{} {}{} HTTP/1.1", method, self.path, target), ..Default::default() };
+        let agent = format!("mygit/{}", env!("CARGO_PKG_VERSION"));
+        let mut all: Vec<(&str, &str)> = vec![("Host", &self.host), ("User-Agent", &agent), ("Accept-Encoding", "gzip"), ("Connection", "close")];
+        all.extend_from_slice(headers);
+        let authorization = self
+            .credential
+            .borrow()
+            .as_ref()
+            .map(|v| format!("Basic {}", base64_encode(format!("{}:{}", v.username, v.password).as_bytes())));
+        if let Some(authorization) = &authorization {
+            all.push(("Authorization", authorization));
+        }
+        request.headers = all.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        request.body = body.unwrap_or_default().to_vec();
+
+        let stream = TcpStream::connect(&self.address).map_err(unable)?;
+        request.write(&mut &stream, body.is_some()).map_err(unable)?;
+        HttpMessage::read(&mut BufReader::new(&stream), true).map_err(unable)
+    }
+
+    /// Sends a request, asking the credential helpers for a username and password when the
+    /// server wants one, and telling them whether it worked.
+    fn request(&self, method: &str, target: &str, headers: &[(&str, &str)], body: Option<&[u8]>) -> Result<HttpMessage, String> {
+        loop {
+            let response = self.send(method, target, headers, body)?;
+            let status = response.start.split(' ').nth(1).unwrap_or_default().to_string();
+            let credential = self.credential.borrow().clone();
+            match (status.as_str(), credential) {
+                ("401", None) => *self.credential.borrow_mut() = Some(self.credential_fill()?),
+                ("401", Some(credential)) => {
+                    self.credential_helpers("erase", &credential);
+                    response.print_text();
+                    return Err(format!("Authentication failed for '{}/'", self.url));
+                }
+                ("200", credential) => {
+                    if let Some(credential) = credential.filter(|v| !v.approved) {
+                        self.credential_helpers("store", &credential);
+                        *self.credential.borrow_mut() = Some(Credential { approved: true, ..credential });
+                    }
+                    return Ok(response);
+                }
+                ("404", _) => {
+                    response.print_text();
+                    return Err(format!("repository '{}/' not found", self.url));
+                }
+                (status, _) => {
+                    response.print_text();
+                    return Err(format!("unable to access '{}/': The requested URL returned error: {}", self.url, status));
+                }
+            }
+        }
+    }
+
+    /// What the credential helpers are told about the server, and the credential if known.
+    fn credential_description(&self, username: Option<&str>, password: Option<&str>) -> String {
+        let mut description = format!("protocol=http\nhost={}\n", self.host);
+        if let Some(username) = username {
+            description.push_str(&format!("username={}\n", username));
+        }
+        if let Some(password) = password {
+            description.push_str(&format!("password={}\n", password));
+        }
+        description
+    }
+
+    /// Runs each helper with `action`, `get`, `store` or `erase`, handing it `input` and
+    /// returning what it prints.
+    fn credential_helper(helper: &str, action: &str, input: &str) -> Option<String> {
+        let command = match helper.strip_prefix('!') {
+            Some(command) => format!("{} {}", command, action),
+            None if Path::new(helper).is_absolute() => format!("{} {}", helper, action),
+            None => format!("git credential-{} {}", helper, action),
+        };
+        let mut child = Command::new("sh").arg("-c").arg(&command).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().ok()?;
+        // A helper may ignore its input.
+        let _ = child.stdin.take()?.write_all(input.as_bytes());
+        let mut output = String::new();
+        let _ = child.stdout.take()?.read_to_string(&mut output);
+        let _ = child.wait();
+        Some(output)
+    }
+
+    fn credential_helpers(&self, action: &str, credential: &Credential) {
+        let input = self.credential_description(Some(&credential.username), Some(&credential.password));
+        for helper in &self.helpers {
+            Self::credential_helper(helper, action, &input);
+        }
+    }
+
+    /// Works out the username and password, from the URL and then the helpers.
+    fn credential_fill(&self) -> Result<Credential, String> {
+        let (mut username, mut password) = (self.username.clone(), self.password.clone());
+        for helper in &self.helpers {
+            if username.is_some() && password.is_some() {
+                break;
+            }
+            let input = self.credential_description(username.as_deref(), password.as_deref());
+            let output = Self::credential_helper(helper, "get", &input).unwrap_or_default();
+            let mut quit = false;
+            for (key, value) in output.lines().filter_map(|v| v.split_once('=')) {
+                match key {
+                    "username" => username = Some(value.to_string()),
+                    "password" => password = Some(value.to_string()),
+                    "quit" => quit = value == "1" || value == "true",
+                    _ => {}
+                }
+            }
+            if quit {
+                break;
+            }
+        }
+        let username = username.ok_or_else(|| format!("could not read Username for 'http://{}': terminal prompts disabled", self.host))?;
+        let password = password.ok_or_else(|| format!("could not read Password for 'http://{}@{}': terminal prompts disabled", username, self.host))?;
+        Ok(Credential { username, password, approved: false })
+    }
+
+    /// Asks for the advertisement of `service`, checking it came from a smart server.
+    fn discover(&self, service: &str, headers: &[(&str, &str)]) -> Result<Vec<u8>, String> {
+        let response = self.request("GET", &format!("/info/refs?service={}", service), headers, None)?;
+        if response.header("Content-Type") != Some(&format!("application/x-{}-advertisement", service)) {
+            return Err(format!("{}/info/refs not valid: is this a git repository?", self.url));
+        }
+        // A server speaking protocol v2 may leave out the line naming the service.
+        let mut body = response.body.as_slice();
+        let mut peek = body;
+        if pkt_read(&mut peek).ok().and_then(|v| v.line()) == Some(format!("# service={}", service)) {
+            pkt_read(&mut peek).map_err(|e| e.to_string())?;
+            body = peek;
+        }
+        Ok(body.to_vec())
+    }
+
+    /// Sends a protocol v2 command with its arguments to `git-upload-pack`.
+    fn upload_pack_command(&self, command: &str, args: &[String]) -> Result<Vec<u8>, String> {
+        let mut body = Vec::new();
+        let io_error = |e: io::Error| e.to_string();
+        pkt_write_line(&mut body, &format!("command={}", command)).map_err(io_error)?;
+        pkt_write_line(&mut body, &format!("agent=mygit/{}", env!("CARGO_PKG_VERSION"))).map_err(io_error)?;
+        pkt_write_line(&mut body, "object-format=sha1").map_err(io_error)?;
+        pkt_delim(&mut body).map_err(io_error)?;
+        for arg in args {
+            pkt_write_line(&mut body, arg).map_err(io_error)?;
+        }
+        pkt_flush(&mut body).map_err(io_error)?;
+        let headers = [
+            ("Content-Type", "application/x-git-upload-pack-request"),
+            ("Accept", "application/x-git-upload-pack-result"),
+            ("Git-Protocol", "version=2"),
+        ];
+        Ok(self.request("POST", "/git-upload-pack", &headers, Some(&body))?.body)
+    }
+
+    /// Lists the refs of the repository with `ls-refs`, after checking the server speaks
+    /// protocol v2.
+    pub fn ls_refs(&self) -> Result<RemoteRefs, String> {
+        let advertisement = self.discover("git-upload-pack", &[("Git-Protocol", "version=2")])?;
+        if pkt_read(&mut advertisement.as_slice()).ok().and_then(|v| v.line()).as_deref() != Some("version 2") {
+            return Err(format!("{}/: server does not support protocol version 2", self.url));
+        }
+        let args = ["peel", "symrefs", "unborn"].map(|v| v.to_string());
+        let response = self.upload_pack_command("ls-refs", &args)?;
+
+        let mut refs = RemoteRefs::default();
+        let mut input = response.as_slice();
+        loop {
+            let line = match pkt_read(&mut input).map_err(|e| e.to_string())? {
+                Packet::Flush => break,
+                packet => packet.line().unwrap_or_default(),
+            };
+            if let Some(message) = line.strip_prefix("ERR ") {
+                return Err(format!("remote error: {}", message));
+            }
+            let mut parts = line.split(' ');
+            let (sha, name) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default().to_string());
+            for attribute in parts {
+                if let Some(target) = attribute.strip_prefix("symref-target:").filter(|_| name == "HEAD") {
+                    refs.head = Some(target.to_string());
+                } else if let Some(peeled) = attribute.strip_prefix("peeled:") {
+                    refs.peeled.insert(name.clone(), peeled.to_string());
+                }
+            }
+            if sha != "unborn" {
+                refs.refs.insert(name, sha.to_string());
+            }
+        }
+        Ok(refs)
+    }
+
+    /// Fetches the objects `wants` lead to that a repository with the commits `haves`, cut at
+    /// `shallow`, lacks, along with the tags pointing into them, `depth` commits deep if given.
+    ///
+    /// Returns the pack and the commits the history now stops at, and those it no longer does.
+    pub fn fetch_pack(&self, wants: &[String], haves: &[String], shallow: &[String], depth: Option<usize>) -> Result<FetchedPack, String> {
+        let mut args: Vec<String> = ["ofs-delta", "no-progress", "include-tag"].map(|v| v.to_string()).to_vec();
+        args.extend(wants.iter().map(|v| format!("want {}", v)));
+        args.extend(haves.iter().map(|v| format!("have {}", v)));
+        args.extend(shallow.iter().map(|v| format!("shallow {}", v)));
+        args.extend(depth.map(|v| format!("deepen {}", v)));
+        args.push("done".to_string());
+        let response = self.upload_pack_command("fetch", &args)?;
+
+        let mut fetched = FetchedPack::default();
+        let mut input = response.as_slice();
+        let io_error = |e: io::Error| e.to_string();
+        loop {
+            match pkt_read(&mut input).map_err(io_error)?.line().unwrap_or_default().as_str() {
+                "packfile" => break,
+                line if line.starts_with("ERR ") => return Err(format!("remote error: {}", &line[4..])),
+                line => match line.split_once(' ') {
+                    Some(("shallow", sha)) => fetched.shallow.push(sha.to_string()),
+                    Some(("unshallow", sha)) => fetched.unshallow.push(sha.to_string()),
+                    _ => {}
+                },
+            }
+        }
+        // The pack comes on side band 1, progress on 2 and errors on 3.
+        while let Packet::Data(data) = pkt_read(&mut input).map_err(io_error)? {
+            match data.split_first() {
+                Some((1, data)) => fetched.pack.extend_from_slice(data),
+                Some((2, data)) => eprint!("remote: {}", String::from_utf8_lossy(data)),
+                Some((3, data)) => return Err(format!("remote error: {}", String::from_utf8_lossy(data).trim_end())),
+                _ => {}
+            }
+        }
+        Ok(fetched)
+    }
+
+    /// Lists the refs a push would update, from the advertisement of `git-receive-pack`.
+    pub fn receive_refs(&self) -> Result<BTreeMap<String, String>, String> {
+        let advertisement = self.discover("git-receive-pack", &[])?;
+        let mut refs = BTreeMap::new();
+        let mut input = advertisement.as_slice();
+        while let Packet::Data(data) = pkt_read(&mut input).map_err(|e| e.to_string())? {
+            let line = String::from_utf8_lossy(&data);
+            let line = line.split('\0').next().unwrap_or_default().trim_end();
+            match line.split_once(' ') {
+                Some((_, "capabilities^{}")) | None => {}
+                Some((sha, name)) => {
+                    refs.insert(name.to_string(), sha.to_string());
+                }
+            }
+        }
+        Ok(refs)
+    }
+
+    /// Sends the ref updates `commands`, old and new values and the ref, with the `pack` they
+    /// need, and reads back what the server did with each: nothing when it took it, or why it
+    /// refused it. What the server printed along the way comes along in lines.
+    pub fn send_pack(&self, commands: &[(String, String, String)], pack: &[u8]) -> Result<PushReport, String> {
+        let io_error = |e: io::Error| e.to_string();
+        let mut body = Vec::new();
+        for (i, (old, new, name)) in commands.iter().enumerate() {
+            let line = match i {
+                0 => format!("{} {} {}\0report-status side-band-64k agent=mygit/{}", old, new, name, env!("CARGO_PKG_VERSION")),
+                _ => format!("{} {} {}", old, new, name),
+            };
+            pkt_write_line(&mut body, &line).map_err(io_error)?;
+        }
+        pkt_flush(&mut body).map_err(io_error)?;
+        body.extend_from_slice(pack);
+        let headers = [
+            ("Content-Type", "application/x-git-receive-pack-request"),
+            ("Accept", "application/x-git-receive-pack-result"),
+        ];
+        let response = self.request("POST", "/git-receive-pack", &headers, Some(&body))?;
+
+        let (mut status, mut messages) = (Vec::new(), Vec::new());
+        let mut input = response.body.as_slice();
+        while let Packet::Data(data) = pkt_read(&mut input).map_err(io_error)? {
+            match data.split_first() {
+                Some((1, data)) => status.extend_from_slice(data),
+                Some((2, data)) => messages.extend_from_slice(data),
+                Some((3, data)) => return Err(format!("remote error: {}", String::from_utf8_lossy(data).trim_end())),
+                _ => {}
+            }
+        }
+        let mut report = PushReport { messages: String::from_utf8_lossy(&messages).lines().map(|v| v.to_string()).collect(), ..Default::default() };
+        let mut input = status.as_slice();
+        while let Some(line) = pkt_read(&mut input).map_err(io_error)?.line() {
+            match line.split_once(' ') {
+                Some(("unpack", "ok")) => {}
+                Some(("unpack", error)) => report.messages.push(format!("error: remote unpack failed: {}", error)),
+                Some(("ok", name)) => {
+                    report.refs.insert(name.to_string(), None);
+                }
+                Some(("ng", rest)) => {
+                    let (name, reason) = rest.split_once(' ').unwrap_or((rest, "failed"));
+                    report.refs.insert(name.to_string(), Some(reason.to_string()));
+                }
+                _ => {}
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// The answer to a fetch: the pack and how the history of the client is cut.
+#[derive(Debug, Default)]
+pub struct FetchedPack {
+    pub pack: Vec<u8>,
+    pub shallow: Vec<String>,
+    pub unshallow: Vec<String>,
+}
+
+/// The answer to a push: for each ref reported, why the server refused it if it did, and what
+/// it printed.
+#[derive(Debug, Default)]
+pub struct PushReport {
+    pub refs: BTreeMap<String, Option<String>>,
+    pub messages: Vec<String>,
+}
+
+impl GitRepo {
+    /// The `credential.helper` commands, an empty one dropping those before it.
+    pub fn credential_helpers(&self) -> Vec<String> {
+        let mut helpers = Vec::new();
+        for value in self.config.get_all("credential.helper").iter().filter_map(|v| v.value.clone()) {
+            match value.is_empty() {
+                true => helpers.clear(),
+                false => helpers.push(value),
+            }
+        }
+        helpers
+    }
+
+    /// Sends `remote` the updates that have something to do, with the objects it lacks,
+    /// marking those it refuses and adding what it printed to `messages`.
+    pub fn http_push(&self, remote: &HttpRemote, updates: &mut [RefUpdate], remote_refs: &BTreeMap<String, String>, messages: &mut Vec<String>) -> Result<(), String> {
+        let pending: Vec<&mut RefUpdate> = updates.iter_mut().filter(|v| v.ok() && v.status != RefStatus::UpToDate).collect();
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let null = "0".repeat(40);
+        let commands: Vec<(String, String, String)> = pending
+            .iter()
+            .map(|v| (v.old.clone().unwrap_or(null.clone()), v.new.clone().unwrap_or(null.clone()), v.dst.clone().unwrap_or_default()))
+            .collect();
+        // Only deletions need no pack.
+        let wants: Vec<String> = pending.iter().filter_map(|v| v.new.clone()).collect();
+        let pack = match wants.is_empty() {
+            true => Vec::new(),
+            false => {
+                let haves: Vec<String> = remote_refs.values().cloned().collect();
+                self.pack_build(&self.objects_missing(&wants, &haves)?)?
+            }
+        };
+
+        let report = remote.send_pack(&commands, &pack)?;
+        messages.extend(report.messages);
+        for update in pending {
+            match report.refs.get(update.dst.as_deref().unwrap_or_default()) {
+                Some(None) => {}
+                Some(Some(reason)) => update.status = RefStatus::RemoteRejected(reason.clone()),
+                None => update.status = RefStatus::RemoteRejected("remote failed to report status".to_string()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetches what `wants` lead to from `remote`, `depth` commits deep if given, and records
+    /// where the history is cut.
+    pub fn http_fetch(&self, remote: &HttpRemote, wants: &[String], depth: Option<usize>) -> Result<(), String> {
+        let wants: Vec<String> = wants.iter().filter(|v| depth.is_some() || !self.object_exists(v)).cloned().collect();
+        if wants.is_empty() {
+            return Ok(());
+        }
+        let haves: Vec<String> = list_refs(&self.common_dir).into_values().filter(|v| self.object_exists(v)).collect();
+        let shallow_file = self.common_dir.join("shallow");
+        let shallow = fs::read_to_string(&shallow_file).unwrap_or_default();
+        let mut shallow: Vec<String> = shallow.lines().map(|v| v.to_string()).collect();
+
+        let fetched = remote.fetch_pack(&wants, &haves, &shallow, depth)?;
+        let objects = self.pack_parse(&fetched.pack)?;
+        self.pack_unpack(&objects, "fetch.unpackLimit")?;
+        if fetched.shallow.is_empty() && fetched.unshallow.is_empty() {
+            return Ok(());
+        }
+        shallow.retain(|v| !fetched.unshallow.contains(v));
+        shallow.extend(fetched.shallow);
+        shallow.sort();
+        shallow.dedup();
+        match shallow.is_empty() {
+            true => fs::remove_file(&shallow_file).map_err(|e| e.to_string()),
+            false => fs::write(&shallow_file, shallow.iter().map(|v| format!("{}\n", v)).collect::<String>()).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::clone::local_git_dir;
+    use crate::helpers::file::create_new_my_git;
+    use std::net::TcpListener;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Component, PathBuf};
+
+    /// Answers one request for a repository below `root`, asking for the `authorization` header
+    /// given, if any.
+    fn http_answer(root: &Path, authorization: Option<&str>, request: &HttpMessage) -> HttpMessage {
+        let respond = |status: &str, content_type: &str, body: Vec<u8>| HttpMessage {
+            start: format!("HTTP/1.1 {}", status),
+            headers: vec![
+                ("Content-Type".to_string(), content_type.to_string()),
+                ("Cache-Control".to_string(), "no-cache".to_string()),
+                ("Connection".to_string(), "close".to_string()),
+            ],
+            body,
+        };
+        let not_found = || respond("404 Not Found", "text/plain", b"Repository not found\n".to_vec());
+        if authorization.is_some() && request.header("Authorization") != authorization {
+            let mut response = respond("401 Unauthorized", "text/plain", b"Authentication required\n".to_vec());
+            response.headers.push(("WWW-Authenticate".to_string(), "Basic realm=\"mygit\"".to_string()));
+            return response;
+        }
+
+        let mut parts = request.start.split(' ');
+        let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let service = query.split('&').find_map(|v| v.strip_prefix("service="));
+        let (repository, action) = match path.rsplit_once('/') {
+            Some((repository, "refs")) if repository.ends_with("/info") => (repository.trim_end_matches("/info"), "info/refs"),
+            Some((repository, action)) => (repository, action),
+            None => return not_found(),
+        };
+        // Only repositories below the root are served, so `..` and the like are refused.
+        let repository = Path::new(repository.trim_start_matches('/'));
+        if !repository.components().all(|v| matches!(v, Component::Normal(_))) {
+            return not_found();
+        }
+        let git_dir = match local_git_dir(&root.join(repository).to_string_lossy()) {
+            Some(v) => v,
+            None => return not_found(),
+        };
+        let repo = GitRepo::from_dirs(None, git_dir, false);
+
+        let mut body = Vec::new();
+        let result = match (method, action, service) {
+            ("GET", "info/refs", Some("git-upload-pack")) => {
+                // Like `git http-backend`, only a client not asking for protocol v2 gets the
+                // service line.
+                if !request.header("Git-Protocol").is_some_and(|v| v.contains("version=2")) {
+                    let _ = pkt_write_line(&mut body, "# service=git-upload-pack").and_then(|_| pkt_flush(&mut body));
+                }
+                repo.upload_pack_advertise(&mut body)
+            }
+            ("GET", "info/refs", Some("git-receive-pack")) => {
+                let _ = pkt_write_line(&mut body, "# service=git-receive-pack").and_then(|_| pkt_flush(&mut body));
+                repo.receive_pack_advertise(&mut body)
+            }
+            ("POST", "git-upload-pack", None) => repo.upload_pack_commands(&mut request.body.as_slice(), &mut body),
+            ("POST", "git-receive-pack", None) => repo.receive_pack_commands(&mut request.body.as_slice(), &mut body),
+            _ => return not_found(),
+        };
+        // Errors reach the client as a packet.
+        if let Err(e) = result {
+            let _ = pkt_write_line(&mut body, &format!("ERR {}", e));
+        }
+        let content_type = match (action, service) {
+            ("info/refs", Some(service)) => format!("application/x-{}-advertisement", service),
+            (action, _) => format!("application/x-{}-result", action),
+        };
+        respond("200 OK", &content_type, body)
+    }
+
+    /// Serves the repositories below `root` over smart HTTP to the clients connecting to
+    /// `listener`, one request at a time, asking for `credential`, `user:password`, if given.
+    fn http_serve(listener: &TcpListener, root: &Path, credential: Option<&str>) -> Result<(), String> {
+        let authorization = credential.map(|v| format!("Basic {}", base64_encode(v.as_bytes())));
+        for stream in listener.incoming() {
+            let stream = stream.map_err(|e| e.to_string())?;
+            let request = match HttpMessage::read(&mut BufReader::new(&stream), false) {
+                Ok(v) => v,
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    continue;
+                }
+            };
+            let response = http_answer(root, authorization.as_deref(), &request);
+            eprintln!("{} {}", request.start, response.start.trim_start_matches("HTTP/1.1 "));
+            let gzip = !response.body.is_empty() && request.header("Accept-Encoding").is_some_and(|v| v.contains("gzip"));
+            if let Err(e) = response.write(&mut &stream, gzip) {
+                eprintln!("error: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a credential helper that logs each action it is run with to `log` and answers
+    /// `get` with `u` and `password`.
+    fn credential_stub(dir: &Path, name: &str, password: &str) -> (String, PathBuf) {
+        let (script, log) = (dir.join(name), dir.join(format!("{}.log", name)));
+        let content = format!(
+            "#!/bin/sh\necho \"$1\" >> '{}'\ntest \"$1\" = get && printf 'username=u\\npassword={}\\n'\nexit 0\n",
+            log.display(),
+            password
+        );
+        fs::write(&script, content).unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        (script.to_string_lossy().into_owned(), log)
+    }
+
+    #[test]
+    fn push_and_fetch_through_local_server() {
+        let root = std::env::temp_dir().join(format!("mygit-http-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        create_new_my_git(root.join("srv.git"), true).unwrap();
+        create_new_my_git(root.join("client.git"), true).unwrap();
+        let client = GitRepo::from_dirs(None, root.join("client.git"), false);
+
+        let blob = client.object_store(b"blob", b"hello\n").unwrap();
+        let tree = client.object_store(b"tree", &[&b"100644 hello.txt\0"[..], &hex::decode(&blob).unwrap()].concat()).unwrap();
+        let commit_data = format!(
+            "tree {}\nauthor A U Thor <a@example.com> 0 +0000\ncommitter A U Thor <a@example.com> 0 +0000\n\nfirst\n",
+            tree
+        );
+        let commit = client.object_store(b"commit", commit_data.as_bytes()).unwrap();
+
+        // The server wants `u:p`.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/srv.git", listener.local_addr().unwrap());
+        let served = root.clone();
+        std::thread::spawn(move || http_serve(&listener, &served, Some("u:p")));
+
+        let (helper, log) = credential_stub(&root, "good", "p");
+        let remote = HttpRemote::new(&url, vec![helper]).unwrap();
+        assert!(remote.receive_refs().unwrap().is_empty());
+        let pack = client.pack_build(&[commit.clone(), tree.clone(), blob.clone()]).unwrap();
+        let report = remote.send_pack(&[("0".repeat(40), commit.clone(), "refs/heads/main".to_string())], &pack).unwrap();
+        assert_eq!(report.refs.get("refs/heads/main"), Some(&None));
+
+        let listed = remote.ls_refs().unwrap();
+        assert_eq!(listed.refs.get("refs/heads/main"), Some(&commit));
+        let fetched = remote.fetch_pack(std::slice::from_ref(&commit), &[], &[], None).unwrap();
+        let mut received: Vec<String> = client
+            .pack_parse(&fetched.pack)
+            .unwrap()
+            .iter()
+            .map(|(kind, data)| GitRepo::object_hash(kind, data))
+            .collect();
+        received.sort();
+        let mut expected = vec![commit, tree, blob];
+        expected.sort();
+        assert_eq!(received, expected);
+        // Asked once on the first 401, told it worked on the first 200.
+        assert_eq!(fs::read_to_string(&log).unwrap(), "get\nstore\n");
+
+        // A password the server refuses is erased from the helpers.
+        let (helper, log) = credential_stub(&root, "bad", "wrong");
+        let error = HttpRemote::new(&url, vec![helper]).unwrap().ls_refs().unwrap_err();
+        assert_eq!(error, format!("Authentication failed for '{}/'", url));
+        assert_eq!(fs::read_to_string(&log).unwrap(), "get\nerase\n");
+
+        // Requests and responses both go compressed when the client accepts it.
+        let mut body = Vec::new();
+        pkt_write_line(&mut body, "command=ls-refs").unwrap();
+        pkt_flush(&mut body).unwrap();
+        let request = HttpMessage {
+            start: "POST /srv.git/git-upload-pack HTTP/1.1".to_string(),
+            headers: vec![
+                ("Authorization".to_string(), format!("Basic {}", base64_encode(b"u:p"))),
+                ("Accept-Encoding".to_string(), "gzip".to_string()),
+                ("Git-Protocol".to_string(), "version=2".to_string()),
+            ],
+            body,
+        };
+        let stream = TcpStream::connect(url.trim_start_matches("http://").trim_end_matches("/srv.git")).unwrap();
+        request.write(&mut &stream, true).unwrap();
+        let response = HttpMessage::read(&mut BufReader::new(&stream), true).unwrap();
+        assert_eq!(response.start, "HTTP/1.1 200 OK");
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert!(String::from_utf8_lossy(&response.body).contains("refs/heads/main"));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
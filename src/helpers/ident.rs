@@ -0,0 +1,136 @@
+use crate::helpers::git::GitRepo;
+use chrono::{DateTime, FixedOffset, Local, TimeZone};
+
+/// Date layout git uses for the `Date:` lines of `log` and `show`.
+pub const DATE_FORMAT: &str = "%a %b %-d %H:%M:%S %Y %z";
+
+/// A `Name <email> timestamp timezone` line, as stored in the `author`,
+/// `committer` and `tagger` headers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitIdent {
+    pub name: String,
+    pub email: String,
+    pub timestamp: i64,
+    pub timezone: String,
+}
+
+impl GitIdent {
+    pub fn parse(raw: &[u8]) -> Option<Self> {
+        let raw = String::from_utf8_lossy(raw);
+        let open = raw.find('<')?;
+        let close = open + raw[open..].find('>')?;
+        let name = raw[..open].trim().to_string();
+        let email = raw[open + 1..close].to_string();
+
+        let mut rest = raw[close + 1..].split_whitespace();
+        let timestamp = rest.next().and_then(|v| v.parse().ok()).unwrap_or(0);
+        let timezone = rest.next().unwrap_or("+0000").to_string();
+
+        Some(Self {
+            name,
+            email,
+            timestamp,
+            timezone,
+        })
+    }
+
+    /// Builds an identity stamped with the current time and local timezone.
+    pub fn now(name: String, email: String) -> Self {
+        let now = Local::now();
+        let offset = now.offset().local_minus_utc() / 60;
+        let sign = if offset < 0 { '-' } else { '+' };
+        Self {
+            name,
+            email,
+            timestamp: now.timestamp(),
+            timezone: format!("{}{:02}{:02}", sign, offset.abs() / 60, offset.abs() % 60),
+        }
+    }
+
+    /// Parses the date formats accepted in `GIT_AUTHOR_DATE`/`GIT_COMMITTER_DATE`:
+    /// the raw `<timestamp> <tz>` form (optionally prefixed with `@`), RFC 2822 and ISO 8601.
+    pub fn parse_date(value: &str) -> Option<(i64, String)> {
+        let value = value.trim();
+        let mut parts = value.trim_start_matches('@').split_whitespace();
+        if let Some(Ok(timestamp)) = parts.next().map(|v| v.parse::<i64>()) {
+            let timezone = parts.next().unwrap_or("+0000").to_string();
+            return Some((timestamp, timezone));
+        }
+        let date = DateTime::parse_from_rfc2822(value)
+            .or_else(|_| DateTime::parse_from_rfc3339(value))
+            .ok()?;
+        Some((date.timestamp(), date.format("%z").to_string()))
+    }
+
+    pub fn offset(&self) -> FixedOffset {
+        let value = self.timezone.parse::<i32>().unwrap_or(0);
+        let seconds = (value.abs() / 100) * 3600 + (value.abs() % 100) * 60;
+        let seconds = if value < 0 { -seconds } else { seconds };
+        FixedOffset::east_opt(seconds).unwrap_or(FixedOffset::east_opt(0).unwrap())
+    }
+
+    pub fn date(&self) -> DateTime<FixedOffset> {
+        let utc = DateTime::from_timestamp(self.timestamp, 0).unwrap_or_default();
+        self.offset().from_utc_datetime(&utc.naive_utc())
+    }
+
+    /// Formats the date the way git's default `log`/`show` output does.
+    pub fn date_string(&self) -> String {
+        self.date().format(DATE_FORMAT).to_string()
+    }
+
+    /// `Name <email>` without the timestamp.
+    pub fn who(&self) -> String {
+        format!("{} <{}>", self.name, self.email)
+    }
+}
+
+impl std::fmt::Display for GitIdent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} <{}> {} {}",
+            self.name, self.email, self.timestamp, self.timezone
+        )
+    }
+}
+
+impl GitRepo {
+    /// Resolves the identity used for `role` ("AUTHOR" or "COMMITTER").
+    ///
+    /// `GIT_<role>_NAME`, `GIT_<role>_EMAIL` and `GIT_<role>_DATE` win over `user.name`
+    /// and `user.email` from the configuration, like they do in git.
+    pub fn ident(&self, role: &str) -> Result<GitIdent, String> {
+        let name = std::env::var(format!("GIT_{}_NAME", role))
+            .ok()
+            .or_else(|| self.config_get("user.name"));
+        let email = std::env::var(format!("GIT_{}_EMAIL", role))
+            .ok()
+            .or_else(|| self.config_get("user.email"))
+            .or_else(|| std::env::var("EMAIL").ok());
+
+        let (name, email) = match (name, email) {
+            (Some(name), Some(email)) if !name.trim().is_empty() => (name, email),
+            _ => {
+                return Err(format!(
+                    "{} identity unknown\n\n\
+                     *** Please tell me who you are.\n\n\
+                     Set user.name and user.email in the configuration, or use\n\
+                     GIT_{}_NAME and GIT_{}_EMAIL.",
+                    role.to_lowercase(),
+                    role,
+                    role
+                ))
+            }
+        };
+
+        let mut ident = GitIdent::now(name.trim().to_string(), email.trim().to_string());
+        if let Ok(date) = std::env::var(format!("GIT_{}_DATE", role)) {
+            let (timestamp, timezone) = GitIdent::parse_date(&date)
+                .ok_or_else(|| format!("invalid date format: {}", date))?;
+            ident.timestamp = timestamp;
+            ident.timezone = timezone;
+        }
+        Ok(ident)
+    }
+}
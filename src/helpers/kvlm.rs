@@ -1,13 +1,12 @@
 use ordermap::OrderMap;
-use sha1::digest::typenum::Integer;
 use std::hash::RandomState;
 
-pub fn kvlm_parse<'a>(
-    raw: &'a [u8],
+pub fn kvlm_parse(
+    raw: &[u8],
     start: Option<usize>,
     dct: Option<OrderMap<Vec<u8>, Vec<Vec<u8>>, RandomState>>,
 ) -> OrderMap<Vec<u8>, Vec<Vec<u8>>, RandomState> {
-    let mut dct = dct.unwrap_or(OrderMap::new());
+    let mut dct = dct.unwrap_or_default();
     let start = start.unwrap_or(0);
 
     let spc = raw
@@ -48,18 +47,17 @@ pub fn kvlm_parse<'a>(
         }
     }
 
+    // Continuation lines (e.g. a multi-line `gpgsig`) start with a single space.
     let slice: &[u8] = &raw[spc + 1..end];
-    let data = slice
-        .chunks(2)
-        .flat_map(|chunk| {
-            if chunk == b"\n ".as_ref() {
-                b"\n".iter().copied()
-            } else {
-                chunk.iter().copied()
-            }
-        })
-        .collect::<Vec<u8>>()
-        .to_owned();
+    let mut data = Vec::with_capacity(slice.len());
+    let mut i = 0;
+    while i < slice.len() {
+        data.push(slice[i]);
+        if slice[i] == b'\n' && slice.get(i + 1) == Some(&b' ') {
+            i += 1;
+        }
+        i += 1;
+    }
 
     if let Some(existing) = dct.get_mut(key) {
         existing.push(data);
@@ -70,40 +68,33 @@ pub fn kvlm_parse<'a>(
     kvlm_parse(raw, Some(end + 1), Some(dct))
 }
 
-pub fn kvlm_serialize(kvlm: &OrderMap<Vec<u8>, Vec<Vec<u8>>, RandomState>) -> String {
-    let mut ret = String::new();
+pub fn kvlm_serialize(kvlm: &OrderMap<Vec<u8>, Vec<Vec<u8>>, RandomState>) -> Vec<u8> {
+    let mut ret = Vec::new();
 
     for (key, values) in kvlm.iter() {
         if key == b"None" {
             continue;
         }
-        // Convert key to a String
-        if let Ok(key_str) = String::from_utf8(key.clone()) {
-            for value in values {
-                // Convert value to a String
-                if let Ok(value_str) = String::from_utf8(value.clone()) {
-                    // Replace '\n' with '\n '
-                    let replaced_value = value_str.replace('\n', "\n ");
-
-                    // Append key, replaced value, and newline
-                    ret.push_str(&key_str);
-                    ret.push(' ');
-                    ret.push_str(&replaced_value);
-                    ret.push('\n');
+        for value in values {
+            ret.extend_from_slice(key);
+            ret.push(b' ');
+            // Every line after the first one is continued with a leading space.
+            for byte in value {
+                ret.push(*byte);
+                if *byte == b'\n' {
+                    ret.push(b' ');
                 }
             }
+            ret.push(b'\n');
         }
     }
 
-    // Append message
+    // Append message, which already carries its own trailing newline.
     if let Some(message) = kvlm.get(&b"None".to_vec()) {
-        ret.push('\n');
+        ret.push(b'\n');
         for line in message {
-            if let Ok(line_str) = String::from_utf8(line.clone()) {
-                ret.push_str(&line_str);
-            }
+            ret.extend_from_slice(line);
         }
-        ret.push('\n');
     }
 
     ret
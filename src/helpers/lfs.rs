@@ -0,0 +1,262 @@
+use crate::helpers::clone::copy_missing;
+use crate::helpers::git::GitRepo;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::path::PathBuf;
+
+const LFS_SPEC: &str = "https://git-lfs.github.com/spec/v1";
+
+/// Pointer files are small; anything bigger is real content.
+const LFS_POINTER_MAX_SIZE: usize = 1024;
+
+/// The blob stored in place of a large file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LfsPointer {
+    pub oid: String,
+    pub size: u64,
+}
+
+impl LfsPointer {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() > LFS_POINTER_MAX_SIZE {
+            return None;
+        }
+        let text = std::str::from_utf8(data).ok()?;
+        let mut lines = text.lines();
+        if lines.next()? != format!("version {}", LFS_SPEC) {
+            return None;
+        }
+        let (mut oid, mut size) = (None, None);
+        for line in lines {
+            let (key, value) = line.split_once(' ')?;
+            match key {
+                "oid" => {
+                    let hash = value.strip_prefix("sha256:")?;
+                    if hash.len() != 64 || !hash.bytes().all(|c| c.is_ascii_hexdigit()) {
+                        return None;
+                    }
+                    oid = Some(hash.to_ascii_lowercase());
+                }
+                "size" => size = Some(value.parse().ok()?),
+                _ => {}
+            }
+        }
+        Some(LfsPointer { oid: oid?, size: size? })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        format!("version {}\noid sha256:{}\nsize {}\n", LFS_SPEC, self.oid, self.size).into_bytes()
+    }
+}
+
+/// Formats a byte count the way `git lfs` prints sizes.
+pub fn format_size(size: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < units.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", size)
+    } else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
+
+impl GitRepo {
+    /// Where the content of `oid` lives: `.git/lfs/objects/ab/cd/abcd...`.
+    pub fn lfs_object_path(&self, oid: &str) -> PathBuf {
+        self.common_dir
+            .join("lfs/objects")
+            .join(&oid[0..2])
+            .join(&oid[2..4])
+            .join(oid)
+    }
+
+    /// Moves worktree content into the local store and returns its pointer blob.
+    pub fn lfs_clean(&self, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        // Already a pointer, e.g. a file whose object was never smudged.
+        if LfsPointer::parse(&data).is_some() {
+            return Ok(data);
+        }
+        let pointer = LfsPointer {
+            oid: hex::encode(Sha256::digest(&data)),
+            size: data.len() as u64,
+        };
+        let path = self.lfs_object_path(&pointer.oid);
+        if !path.exists() {
+            let dir = path.parent().unwrap();
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            // Write next to the object and rename it so a reader never sees half of it.
+            let tmp = dir.join(format!("{}.tmp", pointer.oid));
+            std::fs::write(&tmp, &data).map_err(|e| e.to_string())?;
+            std::fs::rename(&tmp, &path).map_err(|e| e.to_string())?;
+        }
+        Ok(pointer.serialize())
+    }
+
+    /// Turns a pointer blob back into the content from the local store.
+    ///
+    /// Pointers whose object is missing are written as they are, so checkout works offline.
+    pub fn lfs_smudge(&self, path: &str, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        let pointer = match LfsPointer::parse(&data) {
+            Some(v) => v,
+            None => return Ok(data),
+        };
+        match std::fs::read(self.lfs_object_path(&pointer.oid)) {
+            Ok(v) => Ok(v),
+            Err(_) => {
+                eprintln!("warning: {}: object {} is not in the local LFS store", path, pointer.oid);
+                Ok(data)
+            }
+        }
+    }
+
+    /// Finds the pointer blobs of the tree that `reference` names.
+    pub fn lfs_pointers_in_tree(&self, reference: &str) -> Result<BTreeMap<String, LfsPointer>, String> {
+        let tree = self.obj_find(reference.to_string(), Some("tree".to_string()), None)?;
+        Ok(self
+            .tree_leafs_flat(&tree, "")
+            .into_iter()
+            .filter(|(_, leaf)| !leaf.mode.starts_with("16"))
+            .filter_map(|(path, leaf)| Some((path, self.lfs_pointer_of_blob(&leaf.sha)?)))
+            .collect())
+    }
+
+    /// Finds the pointer blobs staged in the index.
+    pub fn lfs_pointers_in_index(&self) -> BTreeMap<String, LfsPointer> {
+        self.index_read()
+            .entries
+            .iter()
+            .filter(|e| e.mode_type == 0b1000)
+            .filter_map(|e| Some((e.name.clone(), self.lfs_pointer_of_blob(&e.sha)?)))
+            .collect()
+    }
+
+    fn lfs_pointer_of_blob(&self, sha: &str) -> Option<LfsPointer> {
+        let obj = self.object_read(sha.to_string()).ok()?;
+        if obj.format() != b"blob".to_vec() {
+            return None;
+        }
+        LfsPointer::parse(&obj.data())
+    }
+
+    /// Lists the oids in the local store.
+    pub fn lfs_local_objects(&self) -> BTreeSet<String> {
+        let mut ret = BTreeSet::new();
+        let root = self.common_dir.join("lfs/objects");
+        let dirs = |path: &std::path::Path| -> Vec<PathBuf> {
+            std::fs::read_dir(path)
+                .map(|v| v.flatten().map(|v| v.path()).collect())
+                .unwrap_or_default()
+        };
+        for first in dirs(&root) {
+            for second in dirs(&first) {
+                for object in dirs(&second) {
+                    let name = object.file_name().unwrap().to_string_lossy().into_owned();
+                    if name.len() == 64 && object.is_file() {
+                        ret.insert(name);
+                    }
+                }
+            }
+        }
+        ret
+    }
+
+    /// Tells the oids that must stay: the ones in any commit reachable from a ref or from the
+    /// HEAD of a worktree, and in the index of every worktree.
+    pub fn lfs_retained_objects(&self) -> BTreeSet<String> {
+        let mut references: Vec<String> = self.ref_list(None, String::new()).unwrap_or_default().into_values().collect();
+        let mut ret = BTreeSet::new();
+        for worktree in self.worktrees() {
+            references.extend(worktree.head);
+            let git_dir = match &worktree.id {
+                Some(id) => self.common_dir.join("worktrees").join(id),
+                None => self.common_dir.clone(),
+            };
+            let repo = GitRepo::from_dirs(Some(worktree.path), git_dir, true);
+            ret.extend(repo.lfs_pointers_in_index().into_values().map(|v| v.oid));
+        }
+
+        // Refs to trees or blobs have no history; keep what they name as it is.
+        let mut queue: VecDeque<String> = references
+            .into_iter()
+            .map(|v| self.obj_find(v.clone(), Some("commit".to_string()), None).unwrap_or(v))
+            .collect();
+        let (mut seen, mut blobs) = (HashSet::new(), HashSet::new());
+        while let Some(sha) = queue.pop_front() {
+            if !seen.insert(sha.clone()) {
+                continue;
+            }
+            queue.extend(self.commit_parents(&sha));
+            let tree = match self.obj_find(sha, Some("tree".to_string()), None) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            for (_, leaf) in self.tree_leafs_flat(&tree, "") {
+                // Commits share most of their blobs; read each one once.
+                if !leaf.mode.starts_with("16") && blobs.insert(leaf.sha.clone()) {
+                    ret.extend(self.lfs_pointer_of_blob(&leaf.sha).map(|v| v.oid));
+                }
+            }
+        }
+        ret
+    }
+
+    /// Copies the content of `source`'s store that this one lacks, as hard links when `link`
+    /// is set, since local transports only move the pointer blobs.
+    pub fn lfs_objects_copy(&self, source: &GitRepo, link: bool) -> Result<(), String> {
+        copy_missing(&source.common_dir.join("lfs/objects"), &self.common_dir.join("lfs/objects"), link)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Checks that the content of `oid` is in the store and still hashes to it.
+    pub fn lfs_object_check(&self, oid: &str) -> Result<bool, String> {
+        let data = std::fs::read(self.lfs_object_path(oid)).map_err(|e| e.to_string())?;
+        Ok(hex::encode(Sha256::digest(&data)) == oid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::file::create_new_my_git;
+
+    #[test]
+    fn prune_keeps_the_objects_of_older_commits() {
+        let dir = std::env::temp_dir().join(format!("mygit-lfs-prune-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        create_new_my_git(dir.clone(), true).unwrap();
+        let repo = GitRepo::from_dirs(None, dir.clone(), false);
+
+        let mut parent = None;
+        let mut pointers = Vec::new();
+        for content in [&b"first version\n"[..], b"second version\n"] {
+            let pointer = repo.lfs_clean(content.to_vec()).unwrap();
+            let blob = repo.object_store(b"blob", &pointer).unwrap();
+            let tree = repo.object_store(b"tree", &[&b"100644 big.bin\0"[..], &hex::decode(&blob).unwrap()].concat()).unwrap();
+            let commit = format!(
+                "tree {}\n{}author A U Thor <a@example.com> 0 +0000\ncommitter A U Thor <a@example.com> 0 +0000\n\nedit\n",
+                tree,
+                parent.as_ref().map(|v| format!("parent {}\n", v)).unwrap_or_default()
+            );
+            parent = Some(repo.object_store(b"commit", commit.as_bytes()).unwrap());
+            pointers.push(pointer);
+        }
+        std::fs::write(dir.join("refs/heads/master"), format!("{}\n", parent.unwrap())).unwrap();
+        let unused = LfsPointer::parse(&repo.lfs_clean(b"never committed\n".to_vec()).unwrap()).unwrap();
+
+        // What `lfs prune` deletes.
+        let retained = repo.lfs_retained_objects();
+        for oid in repo.lfs_local_objects().iter().filter(|v| !retained.contains(*v)) {
+            std::fs::remove_file(repo.lfs_object_path(oid)).unwrap();
+        }
+
+        assert!(!repo.lfs_object_path(&unused.oid).exists());
+        assert_eq!(repo.lfs_smudge("big.bin", pointers[0].clone()).unwrap(), b"first version\n");
+        assert_eq!(repo.lfs_smudge("big.bin", pointers[1].clone()).unwrap(), b"second version\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,31 @@
-mod config;
+pub mod alias;
+pub mod branch;
+pub mod clean;
+pub mod clone;
+pub mod config;
+pub mod convert;
+pub mod describe;
+pub mod diff;
+pub mod editor;
 pub mod file;
+pub mod filter;
 pub(crate) mod git;
 pub mod git_objects;
+pub mod hooks;
+pub mod http;
+pub mod ident;
 pub mod kvlm;
+pub mod lfs;
+pub mod pack;
 pub mod pager;
+pub mod pkt_line;
+pub mod receive_pack;
+pub mod refspec;
+pub mod remote;
+pub mod show;
+pub mod signing;
+pub mod sparse;
+pub mod submodule;
+pub mod upload_pack;
+pub mod wildmatch;
+pub mod worktree;
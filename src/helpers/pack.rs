@@ -0,0 +1,467 @@
+use crate::helpers::git::GitRepo;
+use sha1::Digest;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::rc::Rc;
+
+/// A packfile under `objects/pack`, read along with its version 2 `.idx`.
+pub struct Pack {
+    index: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl std::fmt::Debug for Pack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Pack({} objects)", self.len())
+    }
+}
+
+/// The type and content of an object.
+pub type RawObject = (Vec<u8>, Vec<u8>);
+
+const OBJECT_TYPES: [&[u8]; 5] = [b"", b"commit", b"tree", b"blob", b"tag"];
+
+impl Pack {
+    pub fn open(index_path: &Path) -> Result<Pack, String> {
+        let index = std::fs::read(index_path).map_err(|e| e.to_string())?;
+        let data = std::fs::read(index_path.with_extension("pack")).map_err(|e| e.to_string())?;
+        if index.len() < 8 + 256 * 4 || index[..4] != *b"\xfftOc" || index[4..8] != [0, 0, 0, 2] {
+            return Err(format!("index file {} has unsupported version", index_path.display()));
+        }
+        if data.len() < 12 || data[..4] != *b"PACK" {
+            return Err(format!("packfile {} is not a pack", index_path.with_extension("pack").display()));
+        }
+        Ok(Pack { index, data })
+    }
+
+    fn u32_at(&self, pos: usize) -> u32 {
+        u32::from_be_bytes(self.index[pos..pos + 4].try_into().unwrap())
+    }
+
+    pub fn len(&self) -> usize {
+        self.u32_at(8 + 255 * 4) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn sha_at(&self, i: usize) -> &[u8] {
+        let pos = 8 + 256 * 4 + i * 20;
+        &self.index[pos..pos + 20]
+    }
+
+    fn offset_at(&self, i: usize) -> usize {
+        let n = self.len();
+        let offset = self.u32_at(8 + 256 * 4 + n * 24 + i * 4);
+        if offset & 0x8000_0000 == 0 {
+            return offset as usize;
+        }
+        // Offsets past 2GiB are kept in a table of 64 bit ones after the others.
+        let pos = 8 + 256 * 4 + n * 28 + (offset & 0x7fff_ffff) as usize * 8;
+        u64::from_be_bytes(self.index[pos..pos + 8].try_into().unwrap()) as usize
+    }
+
+    /// Where the object `sha` starts in the pack, if the pack has it.
+    pub fn find(&self, sha: &[u8]) -> Option<usize> {
+        let first = sha[0] as usize;
+        let mut lo = if first == 0 { 0 } else { self.u32_at(8 + (first - 1) * 4) as usize };
+        let mut hi = self.u32_at(8 + first * 4) as usize;
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.sha_at(mid).cmp(sha) {
+                std::cmp::Ordering::Equal => return Some(self.offset_at(mid)),
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+            }
+        }
+        None
+    }
+
+    pub fn shas(&self) -> impl Iterator<Item = String> + '_ {
+        (0..self.len()).map(|i| hex::encode(self.sha_at(i)))
+    }
+
+    /// Reads the object at `offset`, applying the deltas it is made of, and returns its
+    /// type and content.
+    pub fn read(&self, offset: usize) -> Result<RawObject, String> {
+        let corrupt = || format!("packed object at offset {} is corrupt", offset);
+        let mut pos = offset;
+        let mut c = *self.data.get(pos).ok_or_else(corrupt)?;
+        pos += 1;
+        let kind = (c >> 4) & 7;
+        while c & 0x80 != 0 {
+            c = *self.data.get(pos).ok_or_else(corrupt)?;
+            pos += 1;
+        }
+
+        let base = match kind {
+            1..=4 => None,
+            6 => {
+                let mut c = *self.data.get(pos).ok_or_else(corrupt)?;
+                pos += 1;
+                let mut distance = (c & 0x7f) as usize;
+                while c & 0x80 != 0 {
+                    c = *self.data.get(pos).ok_or_else(corrupt)?;
+                    pos += 1;
+                    distance = ((distance + 1) << 7) | (c & 0x7f) as usize;
+                }
+                Some(self.read(offset.checked_sub(distance).ok_or_else(corrupt)?)?)
+            }
+            7 => {
+                let sha = self.data.get(pos..pos + 20).ok_or_else(corrupt)?;
+                pos += 20;
+                let base = self.find(sha).ok_or_else(|| format!("delta base {} is missing", hex::encode(sha)))?;
+                Some(self.read(base)?)
+            }
+            _ => return Err(corrupt()),
+        };
+
+        let content = zune_inflate::DeflateDecoder::new(&self.data[pos..]).decode_zlib().map_err(|e| e.to_string())?;
+        match base {
+            None => Ok((OBJECT_TYPES[kind as usize].to_vec(), content)),
+            Some((fmt, base)) => Ok((fmt, delta_apply(&base, &content)?)),
+        }
+    }
+}
+
+/// What a delta in a pack is applied to.
+enum DeltaBase {
+    /// The object starting there in the same pack.
+    Offset(usize),
+    Sha(String),
+}
+
+/// Where an object starts in a pack, and the CRC32 of its entry, which the index records.
+type PackEntry = (usize, u32);
+
+/// Builds a version 2 pack of `objects`, each stored whole, and tells where each starts in it.
+fn pack_encode(objects: &[RawObject]) -> Result<(Vec<u8>, Vec<PackEntry>), String> {
+    let mut data = b"PACK".to_vec();
+    data.extend(2u32.to_be_bytes());
+    data.extend((objects.len() as u32).to_be_bytes());
+    let mut entries = Vec::new();
+    for (fmt, content) in objects {
+        let start = data.len();
+        let kind = OBJECT_TYPES[1..]
+            .iter()
+            .position(|v| *v == fmt.as_slice())
+            .ok_or_else(|| format!("object of unknown type {}", String::from_utf8_lossy(fmt)))?
+            + 1;
+        // The type and the size, four bits of it in the first byte and seven in the others.
+        let mut size = content.len() >> 4;
+        let mut c = (kind as u8) << 4 | (content.len() & 0x0f) as u8;
+        while size > 0 {
+            data.push(c | 0x80);
+            c = (size & 0x7f) as u8;
+            size >>= 7;
+        }
+        data.push(c);
+        data.extend(zune_inflate::DeflateEncoder::new(content).encode_zlib());
+        entries.push((start, crc32(&data[start..])));
+    }
+    let checksum = sha1::Sha1::digest(&data);
+    data.extend_from_slice(&checksum);
+    Ok((data, entries))
+}
+
+/// Inflates the zlib stream at the start of `data` and tells how many bytes it takes up, which
+/// the decoder doesn't report. The stream ends with the Adler-32 of its content, so the end is
+/// the first place that checksum shows up at which the stream also decodes alone.
+fn inflate_prefix(data: &[u8]) -> Result<(Vec<u8>, usize), String> {
+    let content = zune_inflate::DeflateDecoder::new(data).decode_zlib().map_err(|e| e.to_string())?;
+    let checksum = adler32(&content).to_be_bytes();
+    (6..=data.len())
+        .filter(|&end| data[end - 4..end] == checksum)
+        .find(|&end| zune_inflate::DeflateDecoder::new(&data[..end]).decode_zlib().is_ok())
+        .map(|end| (content, end))
+        .ok_or_else(|| "zlib stream has no end".to_string())
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &c in chunk {
+            a += c as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &c in data {
+        crc ^= c as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// Reads a size as deltas encode them, seven bits a byte, least significant first.
+fn delta_size(delta: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut size = 0;
+    let mut shift = 0;
+    loop {
+        let c = *delta.get(*pos)?;
+        *pos += 1;
+        size |= ((c & 0x7f) as usize) << shift;
+        shift += 7;
+        if c & 0x80 == 0 {
+            return Some(size);
+        }
+    }
+}
+
+/// Rebuilds an object from the one it is a delta of.
+pub fn delta_apply(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
+    let corrupt = || "delta is corrupt".to_string();
+    let mut pos = 0;
+    let base_size = delta_size(delta, &mut pos).ok_or_else(corrupt)?;
+    let size = delta_size(delta, &mut pos).ok_or_else(corrupt)?;
+    if base_size != base.len() {
+        return Err(corrupt());
+    }
+
+    let mut ret = Vec::with_capacity(size);
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        if op & 0x80 != 0 {
+            // Copy from the base, with the bytes of the offset and size that are set.
+            let mut fields = [0usize; 7];
+            for (bit, field) in fields.iter_mut().enumerate() {
+                if op & (1 << bit) != 0 {
+                    *field = *delta.get(pos).ok_or_else(corrupt)? as usize;
+                    pos += 1;
+                }
+            }
+            let start = fields[0] | fields[1] << 8 | fields[2] << 16 | fields[3] << 24;
+            let len = match fields[4] | fields[5] << 8 | fields[6] << 16 {
+                0 => 0x10000,
+                v => v,
+            };
+            ret.extend_from_slice(base.get(start..start + len).ok_or_else(corrupt)?);
+        } else if op != 0 {
+            let len = op as usize;
+            ret.extend_from_slice(delta.get(pos..pos + len).ok_or_else(corrupt)?);
+            pos += len;
+        } else {
+            return Err(corrupt());
+        }
+    }
+    if ret.len() != size {
+        return Err(corrupt());
+    }
+    Ok(ret)
+}
+
+impl GitRepo {
+    /// The packs of the repository, read once and shared by clones of the handle.
+    pub fn packs(&self) -> Rc<Vec<Pack>> {
+        if let Some(packs) = self.packs.borrow().as_ref() {
+            return packs.clone();
+        }
+        let mut packs = Vec::new();
+        if let Ok(entries) = std::fs::read_dir(self.common_dir.join("objects/pack")) {
+            let mut paths: Vec<_> = entries.flatten().map(|v| v.path()).collect();
+            paths.sort();
+            for path in paths {
+                if path.extension().is_some_and(|v| v == "idx") {
+                    match Pack::open(&path) {
+                        Ok(pack) => packs.push(pack),
+                        Err(e) => eprintln!("warning: {}", e),
+                    }
+                }
+            }
+        }
+        let packs = Rc::new(packs);
+        *self.packs.borrow_mut() = Some(packs.clone());
+        packs
+    }
+
+    /// Looks for `sha` in the packs, giving its type and content.
+    pub fn pack_object_read(&self, sha: &str) -> Option<Result<RawObject, String>> {
+        let raw = hex::decode(sha).ok().filter(|v| v.len() == 20)?;
+        let packs = self.packs();
+        packs.iter().find_map(|pack| pack.find(&raw).map(|offset| pack.read(offset)))
+    }
+
+    pub fn pack_contains(&self, sha: &str) -> bool {
+        let raw = hex::decode(sha).unwrap_or_default();
+        raw.len() == 20 && self.packs().iter().any(|pack| pack.find(&raw).is_some())
+    }
+
+    /// Builds a version 2 pack of the objects `shas`, each stored whole.
+    pub fn pack_build(&self, shas: &[String]) -> Result<Vec<u8>, String> {
+        let objects = shas.iter().map(|v| self.object_read_raw(v)).collect::<Result<Vec<_>, _>>()?;
+        Ok(pack_encode(&objects)?.0)
+    }
+
+    /// Reads a pack someone sent, resolving its deltas against each other and, for a thin
+    /// pack, against the objects of the repository.
+    pub fn pack_parse(&self, data: &[u8]) -> Result<Vec<RawObject>, String> {
+        let corrupt = || "pack is corrupt".to_string();
+        if data.len() < 32 || data[..4] != *b"PACK" || !matches!(data[4..8], [0, 0, 0, 2 | 3]) {
+            return Err("protocol error (pack signature mismatch detected)".to_string());
+        }
+        let (content, checksum) = data.split_at(data.len() - 20);
+        if sha1::Sha1::digest(content).as_slice() != checksum {
+            return Err("pack is truncated or corrupt".to_string());
+        }
+        let count = u32::from_be_bytes(data[8..12].try_into().unwrap()) as usize;
+
+        // Whole objects are ready at once; deltas wait for their base.
+        let mut resolved: Vec<Option<RawObject>> = Vec::with_capacity(count);
+        let mut deltas = Vec::new();
+        let mut offsets = HashMap::new();
+        let mut pos = 12;
+        for i in 0..count {
+            let offset = pos;
+            offsets.insert(offset, i);
+            let mut c = *content.get(pos).ok_or_else(corrupt)?;
+            pos += 1;
+            let kind = (c >> 4) & 7;
+            while c & 0x80 != 0 {
+                c = *content.get(pos).ok_or_else(corrupt)?;
+                pos += 1;
+            }
+            let base = match kind {
+                1..=4 => None,
+                6 => {
+                    let mut c = *content.get(pos).ok_or_else(corrupt)?;
+                    pos += 1;
+                    let mut distance = (c & 0x7f) as usize;
+                    while c & 0x80 != 0 {
+                        c = *content.get(pos).ok_or_else(corrupt)?;
+                        pos += 1;
+                        distance = ((distance + 1) << 7) | (c & 0x7f) as usize;
+                    }
+                    Some(DeltaBase::Offset(offset.checked_sub(distance).ok_or_else(corrupt)?))
+                }
+                7 => {
+                    let sha = content.get(pos..pos + 20).ok_or_else(corrupt)?;
+                    pos += 20;
+                    Some(DeltaBase::Sha(hex::encode(sha)))
+                }
+                _ => return Err(corrupt()),
+            };
+            let (inflated, used) = inflate_prefix(&content[pos..])?;
+            pos += used;
+            match base {
+                None => resolved.push(Some((OBJECT_TYPES[kind as usize].to_vec(), inflated))),
+                Some(base) => {
+                    resolved.push(None);
+                    deltas.push((i, base, inflated));
+                }
+            }
+        }
+        if pos != content.len() {
+            return Err(corrupt());
+        }
+
+        let mut shas: HashMap<String, usize> = HashMap::new();
+        for (i, object) in resolved.iter().enumerate() {
+            if let Some((fmt, data)) = object {
+                shas.insert(GitRepo::object_hash(fmt, data), i);
+            }
+        }
+        while !deltas.is_empty() {
+            let before = deltas.len();
+            let mut pending = Vec::new();
+            for (i, base, delta) in deltas {
+                let base_object = match &base {
+                    DeltaBase::Offset(offset) => offsets.get(offset).and_then(|v| resolved[*v].clone()),
+                    DeltaBase::Sha(sha) => match shas.get(sha) {
+                        Some(v) => resolved[*v].clone(),
+                        None => self.object_read_raw(sha).ok(),
+                    },
+                };
+                match base_object {
+                    Some((fmt, base_data)) => {
+                        let data = delta_apply(&base_data, &delta)?;
+                        shas.insert(GitRepo::object_hash(&fmt, &data), i);
+                        resolved[i] = Some((fmt, data));
+                    }
+                    None => pending.push((i, base, delta)),
+                }
+            }
+            if pending.len() == before {
+                return Err(format!("pack has {} unresolved deltas", before));
+            }
+            deltas = pending;
+        }
+        Ok(resolved.into_iter().flatten().collect())
+    }
+
+    /// Stores objects a client or a server sent, loose when there are fewer of them than
+    /// `limit_key`, or `transfer.unpackLimit`, allows and as a pack otherwise, and lists them.
+    pub fn pack_unpack(&self, objects: &[RawObject], limit_key: &str) -> Result<HashSet<String>, String> {
+        let limit = self
+            .config_get(limit_key)
+            .or_else(|| self.config_get("transfer.unpackLimit"))
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(100);
+        let shas = objects.iter().map(|(fmt, data)| GitRepo::object_hash(fmt, data)).collect();
+        if objects.len() < limit {
+            for (fmt, data) in objects {
+                self.object_store(fmt, data)?;
+            }
+        } else {
+            self.pack_store(objects)?;
+        }
+        Ok(shas)
+    }
+
+    /// Writes `objects` as a new pack under `objects/pack`, along with its index.
+    pub fn pack_store(&self, objects: &[RawObject]) -> Result<(), String> {
+        let (pack, entries) = pack_encode(objects)?;
+        let checksum = &pack[pack.len() - 20..];
+        let mut entries: Vec<(Vec<u8>, u32, usize)> = objects
+            .iter()
+            .zip(entries)
+            .map(|((fmt, data), (offset, crc))| (hex::decode(GitRepo::object_hash(fmt, data)).unwrap(), crc, offset))
+            .collect();
+        entries.sort();
+        entries.dedup_by(|a, b| a.0 == b.0);
+
+        let mut index = b"\xfftOc".to_vec();
+        index.extend(2u32.to_be_bytes());
+        for first in 0..=255u8 {
+            index.extend((entries.iter().filter(|v| v.0[0] <= first).count() as u32).to_be_bytes());
+        }
+        for (sha, _, _) in &entries {
+            index.extend(sha);
+        }
+        for (_, crc, _) in &entries {
+            index.extend(crc.to_be_bytes());
+        }
+        // Offsets past 2GiB go into a second table of 64 bit ones.
+        let mut large = Vec::new();
+        for (_, _, offset) in &entries {
+            match u32::try_from(*offset).ok().filter(|v| v & 0x8000_0000 == 0) {
+                Some(v) => index.extend(v.to_be_bytes()),
+                None => {
+                    index.extend((0x8000_0000 | (large.len() as u32 / 8)).to_be_bytes());
+                    large.extend((*offset as u64).to_be_bytes());
+                }
+            }
+        }
+        index.extend(large);
+        index.extend_from_slice(checksum);
+        let index_checksum = sha1::Sha1::digest(&index);
+        index.extend_from_slice(&index_checksum);
+
+        let dir = self.common_dir.join("objects/pack");
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let name = format!("pack-{}", hex::encode(checksum));
+        std::fs::write(dir.join(format!("{}.pack", name)), &pack).map_err(|e| e.to_string())?;
+        std::fs::write(dir.join(format!("{}.idx", name)), &index).map_err(|e| e.to_string())?;
+        *self.packs.borrow_mut() = None;
+        Ok(())
+    }
+}
@@ -1,17 +1,38 @@
-use std::io::Write;
+use crate::helpers::git::GitRepo;
+use std::io::{IsTerminal, Write};
 use std::process::{Command, Stdio};
 
-pub fn display_with_pager(data: &str) {
-    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less".to_string());
+/// Picks the pager the same way git does: `GIT_PAGER`, `core.pager`, `PAGER`, then `less`.
+fn pager_command(repo: Option<&GitRepo>) -> String {
+    if let Ok(pager) = std::env::var("GIT_PAGER") {
+        return pager;
+    }
+    if let Some(pager) = repo.and_then(|v| v.config_get("core.pager")) {
+        return pager;
+    }
+    std::env::var("PAGER").unwrap_or_else(|_| "less".to_string())
+}
+
+pub fn display_with_pager<D: AsRef<[u8]>>(repo: Option<&GitRepo>, data: D) {
+    let pager = pager_command(repo);
+
+    if !std::io::stdout().is_terminal() || pager.is_empty() || pager == "cat" {
+        std::io::stdout()
+            .write_all(data.as_ref())
+            .expect("Failed to write to stdout");
+        return;
+    }
 
-    let mut child = Command::new(&pager)
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&pager)
         .stdin(Stdio::piped())
         .spawn()
         .unwrap_or_else(|_| panic!("Failed to start pager: {}", pager));
 
     if let Some(mut stdin) = child.stdin.take() {
         stdin
-            .write_all(data.as_bytes())
+            .write_all(data.as_ref())
             .expect("Failed to write to pager");
     }
 
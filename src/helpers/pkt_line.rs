@@ -0,0 +1,106 @@
+use std::io::{self, Read, Write};
+
+/// Largest payload a single packet may carry.
+pub const LARGE_PACKET_DATA_MAX: usize = 65516;
+
+/// One unit of the pkt-line framing used by the wire protocols and filter processes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet {
+    Data(Vec<u8>),
+    /// `0000`, ends a list or a section.
+    Flush,
+    /// `0001`, separates sections of a protocol v2 message.
+    Delim,
+    /// `0002`, ends a protocol v2 response.
+    ResponseEnd,
+}
+
+impl Packet {
+    /// Returns the payload of a data packet without its trailing newline.
+    pub fn line(&self) -> Option<String> {
+        match self {
+            Packet::Data(v) => {
+                let v = v.strip_suffix(b"\n").unwrap_or(v);
+                Some(String::from_utf8_lossy(v).into_owned())
+            }
+            _ => None,
+        }
+    }
+}
+
+pub fn pkt_write(w: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    if data.len() > LARGE_PACKET_DATA_MAX {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "packet too long"));
+    }
+    w.write_all(format!("{:04x}", data.len() + 4).as_bytes())?;
+    w.write_all(data)
+}
+
+/// Writes a text packet, adding the newline git puts after each line.
+pub fn pkt_write_line(w: &mut impl Write, line: &str) -> io::Result<()> {
+    pkt_write(w, format!("{}\n", line).as_bytes())
+}
+
+pub fn pkt_flush(w: &mut impl Write) -> io::Result<()> {
+    w.write_all(b"0000")
+}
+
+pub fn pkt_delim(w: &mut impl Write) -> io::Result<()> {
+    w.write_all(b"0001")
+}
+
+/// Sends `data` as a sequence of maximally sized packets, without the closing flush.
+pub fn pkt_write_data(w: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    for chunk in data.chunks(LARGE_PACKET_DATA_MAX) {
+        pkt_write(w, chunk)?;
+    }
+    Ok(())
+}
+
+pub fn pkt_read(r: &mut impl Read) -> io::Result<Packet> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len)?;
+    let len = std::str::from_utf8(&len)
+        .ok()
+        .and_then(|v| usize::from_str_radix(v, 16).ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("protocol error: bad line length character: {}", String::from_utf8_lossy(&len)),
+            )
+        })?;
+    match len {
+        0 => Ok(Packet::Flush),
+        1 => Ok(Packet::Delim),
+        2 => Ok(Packet::ResponseEnd),
+        3 => Err(io::Error::new(io::ErrorKind::InvalidData, "protocol error: bad line length 3")),
+        _ => {
+            let mut data = vec![0u8; len - 4];
+            r.read_exact(&mut data)?;
+            Ok(Packet::Data(data))
+        }
+    }
+}
+
+/// Reads text packets up to the next flush.
+pub fn pkt_read_lines(r: &mut impl Read) -> io::Result<Vec<String>> {
+    let mut lines = Vec::new();
+    loop {
+        match pkt_read(r)? {
+            Packet::Flush => return Ok(lines),
+            packet => lines.extend(packet.line()),
+        }
+    }
+}
+
+/// Reads data packets up to the next flush and joins their payloads.
+pub fn pkt_read_data(r: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    loop {
+        match pkt_read(r)? {
+            Packet::Data(v) => data.extend(v),
+            Packet::Flush => return Ok(data),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use crate::helpers::clone::list_refs;
+use crate::helpers::git::GitRepo;
+use crate::helpers::git_objects::commit::GitCommit;
+use crate::helpers::git_objects::tag::GitTag;
+use crate::helpers::git_objects::tree::GitTree;
+use crate::helpers::pkt_line::{pkt_flush, pkt_read, pkt_write, pkt_write_line, Packet, LARGE_PACKET_DATA_MAX};
+use crate::helpers::upload_pack::object_field;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read, Write};
+
+const NULL_SHA: &str = "0000000000000000000000000000000000000000";
+
+/// A ref update a pushing client asks for, and why it was refused if it was.
+#[derive(Debug, Clone)]
+struct PushCommand {
+    old: String,
+    new: String,
+    name: String,
+    error: Option<String>,
+}
+
+impl PushCommand {
+    /// The line hooks read the command as.
+    fn line(&self) -> String {
+        format!("{} {} {}\n", self.old, self.new, self.name)
+    }
+}
+
+/// Sends text from the receiving side to the client, which shows it after `remote: `: on side
+/// band 2 when the client asked for one, otherwise on stderr, which a local client shares.
+fn send_message(output: &mut impl Write, sideband: bool, text: &[u8]) -> io::Result<()> {
+    if !sideband {
+        return io::stderr().write_all(text);
+    }
+    for chunk in text.chunks(LARGE_PACKET_DATA_MAX - 1) {
+        let mut packet = vec![2u8];
+        packet.extend_from_slice(chunk);
+        pkt_write(output, &packet)?;
+    }
+    Ok(())
+}
+
+/// Reads the names a commit, tag or tree leads to.
+fn object_links(fmt: &[u8], data: Vec<u8>) -> Vec<String> {
+    match fmt {
+        b"commit" => {
+            let commit = GitCommit::new(data);
+            let mut links = object_field(commit.kvlm.get(b"tree".as_ref()));
+            links.extend(object_field(commit.kvlm.get(b"parent".as_ref())));
+            links
+        }
+        b"tag" => object_field(GitTag::new(data).kvlm.get(b"object".as_ref())),
+        b"tree" => GitTree::from_raw(&data).leafs.into_iter().filter(|v| v.mode != "160000").map(|v| v.sha).collect(),
+        _ => Vec::new(),
+    }
+}
+
+impl GitRepo {
+    /// Takes a push from a client on `input` and `output`, the way `git receive-pack` does
+    /// over ssh: advertises the refs, reads the updates and the pack they need, runs the
+    /// `pre-receive`, `update` and `post-receive` hooks, updates the refs, all of them or none
+    /// with `atomic`, and reports how each went.
+    pub fn receive_pack(&self, input: &mut impl Read, output: &mut impl Write) -> Result<(), String> {
+        self.receive_pack_advertise(output)?;
+        self.receive_pack_commands(input, output)
+    }
+
+    /// Advertises the refs and capabilities a pushing client starts with.
+    pub fn receive_pack_advertise(&self, output: &mut impl Write) -> Result<(), String> {
+        let io_error = |e: io::Error| e.to_string();
+        let capabilities = format!(
+            "report-status delete-refs side-band-64k quiet atomic ofs-delta object-format=sha1 agent=mygit/{}",
+            env!("CARGO_PKG_VERSION")
+        );
+        let refs = list_refs(&self.common_dir);
+        if refs.is_empty() {
+            pkt_write_line(output, &format!("{} capabilities^{{}}\0{}", NULL_SHA, capabilities)).map_err(io_error)?;
+        }
+        for (i, (name, sha)) in refs.iter().enumerate() {
+            match i {
+                0 => pkt_write_line(output, &format!("{} {}\0{}", sha, name, capabilities)),
+                _ => pkt_write_line(output, &format!("{} {}", sha, name)),
+            }
+            .map_err(io_error)?;
+        }
+        pkt_flush(output).map_err(io_error)?;
+        output.flush().map_err(io_error)
+    }
+
+    /// Reads the updates of a pushing client and the pack they need, then applies them and
+    /// reports back.
+    pub fn receive_pack_commands(&self, input: &mut impl Read, output: &mut impl Write) -> Result<(), String> {
+        let io_error = |e: io::Error| e.to_string();
+        // The capabilities of the client follow the first command.
+        let mut commands = Vec::new();
+        let mut client_capabilities = Vec::new();
+        loop {
+            let line = match pkt_read(input) {
+                Ok(Packet::Flush) => break,
+                // A client with nothing to push may just hang up.
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && commands.is_empty() => return Ok(()),
+                Ok(packet) => packet.line().unwrap_or_default(),
+                Err(e) => return Err(e.to_string()),
+            };
+            let line = match line.split_once('\0') {
+                Some((line, capabilities)) => {
+                    client_capabilities.extend(capabilities.split(' ').map(|v| v.to_string()));
+                    line.to_string()
+                }
+                None => line,
+            };
+            let mut parts = line.splitn(3, ' ');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(old), Some(new), Some(name)) if old.len() == 40 && new.len() == 40 => {
+                    commands.push(PushCommand { old: old.to_string(), new: new.to_string(), name: name.to_string(), error: None })
+                }
+                _ => return Err(format!("protocol error: expected old/new/ref, got '{}'", line)),
+            }
+        }
+        if commands.is_empty() {
+            return Ok(());
+        }
+        let has = |name: &str| client_capabilities.iter().any(|v| v == name);
+        let (sideband, atomic, report) = (has("side-band-64k"), has("atomic"), has("report-status"));
+
+        let mut unpack_error = None;
+        let mut received = HashSet::new();
+        if commands.iter().any(|v| v.new != NULL_SHA) {
+            // The client closes its side once the pack is sent.
+            let mut data = Vec::new();
+            input.read_to_end(&mut data).map_err(io_error)?;
+            match self.pack_parse(&data).and_then(|objects| self.pack_unpack(&objects, "receive.unpackLimit")) {
+                Ok(shas) => received = shas,
+                Err(e) => unpack_error = Some(e),
+            }
+        }
+
+        if unpack_error.is_some() {
+            for command in commands.iter_mut() {
+                command.error = Some("unpacker error".to_string());
+            }
+        } else {
+            self.receive_execute(&mut commands, &received, atomic, output, sideband).map_err(io_error)?;
+        }
+
+        if report {
+            let mut status = Vec::new();
+            let unpack = match &unpack_error {
+                None => "unpack ok".to_string(),
+                Some(e) => format!("unpack {}", e),
+            };
+            pkt_write_line(&mut status, &unpack).map_err(io_error)?;
+            for command in &commands {
+                match &command.error {
+                    None => pkt_write_line(&mut status, &format!("ok {}", command.name)),
+                    Some(e) => pkt_write_line(&mut status, &format!("ng {} {}", command.name, e)),
+                }
+                .map_err(io_error)?;
+            }
+            pkt_flush(&mut status).map_err(io_error)?;
+            match sideband {
+                true => {
+                    for chunk in status.chunks(LARGE_PACKET_DATA_MAX - 1) {
+                        let mut packet = vec![1u8];
+                        packet.extend_from_slice(chunk);
+                        pkt_write(output, &packet).map_err(io_error)?;
+                    }
+                }
+                false => output.write_all(&status).map_err(io_error)?,
+            }
+        }
+        if sideband {
+            pkt_flush(output).map_err(io_error)?;
+        }
+        output.flush().map_err(io_error)
+    }
+
+    /// Tells if everything `sha` leads to is here, following only what was just `received`,
+    /// as the rest was already connected.
+    fn receive_connected(&self, sha: &str, received: &HashSet<String>) -> bool {
+        let mut pending = vec![sha.to_string()];
+        let mut seen = HashSet::new();
+        while let Some(sha) = pending.pop() {
+            if !seen.insert(sha.clone()) {
+                continue;
+            }
+            if !received.contains(&sha) {
+                if !self.object_exists(&sha) {
+                    return false;
+                }
+                continue;
+            }
+            match self.object_read_raw(&sha) {
+                Ok((fmt, data)) => pending.extend(object_links(&fmt, data)),
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    /// Checks the commands, runs the hooks and updates the refs that get through.
+    fn receive_execute(
+        &self,
+        commands: &mut [PushCommand],
+        received: &HashSet<String>,
+        atomic: bool,
+        output: &mut impl Write,
+        sideband: bool,
+    ) -> io::Result<()> {
+        for command in commands.iter_mut() {
+            if command.new != NULL_SHA && !self.receive_connected(&command.new, received) {
+                command.error = Some("missing necessary objects".to_string());
+            }
+        }
+
+        let lines = |commands: &[PushCommand]| commands.iter().filter(|v| v.error.is_none()).map(|v| v.line()).collect::<String>();
+        if let Some((success, text)) = self.hook_run("pre-receive", &[], &lines(commands)) {
+            send_message(output, sideband, &text)?;
+            if !success {
+                for command in commands.iter_mut().filter(|v| v.error.is_none()) {
+                    command.error = Some("pre-receive hook declined".to_string());
+                }
+            }
+        }
+
+        for command in commands.iter_mut().filter(|v| v.error.is_none()) {
+            let old = Some(command.old.as_str()).filter(|v| *v != NULL_SHA);
+            let new = Some(command.new.as_str()).filter(|v| *v != NULL_SHA);
+            let current = self.ref_resolve(command.name.clone().into());
+            let mut messages = Vec::new();
+            command.error = if !command.name.starts_with("refs/") || !GitRepo::ref_name_valid(&command.name) {
+                messages.push(format!("error: refusing to create funny ref '{}' remotely", command.name));
+                Some("funny refname".to_string())
+            } else if let Some(reason) = self.receive_denied(&command.name, old, new, &mut messages) {
+                Some(reason)
+            } else {
+                match self.hook_run("update", &[&command.name, &command.old, &command.new], "") {
+                    Some((success, text)) => {
+                        send_message(output, sideband, &text)?;
+                        if !success {
+                            messages.push(format!("error: hook declined to update {}", command.name));
+                        }
+                        (!success).then(|| "hook declined".to_string())
+                    }
+                    None => None,
+                }
+            };
+            // The ref must still be where the client saw it.
+            if command.error.is_none() && new.is_some() && current.as_deref() != old {
+                let reason = match (&current, old) {
+                    (Some(current), Some(_)) => format!("is at {} but expected {}", current, command.old),
+                    (Some(_), None) => "reference already exists".to_string(),
+                    (None, _) => format!("unable to resolve reference '{}'", command.name),
+                };
+                messages.push(format!("error: cannot lock ref '{}': {}", command.name, reason));
+                command.error = Some("failed to update ref".to_string());
+            }
+            if command.error.is_none() && new.is_none() && current.is_none() {
+                messages.push("warning: deleting a non-existent ref".to_string());
+            }
+            for message in messages {
+                send_message(output, sideband, format!("{}\n", message).as_bytes())?;
+            }
+        }
+
+        // Atomically, one refused update refuses them all.
+        if atomic && commands.iter().any(|v| v.error.is_some()) {
+            for command in commands.iter_mut().filter(|v| v.error.is_none()) {
+                command.error = Some("atomic push failure".to_string());
+            }
+        }
+        for command in commands.iter_mut().filter(|v| v.error.is_none()) {
+            let updated = match command.new.as_str() {
+                NULL_SHA => self.ref_delete(&command.name),
+                new => fs::create_dir_all(self.common_dir.join(&command.name).parent().unwrap())
+                    .and_then(|_| fs::write(self.common_dir.join(&command.name), format!("{}\n", new)))
+                    .map_err(|e| e.to_string()),
+            };
+            if let Err(e) = updated {
+                send_message(output, sideband, format!("error: {}\n", e).as_bytes())?;
+                command.error = Some("failed to update ref".to_string());
+            }
+        }
+
+        let done: Vec<PushCommand> = commands.iter().filter(|v| v.error.is_none()).cloned().collect();
+        if !done.is_empty() {
+            if let Some((_, text)) = self.hook_run("post-receive", &[], &lines(&done)) {
+                send_message(output, sideband, &text)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Why this repository refuses a push setting the ref `name` from `old` to `new`, or
+    /// deleting it, if it does, adding what it explains to `messages`.
+    pub fn receive_denied(&self, name: &str, old: Option<&str>, new: Option<&str>, messages: &mut Vec<String>) -> Option<String> {
+        let head = fs::read_to_string(self.git_dir.join("HEAD")).unwrap_or_default();
+        let is_head = head.trim().strip_prefix("ref: ") == Some(name);
+        let non_bare = self.config_bool("core.bare") != Some(true);
+        // Unless set, the default of refusing comes with an explanation.
+        let deny = |key: &str| match self.config_get(key).as_deref() {
+            Some("ignore" | "warn" | "false") => None,
+            Some(_) => Some(false),
+            None => Some(true),
+        };
+
+        if let (true, true, Some(explain)) = (is_head, non_bare, deny("receive.denyCurrentBranch")) {
+            messages.push(format!("error: refusing to update checked out branch: {}", name));
+            if explain {
+                messages.extend(DENY_CURRENT_BRANCH.lines().map(|v| v.to_string()));
+            }
+            return Some("branch is currently checked out".to_string());
+        }
+        if new.is_none() && old.is_some() {
+            if self.config_bool("receive.denyDeletes") == Some(true) && name.starts_with("refs/heads/") {
+                messages.push(format!("error: denying ref deletion for {}", name));
+                return Some("deletion prohibited".to_string());
+            }
+            if let (true, Some(explain)) = (is_head, deny("receive.denyDeleteCurrent")) {
+                if explain {
+                    messages.extend(DENY_DELETE_CURRENT.lines().map(|v| v.to_string()));
+                }
+                messages.push(format!("error: refusing to delete the current branch: {}", name));
+                return Some("deletion of the current branch prohibited".to_string());
+            }
+        }
+        if let (Some(old), Some(new), Some(true)) = (old, new, self.config_bool("receive.denyNonFastForwards")) {
+            if name.starts_with("refs/heads/") && !self.commit_ancestors(new).contains(old) {
+                messages.push(format!("error: denying non-fast-forward {} (you should pull first)", name));
+                return Some("non-fast-forward".to_string());
+            }
+        }
+        None
+    }
+}
+
+/// What a non-bare repository answers a push to the branch it has checked out.
+const DENY_CURRENT_BRANCH: &str = "error: By default, updating the current branch in a non-bare repository
+is denied, because it will make the index and work tree inconsistent
+with what you pushed, and will require 'git reset --hard' to match
+the work tree to HEAD.
+
+You can set the 'receive.denyCurrentBranch' configuration variable
+to 'ignore' or 'warn' in the remote repository to allow pushing into
+its current branch; however, this is not recommended unless you
+arranged to update its work tree to match what you pushed in some
+other way.
+
+To squelch this message and still keep the default behaviour, set
+'receive.denyCurrentBranch' configuration variable to 'refuse'.";
+
+/// What a repository answers a push deleting the branch its HEAD points at.
+const DENY_DELETE_CURRENT: &str = "error: By default, deleting the current branch is denied, because the next
+'git clone' won't result in any file checked out, causing confusion.
+
+You can set 'receive.denyDeleteCurrent' configuration variable to
+'warn' or 'ignore' in the remote repository to allow deleting the
+current branch, with or without a warning message.
+
+To squelch this message, you can set it to 'refuse'.";
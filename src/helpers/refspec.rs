@@ -0,0 +1,120 @@
+/// A refspec: `[+]<src>[:<dst>]`, or `^<src>` to leave matching refs out, where both
+/// sides may have one `*` standing for the same part of a ref name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Refspec {
+    pub force: bool,
+    pub negative: bool,
+    pub src: String,
+    pub dst: Option<String>,
+}
+
+impl Refspec {
+    pub fn parse(spec: &str) -> Result<Refspec, String> {
+        let invalid = || format!("invalid refspec '{}'", spec);
+        let (negative, rest) = match spec.strip_prefix('^') {
+            Some(v) => (true, v),
+            None => (false, spec),
+        };
+        let (force, rest) = match rest.strip_prefix('+') {
+            Some(v) if !negative => (true, v),
+            Some(_) => return Err(invalid()),
+            None => (false, rest),
+        };
+        let (src, dst) = match rest.rsplit_once(':') {
+            Some((src, dst)) => (src.to_string(), Some(dst.to_string())),
+            None => (rest.to_string(), None),
+        };
+
+        let globs = |v: &str| v.matches('*').count();
+        // The source side of a push may be any revision, so only the destination is checked.
+        let valid = |v: &str| v.is_empty() || crate::helpers::git::GitRepo::ref_name_valid(&v.replacen('*', "x", 1));
+        if globs(&src) > 1 || (negative && (dst.is_some() || src.is_empty())) {
+            return Err(invalid());
+        }
+        if let Some(dst) = &dst {
+            if globs(dst) > 1 || !valid(dst) || (!dst.is_empty() && globs(dst) != globs(&src)) {
+                return Err(invalid());
+            }
+        }
+        Ok(Refspec { force, negative, src, dst })
+    }
+
+    pub fn is_glob(&self) -> bool {
+        self.src.contains('*')
+    }
+
+    /// Tells if the source side matches the full ref `name`.
+    pub fn matches(&self, name: &str) -> bool {
+        match self.src.split_once('*') {
+            Some((prefix, suffix)) => name.len() >= prefix.len() + suffix.len() && name.starts_with(prefix) && name.ends_with(suffix),
+            None => self.src == name,
+        }
+    }
+
+    /// What `name`, matched by the source side, is called on the destination side.
+    pub fn map(&self, name: &str) -> Option<String> {
+        let dst = self.dst.as_ref().filter(|v| !v.is_empty())?;
+        if !self.matches(name) {
+            return None;
+        }
+        match (self.src.split_once('*'), dst.split_once('*')) {
+            (Some((prefix, suffix)), Some((dst_prefix, dst_suffix))) => {
+                let middle = &name[prefix.len()..name.len() - suffix.len()];
+                Some(format!("{}{}{}", dst_prefix, middle, dst_suffix))
+            }
+            _ => Some(dst.clone()),
+        }
+    }
+
+    /// What `name`, matched by the destination side, is called on the source side.
+    pub fn unmap(&self, name: &str) -> Option<String> {
+        let dst = self.dst.clone().filter(|v| !v.is_empty() && !self.negative)?;
+        Refspec { force: self.force, negative: false, src: dst, dst: Some(self.src.clone()) }.map(name)
+    }
+}
+
+impl std::fmt::Display for Refspec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let prefix = match (self.negative, self.force) {
+            (true, _) => "^",
+            (false, true) => "+",
+            (false, false) => "",
+        };
+        match &self.dst {
+            Some(dst) => write!(f, "{}{}:{}", prefix, self.src, dst),
+            None => write!(f, "{}{}", prefix, self.src),
+        }
+    }
+}
+
+/// Tells if one of the negative refspecs leaves `name` out.
+pub fn refspecs_exclude(refspecs: &[Refspec], name: &str) -> bool {
+    refspecs.iter().any(|v| v.negative && v.matches(name))
+}
+
+pub fn is_object_name(name: &str) -> bool {
+    name.len() == 40 && name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// The full names a short one may stand for, in the order git tries them.
+pub fn ref_candidates(name: &str) -> Vec<String> {
+    if name.starts_with("refs/") || name == "HEAD" {
+        return vec![name.to_string()];
+    }
+    vec![
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+        format!("refs/remotes/{}/HEAD", name),
+    ]
+}
+
+/// Shows a full ref name the short way git prints it, e.g. `origin/main` for
+/// `refs/remotes/origin/main`.
+pub fn ref_shorten(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|v| name.strip_prefix(v))
+        .unwrap_or(name)
+}
@@ -0,0 +1,653 @@
+use crate::helpers::clone::{list_refs, local_git_dir};
+use crate::helpers::config;
+use crate::helpers::git::GitRepo;
+use crate::helpers::git_objects::commit::GitCommit;
+use crate::helpers::git_objects::tag::GitTag;
+use crate::helpers::git_objects::tree::GitTree;
+use crate::helpers::http::{is_http_url, HttpRemote};
+use crate::helpers::refspec::{ref_candidates, ref_shorten, refspecs_exclude, Refspec};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+
+/// A `remote.<name>` section, or a URL given in place of a remote name.
+#[derive(Debug, Clone)]
+pub struct Remote {
+    pub name: String,
+    pub url: String,
+    pub fetch: Vec<Refspec>,
+    pub push: Vec<Refspec>,
+}
+
+impl Remote {
+    /// The URL as it is shown, without the password or username it may hold.
+    pub fn url_display(&self) -> String {
+        match self.url.strip_prefix("http://").and_then(|v| v.split_once('@').filter(|(userinfo, _)| !userinfo.contains('/'))) {
+            Some((_, rest)) => format!("http://{}", rest),
+            None => self.url.clone(),
+        }
+    }
+
+    /// The URL as fetches name where they fetched from, also without any trailing `.git`.
+    pub fn url_fetched(&self) -> String {
+        let url = self.url_display();
+        let url = url.trim_end_matches('/');
+        url.strip_suffix(".git").unwrap_or(url).to_string()
+    }
+}
+
+/// How the refs and objects of a remote are reached: directly when it is a local repository,
+/// otherwise over smart HTTP.
+#[derive(Debug)]
+pub enum Transport {
+    Local(GitRepo),
+    Http(HttpRemote),
+}
+
+/// The refs of a remote, with `HEAD` when it points at a commit, along with the ref `HEAD`
+/// is, born or not, and what annotated tags peel to.
+#[derive(Debug, Default)]
+pub struct RemoteRefs {
+    pub refs: BTreeMap<String, String>,
+    pub head: Option<String>,
+    pub peeled: BTreeMap<String, String>,
+}
+
+impl RemoteRefs {
+    /// What the ref `name`, at `sha`, points at in the end.
+    pub fn peel<'a>(&'a self, name: &str, sha: &'a str) -> &'a str {
+        self.peeled.get(name).map(|v| v.as_str()).unwrap_or(sha)
+    }
+}
+
+impl Transport {
+    pub fn refs(&self) -> Result<RemoteRefs, String> {
+        match self {
+            Transport::Local(repo) => {
+                let refs = refs_with_head(repo);
+                let head = fs::read_to_string(repo.git_dir.join("HEAD")).unwrap_or_default();
+                let head = head.trim().strip_prefix("ref: ").map(|v| v.to_string());
+                let peeled = refs
+                    .iter()
+                    .filter(|(k, _)| k.starts_with("refs/tags/"))
+                    .filter_map(|(k, v)| Some((k.clone(), repo.object_peel(v).ok().filter(|p| p != v)?)))
+                    .collect();
+                Ok(RemoteRefs { refs, head, peeled })
+            }
+            Transport::Http(remote) => remote.ls_refs(),
+        }
+    }
+}
+
+/// What happened to a ref a fetch or a push went through.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RefStatus {
+    UpToDate,
+    New,
+    FastForward,
+    Forced,
+    /// A tag moved by a forcing refspec.
+    TagUpdate,
+    Deleted,
+    /// Only written to `FETCH_HEAD`.
+    Fetched,
+    Rejected(String),
+    /// Refused by the receiving repository.
+    RemoteRejected(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct RefUpdate {
+    /// The ref, or revision, the update comes from.
+    pub src: String,
+    /// The full ref it goes to; nothing when fetched into `FETCH_HEAD` only.
+    pub dst: Option<String>,
+    pub old: Option<String>,
+    /// Nothing for a deletion.
+    pub new: Option<String>,
+    pub force: bool,
+    /// How the update is listed in `FETCH_HEAD`, for merge or not, if it is.
+    pub merge: Option<bool>,
+    pub status: RefStatus,
+}
+
+impl RefUpdate {
+    fn new(src: String, dst: Option<String>, new: Option<String>, force: bool) -> Self {
+        RefUpdate { src, dst, old: None, new, force, merge: None, status: RefStatus::UpToDate }
+    }
+
+    /// Tells if the update went through, or had nothing to do.
+    pub fn ok(&self) -> bool {
+        !matches!(self.status, RefStatus::Rejected(_) | RefStatus::RemoteRejected(_))
+    }
+
+    /// The flag and summary that start the line git prints for the update, and the note that
+    /// ends it. Fetch names new refs after where they come from, push after where they go.
+    pub fn summary(&self, push: bool) -> (char, String, Option<String>) {
+        let abbrev = |v: &Option<String>| v.as_deref().map(|v| v[..7.min(v.len())].to_string()).unwrap_or_default();
+        let kind = if push { self.dst.as_deref().unwrap_or_default() } else { &self.src };
+        match &self.status {
+            RefStatus::UpToDate => ('=', "[up to date]".to_string(), None),
+            RefStatus::New if kind.starts_with("refs/tags/") => ('*', "[new tag]".to_string(), None),
+            RefStatus::New if kind.starts_with("refs/heads/") => ('*', "[new branch]".to_string(), None),
+            RefStatus::New if push => ('*', "[new reference]".to_string(), None),
+            RefStatus::New => ('*', "[new ref]".to_string(), None),
+            RefStatus::FastForward => (' ', format!("{}..{}", abbrev(&self.old), abbrev(&self.new)), None),
+            RefStatus::Forced => ('+', format!("{}...{}", abbrev(&self.old), abbrev(&self.new)), Some("forced update".to_string())),
+            RefStatus::TagUpdate => ('t', "[tag update]".to_string(), None),
+            RefStatus::Deleted => ('-', "[deleted]".to_string(), None),
+            RefStatus::Fetched if self.src.starts_with("refs/tags/") => ('*', "tag".to_string(), None),
+            RefStatus::Fetched if self.src.starts_with("refs/heads/") || self.src == "HEAD" => ('*', "branch".to_string(), None),
+            RefStatus::Fetched => ('*', String::new(), None),
+            RefStatus::Rejected(reason) => ('!', "[rejected]".to_string(), Some(reason.clone())),
+            RefStatus::RemoteRejected(reason) => ('!', "[remote rejected]".to_string(), Some(reason.clone())),
+        }
+    }
+}
+
+/// What `--force-with-lease` expects a remote ref to be before it is overwritten.
+#[derive(Debug, Clone)]
+pub enum Lease {
+    /// Every ref pushed, as its remote-tracking ref has it.
+    Tracking,
+    /// One ref, as given or as its remote-tracking ref has it.
+    Ref(String, Option<String>),
+}
+
+/// The refs of a repository, with the commit HEAD is at as `HEAD`.
+fn refs_with_head(repo: &GitRepo) -> BTreeMap<String, String> {
+    let mut refs = list_refs(&repo.common_dir);
+    let head = fs::read_to_string(repo.git_dir.join("HEAD")).unwrap_or_default();
+    let head = match head.trim().strip_prefix("ref: ") {
+        Some(target) => refs.get(target).cloned(),
+        None => Some(head.trim().to_string()).filter(|v| !v.is_empty()),
+    };
+    if let Some(sha) = head {
+        refs.insert("HEAD".to_string(), sha);
+    }
+    refs
+}
+
+/// Tells if `name` may name a remote, which it can when it fits in a remote-tracking ref.
+pub fn remote_name_valid(name: &str) -> bool {
+    GitRepo::ref_name_valid(&format!("refs/remotes/{}/test", name))
+}
+
+/// Makes the destination of a refspec a full ref name, of the same kind as its source.
+fn qualify_dst(dst: &str, src: &str, existing: &BTreeMap<String, String>) -> String {
+    if dst.starts_with("refs/") || dst == "HEAD" {
+        return dst.to_string();
+    }
+    if let Some(name) = ref_candidates(dst).into_iter().find(|v| existing.contains_key(v)) {
+        return name;
+    }
+    match src.starts_with("refs/tags/") {
+        true => format!("refs/tags/{}", dst),
+        false => format!("refs/heads/{}", dst),
+    }
+}
+
+impl GitRepo {
+    /// Reads `remote.<name>`, taking `name` as the URL of a remote that isn't configured when it
+    /// is the path of a repository.
+    pub fn remote_get(&self, name: &str) -> Option<Remote> {
+        let refspecs = |key: &str| {
+            self.config
+                .get_all(&format!("remote.{}.{}", name, key))
+                .iter()
+                .filter_map(|v| v.value.as_deref())
+                .filter_map(|v| Refspec::parse(v).ok())
+                .collect()
+        };
+        match self.config_get(&format!("remote.{}.url", name)) {
+            Some(url) => Some(Remote { name: name.to_string(), url, fetch: refspecs("fetch"), push: refspecs("push") }),
+            None if local_git_dir(name).is_some() || is_http_url(name) => Some(Remote {
+                name: name.to_string(),
+                url: name.to_string(),
+                fetch: Vec::new(),
+                push: Vec::new(),
+            }),
+            None => None,
+        }
+    }
+
+    /// Opens the repository at `url`, a local path, a `file://` URL or an `http://` one.
+    pub fn transport_open(&self, url: &str) -> Result<Transport, String> {
+        if is_http_url(url) {
+            return Ok(Transport::Http(HttpRemote::new(url, self.credential_helpers())?));
+        }
+        let git_dir = local_git_dir(url).ok_or_else(|| format!("'{}' does not appear to be a git repository", url))?;
+        Ok(Transport::Local(GitRepo::from_dirs(None, git_dir, false)))
+    }
+
+    /// The remote-tracking ref a remote's ref is fetched into, if any.
+    pub fn remote_tracking_ref(remote: &Remote, name: &str) -> Option<String> {
+        if refspecs_exclude(&remote.fetch, name) {
+            return None;
+        }
+        remote.fetch.iter().filter(|v| !v.negative).find_map(|v| v.map(name))
+    }
+
+    /// The names of the configured remotes, sorted.
+    pub fn remote_names(&self) -> Vec<String> {
+        let names: BTreeSet<String> = self
+            .config
+            .entries
+            .iter()
+            .filter_map(|v| v.key.strip_prefix("remote.")?.rsplit_once('.'))
+            .map(|(name, _)| name.to_string())
+            .collect();
+        names.into_iter().collect()
+    }
+
+    /// Configures the remote `name`, fetching every branch into `refs/remotes/<name>/`.
+    pub fn remote_add(&self, name: &str, url: &str) -> Result<(), String> {
+        let config_file = self.common_dir.join("config");
+        config::config_set(&config_file, &format!("remote.{}.url", name), url, false)?;
+        config::config_set(&config_file, &format!("remote.{}.fetch", name), &format!("+refs/heads/*:refs/remotes/{}/*", name), false)
+    }
+
+    pub fn remote_set_url(&self, name: &str, url: &str) -> Result<(), String> {
+        config::config_set(&self.common_dir.join("config"), &format!("remote.{}.url", name), url, false)
+    }
+
+    /// Forgets the remote `name` along with its remote-tracking refs, and the upstream of the
+    /// branches that track it.
+    pub fn remote_remove(&self, name: &str) -> Result<(), String> {
+        let config_file = self.common_dir.join("config");
+        for branch in self.remote_branches(name) {
+            let section = format!("branch.{}", branch);
+            let others = self.config.entries.iter().filter_map(|v| v.key.strip_prefix(&format!("{}.", section)));
+            if others.clone().all(|v| v == "remote" || v == "merge") {
+                config::config_section_remove(&config_file, &section)?;
+            } else {
+                config::config_unset(&config_file, &format!("{}.remote", section), true)?;
+                config::config_unset(&config_file, &format!("{}.merge", section), true)?;
+            }
+        }
+        let prefix = format!("refs/remotes/{}/", name);
+        for tracking in list_refs(&self.common_dir).keys().filter(|v| v.starts_with(&prefix)) {
+            self.ref_delete(tracking)?;
+        }
+        let _ = fs::remove_dir_all(self.common_dir.join(&prefix));
+        config::config_section_remove(&config_file, &format!("remote.{}", name))?;
+        Ok(())
+    }
+
+    /// Renames the remote `old` to `new`, moving its remote-tracking refs along and the
+    /// refspecs that fetch into them.
+    pub fn remote_rename(&self, old: &str, new: &str) -> Result<(), String> {
+        let config_file = self.common_dir.join("config");
+        config::config_section_rename(&config_file, &format!("remote.{}", old), &format!("remote.{}", new))?;
+        let (old_prefix, new_prefix) = (format!("refs/remotes/{}/", old), format!("refs/remotes/{}/", new));
+        let fetch_key = format!("remote.{}.fetch", new);
+        let fetch: Vec<String> = self.config.get_all(&format!("remote.{}.fetch", old)).iter().filter_map(|v| v.value.clone()).collect();
+        if fetch.iter().any(|v| v.contains(&format!(":{}", old_prefix))) {
+            config::config_unset(&config_file, &fetch_key, true)?;
+            for refspec in fetch {
+                let refspec = refspec.replace(&format!(":{}", old_prefix), &format!(":{}", new_prefix));
+                config::config_set(&config_file, &fetch_key, &refspec, true)?;
+            }
+        }
+        for branch in self.remote_branches(old) {
+            config::config_set(&config_file, &format!("branch.{}.remote", branch), new, false)?;
+        }
+
+        for (name, sha) in list_refs(&self.common_dir).iter().filter(|(k, _)| k.starts_with(&old_prefix)) {
+            self.ref_create(&format!("remotes/{}/{}", new, &name[old_prefix.len()..]), sha);
+            self.ref_delete(name)?;
+        }
+        let head = fs::read_to_string(self.common_dir.join(&old_prefix).join("HEAD")).unwrap_or_default();
+        if let Some(target) = head.trim().strip_prefix(&format!("ref: {}", old_prefix)) {
+            fs::create_dir_all(self.common_dir.join(&new_prefix)).map_err(|e| e.to_string())?;
+            fs::write(self.common_dir.join(&new_prefix).join("HEAD"), format!("ref: {}{}\n", new_prefix, target)).map_err(|e| e.to_string())?;
+        }
+        let _ = fs::remove_dir_all(self.common_dir.join(&old_prefix));
+        Ok(())
+    }
+
+    /// The branches whose upstream is on the remote `name`.
+    fn remote_branches(&self, name: &str) -> Vec<String> {
+        let branches = self.config.entries.iter().filter(|v| v.value.as_deref() == Some(name));
+        branches.filter_map(|v| v.key.strip_prefix("branch.")?.strip_suffix(".remote")).map(|v| v.to_string()).collect()
+    }
+
+    /// Copies the object `sha` from `source` along with everything it leads to that this
+    /// repository doesn't have, going to the parents of commits only with `parents`.
+    pub fn objects_copy(&self, source: &GitRepo, sha: &str, parents: bool, copied: &mut HashSet<String>) -> Result<(), String> {
+        // The history of a shallow repository stops at these commits.
+        let shallow = fs::read_to_string(source.common_dir.join("shallow")).unwrap_or_default();
+        let shallow: HashSet<&str> = shallow.lines().collect();
+        let mut pending = vec![sha.to_string()];
+        while let Some(sha) = pending.pop() {
+            if !copied.insert(sha.clone()) || self.object_exists(&sha) {
+                continue;
+            }
+            let (fmt, data) = source.object_read_raw(&sha)?;
+            self.object_store(&fmt, &data)?;
+            match fmt.as_slice() {
+                b"commit" => {
+                    let commit = GitCommit::new(data);
+                    let value = |key: &[u8]| -> Vec<String> {
+                        commit.kvlm.get(key).into_iter().flatten().map(|v| String::from_utf8_lossy(v).into_owned()).collect()
+                    };
+                    pending.extend(value(b"tree"));
+                    if parents && !shallow.contains(sha.as_str()) {
+                        pending.extend(value(b"parent"));
+                    }
+                }
+                b"tag" => {
+                    let tag = GitTag::new(data);
+                    pending.extend(tag.kvlm.get(b"object".as_ref()).into_iter().flatten().map(|v| String::from_utf8_lossy(v).into_owned()));
+                }
+                b"tree" => pending.extend(GitTree::from_raw(&data).leafs.into_iter().filter(|v| v.mode != "160000").map(|v| v.sha)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Brings the objects `wants` lead to from `source`, going to the parents of commits only
+    /// with `parents`; over HTTP the server leaves out what the refs here already have.
+    pub fn objects_fetch(&self, source: &Transport, wants: &[String], parents: bool, copied: &mut HashSet<String>) -> Result<(), String> {
+        match source {
+            Transport::Local(repo) => wants.iter().try_for_each(|sha| self.objects_copy(repo, sha, parents, copied)),
+            Transport::Http(remote) => self.http_fetch(remote, wants, None),
+        }
+    }
+
+    /// How moving a ref from `old` to `new` goes: tags are only replaced, and branches only
+    /// rewound, when forced.
+    fn ref_update_status(&self, dst: &str, old: &Option<String>, new: &str, force: bool) -> RefStatus {
+        let old = match old {
+            None => return RefStatus::New,
+            Some(v) if v == new => return RefStatus::UpToDate,
+            Some(v) => v,
+        };
+        if dst.starts_with("refs/tags/") {
+            return match force {
+                true => RefStatus::TagUpdate,
+                false => RefStatus::Rejected("would clobber existing tag".to_string()),
+            };
+        }
+        if self.object_exists(old) && self.commit_ancestors(new).contains(old) {
+            RefStatus::FastForward
+        } else if force {
+            RefStatus::Forced
+        } else if !self.object_exists(old) {
+            RefStatus::Rejected("fetch first".to_string())
+        } else {
+            RefStatus::Rejected("non-fast-forward".to_string())
+        }
+    }
+
+    /// Fetches what `refspecs`, or the ones configured for the remote, name from `source`:
+    /// copies the objects that are missing, updates the refs they are fetched into and
+    /// writes `FETCH_HEAD`.
+    ///
+    /// Configured refspecs also bring the tags pointing into what was fetched; given ones
+    /// also update the remote-tracking refs the configured ones would.
+    pub fn fetch(&self, remote: &Remote, source: &Transport, refspecs: &[Refspec]) -> Result<Vec<RefUpdate>, String> {
+        let listed = source.refs()?;
+        let remote_refs = &listed.refs;
+        let configured = refspecs.is_empty();
+        let refspecs = match (configured, remote.fetch.is_empty()) {
+            (true, true) => vec![Refspec::parse("HEAD")?],
+            (true, false) => remote.fetch.clone(),
+            (false, _) => refspecs.to_vec(),
+        };
+
+        let mut updates = Vec::new();
+        for refspec in refspecs.iter().filter(|v| !v.negative) {
+            let matched: Vec<(&String, &String)> = match refspec.is_glob() {
+                true => remote_refs.iter().filter(|(k, _)| refspec.matches(k)).collect(),
+                false => {
+                    let found = ref_candidates(&refspec.src).into_iter().find_map(|v| remote_refs.get_key_value(&v));
+                    vec![found.ok_or_else(|| format!("couldn't find remote ref {}", refspec.src))?]
+                }
+            };
+            for (name, sha) in matched {
+                if refspecs_exclude(&refspecs, name) {
+                    continue;
+                }
+                let dst = match refspec.is_glob() {
+                    true => refspec.map(name),
+                    false => refspec.dst.as_deref().filter(|v| !v.is_empty()).map(|v| qualify_dst(v, name, &BTreeMap::new())),
+                };
+                updates.push(RefUpdate::new(name.clone(), dst, Some(sha.clone()), refspec.force));
+            }
+        }
+
+        // What FETCH_HEAD offers for merging: the upstream of the current branch, or all that
+        // was asked for.
+        let upstream = self.get_active_branch().and_then(|branch| {
+            let remote_name = self.config_get(&format!("branch.{}.remote", branch))?;
+            let merge = self.config_get(&format!("branch.{}.merge", branch))?;
+            (remote_name == remote.name).then_some(merge)
+        });
+        for update in updates.iter_mut() {
+            let for_merge = !configured || remote.fetch.is_empty() || upstream.as_deref() == Some(update.src.as_str());
+            update.merge = Some(for_merge);
+        }
+        updates.sort_by_key(|v| v.merge != Some(true));
+        if !configured {
+            let opportunistic: Vec<RefUpdate> = updates
+                .iter()
+                .filter_map(|v| {
+                    let dst = Self::remote_tracking_ref(remote, &v.src)?;
+                    let force = remote.fetch.iter().any(|s| !s.negative && s.force && s.matches(&v.src));
+                    Some(RefUpdate::new(v.src.clone(), Some(dst), v.new.clone(), force))
+                })
+                .collect();
+            for update in opportunistic {
+                if !updates.iter().any(|v| v.dst == update.dst) {
+                    updates.push(update);
+                }
+            }
+        }
+
+        if !self.bare {
+            for dst in updates.iter().filter_map(|v| v.dst.as_ref()) {
+                if let Some(worktree) = self.worktree_with_branch(dst) {
+                    return Err(format!("refusing to fetch into branch '{}' checked out at '{}'", dst, worktree.path.display()));
+                }
+            }
+        }
+
+        let mut copied = HashSet::new();
+        let wants: Vec<String> = updates.iter().filter_map(|v| v.new.clone()).collect();
+        self.objects_fetch(source, &wants, true, &mut copied)?;
+        if let Transport::Local(repo) = source {
+            self.lfs_objects_copy(repo, false)?;
+        }
+        if configured && self.config_get(&format!("remote.{}.tagOpt", remote.name)).as_deref() != Some("--no-tags") {
+            for (name, sha) in remote_refs.iter().filter(|(k, _)| k.starts_with("refs/tags/")) {
+                let known = updates.iter().any(|v| v.dst.as_ref() == Some(name)) || self.ref_resolve(name.into()).is_some();
+                let follows = self.object_exists(listed.peel(name, sha));
+                if !known && follows {
+                    self.objects_fetch(source, std::slice::from_ref(sha), false, &mut copied)?;
+                    let mut update = RefUpdate::new(name.clone(), Some(name.clone()), Some(sha.clone()), false);
+                    update.merge = Some(false);
+                    updates.push(update);
+                }
+            }
+        }
+
+        for update in updates.iter_mut() {
+            let new = update.new.clone().unwrap_or_default();
+            update.status = match &update.dst {
+                None => RefStatus::Fetched,
+                Some(dst) => {
+                    update.old = self.ref_resolve(dst.into());
+                    self.ref_update_status(dst, &update.old, &new, update.force)
+                }
+            };
+            if let (Some(dst), RefStatus::New | RefStatus::FastForward | RefStatus::Forced | RefStatus::TagUpdate) = (&update.dst, &update.status) {
+                self.ref_create(dst.trim_start_matches("refs/"), &new);
+            }
+        }
+
+        let content: String = updates
+            .iter()
+            .filter(|v| v.merge.is_some())
+            .map(|v| {
+                let marker = if v.merge == Some(true) { "" } else { "not-for-merge" };
+                let kind = match v.src.as_str() {
+                    "HEAD" => String::new(),
+                    name if name.starts_with("refs/heads/") => format!("branch '{}' of ", ref_shorten(name)),
+                    name if name.starts_with("refs/tags/") => format!("tag '{}' of ", ref_shorten(name)),
+                    name if name.starts_with("refs/remotes/") => format!("remote-tracking branch '{}' of ", ref_shorten(name)),
+                    name => format!("'{}' of ", name),
+                };
+                format!("{}\t{}\t{}{}\n", v.new.as_deref().unwrap_or_default(), marker, kind, remote.url_fetched())
+            })
+            .collect();
+        fs::write(self.git_dir.join("FETCH_HEAD"), content).map_err(|e| e.to_string())?;
+        Ok(updates)
+    }
+
+    /// The refspec a push without any uses when the remote has none configured: the current
+    /// branch to its upstream, or to the same name on a remote that isn't its own.
+    pub fn push_default_refspec(&self, remote: &Remote) -> Result<Refspec, String> {
+        let branch = self.get_active_branch().ok_or_else(|| {
+            "You are not currently on a branch.\n\
+             To push the history leading to the current (detached HEAD)\n\
+             state now, use\n\
+             \n    git push {} HEAD:<name-of-remote-branch>\n"
+                .replace("{}", &remote.name)
+        })?;
+        let branch_remote = self.config_get(&format!("branch.{}.remote", branch));
+        let merge = self.config_get(&format!("branch.{}.merge", branch));
+        if branch_remote.as_deref().unwrap_or("origin") != remote.name {
+            return Refspec::parse(&format!("refs/heads/{}:refs/heads/{}", branch, branch));
+        }
+        match merge {
+            Some(merge) => Refspec::parse(&format!("refs/heads/{}:{}", branch, merge)),
+            None => Err(format!(
+                "The current branch {0} has no upstream branch.\n\
+                 To push the current branch and set the remote as upstream, use\n\
+                 \n    git push --set-upstream {1} {0}\n\
+                 \nTo have this happen automatically for branches without a tracking\n\
+                 upstream, see 'push.autoSetupRemote' in 'git help config'.\n\n",
+                branch, remote.name
+            )),
+        }
+    }
+
+    /// Pushes what `refspecs` name to `target`, checking that branches only move forward
+    /// unless forced, and updates the remote-tracking refs of what went through.
+    ///
+    /// Returns the updates along with the messages of the receiving side.
+    pub fn push(&self, remote: &Remote, target: &Transport, refspecs: &[Refspec], force: bool, leases: &[Lease]) -> Result<(Vec<RefUpdate>, Vec<String>), String> {
+        let local_refs = list_refs(&self.common_dir);
+        let remote_refs = match target {
+            Transport::Local(repo) => list_refs(&repo.common_dir),
+            Transport::Http(http) => http.receive_refs()?,
+        };
+
+        let mut updates = Vec::new();
+        for refspec in refspecs.iter().filter(|v| !v.negative) {
+            let force = force || refspec.force;
+            if refspec.src.is_empty() {
+                let dst = refspec.dst.as_deref().unwrap_or_default();
+                // A full refname is sent as is, and the receiving side warns if there is nothing to delete.
+                let name = ref_candidates(dst)
+                    .into_iter()
+                    .find(|v| remote_refs.contains_key(v))
+                    .or_else(|| dst.starts_with("refs/").then(|| dst.to_string()))
+                    .ok_or_else(|| format!("unable to delete '{}': remote ref does not exist", dst))?;
+                updates.push(RefUpdate::new(String::new(), Some(name), None, force));
+            } else if refspec.is_glob() {
+                for (name, sha) in local_refs.iter().filter(|(k, _)| refspec.matches(k) && !refspecs_exclude(refspecs, k)) {
+                    updates.push(RefUpdate::new(name.clone(), refspec.map(name), Some(sha.clone()), force));
+                }
+            } else {
+                let no_match = || format!("src refspec {} does not match any", refspec.src);
+                let full = match refspec.src.as_str() {
+                    "HEAD" => self.get_active_branch().map(|v| format!("refs/heads/{}", v)),
+                    src => ref_candidates(src).into_iter().find(|v| local_refs.contains_key(v)),
+                };
+                let sha = match &full {
+                    Some(name) if name != "HEAD" => local_refs.get(name).cloned().ok_or_else(no_match)?,
+                    _ => self.obj_find(refspec.src.clone(), None, None).map_err(|_| no_match())?,
+                };
+                let dst = match (refspec.dst.as_deref().filter(|v| !v.is_empty()), &full) {
+                    (Some(dst), _) => qualify_dst(dst, full.as_deref().unwrap_or_default(), &remote_refs),
+                    (None, Some(name)) => name.clone(),
+                    (None, None) => return Err(no_match()),
+                };
+                let src = match refspec.src.as_str() {
+                    "HEAD" => "HEAD".to_string(),
+                    _ => full.unwrap_or(refspec.src.clone()),
+                };
+                updates.push(RefUpdate::new(src, Some(dst), Some(sha), force));
+            }
+        }
+
+        let mut messages = Vec::new();
+        for update in updates.iter_mut() {
+            let dst = update.dst.clone().unwrap_or_default();
+            update.old = remote_refs.get(&dst).cloned();
+            let lease = leases.iter().find_map(|v| match v {
+                Lease::Tracking => Some(None),
+                Lease::Ref(name, expect) => ref_candidates(name).contains(&dst).then_some(expect.clone()),
+            });
+            update.status = match (&update.new, lease) {
+                (None, _) => RefStatus::Deleted,
+                (Some(new), _) if update.old.as_ref() == Some(new) => RefStatus::UpToDate,
+                (Some(_), _) if update.old.is_none() => RefStatus::New,
+                (Some(new), Some(expect)) => {
+                    let expect = match expect {
+                        Some(v) => self.obj_find(v, None, None).ok(),
+                        None => Self::remote_tracking_ref(remote, &dst).and_then(|v| self.ref_resolve(v.into())),
+                    };
+                    match expect == update.old {
+                        true => self.ref_update_status(&dst, &update.old, new, true),
+                        false => RefStatus::Rejected("stale info".to_string()),
+                    }
+                }
+                (Some(new), None) => match self.ref_update_status(&dst, &update.old, new, update.force) {
+                    RefStatus::Rejected(v) if v == "would clobber existing tag" => RefStatus::Rejected("already exists".to_string()),
+                    RefStatus::TagUpdate => RefStatus::Forced,
+                    v => v,
+                },
+            };
+            if !update.ok() || update.status == RefStatus::UpToDate {
+                continue;
+            }
+            // A server checks for itself.
+            if let Transport::Local(target) = target {
+                if let Some(reason) = target.receive_denied(&dst, update.old.as_deref(), update.new.as_deref(), &mut messages) {
+                    update.status = RefStatus::RemoteRejected(reason);
+                } else if update.new.is_none() && update.old.is_none() {
+                    messages.push("warning: deleting a non-existent ref".to_string());
+                }
+            }
+        }
+
+        match target {
+            Transport::Local(target) => {
+                let mut copied = HashSet::new();
+                for update in updates.iter().filter(|v| v.ok() && v.status != RefStatus::UpToDate) {
+                    let dst = update.dst.as_deref().unwrap_or_default();
+                    match &update.new {
+                        Some(sha) => {
+                            target.objects_copy(self, sha, true, &mut copied)?;
+                            target.ref_create(dst.trim_start_matches("refs/"), sha);
+                        }
+                        None => target.ref_delete(dst)?,
+                    }
+                }
+            }
+            Transport::Http(http) => self.http_push(http, &mut updates, &remote_refs, &mut messages)?,
+        }
+        for update in updates.iter().filter(|v| v.ok()) {
+            let tracking = update.dst.as_deref().and_then(|v| Self::remote_tracking_ref(remote, v));
+            match (tracking, &update.new) {
+                (Some(tracking), Some(sha)) => self.ref_create(tracking.trim_start_matches("refs/"), sha),
+                (Some(tracking), None) => self.ref_delete(&tracking)?,
+                (None, _) => {}
+            }
+        }
+        Ok((updates, messages))
+    }
+}
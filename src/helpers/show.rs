@@ -0,0 +1,353 @@
+use crate::helpers::diff::{format_stat, FileStat};
+use crate::helpers::git::GitRepo;
+use crate::helpers::git_objects::commit::GitCommit;
+use crate::helpers::git_objects::git_object::GitObject;
+use crate::helpers::git_objects::tag::GitTag;
+use crate::helpers::git_objects::tree::GitTree;
+use crate::helpers::ident::GitIdent;
+use ordermap::OrderMap;
+use std::hash::RandomState;
+
+/// How a commit header is rendered by `show --format`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommitFormat {
+    Oneline,
+    Short,
+    Medium,
+    Full,
+    Fuller,
+    Raw,
+    /// `format:<string>` and `tformat:<string>`; the flag tells if a newline terminates it.
+    Custom(String, bool),
+}
+
+impl CommitFormat {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "oneline" => Ok(CommitFormat::Oneline),
+            "short" => Ok(CommitFormat::Short),
+            "medium" => Ok(CommitFormat::Medium),
+            "full" => Ok(CommitFormat::Full),
+            "fuller" => Ok(CommitFormat::Fuller),
+            "raw" => Ok(CommitFormat::Raw),
+            v => {
+                if let Some(v) = v.strip_prefix("format:") {
+                    Ok(CommitFormat::Custom(v.to_string(), false))
+                } else if let Some(v) = v.strip_prefix("tformat:") {
+                    Ok(CommitFormat::Custom(v.to_string(), true))
+                } else if v.contains('%') {
+                    Ok(CommitFormat::Custom(v.to_string(), true))
+                } else {
+                    Err(format!("invalid --pretty format: {}", v))
+                }
+            }
+        }
+    }
+}
+
+fn kvlm_first(kvlm: &OrderMap<Vec<u8>, Vec<Vec<u8>>, RandomState>, key: &[u8]) -> String {
+    kvlm.get(key)
+        .and_then(|v| v.first())
+        .map(|v| String::from_utf8_lossy(v).into_owned())
+        .unwrap_or_default()
+}
+
+fn kvlm_all(kvlm: &OrderMap<Vec<u8>, Vec<Vec<u8>>, RandomState>, key: &[u8]) -> Vec<String> {
+    kvlm.get(key)
+        .map(|v| {
+            v.iter()
+                .map(|v| String::from_utf8_lossy(v).into_owned())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn abbrev(sha: &str) -> &str {
+    &sha[..sha.len().min(7)]
+}
+
+/// Splits a message into its subject (first paragraph joined on one line) and body.
+pub fn message_parts(message: &str) -> (String, String) {
+    let message = message.trim_start_matches('\n');
+    let (subject, body) = match message.find("\n\n") {
+        Some(pos) => (&message[..pos], message[pos + 2..].trim_start_matches('\n')),
+        None => (message.trim_end_matches('\n'), ""),
+    };
+    let subject = subject
+        .lines()
+        .map(|v| v.trim())
+        .collect::<Vec<_>>()
+        .join(" ");
+    (subject, body.to_string())
+}
+
+fn indent(message: &str) -> String {
+    message
+        .trim_end_matches('\n')
+        .lines()
+        .map(|v| format!("    {}\n", v))
+        .collect()
+}
+
+impl GitRepo {
+    /// The first paragraph of the message of `sha`, on one line.
+    pub fn commit_subject(&self, sha: &str) -> Result<String, String> {
+        let obj = self.object_read(sha.to_string())?;
+        let commit = obj
+            .as_ref()
+            .as_any()
+            .downcast_ref::<GitCommit>()
+            .ok_or_else(|| format!("{} is not a commit", sha))?;
+        let (subject, _) = message_parts(&kvlm_first(&commit.kvlm, b"None"));
+        Ok(subject.replace('\n', " "))
+    }
+
+    /// Renders the header and message part of a commit in the requested format.
+    pub fn format_commit(&self, sha: &str, commit: &GitCommit, format: &CommitFormat) -> String {
+        let message = kvlm_first(&commit.kvlm, b"None");
+        let (subject, body) = message_parts(&message);
+        let parents = kvlm_all(&commit.kvlm, b"parent");
+        let tree = kvlm_first(&commit.kvlm, b"tree");
+        let author = commit
+            .kvlm
+            .get(b"author".as_ref())
+            .and_then(|v| v.first())
+            .and_then(|v| GitIdent::parse(v));
+        let committer = commit
+            .kvlm
+            .get(b"committer".as_ref())
+            .and_then(|v| v.first())
+            .and_then(|v| GitIdent::parse(v));
+
+        let mut output = String::new();
+        match format {
+            CommitFormat::Oneline => {
+                output.push_str(&format!("{} {}\n", sha, subject));
+            }
+            CommitFormat::Custom(fmt, terminated) => {
+                output.push_str(&format_placeholders(
+                    fmt,
+                    sha,
+                    &tree,
+                    &parents,
+                    author.as_ref(),
+                    committer.as_ref(),
+                    &subject,
+                    &body,
+                ));
+                if *terminated {
+                    output.push('\n');
+                }
+            }
+            CommitFormat::Raw => {
+                output.push_str(&format!("commit {}\n", sha));
+                let headers = String::from_utf8_lossy(&commit.data()).into_owned();
+                let headers = headers.split("\n\n").next().unwrap_or("");
+                output.push_str(headers);
+                output.push_str("\n\n");
+                output.push_str(&indent(&message));
+            }
+            _ => {
+                output.push_str(&format!("commit {}\n", sha));
+                if parents.len() > 1 {
+                    let parents: Vec<&str> = parents.iter().map(|v| abbrev(v)).collect();
+                    output.push_str(&format!("Merge: {}\n", parents.join(" ")));
+                }
+                if let Some(author) = &author {
+                    match format {
+                        CommitFormat::Fuller => output.push_str(&format!(
+                            "Author:     {}\nAuthorDate: {}\n",
+                            author.who(),
+                            author.date_string()
+                        )),
+                        _ => output.push_str(&format!("Author: {}\n", author.who())),
+                    }
+                }
+                if let Some(committer) = &committer {
+                    match format {
+                        CommitFormat::Fuller => output.push_str(&format!(
+                            "Commit:     {}\nCommitDate: {}\n",
+                            committer.who(),
+                            committer.date_string()
+                        )),
+                        CommitFormat::Full => {
+                            output.push_str(&format!("Commit: {}\n", committer.who()))
+                        }
+                        _ => {}
+                    }
+                }
+                if *format == CommitFormat::Medium {
+                    if let Some(author) = &author {
+                        output.push_str(&format!("Date:   {}\n", author.date_string()));
+                    }
+                }
+                output.push('\n');
+                if *format == CommitFormat::Short {
+                    output.push_str(&indent(&subject));
+                } else {
+                    output.push_str(&indent(&message));
+                }
+            }
+        }
+        output
+    }
+
+    /// Renders the patch (or the `--stat` summary) of a commit against its first parent.
+    pub fn format_commit_diff(&self, commit: &GitCommit, stat: bool) -> Vec<u8> {
+        let parents = kvlm_all(&commit.kvlm, b"parent");
+        // Like git, merges are not diffed against a single parent by default.
+        if parents.len() > 1 {
+            return Vec::new();
+        }
+        let tree = kvlm_first(&commit.kvlm, b"tree");
+        let parent_tree = parents.first().and_then(|v| {
+            self.obj_find(v.clone(), Some("tree".to_string()), None)
+                .ok()
+        });
+
+        let mut patches = Vec::new();
+        let mut stats: Vec<FileStat> = Vec::new();
+        for change in self.diff_trees(parent_tree.as_deref(), Some(&tree)) {
+            let (patch, file_stat) = self.diff_file(&change);
+            patches.extend_from_slice(&patch);
+            stats.push(file_stat);
+        }
+        if stat {
+            format_stat(&stats).into_bytes()
+        } else {
+            patches
+        }
+    }
+
+    /// Pretty-prints any object the way `git show` does.
+    pub fn show(&self, name: &str, format: &CommitFormat, stat: bool) -> Result<Vec<u8>, String> {
+        let sha = self.obj_find(name.to_string(), None, None)?;
+        let obj = self.object_read(sha.clone())?;
+        let mut output = Vec::new();
+
+        if let Some(commit) = obj.as_ref().as_any().downcast_ref::<GitCommit>() {
+            output.extend_from_slice(self.format_commit(&sha, commit, format).as_bytes());
+            let diff = self.format_commit_diff(commit, stat);
+            if !diff.is_empty() {
+                if *format != CommitFormat::Oneline {
+                    output.push(b'\n');
+                }
+                output.extend_from_slice(&diff);
+            }
+        } else if let Some(tag) = obj.as_ref().as_any().downcast_ref::<GitTag>() {
+            output.extend_from_slice(format!("tag {}\n", kvlm_first(&tag.kvlm, b"tag")).as_bytes());
+            if let Some(tagger) = tag
+                .kvlm
+                .get(b"tagger".as_ref())
+                .and_then(|v| v.first())
+                .and_then(|v| GitIdent::parse(v))
+            {
+                output.extend_from_slice(
+                    format!(
+                        "Tagger: {}\nDate:   {}\n",
+                        tagger.who(),
+                        tagger.date_string()
+                    )
+                    .as_bytes(),
+                );
+            }
+            output.push(b'\n');
+            let message = kvlm_first(&tag.kvlm, b"None");
+            output.extend_from_slice(message.trim_end_matches('\n').as_bytes());
+            output.extend_from_slice(b"\n\n");
+            let target = kvlm_first(&tag.kvlm, b"object");
+            output.extend_from_slice(&self.show(&target, format, stat)?);
+        } else if let Some(tree) = obj.as_ref().as_any().downcast_ref::<GitTree>() {
+            output.extend_from_slice(format!("tree {}\n\n", name).as_bytes());
+            let mut leafs = tree.leafs.clone();
+            leafs.sort_by_key(|v| v.clone().sort_keys());
+            for leaf in leafs {
+                output.extend_from_slice(leaf.sort_keys().as_bytes());
+                output.push(b'\n');
+            }
+        } else {
+            output.extend_from_slice(&obj.data());
+        }
+        Ok(output)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn format_placeholders(
+    fmt: &str,
+    sha: &str,
+    tree: &str,
+    parents: &[String],
+    author: Option<&GitIdent>,
+    committer: Option<&GitIdent>,
+    subject: &str,
+    body: &str,
+) -> String {
+    let ident = |ident: Option<&GitIdent>, field: char| -> Option<String> {
+        let ident = ident?;
+        match field {
+            'n' => Some(ident.name.clone()),
+            'e' => Some(ident.email.clone()),
+            'd' => Some(ident.date_string()),
+            'D' => Some(ident.date().to_rfc2822()),
+            'I' => Some(ident.date().to_rfc3339()),
+            't' => Some(ident.timestamp.to_string()),
+            _ => None,
+        }
+    };
+
+    let mut output = String::new();
+    let mut chars = fmt.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => output.push('%'),
+            Some('n') => output.push('\n'),
+            Some('H') => output.push_str(sha),
+            Some('h') => output.push_str(abbrev(sha)),
+            Some('T') => output.push_str(tree),
+            Some('t') => output.push_str(abbrev(tree)),
+            Some('P') => output.push_str(&parents.join(" ")),
+            Some('p') => {
+                let parents: Vec<&str> = parents.iter().map(|v| abbrev(v)).collect();
+                output.push_str(&parents.join(" "))
+            }
+            Some('s') => output.push_str(subject),
+            Some('b') => output.push_str(body),
+            Some('B') => {
+                output.push_str(subject);
+                output.push('\n');
+                if !body.is_empty() {
+                    output.push('\n');
+                    output.push_str(body);
+                }
+            }
+            Some(who @ ('a' | 'c')) => {
+                let person = if who == 'a' { author } else { committer };
+                match chars.next() {
+                    Some(field) => match ident(person, field) {
+                        Some(v) => output.push_str(&v),
+                        None => {
+                            output.push('%');
+                            output.push(who);
+                            output.push(field);
+                        }
+                    },
+                    None => {
+                        output.push('%');
+                        output.push(who);
+                    }
+                }
+            }
+            Some(other) => {
+                output.push('%');
+                output.push(other);
+            }
+            None => output.push('%'),
+        }
+    }
+    output
+}
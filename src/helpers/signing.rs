@@ -0,0 +1,208 @@
+use crate::helpers::git::GitRepo;
+use crate::helpers::git_objects::commit::GitCommit;
+use crate::helpers::git_objects::git_object::GitObject;
+use crate::helpers::git_objects::tag::GitTag;
+use crate::helpers::kvlm::kvlm_serialize;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+pub const SSH_SIGNATURE_BEGIN: &str = "-----BEGIN SSH SIGNATURE-----";
+const SIGNING_NAMESPACE: &str = "git";
+
+/// Result of checking a signature with `ssh-keygen -Y verify`.
+#[derive(Debug, Clone)]
+pub struct SignatureCheck {
+    pub good: bool,
+    /// What ssh-keygen reported, e.g. `Good "git" signature for ...`.
+    pub message: String,
+}
+
+/// A scratch file in the system temp directory that is removed when dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str, content: &[u8]) -> Result<Self, String> {
+        let path = std::env::temp_dir().join(format!("mygit-{}-{}", std::process::id(), name));
+        std::fs::write(&path, content).map_err(|e| e.to_string())?;
+        Ok(Self(path))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+        let _ = std::fs::remove_file(self.0.with_extension("sig"));
+    }
+}
+
+/// Splits a tag body into the signed payload and the trailing signature, if any.
+pub fn split_tag_signature(data: &[u8]) -> (&[u8], Option<&[u8]>) {
+    let marker = SSH_SIGNATURE_BEGIN.as_bytes();
+    match data.windows(marker.len()).rposition(|v| v == marker) {
+        Some(pos) if pos == 0 || data[pos - 1] == b'\n' => (&data[..pos], Some(&data[pos..])),
+        _ => (data, None),
+    }
+}
+
+impl GitRepo {
+    fn ensure_ssh_format(&self) -> Result<(), String> {
+        match self.config_get("gpg.format") {
+            Some(v) if v == "ssh" => Ok(()),
+            Some(v) => Err(format!("unsupported value for gpg.format: {}", v)),
+            None => Err("signing requires gpg.format=ssh".to_string()),
+        }
+    }
+
+    /// Signs a commit or tag payload with `ssh-keygen -Y sign` and `user.signingkey`.
+    pub fn sign_payload(&self, payload: &[u8]) -> Result<String, String> {
+        self.ensure_ssh_format()?;
+        let key = self
+            .config_get("user.signingkey")
+            .ok_or("user.signingkey needs to be set for ssh signing")?;
+
+        // A literal public key means the private half lives in the ssh agent.
+        let literal = key.strip_prefix("key::").map(|v| v.to_string()).or_else(|| {
+            if key.starts_with("ssh-") {
+                Some(key.clone())
+            } else {
+                None
+            }
+        });
+        let key_file = match &literal {
+            Some(v) => Some(TempFile::new("signing-key.pub", v.as_bytes())?),
+            None => None,
+        };
+        let key_path = match &key_file {
+            Some(v) => v.0.clone(),
+            None => PathBuf::from(expand_home(&key)),
+        };
+
+        let buffer = TempFile::new("signing-buffer", payload)?;
+        let mut command = Command::new("ssh-keygen");
+        command
+            .args(["-Y", "sign", "-n", SIGNING_NAMESPACE, "-f"])
+            .arg(&key_path);
+        if literal.is_some() {
+            command.arg("-U");
+        }
+        let output = command
+            .arg(&buffer.0)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("failed to run ssh-keygen: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "ssh-keygen failed to sign the data:\n{}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        let signature = std::fs::read_to_string(buffer.0.with_extension("sig"))
+            .map_err(|e| format!("failed to read ssh signing data: {}", e))?;
+        Ok(signature)
+    }
+
+    /// Checks `signature` over `payload` against `gpg.ssh.allowedSignersFile`.
+    pub fn verify_payload(&self, payload: &[u8], signature: &[u8]) -> Result<SignatureCheck, String> {
+        self.ensure_ssh_format()?;
+        let allowed = self
+            .config_get("gpg.ssh.allowedSignersFile")
+            .map(|v| PathBuf::from(expand_home(&v)))
+            .filter(|v| v.is_file())
+            .ok_or(
+                "gpg.ssh.allowedSignersFile needs to be configured and exist for ssh signature verification",
+            )?;
+
+        let signature_file = TempFile::new("signature", signature)?;
+        let principals = Command::new("ssh-keygen")
+            .args(["-Y", "find-principals", "-f"])
+            .arg(&allowed)
+            .arg("-s")
+            .arg(&signature_file.0)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("failed to run ssh-keygen: {}", e))?;
+        let principal = String::from_utf8_lossy(&principals.stdout)
+            .lines()
+            .next()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty());
+        let principal = match principal {
+            Some(v) if principals.status.success() => v,
+            _ => {
+                return Ok(SignatureCheck {
+                    good: false,
+                    message: "No principal matched.".to_string(),
+                })
+            }
+        };
+
+        let mut child = Command::new("ssh-keygen")
+            .args(["-Y", "verify", "-n", SIGNING_NAMESPACE, "-f"])
+            .arg(&allowed)
+            .arg("-I")
+            .arg(&principal)
+            .arg("-s")
+            .arg(&signature_file.0)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to run ssh-keygen: {}", e))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(payload).map_err(|e| e.to_string())?;
+        }
+        let output = child.wait_with_output().map_err(|e| e.to_string())?;
+        let mut message = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if message.is_empty() {
+            message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        }
+        Ok(SignatureCheck {
+            good: output.status.success(),
+            message,
+        })
+    }
+
+    /// Verifies the `gpgsig` header of a commit.
+    pub fn verify_commit(&self, sha: &str) -> Result<SignatureCheck, String> {
+        let obj = self.object_read(sha.to_string())?;
+        let commit = obj
+            .as_ref()
+            .as_any()
+            .downcast_ref::<GitCommit>()
+            .ok_or_else(|| format!("{}: cannot verify a non-commit object", sha))?;
+
+        let mut kvlm = commit.kvlm.clone();
+        let signature = kvlm
+            .remove(b"gpgsig".as_ref())
+            .and_then(|v| v.into_iter().next())
+            .ok_or("no signature found")?;
+        let mut signature = signature;
+        signature.push(b'\n');
+        self.verify_payload(&kvlm_serialize(&kvlm), &signature)
+    }
+
+    /// Verifies the signature trailing the message of an annotated tag.
+    pub fn verify_tag(&self, sha: &str) -> Result<SignatureCheck, String> {
+        let obj = self.object_read(sha.to_string())?;
+        let tag = obj
+            .as_ref()
+            .as_any()
+            .downcast_ref::<GitTag>()
+            .ok_or_else(|| format!("{}: cannot verify a non-tag object", sha))?;
+        let data = tag.data();
+        match split_tag_signature(&data) {
+            (payload, Some(signature)) => self.verify_payload(payload, signature),
+            _ => Err("no signature found".to_string()),
+        }
+    }
+}
+
+/// Expands a leading `~/` the way git does for path-valued settings.
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{}", home, rest),
+        _ => path.to_string(),
+    }
+}
@@ -0,0 +1,310 @@
+use crate::helpers::config::{self, ConfigScope};
+use crate::helpers::git::GitRepo;
+use crate::helpers::git_objects::git_ignore::GitIgnore;
+use crate::helpers::git_objects::git_index_entry::GitIndexEntry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// What a sparse checkout keeps in the working tree.
+#[derive(Debug, Clone)]
+pub enum SparsePatterns {
+    /// Cone mode: these directories with everything below them, plus the files directly
+    /// inside their parents and at the top.
+    Cone(BTreeSet<String>),
+    /// Gitignore-style patterns, a path being kept when they match it.
+    Patterns(Vec<String>),
+}
+
+impl SparsePatterns {
+    /// Reads `info/sparse-checkout`. In cone mode the file holds a directory as `/<dir>/`,
+    /// with `!/<dir>/*/` after it when only its direct files are wanted.
+    pub fn parse(content: &str, cone: bool) -> Self {
+        if !cone {
+            return SparsePatterns::Patterns(
+                content
+                    .lines()
+                    .filter(|v| !v.is_empty() && !v.starts_with('#'))
+                    .map(|v| v.to_string())
+                    .collect(),
+            );
+        }
+        let mut dirs = BTreeSet::new();
+        let mut parents = BTreeSet::new();
+        for line in content.lines() {
+            if let Some(dir) = line.strip_prefix("!/").and_then(|v| v.strip_suffix("/*/")) {
+                parents.insert(dir.to_string());
+            } else if let Some(dir) = line.strip_prefix('/').and_then(|v| v.strip_suffix('/')) {
+                if !dir.is_empty() && dir != "*" {
+                    dirs.insert(dir.to_string());
+                }
+            }
+        }
+        SparsePatterns::Cone(dirs.difference(&parents).cloned().collect())
+    }
+
+    /// The content of `info/sparse-checkout`, as git writes it.
+    pub fn serialize(&self) -> String {
+        let dirs = match self {
+            SparsePatterns::Patterns(patterns) => return patterns.iter().map(|v| format!("{}\n", v)).collect(),
+            SparsePatterns::Cone(dirs) => dirs,
+        };
+        let mut ret = "/*\n!/*/\n".to_string();
+        let parents: BTreeSet<String> =
+            dirs.iter().flat_map(|v| cone_parents(v)).filter(|v| !v.is_empty()).collect();
+        for dir in parents.union(dirs) {
+            if dirs.contains(dir) {
+                ret.push_str(&format!("/{}/\n", dir));
+            } else {
+                ret.push_str(&format!("/{}/\n!/{}/*/\n", dir, dir));
+            }
+        }
+        ret
+    }
+
+    /// Adds directories, or patterns, to the ones already there.
+    pub fn extend(&mut self, more: SparsePatterns) {
+        match (self, more) {
+            (SparsePatterns::Cone(dirs), SparsePatterns::Cone(more)) => {
+                dirs.extend(more);
+                let all = dirs.clone();
+                // A directory below another one is already in full.
+                dirs.retain(|v| !cone_parents(v).iter().any(|p| all.contains(p)));
+            }
+            (SparsePatterns::Patterns(patterns), SparsePatterns::Patterns(more)) => patterns.extend(more),
+            (this, more) => *this = more,
+        }
+    }
+
+    /// Tells if the file `path`, relative to the top of the worktree, is kept.
+    pub fn includes(&self, path: &str) -> bool {
+        match self {
+            SparsePatterns::Cone(dirs) => {
+                let parent = Path::new(path).parent().map(|v| v.to_string_lossy().into_owned()).unwrap_or_default();
+                parent.is_empty()
+                    || dirs.iter().any(|v| path.strip_prefix(v.as_str()).is_some_and(|v| v.starts_with('/')))
+                    || dirs.iter().any(|v| cone_parents(v).contains(&parent))
+            }
+            SparsePatterns::Patterns(patterns) => {
+                let mut matcher = GitIgnore::command_line_only(PathBuf::new());
+                matcher.add_excludes(patterns);
+                matcher.is_ignored(path, false)
+            }
+        }
+    }
+
+    /// The directory of cone mode that `path`, left out, is in: the first one down from
+    /// the top that isn't the parent of a directory in the cone.
+    fn cone_excluded_dir(&self, path: &str) -> Option<String> {
+        let dirs = match self {
+            SparsePatterns::Cone(v) => v,
+            SparsePatterns::Patterns(_) => return None,
+        };
+        let parents: BTreeSet<String> = dirs.iter().flat_map(|v| cone_parents(v)).collect();
+        let mut ancestors: Vec<String> = cone_parents(path);
+        ancestors.remove(0);
+        ancestors.into_iter().find(|v| !parents.contains(v))
+    }
+}
+
+/// The directories `dir` is in, from the top down, starting with the empty top one.
+fn cone_parents(dir: &str) -> Vec<String> {
+    let mut ret = vec![String::new()];
+    let mut current = String::new();
+    let parts: Vec<&str> = dir.split('/').collect();
+    for part in &parts[..parts.len() - 1] {
+        if !current.is_empty() {
+            current.push('/');
+        }
+        current.push_str(part);
+        ret.push(current.clone());
+    }
+    ret
+}
+
+/// Checks the directories given to cone mode and makes them relative to the top.
+pub fn cone_dirs(args: &[String]) -> Result<BTreeSet<String>, String> {
+    let mut ret = BTreeSet::new();
+    for arg in args {
+        if arg.starts_with('/') {
+            return Err("specify directories rather than patterns (no leading slash)".to_string());
+        }
+        if arg.contains(['*', '?', '[', ']', '\\']) {
+            return Err(
+                "specify directories rather than patterns.  If your directory really has any of '*?[]\\' in it, pass --skip-checks"
+                    .to_string(),
+            );
+        }
+        let dir: Vec<&str> = arg.split('/').filter(|v| !v.is_empty() && *v != ".").collect();
+        if !dir.is_empty() {
+            ret.insert(dir.join("/"));
+        }
+    }
+    Ok(ret)
+}
+
+impl GitRepo {
+    /// The patterns of the sparse checkout of this worktree, `None` when it isn't sparse.
+    pub fn sparse_checkout_read(&self) -> Option<SparsePatterns> {
+        if !self.config_bool("core.sparseCheckout").unwrap_or(false) {
+            return None;
+        }
+        let cone = self.config_bool("core.sparseCheckoutCone").unwrap_or(false);
+        Some(self.sparse_checkout_file_read(cone).unwrap_or(SparsePatterns::parse("", cone)))
+    }
+
+    /// Reads `info/sparse-checkout` in the given mode, `None` when there is no such file.
+    pub fn sparse_checkout_file_read(&self, cone: bool) -> Option<SparsePatterns> {
+        let content = fs::read_to_string(self.repo_path("info/sparse-checkout".into())).ok()?;
+        Some(SparsePatterns::parse(&content, cone))
+    }
+
+    /// Writes `patterns` to `info/sparse-checkout`.
+    pub fn sparse_checkout_write(&self, patterns: &SparsePatterns) -> Result<(), String> {
+        let file = self.repo_file("info/sparse-checkout".into(), true)?;
+        fs::write(file, patterns.serialize()).map_err(|e| e.to_string())
+    }
+
+    /// Turns the sparse checkout of this worktree on or off, in the per-worktree configuration.
+    pub fn sparse_checkout_configure(&self, enabled: bool, cone: bool) -> Result<(), String> {
+        let common = config::config_file_for(ConfigScope::Local, Some(&self.git_dir))?;
+        config::config_set(&common, "extensions.worktreeConfig", "true", false)?;
+        let worktree = config::config_file_for(ConfigScope::Worktree, Some(&self.git_dir))?;
+        config::config_set(&worktree, "core.sparseCheckout", &enabled.to_string(), false)?;
+        config::config_set(&worktree, "core.sparseCheckoutCone", &(enabled && cone).to_string(), false)
+    }
+
+    /// Records that the index of this worktree is a full one, without sparse directory entries.
+    pub fn sparse_index_disable(&self) -> Result<(), String> {
+        let worktree = config::config_file_for(ConfigScope::Worktree, Some(&self.git_dir))?;
+        config::config_set(&worktree, "index.sparse", "false", false)
+    }
+
+    /// Tells if the worktree file of `entry` still has what the index recorded for it,
+    /// so that removing it loses nothing.
+    fn sparse_entry_up_to_date(&self, entry: &GitIndexEntry) -> bool {
+        let file = self.work_dir.join(&entry.name);
+        let metadata = match fs::symlink_metadata(&file) {
+            Ok(v) => v,
+            Err(_) => return true,
+        };
+        if entry.mode_type == 0b1110 {
+            return file.read_dir().is_ok_and(|mut v| v.next().is_none());
+        }
+        if (metadata.mtime() as u32, metadata.mtime_nsec() as u32) == entry.mtime && metadata.size() as u32 == entry.fsize {
+            return true;
+        }
+        let data = if metadata.file_type().is_symlink() {
+            fs::read_link(&file).map(|v| v.into_os_string().into_encoded_bytes())
+        } else {
+            fs::read(&file)
+        };
+        data.is_ok_and(|v| GitRepo::object_hash(b"blob", &v) == entry.sha)
+    }
+
+    /// Makes the worktree follow `patterns`, everything being kept when it is `None`: files
+    /// coming in are checked out, files going away are deleted and marked skip-worktree.
+    ///
+    /// Files with local changes are left where they are and returned.
+    pub fn sparse_checkout_apply(&self, patterns: Option<&SparsePatterns>) -> Result<Vec<String>, String> {
+        let mut index = self.index_read();
+        let attributes = self.gitattributes_read(true);
+        let mut left = Vec::new();
+        for entry in index.entries.iter_mut() {
+            let included = patterns.is_none_or(|v| v.includes(&entry.name));
+            let file = self.work_dir.join(&entry.name);
+            if included && entry.flag_skip_worktree {
+                entry.flag_skip_worktree = false;
+                if fs::symlink_metadata(&file).is_ok() {
+                    continue;
+                }
+                if let Some(parent) = file.parent() {
+                    fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+                }
+                if entry.mode_type == 0b1110 {
+                    fs::create_dir_all(&file).map_err(|e| e.to_string())?;
+                    continue;
+                }
+                let mode = format!("{:06o}", ((entry.mode_type as u32) << 12) | entry.mode_perms as u32);
+                let data = self.object_read(entry.sha.clone())?.data();
+                self.blob_checkout(&mode, data, &file, &entry.name, &attributes);
+                let metadata = fs::symlink_metadata(&file).map_err(|e| e.to_string())?;
+                *entry = Self::index_entry_from_file(
+                    entry.name.clone(),
+                    &metadata,
+                    entry.sha.clone(),
+                    entry.mode_type,
+                    entry.mode_perms,
+                );
+            } else if !included && !entry.flag_skip_worktree {
+                if !self.sparse_entry_up_to_date(entry) {
+                    left.push(entry.name.clone());
+                    continue;
+                }
+                if entry.mode_type == 0b1110 {
+                    let _ = fs::remove_dir(&file);
+                } else {
+                    let _ = fs::remove_file(&file);
+                }
+                // Like git, drop the directories this leaves empty.
+                for dir in file.ancestors().skip(1) {
+                    if dir == self.work_dir || fs::remove_dir(dir).is_err() {
+                        break;
+                    }
+                }
+                entry.flag_skip_worktree = true;
+            }
+        }
+        self.index_write(&index).map_err(|e| e.to_string())?;
+
+        if let Some(patterns) = patterns {
+            self.sparse_checkout_clean_dirs(patterns, &index.entries)?;
+        }
+        Ok(left)
+    }
+
+    /// Removes the directories cone mode leaves out as a whole, unless untracked files that
+    /// aren't ignored are in them.
+    fn sparse_checkout_clean_dirs(&self, patterns: &SparsePatterns, entries: &[GitIndexEntry]) -> Result<(), String> {
+        // Every entry of such a directory has to be skip-worktree for it to go.
+        let mut dirs: BTreeMap<String, bool> = BTreeMap::new();
+        for entry in entries {
+            if let Some(dir) = patterns.cone_excluded_dir(&entry.name) {
+                *dirs.entry(dir).or_insert(true) &= entry.flag_skip_worktree;
+            }
+        }
+        let tracked: BTreeSet<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        let ignore = self.gitignore_read();
+        for (dir, skipped) in dirs {
+            let path = self.work_dir.join(&dir);
+            if !skipped || !path.is_dir() {
+                continue;
+            }
+            if self.sparse_has_untracked(&path, &dir, &tracked, &ignore) {
+                eprintln!("warning: directory '{}/' contains untracked files, but is not in the sparse-checkout cone", dir);
+                continue;
+            }
+            fs::remove_dir_all(&path).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn sparse_has_untracked(&self, path: &Path, relative: &str, tracked: &BTreeSet<&str>, ignore: &GitIgnore) -> bool {
+        let entries = match fs::read_dir(path) {
+            Ok(v) => v.flatten(),
+            Err(_) => return false,
+        };
+        for entry in entries {
+            let name = format!("{}/{}", relative, entry.file_name().to_string_lossy());
+            let is_dir = entry.file_type().is_ok_and(|v| v.is_dir());
+            if tracked.contains(name.as_str()) || ignore.is_ignored(&name, is_dir) {
+                continue;
+            }
+            if !is_dir || self.sparse_has_untracked(&entry.path(), &name, tracked, ignore) {
+                return true;
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,355 @@
+use crate::helpers::clone::{copy_missing, list_refs, local_git_dir};
+use crate::helpers::config;
+use crate::helpers::config::ConfigScope;
+use crate::helpers::describe::DescribeOptions;
+use crate::helpers::git::GitRepo;
+use crate::helpers::git_objects::git_index_entry::GitIndexEntry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A `submodule.<name>` section of `.gitmodules`.
+#[derive(Debug, Clone)]
+pub struct Submodule {
+    pub name: String,
+    pub path: String,
+    pub url: Option<String>,
+    pub branch: Option<String>,
+}
+
+/// Reads the submodules declared in the content of a `.gitmodules` file.
+pub fn parse_gitmodules(content: &str) -> Result<Vec<Submodule>, String> {
+    let parsed = config::parse_conf(content, Path::new(".gitmodules"), ConfigScope::Local)?;
+    let mut submodules: Vec<Submodule> = Vec::new();
+    for entry in &parsed.entries {
+        let (section, subsection, key) = config::split_key(&entry.key)?;
+        let name = match (section, subsection) {
+            ("submodule", Some(v)) => v,
+            _ => continue,
+        };
+        let index = match submodules.iter().position(|v| v.name == name) {
+            Some(v) => v,
+            None => {
+                submodules.push(Submodule {
+                    name: name.to_string(),
+                    path: String::new(),
+                    url: None,
+                    branch: None,
+                });
+                submodules.len() - 1
+            }
+        };
+        let value = entry.value.clone().unwrap_or_default();
+        match key {
+            "path" => submodules[index].path = value.trim_end_matches('/').to_string(),
+            "url" => submodules[index].url = Some(value),
+            "branch" => submodules[index].branch = Some(value),
+            _ => {}
+        }
+    }
+    submodules.retain(|v| !v.path.is_empty());
+    Ok(submodules)
+}
+
+/// Resolves a `./` or `../` submodule URL against the URL of the superproject.
+fn resolve_relative_url(base: &str, url: &str) -> String {
+    let mut base = base.trim_end_matches('/').to_string();
+    let mut rest = url;
+    loop {
+        if let Some(v) = rest.strip_prefix("./") {
+            rest = v;
+        } else if let Some(v) = rest.strip_prefix("../") {
+            rest = v;
+            base = match base.rfind('/') {
+                Some(i) => base[..i].to_string(),
+                None => String::from("."),
+            };
+        } else {
+            break;
+        }
+    }
+    if rest.is_empty() {
+        base
+    } else {
+        format!("{}/{}", base, rest)
+    }
+}
+
+/// How a submodule's checkout compares to the commit the superproject records.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubmoduleState {
+    /// Not cloned or not checked out.
+    Uninitialized,
+    InSync,
+    /// HEAD of the submodule is another commit.
+    NewCommits,
+    /// Same commit, but the submodule has changes of its own.
+    ModifiedContent,
+}
+
+impl GitRepo {
+    /// The submodules of `.gitmodules` in the worktree, or in the index when it is missing.
+    pub fn submodules(&self) -> Result<Vec<Submodule>, String> {
+        let content = match fs::read(self.work_dir.join(".gitmodules")) {
+            Ok(v) => v,
+            Err(_) => match self.index_read().entries.iter().find(|e| e.name == ".gitmodules") {
+                Some(entry) => self.object_read(entry.sha.clone())?.data(),
+                None => return Ok(Vec::new()),
+            },
+        };
+        parse_gitmodules(&String::from_utf8_lossy(&content))
+    }
+
+    /// The gitlink entries of the index: path to the commit it records.
+    pub fn gitlinks(&self) -> BTreeMap<String, String> {
+        self.index_read()
+            .entries
+            .into_iter()
+            .filter(|e| e.mode_type == 0b1110)
+            .map(|e| (e.name, e.sha))
+            .collect()
+    }
+
+    /// Where the repository of a submodule is kept.
+    pub fn submodule_git_dir(&self, submodule: &Submodule) -> PathBuf {
+        self.common_dir.join("modules").join(&submodule.name)
+    }
+
+    /// The commit checked out in the submodule at `path`, if it is checked out at all.
+    pub fn submodule_head(&self, path: &str) -> Option<String> {
+        GitRepo::open(&self.work_dir.join(path))?.ref_resolve("HEAD".into())
+    }
+
+    pub fn submodule_state(&self, path: &str, recorded: &str) -> SubmoduleState {
+        let repo = match GitRepo::open(&self.work_dir.join(path)) {
+            Some(v) => v,
+            None => return SubmoduleState::Uninitialized,
+        };
+        match repo.ref_resolve("HEAD".into()) {
+            None => SubmoduleState::Uninitialized,
+            Some(head) if head != recorded => SubmoduleState::NewCommits,
+            Some(_) if repo.worktree_is_dirty() => SubmoduleState::ModifiedContent,
+            Some(_) => SubmoduleState::InSync,
+        }
+    }
+
+    /// Names the checkout of a submodule the way `submodule status` shows it.
+    pub fn submodule_describe(&self, path: &str) -> Option<String> {
+        let repo = GitRepo::open(&self.work_dir.join(path))?;
+        let options = DescribeOptions {
+            tags: false,
+            all: true,
+            long: false,
+            abbrev: 7,
+            dirty: None,
+            match_patterns: Vec::new(),
+            exclude_patterns: Vec::new(),
+        };
+        // Without a ref to name it after, git shows the abbreviated commit.
+        let head = repo.ref_resolve("HEAD".into())?;
+        Some(repo.describe("HEAD", &options).unwrap_or_else(|_| head[..7].to_string()))
+    }
+
+    /// Moves the worktree from the tree of the index to `tree`, touching only the paths that differ
+    /// between them, and refuses when one of those has local changes.
+    fn checkout_tree_over_index(&self, tree: &str) -> Result<(), String> {
+        let index: BTreeMap<String, GitIndexEntry> =
+            self.index_read().entries.into_iter().map(|e| (e.name.clone(), e)).collect();
+        let target = self.tree_leafs_flat(tree, "");
+        let changed: BTreeSet<&String> = index
+            .keys()
+            .chain(target.keys())
+            .filter(|path| index.get(*path).map(|e| &e.sha) != target.get(*path).map(|v| &v.sha))
+            .collect();
+
+        let modified: Vec<&String> = changed
+            .iter()
+            .filter(|path| match index.get(**path) {
+                Some(entry) if entry.mode_type != 0b1110 => {
+                    let file = self.work_dir.join(path);
+                    match fs::symlink_metadata(&file) {
+                        Ok(v) if v.file_type().is_symlink() => false,
+                        Ok(_) => fs::read(&file).map(|v| GitRepo::object_hash(b"blob", &v)).ok() != Some(entry.sha.clone()),
+                        Err(_) => false,
+                    }
+                }
+                _ => false,
+            })
+            .copied()
+            .collect();
+        if !modified.is_empty() {
+            let list: String = modified.iter().map(|v| format!("\t{}\n", v)).collect();
+            return Err(format!(
+                "Your local changes to the following files would be overwritten by checkout:\n{}Please commit your changes or stash them before you switch branches.\nAborting",
+                list
+            ));
+        }
+
+        let attributes = self.gitattributes_read_tree(tree);
+        let sparse = self.sparse_checkout_read();
+        for path in changed {
+            let dest = self.work_dir.join(path);
+            if index.get(path).is_some_and(|e| e.mode_type != 0b1110) {
+                let _ = fs::remove_file(&dest);
+            }
+            let leaf = match target.get(path) {
+                Some(v) if sparse.as_ref().is_none_or(|s| s.includes(path)) => v,
+                _ => continue,
+            };
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            if leaf.mode == "160000" {
+                fs::create_dir_all(&dest).map_err(|e| e.to_string())?;
+                continue;
+            }
+            let data = self.object_read(leaf.sha.clone())?.data();
+            self.blob_checkout(&leaf.mode, data, &dest, path, &attributes);
+        }
+        self.index_from_tree(tree)
+    }
+
+    /// Resolves the URL of `.gitmodules`, relative ones being relative to the superproject.
+    fn submodule_resolve_url(&self, url: &str) -> String {
+        if !url.starts_with("./") && !url.starts_with("../") {
+            return url.to_string();
+        }
+        let base = self
+            .config_get("remote.origin.url")
+            .unwrap_or_else(|| self.work_dir.to_string_lossy().into_owned());
+        resolve_relative_url(&base, url)
+    }
+
+    /// Copies the URL of a submodule into `.git/config`, returning it when it wasn't there yet.
+    pub fn submodule_init(&self, submodule: &Submodule) -> Result<Option<String>, String> {
+        let key = |name: &str| format!("submodule.{}.{}", submodule.name, name);
+        if self.config_get(&key("url")).is_some() {
+            return Ok(None);
+        }
+        let url = submodule
+            .url
+            .as_ref()
+            .ok_or_else(|| format!("No url found for submodule path '{}' in .gitmodules", submodule.path))?;
+        let url = self.submodule_resolve_url(url);
+        let config_file = self.common_dir.join("config");
+        config::config_set(&config_file, &key("active"), "true", false)?;
+        config::config_set(&config_file, &key("url"), &url, false)?;
+        Ok(Some(url))
+    }
+
+    /// Clones the repository at the local `url` into the submodule's git directory.
+    pub fn submodule_clone(&self, submodule: &Submodule, url: &str) -> Result<(), String> {
+        let source = local_git_dir(url).ok_or_else(|| format!("repository '{}' does not exist", url))?;
+        let git_dir = self.submodule_git_dir(submodule);
+        let failed = |e: std::io::Error| {
+            format!(
+                "clone of '{}' into submodule path '{}' failed: {}",
+                url,
+                self.work_dir.join(&submodule.path).display(),
+                e
+            )
+        };
+        for dir in ["objects", "refs/heads", "refs/tags", "refs/remotes/origin", "info"] {
+            fs::create_dir_all(git_dir.join(dir)).map_err(failed)?;
+        }
+        copy_missing(&source.join("objects"), &git_dir.join("objects"), false).map_err(failed)?;
+
+        for (name, sha) in list_refs(&source) {
+            let local = if let Some(branch) = name.strip_prefix("refs/heads/") {
+                format!("refs/remotes/origin/{}", branch)
+            } else if name.starts_with("refs/tags/") {
+                name
+            } else {
+                continue;
+            };
+            let path = git_dir.join(&local);
+            fs::create_dir_all(path.parent().unwrap()).map_err(failed)?;
+            fs::write(path, format!("{}\n", sha)).map_err(failed)?;
+        }
+
+        // The branch HEAD of the source is on becomes the local one.
+        let head = fs::read_to_string(source.join("HEAD")).unwrap_or_default();
+        let branch = head.trim().strip_prefix("ref: refs/heads/").map(|v| v.to_string());
+        let depth = Path::new("modules").join(&submodule.name).components().count() + 1;
+        let config_file = git_dir.join("config");
+        config::write_conf(config_file.clone(), config::get_default_conf()).map_err(failed)?;
+        let worktree = format!("{}{}", "../".repeat(depth), submodule.path);
+        config::config_set(&config_file, "core.worktree", &worktree, false)?;
+        config::config_set(&config_file, "remote.origin.url", url, false)?;
+        config::config_set(&config_file, "remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*", false)?;
+        match branch {
+            Some(branch) => {
+                let remote = fs::read_to_string(git_dir.join("refs/remotes/origin").join(&branch));
+                if let Ok(sha) = remote {
+                    let path = git_dir.join("refs/heads").join(&branch);
+                    fs::create_dir_all(path.parent().unwrap()).map_err(failed)?;
+                    fs::write(path, sha).map_err(failed)?;
+                    fs::write(
+                        git_dir.join("refs/remotes/origin/HEAD"),
+                        format!("ref: refs/remotes/origin/{}\n", branch),
+                    )
+                    .map_err(failed)?;
+                }
+                config::config_set(&config_file, &format!("branch.{}.remote", branch), "origin", false)?;
+                config::config_set(&config_file, &format!("branch.{}.merge", branch), &format!("refs/heads/{}", branch), false)?;
+                fs::write(git_dir.join("HEAD"), format!("ref: refs/heads/{}\n", branch)).map_err(failed)?;
+            }
+            None => fs::write(git_dir.join("HEAD"), head).map_err(failed)?,
+        }
+        fs::write(git_dir.join("description"), "Unnamed repository; edit this file 'description' to name the repository.\n")
+            .map_err(failed)?;
+        Ok(())
+    }
+
+    /// Makes the submodule's worktree point at its repository under `.git/modules`.
+    fn submodule_link_worktree(&self, submodule: &Submodule) -> Result<(), String> {
+        let worktree = self.work_dir.join(&submodule.path);
+        fs::create_dir_all(&worktree).map_err(|e| e.to_string())?;
+        let depth = Path::new(&submodule.path).components().count();
+        let git_dir = Path::new(".git/modules").join(&submodule.name);
+        fs::write(
+            worktree.join(".git"),
+            format!("gitdir: {}{}\n", "../".repeat(depth), git_dir.display()),
+        )
+        .map_err(|e| e.to_string())
+    }
+
+    /// Checks out `commit` in the submodule, detached, cloning it first if needed.
+    ///
+    /// Returns whether anything changed, as the submodule may be there already.
+    pub fn submodule_update(&self, submodule: &Submodule, url: &str, commit: &str) -> Result<bool, String> {
+        let git_dir = self.submodule_git_dir(submodule);
+        if !git_dir.is_dir() {
+            let worktree = fs::canonicalize(&self.work_dir).unwrap_or(self.work_dir.clone());
+            eprintln!("Cloning into '{}'...", worktree.join(&submodule.path).display());
+            self.submodule_clone(submodule, url)?;
+            eprintln!("done.");
+        }
+        if !self.work_dir.join(&submodule.path).join(".git").exists() {
+            self.submodule_link_worktree(submodule)?;
+        }
+        let repo = GitRepo::open(&self.work_dir.join(&submodule.path))
+            .ok_or_else(|| format!("not a git repository: {}", submodule.path))?;
+        if repo.ref_resolve("HEAD".into()).as_deref() == Some(commit) && repo.index_read().version.is_some() {
+            return Ok(false);
+        }
+
+        if repo.object_read(commit.to_string()).is_err() {
+            // The commit may have been made upstream after we cloned.
+            if let Some(source) = local_git_dir(url) {
+                copy_missing(&source.join("objects"), &git_dir.join("objects"), false).map_err(|e| e.to_string())?;
+            }
+        }
+        let tree = repo.obj_find(commit.to_string(), Some("tree".to_string()), None).map_err(|_| {
+            format!(
+                "Fetched in submodule path '{}', but it did not contain {}. Direct fetching of that commit failed.",
+                submodule.path, commit
+            )
+        })?;
+
+        repo.checkout_tree_over_index(&tree)?;
+        repo.index_from_tree(&tree)?;
+        fs::write(git_dir.join("HEAD"), format!("{}\n", commit)).map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,346 @@
+use crate::helpers::clone::list_refs;
+use crate::helpers::git::GitRepo;
+use crate::helpers::git_objects::commit::GitCommit;
+use crate::helpers::git_objects::tag::GitTag;
+use crate::helpers::git_objects::tree::GitTree;
+use crate::helpers::pkt_line::{pkt_delim, pkt_flush, pkt_read, pkt_write, pkt_write_line, Packet, LARGE_PACKET_DATA_MAX};
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+
+/// What the client of a `fetch` command asks for.
+#[derive(Debug, Default)]
+struct FetchRequest {
+    wants: Vec<String>,
+    haves: Vec<String>,
+    /// The commits the history of the client stops at.
+    shallow: Vec<String>,
+    depth: Option<usize>,
+    /// The depth counts from the shallow commits of the client rather than the wants.
+    deepen_relative: bool,
+    done: bool,
+    include_tag: bool,
+}
+
+impl FetchRequest {
+    fn parse(args: &[String]) -> Result<FetchRequest, String> {
+        let mut request = FetchRequest::default();
+        for arg in args {
+            let (name, value) = arg.split_once(' ').unwrap_or((arg, ""));
+            match name {
+                "want" => request.wants.push(value.to_string()),
+                "have" => request.haves.push(value.to_string()),
+                "shallow" => request.shallow.push(value.to_string()),
+                "deepen" => request.depth = Some(value.parse().map_err(|_| format!("invalid depth: {}", value))?),
+                "deepen-relative" => request.deepen_relative = true,
+                "done" => request.done = true,
+                "include-tag" => request.include_tag = true,
+                "thin-pack" | "no-progress" | "ofs-delta" => {}
+                _ => return Err(format!("unexpected line: '{}'", arg)),
+            }
+        }
+        Ok(request)
+    }
+}
+
+/// What a fetch sends: the objects of the pack, the commits the history of the client now
+/// stops at, and those it no longer stops at.
+struct FetchResult {
+    objects: Vec<String>,
+    shallow: Vec<String>,
+    unshallow: Vec<String>,
+}
+
+/// Reads a field of a commit or tag that holds object names.
+pub(crate) fn object_field(kvlm_value: Option<&Vec<Vec<u8>>>) -> Vec<String> {
+    kvlm_value.into_iter().flatten().map(|v| String::from_utf8_lossy(v).into_owned()).collect()
+}
+
+impl GitRepo {
+    /// Serves protocol v2 to a fetching client on `input` and `output`, the way
+    /// `git upload-pack` does over ssh: the capabilities first, then `ls-refs` and `fetch`
+    /// commands until the client hangs up.
+    pub fn upload_pack(&self, input: &mut impl Read, output: &mut impl Write) -> Result<(), String> {
+        self.upload_pack_advertise(output)?;
+        self.upload_pack_commands(input, output)
+    }
+
+    /// Sends the capabilities a fetching client starts with; over HTTP they are all
+    /// `info/refs` answers.
+    pub fn upload_pack_advertise(&self, output: &mut impl Write) -> Result<(), String> {
+        let io_error = |e: io::Error| e.to_string();
+        for line in [
+            "version 2".to_string(),
+            format!("agent=mygit/{}", env!("CARGO_PKG_VERSION")),
+            "ls-refs=unborn".to_string(),
+            "fetch=shallow".to_string(),
+            "server-option".to_string(),
+            "object-format=sha1".to_string(),
+        ] {
+            pkt_write_line(output, &line).map_err(io_error)?;
+        }
+        pkt_flush(output).map_err(io_error)?;
+        output.flush().map_err(io_error)
+    }
+
+    /// Answers the commands of a client up to the end of `input`; over HTTP each request
+    /// carries one.
+    pub fn upload_pack_commands(&self, input: &mut impl Read, output: &mut impl Write) -> Result<(), String> {
+        let io_error = |e: io::Error| e.to_string();
+        loop {
+            let command = match pkt_read(input) {
+                Ok(Packet::Flush) => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Ok(packet) => packet.line().unwrap_or_default(),
+                Err(e) => return Err(e.to_string()),
+            };
+            let command = command.strip_prefix("command=").ok_or_else(|| format!("expected command, got '{}'", command))?.to_string();
+            // Capabilities come first, then the arguments after a delimiter.
+            let mut args = Vec::new();
+            let mut in_args = false;
+            loop {
+                match pkt_read(input).map_err(io_error)? {
+                    Packet::Delim => in_args = true,
+                    Packet::Flush => break,
+                    packet if in_args => args.extend(packet.line()),
+                    _ => {}
+                }
+            }
+            match command.as_str() {
+                "ls-refs" => self.serve_ls_refs(&args, output).map_err(io_error)?,
+                "fetch" => self.serve_fetch(&FetchRequest::parse(&args)?, output)?,
+                _ => return Err(format!("invalid command '{}'", command)),
+            }
+            output.flush().map_err(io_error)?;
+        }
+    }
+
+    /// Lists HEAD and the refs starting with one of the `ref-prefix` arguments, or all of them.
+    fn serve_ls_refs(&self, args: &[String], output: &mut impl Write) -> io::Result<()> {
+        let has = |name: &str| args.iter().any(|v| v == name);
+        let prefixes: Vec<&str> = args.iter().filter_map(|v| v.strip_prefix("ref-prefix ")).collect();
+        let wanted = |name: &str| prefixes.is_empty() || prefixes.iter().any(|v| name.starts_with(v));
+
+        let refs = list_refs(&self.common_dir);
+        let head = std::fs::read_to_string(self.git_dir.join("HEAD")).unwrap_or_default();
+        let head_target = head.trim().strip_prefix("ref: ");
+        if wanted("HEAD") {
+            let symref = match (has("symrefs"), head_target) {
+                (true, Some(target)) => format!(" symref-target:{}", target),
+                _ => String::new(),
+            };
+            match head_target.map(|v| refs.get(v)).unwrap_or(Some(&head.trim().to_string())) {
+                Some(sha) => pkt_write_line(output, &format!("{} HEAD{}", sha, symref))?,
+                None if has("unborn") => pkt_write_line(output, &format!("unborn HEAD{}", symref))?,
+                None => {}
+            }
+        }
+        for (name, sha) in refs.iter().filter(|(name, _)| wanted(name)) {
+            let peeled = match has("peel") {
+                true => self.object_peel(sha).ok().filter(|v| v != sha).map(|v| format!(" peeled:{}", v)),
+                false => None,
+            };
+            pkt_write_line(output, &format!("{} {}{}", sha, name, peeled.unwrap_or_default()))?;
+        }
+        pkt_flush(output)
+    }
+
+    /// Answers one round of a fetch: acknowledges the `have`s it knows, and once the client
+    /// is done, or enough of them are common, sends the pack of what it lacks.
+    fn serve_fetch(&self, request: &FetchRequest, output: &mut impl Write) -> Result<(), String> {
+        let io_error = |e: io::Error| e.to_string();
+        for want in &request.wants {
+            if !self.object_exists(want) {
+                return Err(format!("upload-pack: not our ref {}", want));
+            }
+        }
+        let common: Vec<String> = request.haves.iter().filter(|v| self.object_exists(v)).cloned().collect();
+        if !request.done {
+            pkt_write_line(output, "acknowledgments").map_err(io_error)?;
+            if common.is_empty() {
+                pkt_write_line(output, "NAK").map_err(io_error)?;
+            }
+            for sha in &common {
+                pkt_write_line(output, &format!("ACK {}", sha)).map_err(io_error)?;
+            }
+            // Any common commit is a good enough place to stop.
+            if common.is_empty() {
+                return pkt_flush(output).map_err(io_error);
+            }
+            pkt_write_line(output, "ready").map_err(io_error)?;
+            pkt_delim(output).map_err(io_error)?;
+        }
+
+        let FetchResult { objects, shallow, unshallow } = self.upload_objects(request, &common)?;
+        if request.depth.is_some() || !request.shallow.is_empty() {
+            pkt_write_line(output, "shallow-info").map_err(io_error)?;
+            for sha in &shallow {
+                pkt_write_line(output, &format!("shallow {}", sha)).map_err(io_error)?;
+            }
+            for sha in &unshallow {
+                pkt_write_line(output, &format!("unshallow {}", sha)).map_err(io_error)?;
+            }
+            pkt_delim(output).map_err(io_error)?;
+        }
+        pkt_write_line(output, "packfile").map_err(io_error)?;
+        let pack = self.pack_build(&objects)?;
+        // Pack data goes on side band 1.
+        for chunk in pack.chunks(LARGE_PACKET_DATA_MAX - 1) {
+            let mut packet = vec![1u8];
+            packet.extend_from_slice(chunk);
+            pkt_write(output, &packet).map_err(io_error)?;
+        }
+        pkt_flush(output).map_err(io_error)
+    }
+
+    /// Lists the objects `wants` lead to that a repository having the commits `haves` lacks,
+    /// stopping where the history here is cut: what a push sends.
+    pub fn objects_missing(&self, wants: &[String], haves: &[String]) -> Result<Vec<String>, String> {
+        let shallow = std::fs::read_to_string(self.common_dir.join("shallow")).unwrap_or_default();
+        let request = FetchRequest {
+            wants: wants.to_vec(),
+            shallow: shallow.lines().map(|v| v.to_string()).collect(),
+            done: true,
+            ..Default::default()
+        };
+        let common: Vec<String> = haves.iter().filter(|v| self.object_exists(v)).cloned().collect();
+        Ok(self.upload_objects(&request, &common)?.objects)
+    }
+
+    /// Lists the objects the wants of `request` lead to that the client lacks, knowing it has
+    /// the `common` commits, and works out where its history is cut.
+    fn upload_objects(&self, request: &FetchRequest, common: &[String]) -> Result<FetchResult, String> {
+        let client_shallow: HashSet<&String> = request.shallow.iter().collect();
+        let parents = |sha: &str| -> Result<(String, Vec<String>), String> {
+            let (_, data) = self.object_read_raw(sha)?;
+            let commit = GitCommit::new(data);
+            let tree = object_field(commit.kvlm.get(b"tree".as_ref())).pop().unwrap_or_default();
+            Ok((tree, object_field(commit.kvlm.get(b"parent".as_ref()))))
+        };
+
+        // The client has the history of its common commits, down to where it is shallow.
+        let mut client_commits = HashSet::new();
+        let mut pending: Vec<String> = common.to_vec();
+        while let Some(sha) = pending.pop() {
+            if self.object_read_raw(&sha).is_ok_and(|(fmt, _)| fmt == b"commit") && client_commits.insert(sha.clone()) && !client_shallow.contains(&sha) {
+                pending.extend(parents(&sha)?.1);
+            }
+        }
+        let mut client_objects = HashSet::new();
+        for sha in common.iter().filter(|v| client_commits.contains(*v)) {
+            self.tree_objects(&parents(sha)?.0, &HashSet::new(), &mut client_objects)?;
+        }
+
+        let mut objects = Vec::new();
+        let mut seen = HashSet::new();
+        let mut generation = Vec::new();
+        for want in &request.wants {
+            let mut sha = want.clone();
+            loop {
+                let (fmt, data) = self.object_read_raw(&sha)?;
+                match fmt.as_slice() {
+                    b"commit" => generation.push(sha),
+                    b"tag" => {
+                        if seen.insert(sha.clone()) {
+                            objects.push(sha);
+                        }
+                        sha = object_field(GitTag::new(data).kvlm.get(b"object".as_ref())).pop().unwrap_or_default();
+                        continue;
+                    }
+                    b"tree" => self.tree_objects(&sha, &client_objects, &mut seen).map(|v| objects.extend(v))?,
+                    _ => {
+                        if seen.insert(sha.clone()) {
+                            objects.push(sha);
+                        }
+                    }
+                }
+                break;
+            }
+        }
+
+        // A depth counts generations from the wants, whatever the client has, or with
+        // `deepen-relative` from where the client history stops; without one, the walk stops
+        // at what the client has.
+        let walks = match (request.depth, request.deepen_relative) {
+            (Some(depth), true) => vec![(generation, None), (request.shallow.clone(), Some(depth + 1))],
+            (depth, _) => vec![(generation, depth)],
+        };
+        let mut commits = Vec::new();
+        let (mut shallow, mut unshallow) = (Vec::new(), Vec::new());
+        for (mut generation, depth) in walks {
+            let mut walked = HashSet::new();
+            let mut level = 1;
+            while !generation.is_empty() {
+                let mut next = Vec::new();
+                for sha in generation {
+                    if (depth.is_none() && client_commits.contains(&sha)) || !walked.insert(sha.clone()) {
+                        continue;
+                    }
+                    let (tree, commit_parents) = parents(&sha)?;
+                    if !client_commits.contains(&sha) {
+                        commits.push((sha.clone(), tree));
+                    }
+                    match depth {
+                        Some(depth) if level >= depth => {
+                            if !client_shallow.contains(&sha) {
+                                shallow.push(sha);
+                            }
+                        }
+                        None if client_shallow.contains(&sha) => {}
+                        _ => {
+                            if client_shallow.contains(&sha) {
+                                unshallow.push(sha);
+                            }
+                            next.extend(commit_parents);
+                        }
+                    }
+                }
+                generation = next;
+                level += 1;
+            }
+        }
+        for (sha, tree) in &commits {
+            if seen.insert(sha.clone()) {
+                objects.push(sha.clone());
+            }
+            objects.extend(self.tree_objects(tree, &client_objects, &mut seen)?);
+        }
+
+        if request.include_tag {
+            let sent: HashSet<String> = objects.iter().cloned().collect();
+            for sha in list_refs(&self.common_dir).iter().filter(|(k, _)| k.starts_with("refs/tags/")).map(|(_, v)| v) {
+                if !seen.contains(sha) && self.object_peel(sha).is_ok_and(|v| sent.contains(&v)) {
+                    let mut tag = sha.clone();
+                    // Tags of tags bring the ones in between.
+                    while seen.insert(tag.clone()) {
+                        objects.push(tag.clone());
+                        match self.object_read_raw(&tag)? {
+                            (fmt, data) if fmt == b"tag" => tag = object_field(GitTag::new(data).kvlm.get(b"object".as_ref())).pop().unwrap_or_default(),
+                            _ => break,
+                        }
+                    }
+                }
+            }
+        }
+        Ok(FetchResult { objects, shallow, unshallow })
+    }
+
+    /// Lists the tree `sha` and what it holds, leaving out submodule commits, objects in
+    /// `exclude` and those already `seen`, which it adds them to.
+    fn tree_objects(&self, sha: &str, exclude: &HashSet<String>, seen: &mut HashSet<String>) -> Result<Vec<String>, String> {
+        let mut objects = Vec::new();
+        let mut pending = vec![(sha.to_string(), true)];
+        while let Some((sha, is_tree)) = pending.pop() {
+            if exclude.contains(&sha) || !seen.insert(sha.clone()) {
+                continue;
+            }
+            if is_tree {
+                let (_, data) = self.object_read_raw(&sha)?;
+                let leafs = GitTree::from_raw(&data).leafs.into_iter().filter(|v| v.mode != "160000");
+                pending.extend(leafs.map(|v| (v.sha, v.mode.trim_start_matches('0').starts_with('4'))));
+            }
+            objects.push(sha);
+        }
+        Ok(objects)
+    }
+}
+
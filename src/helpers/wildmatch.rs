@@ -0,0 +1,229 @@
+/// Outcome of matching a piece of pattern, as in git's wildmatch.c.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Wild {
+    Match,
+    NoMatch,
+    /// Nothing further along the text can match either.
+    AbortAll,
+    /// Only a `**` further up can still make it match.
+    AbortToStarStar,
+}
+
+fn is_glob_special(c: u8) -> bool {
+    matches!(c, b'*' | b'?' | b'[' | b'\\')
+}
+
+fn class_matches(class: &[u8], c: u8) -> Option<bool> {
+    Some(match class {
+        b"alnum" => c.is_ascii_alphanumeric(),
+        b"alpha" => c.is_ascii_alphabetic(),
+        b"blank" => c == b' ' || c == b'\t',
+        b"cntrl" => c.is_ascii_control(),
+        b"digit" => c.is_ascii_digit(),
+        b"graph" => c.is_ascii_graphic(),
+        b"lower" => c.is_ascii_lowercase(),
+        b"print" => c.is_ascii_graphic() || c == b' ',
+        b"punct" => c.is_ascii_punctuation(),
+        b"space" => c.is_ascii_whitespace() || c == b'\x0b',
+        b"upper" => c.is_ascii_uppercase(),
+        b"xdigit" => c.is_ascii_hexdigit(),
+        _ => return None,
+    })
+}
+
+fn dowild(pattern: &[u8], text: &[u8], pathname: bool) -> Wild {
+    // Past the end reads as NUL, like the C strings this follows.
+    let at = |s: &[u8], i: usize| s.get(i).copied().unwrap_or(0);
+    let (mut p, mut t) = (0usize, 0usize);
+
+    while p < pattern.len() {
+        let mut p_ch = pattern[p];
+        let mut t_ch = at(text, t);
+        if t_ch == 0 && p_ch != b'*' {
+            return Wild::AbortAll;
+        }
+        match p_ch {
+            b'?' => {
+                if pathname && t_ch == b'/' {
+                    return Wild::NoMatch;
+                }
+            }
+            b'*' => {
+                let match_slash;
+                p += 1;
+                if at(pattern, p) == b'*' {
+                    let before_stars = p.checked_sub(2);
+                    while at(pattern, p) == b'*' {
+                        p += 1;
+                    }
+                    let after = at(pattern, p);
+                    if before_stars.is_none_or(|v| pattern[v] == b'/')
+                        && (after == 0 || after == b'/' || (after == b'\\' && at(pattern, p + 1) == b'/'))
+                    {
+                        // `**/` may stand for no directory at all.
+                        if after == b'/' && dowild(&pattern[p + 1..], &text[t..], pathname) == Wild::Match {
+                            return Wild::Match;
+                        }
+                        match_slash = true;
+                    } else {
+                        // Anywhere else `**` is just two `*`.
+                        match_slash = false;
+                    }
+                } else {
+                    match_slash = !pathname;
+                }
+
+                if p >= pattern.len() {
+                    // A trailing `**` takes everything, a trailing `*` the rest of the component.
+                    if !match_slash && text[t..].contains(&b'/') {
+                        return Wild::NoMatch;
+                    }
+                    return Wild::Match;
+                } else if !match_slash && pattern[p] == b'/' {
+                    // `*/` skips to the next slash, which the loop then matches.
+                    match text[t..].iter().position(|c| *c == b'/') {
+                        Some(v) => t += v,
+                        None => return Wild::NoMatch,
+                    }
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+
+                loop {
+                    if t_ch == 0 {
+                        break;
+                    }
+                    // Before a literal, skip the text up to that literal.
+                    if !is_glob_special(pattern[p]) {
+                        let literal = pattern[p];
+                        loop {
+                            t_ch = at(text, t);
+                            if t_ch == 0 || (!match_slash && t_ch == b'/') || t_ch == literal {
+                                break;
+                            }
+                            t += 1;
+                        }
+                        if t_ch != literal {
+                            return Wild::NoMatch;
+                        }
+                    }
+                    match dowild(&pattern[p..], &text[t..], pathname) {
+                        Wild::NoMatch => {
+                            if !match_slash && t_ch == b'/' {
+                                return Wild::AbortToStarStar;
+                            }
+                        }
+                        Wild::AbortToStarStar if match_slash => {}
+                        matched => return matched,
+                    }
+                    t += 1;
+                    t_ch = at(text, t);
+                }
+                return Wild::AbortAll;
+            }
+            b'[' => {
+                p += 1;
+                p_ch = at(pattern, p);
+                if p_ch == b'^' {
+                    p_ch = b'!';
+                }
+                let negated = p_ch == b'!';
+                if negated {
+                    p += 1;
+                    p_ch = at(pattern, p);
+                }
+                let mut prev_ch = 0u8;
+                let mut matched = false;
+                loop {
+                    if p_ch == 0 {
+                        return Wild::AbortAll;
+                    }
+                    if p_ch == b'\\' {
+                        p += 1;
+                        p_ch = at(pattern, p);
+                        if p_ch == 0 {
+                            return Wild::AbortAll;
+                        }
+                        if t_ch == p_ch {
+                            matched = true;
+                        }
+                    } else if p_ch == b'-' && prev_ch != 0 && at(pattern, p + 1) != 0 && at(pattern, p + 1) != b']' {
+                        p += 1;
+                        p_ch = at(pattern, p);
+                        if p_ch == b'\\' {
+                            p += 1;
+                            p_ch = at(pattern, p);
+                            if p_ch == 0 {
+                                return Wild::AbortAll;
+                            }
+                        }
+                        if t_ch <= p_ch && t_ch >= prev_ch {
+                            matched = true;
+                        }
+                        p_ch = 0;
+                    } else if p_ch == b'[' && at(pattern, p + 1) == b':' {
+                        let start = p + 2;
+                        let mut end = start;
+                        while at(pattern, end) != 0 && at(pattern, end) != b']' {
+                            end += 1;
+                        }
+                        if at(pattern, end) == 0 {
+                            return Wild::AbortAll;
+                        }
+                        if end == start || pattern[end - 1] != b':' {
+                            // No `:]`, so the `[` is an ordinary member of the set.
+                            if t_ch == b'[' {
+                                matched = true;
+                            }
+                        } else {
+                            match class_matches(&pattern[start..end - 1], t_ch) {
+                                Some(true) => matched = true,
+                                Some(false) => {}
+                                None => return Wild::AbortAll,
+                            }
+                            p = end;
+                            p_ch = 0;
+                        }
+                    } else if t_ch == p_ch {
+                        matched = true;
+                    }
+                    prev_ch = p_ch;
+                    p += 1;
+                    p_ch = at(pattern, p);
+                    if p_ch == b']' {
+                        break;
+                    }
+                }
+                if matched == negated || (pathname && t_ch == b'/') {
+                    return Wild::NoMatch;
+                }
+            }
+            _ => {
+                if p_ch == b'\\' {
+                    p += 1;
+                    p_ch = at(pattern, p);
+                }
+                if t_ch != p_ch {
+                    return Wild::NoMatch;
+                }
+            }
+        }
+        p += 1;
+        t += 1;
+    }
+
+    if t < text.len() {
+        Wild::NoMatch
+    } else {
+        Wild::Match
+    }
+}
+
+/// Matches `text` against a shell glob the way git does.
+///
+/// With `pathname`, wildcards stop at slashes and only `**` crosses directories:
+/// `**/x` matches at any depth, `x/**` everything inside `x`, `a/**/b` zero or more levels.
+pub fn wildmatch(pattern: &str, text: &str, pathname: bool) -> bool {
+    dowild(pattern.as_bytes(), text.as_bytes(), pathname) == Wild::Match
+}
@@ -0,0 +1,143 @@
+use crate::helpers::git::GitRepo;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A working tree of the repository, the main one or a linked one.
+#[derive(Debug, Clone)]
+pub struct Worktree {
+    pub path: PathBuf,
+    /// The name of its directory under `.git/worktrees`, `None` for the main worktree.
+    pub id: Option<String>,
+    /// The commit checked out, `None` on an unborn branch.
+    pub head: Option<String>,
+    /// The branch checked out, as `refs/heads/<name>`; `None` when detached.
+    pub branch: Option<String>,
+    /// Why `worktree prune` would remove it, if it would.
+    pub prunable: Option<String>,
+    /// A bare main repository, which has no files checked out.
+    pub bare: bool,
+}
+
+impl GitRepo {
+    /// The top of the main worktree, the one the common directory belongs to.
+    pub fn main_worktree_path(&self) -> PathBuf {
+        let common_dir = fs::canonicalize(&self.common_dir).unwrap_or(self.common_dir.clone());
+        match common_dir.file_name() {
+            Some(v) if v == ".git" => common_dir.parent().unwrap_or(&common_dir).to_path_buf(),
+            _ => common_dir,
+        }
+    }
+
+    fn worktree_head(&self, head_file: &Path) -> (Option<String>, Option<String>) {
+        let content = fs::read_to_string(head_file).unwrap_or_default();
+        match content.trim_end().strip_prefix("ref: ") {
+            Some(target) => (self.ref_resolve(target.into()), Some(target.to_string())),
+            None => (Some(content.trim_end().to_string()).filter(|v| !v.is_empty()), None),
+        }
+    }
+
+    /// Lists the main worktree, then the linked ones sorted by path.
+    pub fn worktrees(&self) -> Vec<Worktree> {
+        let (head, branch) = self.worktree_head(&self.common_dir.join("HEAD"));
+        let main = Worktree {
+            path: self.main_worktree_path(),
+            id: None,
+            head,
+            branch,
+            prunable: None,
+            bare: self.config_bool("core.bare").unwrap_or(false),
+        };
+
+        let mut linked = Vec::new();
+        let entries = fs::read_dir(self.common_dir.join("worktrees")).map(|v| v.flatten().collect()).unwrap_or_else(|_| Vec::new());
+        for entry in entries {
+            let admin = entry.path();
+            if !admin.is_dir() {
+                continue;
+            }
+            let gitdir = fs::read_to_string(admin.join("gitdir")).ok().map(|v| PathBuf::from(v.trim_end()));
+            let prunable = match &gitdir {
+                None => Some("gitdir file does not exist".to_string()),
+                Some(v) if !v.exists() => Some("gitdir file points to non-existent location".to_string()),
+                Some(_) => None,
+            };
+            let path = gitdir
+                .as_ref()
+                .and_then(|v| v.parent())
+                .map(|v| v.to_path_buf())
+                .unwrap_or(admin.clone());
+            let (head, branch) = self.worktree_head(&admin.join("HEAD"));
+            linked.push(Worktree {
+                path,
+                id: Some(entry.file_name().to_string_lossy().into_owned()),
+                head,
+                branch,
+                prunable,
+                bare: false,
+            });
+        }
+        linked.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut ret = vec![main];
+        ret.extend(linked);
+        ret
+    }
+
+    /// The worktree that has `branch` (`refs/heads/<name>`) checked out, if any.
+    pub fn worktree_with_branch(&self, branch: &str) -> Option<Worktree> {
+        self.worktrees()
+            .into_iter()
+            .find(|v| v.prunable.is_none() && v.branch.as_deref() == Some(branch))
+    }
+
+    /// Creates a linked worktree at `path` with `head` as its HEAD (`ref: refs/heads/<name>`
+    /// or a commit) and checks `commit` out in it.
+    pub fn worktree_add(&self, path: &Path, head: &str, commit: &str) -> Result<(), String> {
+        fs::create_dir_all(path).map_err(|e| format!("could not create directory of '{}': {}", path.display(), e))?;
+        let path = fs::canonicalize(path).map_err(|e| e.to_string())?;
+
+        // The id is the directory name, with a number added if another worktree has it.
+        let base = path
+            .file_name()
+            .map(|v| v.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "worktree".to_string());
+        let worktrees = self.common_dir.join("worktrees");
+        let mut id = base.clone();
+        let mut counter = 0;
+        while worktrees.join(&id).exists() {
+            counter += 1;
+            id = format!("{}{}", base, counter);
+        }
+        let admin = worktrees.join(&id);
+        fs::create_dir_all(&admin).map_err(|e| e.to_string())?;
+        let admin = fs::canonicalize(&admin).map_err(|e| e.to_string())?;
+
+        let write = |file: PathBuf, content: String| fs::write(file, content).map_err(|e| e.to_string());
+        write(admin.join("commondir"), "../..\n".to_string())?;
+        write(admin.join("gitdir"), format!("{}\n", path.join(".git").display()))?;
+        write(admin.join("HEAD"), format!("{}\n", head))?;
+        write(path.join(".git"), format!("gitdir: {}\n", admin.display()))?;
+
+        let repo = GitRepo::open(&path).ok_or_else(|| format!("not a git repository: {}", path.display()))?;
+        let tree = repo.obj_find(commit.to_string(), Some("tree".to_string()), None)?;
+        repo.tree_checkout(repo.object_read(tree.clone())?, path.clone());
+        repo.index_from_tree(&tree)
+    }
+
+    /// Deletes a linked worktree along with its administrative files.
+    pub fn worktree_remove(&self, worktree: &Worktree) -> Result<(), String> {
+        let id = worktree.id.as_ref().ok_or("cannot remove the main working tree")?;
+        if worktree.path.exists() {
+            fs::remove_dir_all(&worktree.path).map_err(|e| format!("failed to delete '{}': {}", worktree.path.display(), e))?;
+        }
+        self.worktree_prune_admin(id)
+    }
+
+    /// Removes `.git/worktrees/<id>`, and the `worktrees` directory once it is empty.
+    pub fn worktree_prune_admin(&self, id: &str) -> Result<(), String> {
+        let worktrees = self.common_dir.join("worktrees");
+        fs::remove_dir_all(worktrees.join(id)).map_err(|e| e.to_string())?;
+        let _ = fs::remove_dir(worktrees);
+        Ok(())
+    }
+}
@@ -1,9 +1,12 @@
 mod commands;
 pub mod helpers;
 
-use crate::commands::commands::{check_git_ignore, checkout, ls_files, ls_tree, remove, rev_parse, show_ref, status, tag};
-use clap::{Parser, Subcommand};
+use crate::commands::commands::{branch, check_attr, check_git_ignore, checkout, clean, clone, commit, config, describe, fetch, lfs_fsck, lfs_ls_files, lfs_prune, ls_files, ls_tree, push, receive_pack, remote_add, remote_list, remote_remove, remote_rename, remote_set_url, remove, rev_parse, show, show_ref, sparse_checkout_add, sparse_checkout_disable, sparse_checkout_init, sparse_checkout_list, sparse_checkout_reapply, sparse_checkout_set, status, submodule_foreach, submodule_init, submodule_status, submodule_update, tag, upload_pack, worktree_add, worktree_list, worktree_prune, worktree_remove, verify_commit, verify_tag, CheckIgnoreOptions, ConfigOptions, TagOptions};
+use clap::{CommandFactory, Parser, Subcommand};
 use commands::commands::{add, cat_file, hash_obj, init, log};
+use helpers::clean::CleanOptions;
+use helpers::config::ConfigScope;
+use helpers::describe::DescribeOptions;
 use helpers::git_objects::git_object::ObjectType;
 use std::path::PathBuf;
 
@@ -18,12 +21,72 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     Init {
+        /// Make the directory itself the repository, with no working tree.
+        #[clap(long)]
+        bare: bool,
         #[clap(default_value_t = helpers::file::get_exe_dir())]
         path: String,
     },
+    /// Copy a local repository, or one served over HTTP, into a new directory.
+    Clone {
+        /// Make a bare repository, with the branches of the source as its own.
+        #[clap(long)]
+        bare: bool,
+        /// Check out this branch, or tag, instead of the one HEAD of the source is on.
+        #[clap(short, long)]
+        branch: Option<String>,
+        /// Only copy this many commits of history; needs a file:// or http:// URL.
+        #[clap(long)]
+        depth: Option<usize>,
+        url: String,
+        dir: Option<String>,
+    },
+    /// Download objects and refs from another repository.
+    Fetch {
+        remote: Option<String>,
+        refspecs: Vec<String>,
+    },
+    /// Update the refs of another repository, along with the objects they need.
+    Push {
+        /// Update refs even when that loses commits on the remote.
+        #[clap(short, long)]
+        force: bool,
+        /// Only force an update when the remote ref is where we expect it, which is where its
+        /// remote-tracking ref is unless given as `<ref>:<expect>`.
+        #[clap(long, value_name = "REF[:EXPECT]", num_args = 0..=1, default_missing_value = "")]
+        force_with_lease: Vec<String>,
+        /// Delete the given refs from the remote.
+        #[clap(short, long)]
+        delete: bool,
+        remote: Option<String>,
+        refspecs: Vec<String>,
+    },
+    /// Manage the remotes the repository fetches from and pushes to; lists them without a
+    /// subcommand.
+    Remote {
+        /// Show the URL of each remote too.
+        #[clap(short, long)]
+        verbose: bool,
+        #[clap(subcommand)]
+        command: Option<RemoteCommands>,
+    },
+    /// List the branches, or set the upstream of one.
+    Branch {
+        /// Make this branch the upstream of <name>, or of the current branch.
+        #[clap(short = 'u', long, value_name = "UPSTREAM")]
+        set_upstream_to: Option<String>,
+        name: Option<String>,
+    },
+    /// Serve the repository at <dir> to a fetching client, over protocol v2 on stdin and stdout.
+    UploadPack { dir: String },
+    /// Take a push into the repository at <dir> from a client on stdin and stdout, running its hooks.
+    ReceivePack { dir: String },
     Add {
-        #[clap(default_value_t = helpers::file::get_exe_dir())]
-        path: String,
+        /// Also add files that are ignored.
+        #[clap(short, long)]
+        force: bool,
+        #[clap(required = true)]
+        paths: Vec<PathBuf>,
     },
     Checkout {
         commit: String,
@@ -31,8 +94,13 @@ enum Commands {
         path: String,
     },
     Commit {
-        #[clap(default_value_t = helpers::file::get_exe_dir())]
-        path: String,
+        #[clap(short, long)]
+        message: Option<String>,
+        /// Sign the commit with `user.signingkey`.
+        #[clap(short = 'S', long = "gpg-sign")]
+        sign: bool,
+        #[clap(long = "no-gpg-sign")]
+        no_sign: bool,
     },
     Rm {
         paths: Vec<PathBuf>,
@@ -51,6 +119,8 @@ enum Commands {
     Log {
         #[clap(default_value = "HEAD")]
         commit: String,
+        #[clap(long)]
+        show_signature: bool,
     },
     LsTree {
         #[clap(short)]
@@ -58,44 +128,362 @@ enum Commands {
         tree: String,
     },
     Tag {
-        name: Option<String>,
+        /// Tag name and target when creating, patterns with `-l`, names with `-d`.
+        args: Vec<String>,
         #[clap(short = 'a')]
         create: bool,
-        #[clap(default_value = "HEAD")]
-        object: String,
         #[clap(short, long)]
         message: Option<String>,
+        #[clap(short, long)]
+        delete: bool,
+        #[clap(short, long)]
+        list: bool,
+        #[clap(short, long)]
+        force: bool,
+        /// Lines of annotation to list; git only takes the count attached (`-n3`), so a
+        /// detached value is the first pattern.
+        #[clap(short = 'n', num_args = 0..=1, default_missing_value = "1")]
+        lines: Option<String>,
+        /// Make a signed annotated tag.
+        #[clap(short, long)]
+        sign: bool,
     },
     ShowRef,
+    Config {
+        /// `<key> [<value>]`, or nothing with `--list`.
+        args: Vec<String>,
+        #[clap(long)]
+        system: bool,
+        #[clap(long)]
+        global: bool,
+        #[clap(long)]
+        local: bool,
+        #[clap(long)]
+        worktree: bool,
+        #[clap(long)]
+        get: bool,
+        #[clap(long)]
+        get_all: bool,
+        #[clap(long)]
+        add: bool,
+        #[clap(long)]
+        unset: bool,
+        #[clap(long)]
+        unset_all: bool,
+        #[clap(short, long)]
+        list: bool,
+        #[clap(long)]
+        show_origin: bool,
+    },
+    VerifyCommit {
+        commits: Vec<String>,
+    },
+    VerifyTag {
+        tags: Vec<String>,
+    },
+    Describe {
+        commit: Option<String>,
+        #[clap(long)]
+        tags: bool,
+        #[clap(long)]
+        all: bool,
+        #[clap(long)]
+        long: bool,
+        #[clap(long, default_value_t = 7)]
+        abbrev: usize,
+        #[clap(long, num_args = 0..=1, default_missing_value = "-dirty")]
+        dirty: Option<String>,
+        #[clap(long = "match")]
+        match_patterns: Vec<String>,
+        #[clap(long = "exclude")]
+        exclude_patterns: Vec<String>,
+    },
+    Show {
+        objects: Vec<String>,
+        #[clap(long)]
+        stat: bool,
+        #[clap(long, alias = "pretty")]
+        format: Option<String>,
+    },
     RevParse {
-        name: String,
+        /// Revisions to resolve, and `--git-dir`, `--show-toplevel`, `--is-bare-repository`
+        /// or `--is-inside-work-tree` queries, answered in order.
+        #[clap(required = true, allow_hyphen_values = true)]
+        args: Vec<String>,
     },
     LsFiles {
         #[clap(short, long)]
         verbose: bool,
     },
     CheckIgnore {
-        paths: Vec<PathBuf>,
+        /// Show the rule that matched each path.
+        #[clap(short, long)]
+        verbose: bool,
+        /// Read the paths from standard input, one per line.
+        #[clap(long)]
+        stdin: bool,
+        /// Separate input and output records with NUL instead of newlines.
+        #[clap(short = 'z')]
+        nul: bool,
+        /// Also show the paths that no rule matches; needs --verbose.
+        #[clap(short, long)]
+        non_matching: bool,
+        /// Check tracked paths too.
+        #[clap(long)]
+        no_index: bool,
+        paths: Vec<String>,
+    },
+    CheckAttr {
+        /// Report every attribute that is set on the paths.
+        #[clap(short, long)]
+        all: bool,
+        /// Attributes, followed by the paths when no `--` is given.
+        args: Vec<String>,
+        #[clap(last = true)]
+        paths: Vec<String>,
     },
     Status,
+    /// Remove untracked files from the working tree.
+    Clean {
+        /// Only show what would be removed.
+        #[clap(short = 'n', long)]
+        dry_run: bool,
+        /// Needed unless clean.requireForce is false; twice to remove nested repositories.
+        #[clap(short, long, action = clap::ArgAction::Count)]
+        force: u8,
+        /// Also remove untracked directories.
+        #[clap(short)]
+        d: bool,
+        /// Don't use the ignore rules, only the -e patterns.
+        #[clap(short = 'x')]
+        no_ignore: bool,
+        /// Remove only the files the ignore rules match.
+        #[clap(short = 'X')]
+        only_ignored: bool,
+        /// Add an ignore pattern.
+        #[clap(short, long = "exclude")]
+        excludes: Vec<String>,
+        paths: Vec<String>,
+    },
+    /// Large files kept as pointer blobs, with their content in `.git/lfs/objects`.
+    Lfs {
+        #[clap(subcommand)]
+        command: LfsCommands,
+    },
+    Submodule {
+        #[clap(subcommand)]
+        command: SubmoduleCommands,
+    },
+    Worktree {
+        #[clap(subcommand)]
+        command: WorktreeCommands,
+    },
+    /// Check out only part of the tree, marking the rest skip-worktree in the index.
+    SparseCheckout {
+        #[clap(subcommand)]
+        command: SparseCheckoutCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum RemoteCommands {
+    /// Add a remote whose branches are fetched into `refs/remotes/<name>/`.
+    Add { name: String, url: String },
+    /// Remove a remote along with its remote-tracking refs.
+    #[clap(alias = "rm")]
+    Remove { name: String },
+    /// Rename a remote, moving its remote-tracking refs along.
+    Rename { old: String, new: String },
+    /// Change the URL of a remote.
+    SetUrl { name: String, url: String },
+}
+
+#[derive(Subcommand)]
+enum SparseCheckoutCommands {
+    /// Start a sparse checkout with only the files at the top.
+    Init {
+        #[clap(long, overrides_with = "no_cone")]
+        cone: bool,
+        /// Use gitignore-style patterns instead of directories.
+        #[clap(long, overrides_with = "cone")]
+        no_cone: bool,
+    },
+    /// Replace the directories, or patterns, the worktree keeps.
+    Set {
+        #[clap(long, overrides_with = "no_cone")]
+        cone: bool,
+        #[clap(long, overrides_with = "cone")]
+        no_cone: bool,
+        patterns: Vec<String>,
+    },
+    /// Keep more directories, or patterns, in the worktree.
+    Add { patterns: Vec<String> },
+    /// Show the directories, or patterns, of the sparse checkout.
+    List,
+    /// Update the worktree to the patterns again, after files were left behind.
+    Reapply,
+    /// Check every file out again and stop being sparse.
+    Disable,
+}
+
+#[derive(Subcommand)]
+enum WorktreeCommands {
+    /// Check a branch or commit out in a new linked worktree at <path>.
+    Add {
+        /// Create this branch, starting at <commit>.
+        #[clap(short = 'b')]
+        new_branch: Option<String>,
+        /// Check out <commit> detached, even when it names a branch.
+        #[clap(long)]
+        detach: bool,
+        /// Check the branch out even if another worktree has it.
+        #[clap(short, long)]
+        force: bool,
+        path: String,
+        commit: Option<String>,
+    },
+    /// List the main worktree and the linked ones.
+    List {
+        #[clap(long)]
+        porcelain: bool,
+    },
+    /// Delete a linked worktree.
+    Remove {
+        /// Remove it even with modified or untracked files.
+        #[clap(short, long)]
+        force: bool,
+        path: String,
+    },
+    /// Forget linked worktrees whose directory is gone.
+    Prune {
+        #[clap(short = 'n', long)]
+        dry_run: bool,
+        #[clap(short, long)]
+        verbose: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SubmoduleCommands {
+    /// Register the submodules of .gitmodules in .git/config.
+    Init { paths: Vec<String> },
+    /// Clone missing submodules and check out the commits the superproject records.
+    Update {
+        /// Initialize the submodules first.
+        #[clap(long)]
+        init: bool,
+        paths: Vec<String>,
+    },
+    /// Show the commit each submodule has checked out.
+    Status { paths: Vec<String> },
+    /// Run a shell command in every checked out submodule.
+    Foreach {
+        #[clap(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        command: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum LfsCommands {
+    /// List the large files of a tree, HEAD by default.
+    LsFiles {
+        reference: Option<String>,
+        /// Show the whole object id.
+        #[clap(short, long)]
+        long: bool,
+        /// Show the size of each file.
+        #[clap(short, long)]
+        size: bool,
+    },
+    /// Check that the objects HEAD and the index point to are in the store and intact.
+    Fsck {
+        /// Leave corrupt objects where they are.
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Delete objects no ref, HEAD or index points to.
+    Prune {
+        #[clap(short, long)]
+        dry_run: bool,
+        #[clap(short, long)]
+        verbose: bool,
+    },
+}
+
+/// The mode `--cone` or `--no-cone` asks for, `None` without either.
+fn cone_flag(cone: bool, no_cone: bool) -> Option<bool> {
+    match (cone, no_cone) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+/// Applies the leading `-C <path>` options, each relative to the one before, like git.
+fn change_directories(mut args: Vec<String>) -> Vec<String> {
+    while args.len() > 2 && args[1] == "-C" {
+        let path = args.remove(2);
+        args.remove(1);
+        if path.is_empty() {
+            continue;
+        }
+        if let Err(e) = std::env::set_current_dir(&path) {
+            let reason = e.to_string();
+            let reason = reason.split(" (os error").next().unwrap_or(&reason);
+            eprintln!("fatal: cannot change to '{}': {}", path, reason);
+            std::process::exit(128);
+        }
+    }
+    args
 }
 
 fn main() {
-    let cli = Cli::parse();
+    let args = change_directories(std::env::args().collect());
+    let args = helpers::alias::resolve_command(args, |name| {
+        name == "help" || Cli::command().find_subcommand(name).is_some()
+    });
+    let cli = Cli::parse_from(args);
 
     match &cli.command {
-        Commands::Add { path } => {
-            add(path.clone());
+        Commands::Add { force, paths } => {
+            add(paths, *force);
         }
-        Commands::Init { path } => {
-            init(path.clone());
+        Commands::Init { bare, path } => {
+            init(path.clone(), *bare);
         }
+        Commands::Clone {
+            bare,
+            branch,
+            depth,
+            url,
+            dir,
+        } => clone(url, dir.as_deref(), *bare, branch.as_deref(), *depth),
+        Commands::Fetch { remote, refspecs } => fetch(remote.as_deref(), refspecs),
+        Commands::Push {
+            force,
+            force_with_lease,
+            delete,
+            remote,
+            refspecs,
+        } => push(remote.as_deref(), refspecs, *force, force_with_lease, *delete),
+        Commands::Remote { verbose, command } => match command {
+            None => remote_list(*verbose),
+            Some(RemoteCommands::Add { name, url }) => remote_add(name, url),
+            Some(RemoteCommands::Remove { name }) => remote_remove(name),
+            Some(RemoteCommands::Rename { old, new }) => remote_rename(old, new),
+            Some(RemoteCommands::SetUrl { name, url }) => remote_set_url(name, url),
+        },
+        Commands::Branch { set_upstream_to, name } => branch(set_upstream_to.as_deref(), name.as_deref()),
+        Commands::UploadPack { dir } => upload_pack(dir),
+        Commands::ReceivePack { dir } => receive_pack(dir),
         Commands::Checkout { commit, path } => {
             checkout(commit.clone(), path.into());
         }
-        Commands::Commit { path: _path } => {
-            println!("'myapp commit' was used");
-        }
+        Commands::Commit {
+            message,
+            sign,
+            no_sign,
+        } => commit(message, *sign, *no_sign),
         Commands::Rm { paths } => remove(paths),
         Commands::CatFile {
             object_type,
@@ -108,20 +496,199 @@ fn main() {
             path,
             write,
         } => hash_obj(object_type, path, write),
-        Commands::Log { commit } => {
-            log(commit.clone());
+        Commands::Log {
+            commit,
+            show_signature,
+        } => {
+            log(commit.clone(), *show_signature);
         }
         Commands::LsTree { recursive, tree } => ls_tree(recursive, tree),
         Commands::Tag {
-            name,
+            args,
             create,
-            object,
             message,
-        } => tag(name, create, object, message),
+            delete,
+            list,
+            force,
+            lines,
+            sign,
+        } => {
+            let mut args = args.clone();
+            let lines = lines.as_ref().map(|v| {
+                v.parse().unwrap_or_else(|_| {
+                    args.insert(0, v.clone());
+                    1
+                })
+            });
+            tag(
+                &args,
+                TagOptions {
+                    annotate: *create,
+                    message: message.clone(),
+                    delete: *delete,
+                    list: *list,
+                    force: *force,
+                    lines,
+                    sign: *sign,
+                },
+            )
+        }
         Commands::ShowRef => show_ref(),
-        Commands::RevParse { name } => rev_parse(name),
+        Commands::Config {
+            args,
+            system,
+            global,
+            local,
+            worktree,
+            get,
+            get_all,
+            add,
+            unset,
+            unset_all,
+            list,
+            show_origin,
+        } => config(
+            args,
+            ConfigOptions {
+                scope: match (system, global, local, worktree) {
+                    (true, _, _, _) => Some(ConfigScope::System),
+                    (_, true, _, _) => Some(ConfigScope::Global),
+                    (_, _, true, _) => Some(ConfigScope::Local),
+                    (_, _, _, true) => Some(ConfigScope::Worktree),
+                    _ => None,
+                },
+                get: *get,
+                get_all: *get_all,
+                add: *add,
+                unset: *unset,
+                unset_all: *unset_all,
+                list: *list,
+                show_origin: *show_origin,
+            },
+        ),
+        Commands::VerifyCommit { commits } => verify_commit(commits),
+        Commands::VerifyTag { tags } => verify_tag(tags),
+        Commands::Describe {
+            commit,
+            tags,
+            all,
+            long,
+            abbrev,
+            dirty,
+            match_patterns,
+            exclude_patterns,
+        } => describe(
+            commit,
+            DescribeOptions {
+                tags: *tags,
+                all: *all,
+                long: *long,
+                abbrev: *abbrev,
+                dirty: dirty.clone(),
+                match_patterns: match_patterns.clone(),
+                exclude_patterns: exclude_patterns.clone(),
+            },
+        ),
+        Commands::Show {
+            objects,
+            stat,
+            format,
+        } => show(objects, *stat, format),
+        Commands::RevParse { args } => rev_parse(args),
         Commands::LsFiles { verbose } => ls_files(*verbose),
-        Commands::CheckIgnore { paths } => check_git_ignore(paths.clone()),
+        Commands::CheckIgnore {
+            verbose,
+            stdin,
+            nul,
+            non_matching,
+            no_index,
+            paths,
+        } => check_git_ignore(
+            paths,
+            CheckIgnoreOptions {
+                verbose: *verbose,
+                stdin: *stdin,
+                nul: *nul,
+                non_matching: *non_matching,
+                no_index: *no_index,
+            },
+        ),
+        Commands::CheckAttr { all, args, paths } => check_attr(*all, args, paths),
         Commands::Status => status(),
+        Commands::Clean {
+            dry_run,
+            force,
+            d,
+            no_ignore,
+            only_ignored,
+            excludes,
+            paths,
+        } => clean(
+            *dry_run,
+            CleanOptions {
+                force: *force,
+                dirs: *d,
+                no_ignore: *no_ignore,
+                only_ignored: *only_ignored,
+                excludes: excludes.clone(),
+                paths: paths.clone(),
+            },
+        ),
+        Commands::Lfs { command } => match command {
+            LfsCommands::LsFiles { reference, long, size } => lfs_ls_files(reference.as_deref(), *long, *size),
+            LfsCommands::Fsck { dry_run } => lfs_fsck(*dry_run),
+            LfsCommands::Prune { dry_run, verbose } => lfs_prune(*dry_run, *verbose),
+        },
+        Commands::Submodule { command } => match command {
+            SubmoduleCommands::Init { paths } => submodule_init(paths),
+            SubmoduleCommands::Update { init, paths } => submodule_update(*init, paths),
+            SubmoduleCommands::Status { paths } => submodule_status(paths),
+            SubmoduleCommands::Foreach { command } => submodule_foreach(command),
+        },
+        Commands::Worktree { command } => match command {
+            WorktreeCommands::Add {
+                new_branch,
+                detach,
+                force,
+                path,
+                commit,
+            } => worktree_add(path, commit.as_deref(), new_branch.as_deref(), *detach, *force),
+            WorktreeCommands::List { porcelain } => worktree_list(*porcelain),
+            WorktreeCommands::Remove { force, path } => worktree_remove(path, *force),
+            WorktreeCommands::Prune { dry_run, verbose } => worktree_prune(*dry_run, *verbose),
+        },
+        Commands::SparseCheckout { command } => match command {
+            SparseCheckoutCommands::Init { cone, no_cone } => sparse_checkout_init(cone_flag(*cone, *no_cone)),
+            SparseCheckoutCommands::Set { cone, no_cone, patterns } => {
+                sparse_checkout_set(cone_flag(*cone, *no_cone), patterns)
+            }
+            SparseCheckoutCommands::Add { patterns } => sparse_checkout_add(patterns),
+            SparseCheckoutCommands::List => sparse_checkout_list(),
+            SparseCheckoutCommands::Reapply => sparse_checkout_reapply(),
+            SparseCheckoutCommands::Disable => sparse_checkout_disable(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cli, Commands};
+    use clap::Parser;
+
+    #[test]
+    fn tag_n_takes_only_an_attached_count() {
+        for (argv, lines, args) in [
+            (vec!["mygit", "tag", "-n3"], "3", vec![]),
+            (vec!["mygit", "tag", "-n", "v*"], "v*", vec![]),
+            (vec!["mygit", "tag", "-n2", "v*"], "2", vec!["v*"]),
+        ] {
+            match Cli::try_parse_from(argv).unwrap().command {
+                Commands::Tag { lines: v, args: a, .. } => {
+                    assert_eq!(v.as_deref(), Some(lines));
+                    assert_eq!(a, args);
+                }
+                _ => panic!("not a tag command"),
+            }
+        }
     }
 }